@@ -0,0 +1,150 @@
+//! GPU benchmarks: GP0 command decoding and software rasterization.
+//!
+//! Run them with `cargo bench`, the results are stored in
+//! `target/criterion` and compared with the previous run.
+
+#[macro_use]
+extern crate criterion;
+extern crate rustation;
+
+use criterion::{Criterion, black_box};
+
+use rustation::gpu::{Gpu, VideoClock};
+use rustation::gpu::renderer::{Renderer, NullRenderer};
+use rustation::gpu::software::SoftwareRenderer;
+
+/// GP0 commands configuring a 1024x512 drawing area with no offset
+const SETUP: [u32; 3] = [
+    // Drawing area top-left: 0, 0
+    0xe3000000,
+    // Drawing area bottom-right: 1023, 511
+    0xe407fbff,
+    // Drawing offset: 0, 0
+    0xe5000000,
+    ];
+
+/// Build a GPU ready to draw in the whole VRAM
+fn setup_gpu(renderer: &mut dyn Renderer) -> Gpu {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+
+    send(&mut gpu, renderer, &SETUP);
+
+    gpu
+}
+
+fn send(gpu: &mut Gpu, renderer: &mut dyn Renderer, commands: &[u32]) {
+    for &w in commands {
+        gpu.gp0(renderer, black_box(w));
+    }
+}
+
+/// Flat triangle whose bounding box is `size` pixels wide and high
+fn monochrome_triangle(size: u32) -> [u32; 4] {
+    [0x20808080,
+     0x00000000,
+     size,
+     size << 16]
+}
+
+/// Representative mix of commands: draw mode changes, flat and
+/// shaded polygons, textured rectangles and fills
+fn command_stream() -> Vec<u32> {
+    let mut stream = Vec::new();
+
+    for i in 0..64 {
+        let x = (i * 8) & 0x3ff;
+        let y = (i * 4) & 0x1ff;
+        let pos = |dx: u32, dy: u32| ((y + dy) << 16) | (x + dx);
+
+        // Draw mode: 16bpp texture page at 512, 0, dithering enabled
+        stream.push(0xe1000308);
+        // Flat quad
+        stream.extend_from_slice(&[0x28204060,
+                                   pos(0, 0), pos(16, 0),
+                                   pos(0, 16), pos(16, 16)]);
+        // Gouraud triangle
+        stream.extend_from_slice(&[0x30ff0000, pos(0, 0),
+                                   0x0000ff00, pos(16, 0),
+                                   0x000000ff, pos(0, 16)]);
+        // Raw textured 16x16 sprite
+        stream.extend_from_slice(&[0x7d000000, pos(0, 0), 0x00000000]);
+        // Fill
+        stream.extend_from_slice(&[0x02000000, pos(0, 0), 0x00100010]);
+    }
+
+    stream
+}
+
+fn gp0_decode(c: &mut Criterion) {
+    let stream = command_stream();
+
+    c.bench_function("gp0 decode", move |b| {
+        let mut renderer = NullRenderer;
+        let mut gpu = setup_gpu(&mut renderer);
+
+        b.iter(|| send(&mut gpu, &mut renderer, &stream))
+    });
+}
+
+fn rasterize_triangles(c: &mut Criterion) {
+    c.bench_function_over_inputs("flat triangle", |b, &&size| {
+        let mut renderer = SoftwareRenderer::new();
+        let mut gpu = setup_gpu(&mut renderer);
+
+        let triangle = monochrome_triangle(size);
+
+        b.iter(|| send(&mut gpu, &mut renderer, &triangle))
+    }, &[8, 64, 256]);
+}
+
+fn rasterize_textured_quad(c: &mut Criterion) {
+    c.bench_function("textured quad 128x128", |b| {
+        let mut renderer = SoftwareRenderer::new();
+        let mut gpu = setup_gpu(&mut renderer);
+
+        // 4bpp texture page at 512, 0 with its CLUT at 0, 256
+        let quad = [0x2c808080,
+                    0x00000000, 0x40000000,
+                    0x00000080, 0x00080080,
+                    0x00800000, 0x00008000,
+                    0x00800080, 0x00008080];
+
+        b.iter(|| send(&mut gpu, &mut renderer, &quad))
+    });
+}
+
+fn rasterize_shaded_dithered_quad(c: &mut Criterion) {
+    c.bench_function("gouraud dithered quad 128x128", |b| {
+        let mut renderer = SoftwareRenderer::new();
+        let mut gpu = setup_gpu(&mut renderer);
+
+        // Draw mode: dithering enabled
+        send(&mut gpu, &mut renderer, &[0xe1000200]);
+
+        let quad = [0x38ff0000, 0x00000000,
+                    0x0000ff00, 0x00000080,
+                    0x000000ff, 0x00800000,
+                    0x00ffffff, 0x00800080];
+
+        b.iter(|| send(&mut gpu, &mut renderer, &quad))
+    });
+}
+
+fn full_stream(c: &mut Criterion) {
+    let stream = command_stream();
+
+    c.bench_function("gp0 stream software", move |b| {
+        let mut renderer = SoftwareRenderer::new();
+        let mut gpu = setup_gpu(&mut renderer);
+
+        b.iter(|| send(&mut gpu, &mut renderer, &stream))
+    });
+}
+
+criterion_group!(benches,
+                 gp0_decode,
+                 rasterize_triangles,
+                 rasterize_textured_quad,
+                 rasterize_shaded_dithered_quad,
+                 full_stream);
+criterion_main!(benches);
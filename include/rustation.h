@@ -0,0 +1,75 @@
+/* C bindings for the rustation PlayStation emulator, see src/ffi.rs
+ * for the details.
+ *
+ * All the functions taking a context return one of the RUSTATION_*
+ * status codes below. Once a function has returned
+ * RUSTATION_ERROR_PANIC the context can only be freed with
+ * rustation_free. */
+
+#ifndef RUSTATION_H
+#define RUSTATION_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Success */
+#define RUSTATION_OK                0
+/* rustation_run_frame reached the cycle limit before the end of the
+ * frame */
+#define RUSTATION_TIMEOUT           1
+
+/* A NULL pointer was passed where a valid one was expected */
+#define RUSTATION_ERROR_NULL       -1
+/* The BIOS image is invalid */
+#define RUSTATION_ERROR_BAD_BIOS   -2
+/* The disc image couldn't be loaded */
+#define RUSTATION_ERROR_BAD_DISC   -3
+/* No BIOS has been loaded yet */
+#define RUSTATION_ERROR_NO_BIOS    -4
+/* The emulator panicked, the context is unusable */
+#define RUSTATION_ERROR_PANIC      -5
+/* The BIOS and disc can't be changed once the emulation is running */
+#define RUSTATION_ERROR_RUNNING    -6
+
+/* Opaque emulator context */
+typedef struct rustation_context rustation_context;
+
+/* Allocate a new emulator context. Returns NULL on failure. */
+rustation_context *rustation_new(void);
+
+/* Free a context allocated by rustation_new. Does nothing if ctx is
+ * NULL. */
+void rustation_free(rustation_context *ctx);
+
+/* Load the BIOS image from the len bytes at data. The buffer is
+ * copied and can be freed by the caller afterwards. */
+int rustation_load_bios(rustation_context *ctx,
+                        const uint8_t *data,
+                        size_t len);
+
+/* Load the disc image described by the cue sheet at path cue */
+int rustation_load_disc(rustation_context *ctx, const char *cue);
+
+/* Run the emulator until the end of the current frame. The emulation
+ * starts the first time this function is called, a BIOS must have
+ * been loaded by then. */
+int rustation_run_frame(rustation_context *ctx);
+
+/* Retrieve the last frame. On success *pixels points to *width x
+ * *height pixels in 0x00RRGGBB format, line by line. The buffer
+ * remains valid until the next call to rustation_run_frame or
+ * rustation_free. */
+int rustation_get_framebuffer(rustation_context *ctx,
+                              const uint32_t **pixels,
+                              uint32_t *width,
+                              uint32_t *height);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUSTATION_H */
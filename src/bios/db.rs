@@ -12,6 +12,10 @@ pub struct Metadata {
     pub region: Region,
     /// True if this dump is known to be bad
     pub known_bad: bool,
+    /// Offset of the `jal` instruction calling the shell (boot
+    /// animation and memory card/CD player menu) from the bootstrap
+    /// code, if known. Used by `Bios::patch_skip_intro`.
+    pub animation_jump_hook: Option<u32>,
 }
 
 pub fn lookup(binary: &[u8]) -> Option<&'static Metadata> {
@@ -44,6 +48,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x5e, 0xb3, 0xae, 0xe4, 0x95, 0x93, 0x75, 0x58,
@@ -54,6 +59,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x42, 0xe4, 0x12, 0x4b, 0xe7, 0x62, 0x3e, 0x2e,
@@ -64,6 +70,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::NorthAmerica,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x0a, 0xf2, 0xbe, 0x34, 0x68, 0xd3, 0x0b, 0x60,
@@ -74,6 +81,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x6f, 0x71, 0xca, 0x1e, 0x71, 0x6d, 0xa7, 0x61,
@@ -84,6 +92,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x6a, 0xd5, 0x52, 0x1d, 0x10, 0x5a, 0x6b, 0x86,
@@ -94,6 +103,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::NorthAmerica,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x1e, 0xfb, 0x0c, 0xfc, 0x5d, 0xb8, 0xa8, 0x75,
@@ -104,6 +114,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x0c, 0x83, 0x59, 0x87, 0x0c, 0xba, 0xc0, 0xea,
@@ -114,6 +125,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 2,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x8e, 0x03, 0x83, 0x17, 0x1e, 0x67, 0xb3, 0x3e,
@@ -124,6 +136,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 2,
         region: Region::Japan,
         known_bad: true,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x71, 0xaf, 0x94, 0xd1, 0xe4, 0x7a, 0x68, 0xc1,
@@ -134,6 +147,8 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 2,
         region: Region::NorthAmerica,
         known_bad: false,
+        // SCPH-1001
+        animation_jump_hook: Some(0x6990),
     },
     Metadata {
         sha256: [0x3d, 0x06, 0xd2, 0xc4, 0x69, 0x31, 0x3c, 0x2a,
@@ -144,6 +159,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 2,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x40, 0x18, 0x74, 0x9b, 0x36, 0x98, 0xb8, 0x69,
@@ -154,6 +170,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 2,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x9c, 0x04, 0x21, 0x85, 0x8e, 0x21, 0x78, 0x05,
@@ -164,6 +181,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x11, 0x05, 0x2b, 0x64, 0x99, 0xe4, 0x66, 0xbb,
@@ -174,6 +192,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::NorthAmerica,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x1f, 0xaa, 0xa1, 0x8f, 0xa8, 0x20, 0xa0, 0x22,
@@ -184,6 +203,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x9e, 0x1f, 0x8f, 0xb4, 0xfa, 0x35, 0x6a, 0x5a,
@@ -194,6 +214,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::Europe,
         known_bad: true,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0xe9, 0x00, 0x50, 0x4d, 0x17, 0x55, 0xf0, 0x21,
@@ -204,6 +225,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 0,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0xb3, 0xaa, 0x63, 0xcf, 0x30, 0xc8, 0x1e, 0x0a,
@@ -214,6 +236,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x39, 0xdc, 0xc1, 0xa0, 0x71, 0x70, 0x36, 0xc9,
@@ -224,6 +247,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::NorthAmerica,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x5e, 0x84, 0xa9, 0x48, 0x18, 0xcf, 0x52, 0x82,
@@ -234,6 +258,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 1,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0xb2, 0x9b, 0x4b, 0x5f, 0xcd, 0xde, 0xf3, 0x69,
@@ -244,6 +269,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 3,
         region: Region::Japan,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x5c, 0x01, 0x66, 0xda, 0x24, 0xe2, 0x7d, 0xea,
@@ -254,6 +280,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 4,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0xac, 0xa9, 0xcb, 0xfa, 0x97, 0x4b, 0x93, 0x36,
@@ -264,6 +291,7 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 5,
         region: Region::NorthAmerica,
         known_bad: false,
+        animation_jump_hook: None,
     },
     Metadata {
         sha256: [0x42, 0x24, 0x4b, 0x0c, 0x65, 0x08, 0x21, 0x51,
@@ -274,5 +302,6 @@ pub static DATABASE: [Metadata; 24] = [
         version_minor: 5,
         region: Region::Europe,
         known_bad: false,
+        animation_jump_hook: None,
     },
 ];
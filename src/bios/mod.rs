@@ -1,4 +1,11 @@
-use memory::Addressable;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use memory::{Addressable, Word};
+use cdrom::disc::Region;
+use error::Error;
 
 use self::db::Metadata;
 
@@ -28,6 +35,92 @@ impl Bios {
         }
     }
 
+    /// Create a BIOS image from the contents of a BIOS file. Unlike
+    /// `new` the size of `data` is checked and an error describing
+    /// the problem is returned if the image can't be used.
+    pub fn from_data(data: &[u8]) -> Result<Bios, BiosError> {
+        if data.len() != BIOS_SIZE {
+            return Err(BiosError::BadSize(data.len()));
+        }
+
+        let metadata =
+            match db::lookup(data) {
+                Some(m) => m,
+                None => return Err(BiosError::Unknown),
+            };
+
+        if metadata.known_bad {
+            return Err(BiosError::BadDump);
+        }
+
+        let mut binary = box_array![0; BIOS_SIZE];
+
+        binary.copy_from_slice(data);
+
+        Ok(Bios {
+            data: binary,
+            metadata,
+        })
+    }
+
+    /// Load a BIOS image from the file at `path`, see `from_data`
+    pub fn from_file(path: &Path) -> Result<Bios, Error> {
+        let mut data = Vec::new();
+
+        File::open(path)?.read_to_end(&mut data)?;
+
+        Ok(Bios::from_data(&data)?)
+    }
+
+    /// Create a BIOS image from `binary` without looking it up in the
+    /// database. Used to run test programs and homebrew BIOS
+    /// replacements, the image is reported as a Japanese BIOS.
+    pub fn dummy(binary: Box<[u8; BIOS_SIZE]>) -> Bios {
+        Bios {
+            data: binary,
+            metadata: &db::DATABASE[0],
+        }
+    }
+
+    /// Patch the BIOS to skip the intro: the boot animation and the
+    /// shell are never called, the kernel is initialized normally
+    /// and then boots the disc directly. Only useful if a disc is
+    /// present, otherwise the BIOS won't have anything to boot. This
+    /// still runs the real BIOS code, it's not a high level
+    /// emulation of the kernel.
+    ///
+    /// The location of the call to the shell depends on the BIOS
+    /// version, an error is returned if it's not in the database or
+    /// if the instruction found there isn't the expected `jal`. The
+    /// BIOS is left untouched in this case.
+    pub fn patch_skip_intro(&mut self) -> Result<(), BiosError> {
+        let hook =
+            match self.metadata.animation_jump_hook {
+                Some(h) => h,
+                None => return Err(BiosError::UnsupportedPatch),
+            };
+
+        // The shell must be called with a `jal`
+        if self.load::<Word>(hook) >> 26 != 0x03 {
+            return Err(BiosError::UnsupportedPatch);
+        }
+
+        // Replace the call with a NOP, the bootstrap code carries on
+        // as if the shell had returned
+        self.patch_word(hook, 0x00000000);
+
+        Ok(())
+    }
+
+    /// Replace the little endian word at `offset`
+    fn patch_word(&mut self, offset: u32, val: u32) {
+        let offset = offset as usize;
+
+        for i in 0..4 {
+            self.data[offset + i] = (val >> (8 * i)) as u8;
+        }
+    }
+
     /// Fetch the little endian value at `offset`
     pub fn load<T: Addressable>(&self, offset: u32) -> u32 {
         let offset = offset as usize;
@@ -44,7 +137,160 @@ impl Bios {
     pub fn metadata(&self) -> &'static Metadata {
         self.metadata
     }
+
+    /// Region of the console this BIOS comes from. Frontends can use
+    /// it to pick the default video standard.
+    pub fn region(&self) -> Region {
+        self.metadata.region
+    }
+
+    /// BIOS version string, for instance "4.1"
+    pub fn version(&self) -> String {
+        format!("{}.{}",
+                self.metadata.version_major,
+                self.metadata.version_minor)
+    }
+}
+
+/// Error returned by `Bios::from_data`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BiosError {
+    /// The image doesn't have the expected size (512KB)
+    BadSize(usize),
+    /// The image is not in the BIOS database, it's probably corrupted
+    /// or not a PlayStation BIOS at all
+    Unknown,
+    /// The image is a known bad dump
+    BadDump,
+    /// The requested patch isn't supported for this BIOS version
+    UnsupportedPatch,
+}
+
+impl fmt::Display for BiosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BiosError::BadSize(s) =>
+                write!(f, "Invalid BIOS size: expected {} bytes, got {}",
+                       BIOS_SIZE, s),
+            BiosError::Unknown => write!(f, "Unknown BIOS image"),
+            BiosError::BadDump => write!(f, "Known bad BIOS dump"),
+            BiosError::UnsupportedPatch =>
+                write!(f, "Patch not supported for this BIOS"),
+        }
+    }
 }
 
 /// BIOS images are always 512KB in length
 pub const BIOS_SIZE: usize = 512 * 1024;
+
+/// Build a dummy BIOS running an infinite loop
+#[cfg(test)]
+pub fn looping_bios() -> Bios {
+    let mut binary = box_array![0; BIOS_SIZE];
+
+    // Infinite loop at the reset vector: `j 0xbfc00000` followed by a
+    // NOP in the delay slot
+    binary[0] = 0x00;
+    binary[1] = 0x00;
+    binary[2] = 0xf0;
+    binary[3] = 0x0b;
+
+    Bios::dummy(binary)
+}
+
+#[test]
+fn bios_from_data() {
+    let short = Bios::from_data(&[0; 1024]);
+
+    assert!(short.err() == Some(BiosError::BadSize(1024)));
+
+    let data = vec![0; BIOS_SIZE];
+
+    assert!(Bios::from_data(&data).err() == Some(BiosError::Unknown));
+
+    let bios = Bios::dummy(box_array![0; BIOS_SIZE]);
+
+    assert!(bios.region() == Region::Japan);
+    assert!(bios.version() == "1.0");
+}
+
+#[test]
+fn bios_skip_intro() {
+    use gpu::{Gpu, VideoClock};
+    use gpu::renderer::NullRenderer;
+    use machine::Machine;
+
+    static METADATA: Metadata = Metadata {
+        sha256: [0; 32],
+        version_major: 2,
+        version_minor: 2,
+        region: Region::NorthAmerica,
+        known_bad: false,
+        animation_jump_hook: Some(0x0),
+    };
+
+    // Bootstrap calling a fake shell, then looping forever. The shell
+    // sets $s1, the bootstrap sets $s0 once it's back.
+    let program = [
+        // 0xbfc00000: jal   0xbfc00100
+        (0x000, 0x0ff00040),
+        // 0xbfc00008: ori   $s0, $zero, 1
+        (0x008, 0x34100001),
+        // 0xbfc0000c: j     0xbfc0000c
+        (0x00c, 0x0bf00003),
+        // 0xbfc00100: ori   $s1, $zero, 0x1234
+        (0x100, 0x34111234),
+        // 0xbfc00104: jr    $ra
+        (0x104, 0x03e00008),
+        ];
+
+    // Return the values of $s0 and $s1 after running `bios` for a
+    // while
+    fn boot(bios: Bios) -> (u32, u32) {
+        let mut machine = Machine::new(bios, Gpu::new(VideoClock::Ntsc), None);
+        let mut renderer = NullRenderer;
+
+        for _ in 0..20 {
+            machine.step(&mut renderer);
+        }
+
+        let regs = machine.cpu().regs();
+
+        (regs[16], regs[17])
+    }
+
+    let mut binary = box_array![0; BIOS_SIZE];
+
+    for &(offset, instruction) in &program {
+        for i in 0..4 {
+            binary[offset + i] = (instruction >> (i * 8)) as u8;
+        }
+    }
+
+    let mut bios = Bios {
+        data: binary.clone(),
+        metadata: &METADATA,
+    };
+
+    assert!(boot(Bios { data: binary, metadata: &METADATA }) == (1, 0x1234));
+
+    assert!(bios.patch_skip_intro().is_ok());
+
+    let (s0, s1) = boot(bios);
+
+    assert!(s0 == 1);
+    assert!(s1 != 0x1234);
+
+    // No hook for this BIOS
+    let mut bios = Bios::dummy(box_array![0; BIOS_SIZE]);
+
+    assert!(bios.patch_skip_intro() == Err(BiosError::UnsupportedPatch));
+
+    // The hook doesn't point at a `jal`
+    let mut bios = Bios {
+        data: box_array![0; BIOS_SIZE],
+        metadata: &METADATA,
+    };
+
+    assert!(bios.patch_skip_intro() == Err(BiosError::UnsupportedPatch));
+}
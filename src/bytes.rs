@@ -0,0 +1,31 @@
+//! Helpers to decode and encode the little endian values found in
+//! disc and memory card images
+
+/// Read a little endian 32bit value from the first 4 bytes of `b`
+pub fn read_u32_le(b: &[u8]) -> u32 {
+    let mut v = 0;
+
+    for i in 0..4 {
+        v |= (b[i] as u32) << (i * 8);
+    }
+
+    v
+}
+
+/// Write `v` as a little endian 32bit value in the first 4 bytes of
+/// `b`
+pub fn write_u32_le(b: &mut [u8], v: u32) {
+    for i in 0..4 {
+        b[i] = (v >> (i * 8)) as u8;
+    }
+}
+
+#[test]
+fn u32_le() {
+    let mut b = [0u8; 6];
+
+    write_u32_le(&mut b[1..], 0x12345678);
+
+    assert!(b == [0, 0x78, 0x56, 0x34, 0x12, 0]);
+    assert!(read_u32_le(&b[1..]) == 0x12345678);
+}
@@ -1,7 +1,13 @@
+use std::path::Path;
+
 use cdimage::{Image, CdError};
+use cdimage::cue::Cue;
 use cdimage::msf::Msf;
 use cdimage::sector::Sector;
 
+use bytes::read_u32_le;
+use error::Error;
+
 /// PlayStation disc.
 ///
 /// XXX: add support for CD-DA? Not really useful but shouldn't
@@ -11,6 +17,9 @@ pub struct Disc {
     image: Box<Image>,
     /// Disc region
     region: Region,
+    /// Game serial number (e.g. "SLUS-00594") if we managed to find
+    /// it
+    game_id: Option<String>,
 }
 
 impl Disc {
@@ -20,15 +29,34 @@ impl Disc {
             image: image,
             // Use a dummy id for now.
             region: Region::Japan,
+            game_id: None,
         };
 
-        disc.extract_region()
+        let mut disc = disc.extract_region()?;
+
+        disc.game_id = disc.extract_game_id();
+
+        Ok(disc)
+    }
+
+    /// Load the disc image described by the cue sheet at `path`
+    pub fn from_cue(path: &Path) -> Result<Disc, Error> {
+        let cue = Cue::new(path)?;
+
+        Ok(Disc::new(Box::new(cue))?)
     }
 
     pub fn region(&self) -> Region {
         self.region
     }
 
+    /// Return the game's serial number (e.g. "SLUS-00594") as found in
+    /// the disc's SYSTEM.CNF or None if it couldn't be found. Useful
+    /// to key per-game settings and memory cards.
+    pub fn game_id(&self) -> Option<String> {
+        self.game_id.clone()
+    }
+
     pub fn image(&mut self) -> &mut Image {
         &mut*self.image
     }
@@ -82,6 +110,166 @@ impl Disc {
 
         Ok(self)
     }
+
+    /// Look for the SYSTEM.CNF file in the root directory of the
+    /// disc's ISO9660 filesystem and extract the serial number from
+    /// the executable name in its BOOT line. Returns None if anything
+    /// goes wrong, some discs (and most homebrew) don't have a
+    /// SYSTEM.CNF at all.
+    fn extract_game_id(&mut self) -> Option<String> {
+        // The primary volume descriptor is always in sector 16
+        let pvd = match self.read_data_sector(16) {
+            Ok(s) => s,
+            Err(_) => return None,
+        };
+
+        if &pvd[0..6] != b"\x01CD001" {
+            warn!("Couldn't find ISO9660 primary volume descriptor");
+            return None;
+        }
+
+        // Root directory record
+        let root = &pvd[156..190];
+
+        let root_lba = read_u32_le(&root[2..]);
+        let root_size = read_u32_le(&root[10..]);
+
+        let mut cnf = None;
+
+        // Iterate over the root directory sectors
+        for i in 0..(root_size + 2047) / 2048 {
+            let dir = match self.read_data_sector(root_lba + i) {
+                Ok(s) => s,
+                Err(_) => return None,
+            };
+
+            cnf = find_system_cnf(&dir);
+
+            if cnf.is_some() {
+                break;
+            }
+        }
+
+        let (cnf_lba, cnf_size) = match cnf {
+            Some(c) => c,
+            None => return None,
+        };
+
+        // SYSTEM.CNF is a tiny text file, we don't expect it to take
+        // more than a sector
+        let cnf = match self.read_data_sector(cnf_lba) {
+            Ok(s) => s,
+            Err(_) => return None,
+        };
+
+        let cnf_size = ::std::cmp::min(cnf_size as usize, cnf.len());
+
+        parse_system_cnf(&cnf[0..cnf_size])
+    }
+
+    /// Return the 2048 bytes of user data of the mode 2 form 1 sector
+    /// at logical block address `lba` (relative to the beginning of
+    /// the data track)
+    fn read_data_sector(&mut self, lba: u32) -> Result<Vec<u8>, CdError> {
+        // Skip the 2 second pregap
+        let msf =
+            match Msf::from_sector_index(lba + 150) {
+                Some(m) => m,
+                None => return Err(CdError::BadFormat),
+            };
+
+        let mut sector = Sector::empty();
+
+        self.image.read_sector(&mut sector, msf)?;
+
+        let payload = sector.mode2_xa_payload()?;
+
+        Ok(payload[0..2048].to_vec())
+    }
+}
+
+/// Parse the contents of a SYSTEM.CNF file and extract the serial
+/// number from the BOOT line. For instance `BOOT =
+/// cdrom:\SLUS_005.94;1` returns `SLUS-00594`.
+fn parse_system_cnf(cnf: &[u8]) -> Option<String> {
+    let cnf = String::from_utf8_lossy(cnf);
+
+    for line in cnf.lines() {
+        let mut parts = line.splitn(2, '=');
+
+        let key = parts.next().unwrap_or("").trim();
+
+        if key != "BOOT" {
+            continue;
+        }
+
+        let path = parts.next().unwrap_or("").trim();
+
+        // Strip the device and directories
+        let exe = path.rsplit(|c| c == '\\' || c == ':' || c == '/')
+            .next()
+            .unwrap_or("");
+
+        // Strip the version
+        let exe = exe.split(';').next().unwrap_or("");
+
+        // Convert "SLUS_005.94" into "SLUS-00594"
+        let id: String = exe.chars()
+            .filter(|&c| c != '.')
+            .map(|c| match c {
+                '_' => '-',
+                c => c.to_ascii_uppercase(),
+            })
+            .collect();
+
+        if id.is_empty() {
+            return None;
+        }
+
+        return Some(id);
+    }
+
+    None
+}
+
+/// Look for the SYSTEM.CNF entry in the ISO9660 directory sector
+/// `dir`. Returns its location and size if found. Stops at the first
+/// malformed record.
+fn find_system_cnf(dir: &[u8]) -> Option<(u32, u32)> {
+    // Fixed part of a directory record, before the file name
+    const HEADER_LEN: usize = 33;
+
+    let mut offset = 0;
+
+    // Directory records don't cross sector boundaries, the remaining
+    // of the sector is padded with 0s.
+    while offset < dir.len() && dir[offset] != 0 {
+        let record = &dir[offset..];
+        let len = record[0] as usize;
+
+        if len < HEADER_LEN || len > record.len() {
+            warn!("Invalid ISO9660 directory record length {}", len);
+            return None;
+        }
+
+        let name_len = record[32] as usize;
+
+        if HEADER_LEN + name_len > len {
+            warn!("Invalid ISO9660 file name length {}", name_len);
+            return None;
+        }
+
+        let name = &record[HEADER_LEN..HEADER_LEN + name_len];
+
+        if name == b"SYSTEM.CNF;1" {
+            return Some((read_u32_le(&record[2..]),
+                         read_u32_le(&record[10..])));
+        }
+
+        offset += len;
+    }
+
+    None
 }
 
 /// Disc region coding
@@ -94,3 +282,65 @@ pub enum Region {
     /// Europe (PAL): SCEE
     Europe,
 }
+
+#[test]
+fn system_cnf_game_id() {
+    let cnf = b"BOOT = cdrom:\\SLUS_005.94;1\r\nTCB = 4\r\nEVENT = 10\r\n\
+                STACK = 801FFFF0\r\n";
+
+    assert!(parse_system_cnf(cnf) == Some("SLUS-00594".into()));
+
+    let cnf = b"BOOT=cdrom:\\GAME\\SCES_012.37;1\n";
+
+    assert!(parse_system_cnf(cnf) == Some("SCES-01237".into()));
+
+    let cnf = b"TCB = 4\r\n";
+
+    assert!(parse_system_cnf(cnf) == None);
+}
+
+#[test]
+fn system_cnf_directory_record() {
+    // Build a directory record for `name`
+    fn record(name: &[u8], lba: u32, size: u32) -> Vec<u8> {
+        let mut r = vec![0; 33];
+
+        r[0] = (33 + name.len()) as u8;
+        r[2] = lba as u8;
+        r[3] = (lba >> 8) as u8;
+        r[10] = size as u8;
+        r[11] = (size >> 8) as u8;
+        r[32] = name.len() as u8;
+
+        r.extend_from_slice(name);
+
+        r
+    }
+
+    let mut dir = Vec::new();
+
+    dir.extend(record(b"SLUS_005.94;1", 30, 0x1234));
+    dir.extend(record(b"SYSTEM.CNF;1", 24, 68));
+    dir.resize(2048, 0);
+
+    assert!(find_system_cnf(&dir) == Some((24, 68)));
+
+    // File name running past the end of the record
+    let mut bad = record(b"SYSTEM.CNF;1", 24, 68);
+    bad[32] = 200;
+    bad.resize(2048, 0);
+
+    assert!(find_system_cnf(&bad) == None);
+
+    // Record too short to hold the fixed fields
+    let mut bad = vec![0; 2048];
+    bad[0] = 10;
+
+    assert!(find_system_cnf(&bad) == None);
+
+    // Record running past the end of the sector
+    let mut bad = record(b"SYSTEM.CNF;1", 24, 68);
+    bad[0] = 100;
+
+    assert!(find_system_cnf(&bad) == None);
+}
@@ -126,7 +126,8 @@ impl CdRom {
         self.sync(shared);
 
         if T::size() != 1 {
-            panic!("Unhandled CDROM load ({})", T::size());
+            warn!("Unhandled CDROM load ({}) at offset {}", T::size(), offset);
+            return 0;
         }
 
         let index = self.index;
@@ -169,7 +170,9 @@ impl CdRom {
         self.sync(shared);
 
         if T::size() != 1 {
-            panic!("Unhandled CDROM store ({})", T::size());
+            warn!("Unhandled CDROM store ({}) at offset {}: 0x{:08x}",
+                  T::size(), offset, val);
+            return;
         }
 
         // All writeable registers are 8bit wide
@@ -352,6 +355,40 @@ impl CdRom {
         b
     }
 
+    /// Return true if a disc is loaded in the drive
+    pub fn disc_present(&self) -> bool {
+        self.disc.is_some()
+    }
+
+    /// Return the currently loaded disc, if any
+    pub fn disc(&self) -> Option<&Disc> {
+        self.disc.as_ref()
+    }
+
+    /// Current read position
+    pub fn position(&self) -> Msf {
+        self.position
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Return true if the controller is not processing a command
+    pub fn command_idle(&self) -> bool {
+        self.command_state.is_idle()
+    }
+
+    /// Return true if the drive is currently reading sectors
+    pub fn reading(&self) -> bool {
+        !self.read_state.is_idle()
+    }
+
+    /// Return the value of the interrupt flag register
+    pub fn irq_flags(&self) -> u8 {
+        self.irq_flags
+    }
+
     /// The DMA can read the RX buffer one word at a time
     pub fn dma_read_word(&mut self) -> u32 {
         let b0 = self.read_byte() as u32;
@@ -1334,3 +1371,61 @@ impl Mixer {
         }
     }
 }
+
+#[test]
+fn commands_without_disc() {
+    use memory::Byte;
+
+    let mut cdrom = CdRom::new(None);
+    let mut shared = SharedState::new();
+
+    let mut command = |cdrom: &mut CdRom, cmd: u8| -> (u8, Vec<u8>) {
+        cdrom.store::<Byte>(&mut shared, 0, 0);
+        cdrom.store::<Byte>(&mut shared, 1, cmd as u32);
+
+        // The response isn't available immediately
+        assert!(cdrom.irq_flags() == 0);
+
+        shared.tk().advance(30_000);
+        cdrom.sync(&mut shared);
+
+        let irq = cdrom.irq_flags();
+
+        let mut response = Vec::new();
+
+        while !cdrom.response.empty() {
+            response.push(cdrom.load::<Byte>(&mut shared, 1) as u8);
+        }
+
+        // Acknowledge the interrupt
+        cdrom.store::<Byte>(&mut shared, 0, 1);
+        cdrom.store::<Byte>(&mut shared, 3, 0x1f);
+
+        assert!(cdrom.irq_flags() == 0);
+
+        (irq, response)
+    };
+
+    // GetStat: the shell is reported open
+    assert!(command(&mut cdrom, 0x01) == (3, vec![0x10]));
+    // GetID fails with INT5
+    assert!(command(&mut cdrom, 0x1a) == (5, vec![0x11, 0x80]));
+}
+
+#[test]
+fn cdrom_bad_access_width() {
+    use memory::{Byte, HalfWord, Word};
+
+    let mut cdrom = CdRom::new(None);
+    let mut shared = SharedState::new();
+
+    let status = cdrom.load::<Byte>(&mut shared, 0);
+
+    // Select register bank 1, only byte accesses are supported so
+    // these are ignored
+    cdrom.store::<HalfWord>(&mut shared, 0, 1);
+    cdrom.store::<Word>(&mut shared, 0, 1);
+
+    assert!(cdrom.load::<Byte>(&mut shared, 0) == status);
+    assert!(cdrom.load::<Word>(&mut shared, 0) == 0);
+}
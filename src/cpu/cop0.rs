@@ -1,4 +1,5 @@
 use interrupt::InterruptState;
+use savestate::{StateWriter, StateReader, StateError};
 
 /// Coprocessor 0: System control
 pub struct Cop0 {
@@ -8,6 +9,8 @@ pub struct Cop0 {
     cause:  u32,
     /// Cop0 register 14: Exception PC
     epc: u32,
+    /// Cop0 register 8: Bad Virtual Address, set by address errors
+    bad_vaddr: u32,
 }
 
 impl Cop0 {
@@ -17,6 +20,7 @@ impl Cop0 {
             sr:    0,
             cause: 0,
             epc:   0,
+            bad_vaddr: 0,
         }
     }
 
@@ -33,13 +37,39 @@ impl Cop0 {
     /// interrupt (no latch, ack'ing the interrupt in the external
     /// controller resets the value in this register) .
     pub fn cause(&self, irq_state: InterruptState) -> u32 {
-        self.cause | ((irq_state.active() as u32) << 10)
+        self.cause | ((irq_state.irq_pending() as u32) << 10)
     }
 
     pub fn epc(&self) -> u32 {
         self.epc
     }
 
+    pub fn bad_vaddr(&self) -> u32 {
+        self.bad_vaddr
+    }
+
+    pub fn set_bad_vaddr(&mut self, addr: u32) {
+        self.bad_vaddr = addr;
+    }
+
+    /// Serialize the COP0 registers in `w`
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.sr);
+        w.write_u32(self.cause);
+        w.write_u32(self.epc);
+        w.write_u32(self.bad_vaddr);
+    }
+
+    /// Read back the registers serialized by `save_state`
+    pub fn load_state(r: &mut StateReader) -> Result<Cop0, StateError> {
+        Ok(Cop0 {
+            sr: r.read_u32()?,
+            cause: r.read_u32()?,
+            epc: r.read_u32()?,
+            bad_vaddr: r.read_u32()?,
+        })
+    }
+
     pub fn cache_isolated(&self) -> bool {
         self.sr & 0x10000 != 0
     }
@@ -140,3 +170,63 @@ pub enum Exception {
     /// Arithmetic overflow
     Overflow = 0xc,
 }
+
+#[test]
+fn exception_entry() {
+    let mut cop0 = Cop0::new();
+
+    // BEV set, user mode with interrupts enabled
+    cop0.set_sr((1 << 22) | 0x3);
+
+    let handler = cop0.enter_exception(Exception::SysCall, 0x80001000, false);
+
+    assert!(handler == 0xbfc00180);
+    assert!(cop0.epc() == 0x80001000);
+    // Kernel mode with interrupts disabled, the previous mode is
+    // pushed on the stack
+    assert!(cop0.sr() & 0x3f == 0xc);
+    assert!(cop0.cause(InterruptState::new()) == 0x8 << 2);
+
+    cop0.return_from_exception();
+
+    assert!(cop0.sr() & 0x3f == 0x3);
+
+    // BEV cleared, exception in a branch delay slot
+    cop0.set_sr(0x1);
+
+    let handler = cop0.enter_exception(Exception::Interrupt, 0x80001000, true);
+
+    assert!(handler == 0x80000080);
+    // EPC points to the branch instruction and BD is set
+    assert!(cop0.epc() == 0x80000ffc);
+    assert!(cop0.cause(InterruptState::new()) == 1 << 31);
+}
+
+#[test]
+fn irq_active() {
+    use interrupt::Interrupt;
+
+    let mut cop0 = Cop0::new();
+    let mut irq_state = InterruptState::new();
+
+    irq_state.set_mask(1 << (Interrupt::VBlank as u16));
+    irq_state.assert(Interrupt::VBlank);
+
+    assert!(irq_state.irq_pending());
+
+    // Interrupts disabled globally
+    cop0.set_sr(0x400);
+    assert!(!cop0.irq_active(irq_state));
+
+    // External interrupt masked
+    cop0.set_sr(0x001);
+    assert!(!cop0.irq_active(irq_state));
+
+    cop0.set_sr(0x401);
+    assert!(cop0.irq_active(irq_state));
+
+    // Acknowledging the interrupt clears CAUSE bit 10 immediately
+    irq_state.ack(0);
+    assert!(!cop0.irq_active(irq_state));
+    assert!(cop0.cause(irq_state) & 0x400 == 0);
+}
@@ -0,0 +1,281 @@
+//! MIPS R3000 disassembler, used by the debugger to display the
+//! instructions in a human-readable form.
+
+use super::REGISTER_NAMES;
+
+/// Disassemble `instruction` located at address `pc`. Branch and
+/// jump targets are resolved to absolute addresses.
+pub fn disasm(instruction: u32, pc: u32) -> String {
+    let op = instruction >> 26;
+
+    let s = reg(instruction >> 21);
+    let t = reg(instruction >> 16);
+    let imm = instruction & 0xffff;
+
+    match op {
+        0x00 => special(instruction),
+        0x01 => {
+            // The hardware only looks at bit 16 to select between
+            // "BLTZ" and "BGEZ" and at bit 20 for the link variants
+            let cond =
+                if instruction & (1 << 16) != 0 {
+                    "bgez"
+                } else {
+                    "bltz"
+                };
+
+            let link =
+                if instruction & (1 << 20) != 0 {
+                    "al"
+                } else {
+                    ""
+                };
+
+            format!("{}{} {}, 0x{:08x}",
+                    cond, link, s, branch_target(pc, imm))
+        }
+        0x02 => format!("j 0x{:08x}", jump_target(pc, instruction)),
+        0x03 => format!("jal 0x{:08x}", jump_target(pc, instruction)),
+        0x04 => format!("beq {}, {}, 0x{:08x}",
+                        s, t, branch_target(pc, imm)),
+        0x05 => format!("bne {}, {}, 0x{:08x}",
+                        s, t, branch_target(pc, imm)),
+        0x06 => format!("blez {}, 0x{:08x}", s, branch_target(pc, imm)),
+        0x07 => format!("bgtz {}, 0x{:08x}", s, branch_target(pc, imm)),
+        0x08 => format!("addi {}, {}, {}", t, s, signed_hex(imm)),
+        0x09 => format!("addiu {}, {}, {}", t, s, signed_hex(imm)),
+        0x0a => format!("slti {}, {}, {}", t, s, signed_hex(imm)),
+        0x0b => format!("sltiu {}, {}, {}", t, s, signed_hex(imm)),
+        0x0c => format!("andi {}, {}, 0x{:x}", t, s, imm),
+        0x0d => format!("ori {}, {}, 0x{:x}", t, s, imm),
+        0x0e => format!("xori {}, {}, 0x{:x}", t, s, imm),
+        0x0f => format!("lui {}, 0x{:x}", t, imm),
+        0x10 => cop0(instruction),
+        0x12 => cop2(instruction),
+        0x11 | 0x13 => format!("cop{} 0x{:07x}",
+                               op & 3, instruction & 0x1ffffff),
+        0x20 => memory("lb", t, instruction),
+        0x21 => memory("lh", t, instruction),
+        0x22 => memory("lwl", t, instruction),
+        0x23 => memory("lw", t, instruction),
+        0x24 => memory("lbu", t, instruction),
+        0x25 => memory("lhu", t, instruction),
+        0x26 => memory("lwr", t, instruction),
+        0x28 => memory("sb", t, instruction),
+        0x29 => memory("sh", t, instruction),
+        0x2a => memory("swl", t, instruction),
+        0x2b => memory("sw", t, instruction),
+        0x2e => memory("swr", t, instruction),
+        0x30...0x33 => {
+            let name = format!("lwc{}", op & 3);
+            let t = format!("${}", (instruction >> 16) & 0x1f);
+
+            memory(&name, &t, instruction)
+        }
+        0x38...0x3b => {
+            let name = format!("swc{}", op & 3);
+            let t = format!("${}", (instruction >> 16) & 0x1f);
+
+            memory(&name, &t, instruction)
+        }
+        _ => illegal(instruction),
+    }
+}
+
+/// Disassemble the "SPECIAL" opcodes (main opcode 0)
+fn special(instruction: u32) -> String {
+    if instruction == 0 {
+        return "nop".into();
+    }
+
+    let s = reg(instruction >> 21);
+    let t = reg(instruction >> 16);
+    let d = reg(instruction >> 11);
+    let shift = (instruction >> 6) & 0x1f;
+
+    let alu = |name| format!("{} {}, {}, {}", name, d, s, t);
+    let shiftv = |name| format!("{} {}, {}, {}", name, d, t, s);
+    let muldiv = |name| format!("{} {}, {}", name, s, t);
+
+    match instruction & 0x3f {
+        0x00 => format!("sll {}, {}, {}", d, t, shift),
+        0x02 => format!("srl {}, {}, {}", d, t, shift),
+        0x03 => format!("sra {}, {}, {}", d, t, shift),
+        0x04 => shiftv("sllv"),
+        0x06 => shiftv("srlv"),
+        0x07 => shiftv("srav"),
+        0x08 => format!("jr {}", s),
+        0x09 => format!("jalr {}, {}", d, s),
+        0x0c => code("syscall", instruction),
+        0x0d => code("break", instruction),
+        0x10 => format!("mfhi {}", d),
+        0x11 => format!("mthi {}", s),
+        0x12 => format!("mflo {}", d),
+        0x13 => format!("mtlo {}", s),
+        0x18 => muldiv("mult"),
+        0x19 => muldiv("multu"),
+        0x1a => muldiv("div"),
+        0x1b => muldiv("divu"),
+        0x20 => alu("add"),
+        0x21 => alu("addu"),
+        0x22 => alu("sub"),
+        0x23 => alu("subu"),
+        0x24 => alu("and"),
+        0x25 => alu("or"),
+        0x26 => alu("xor"),
+        0x27 => alu("nor"),
+        0x2a => alu("slt"),
+        0x2b => alu("sltu"),
+        _ => illegal(instruction),
+    }
+}
+
+/// Disassemble the coprocessor 0 opcodes
+fn cop0(instruction: u32) -> String {
+    let t = reg(instruction >> 16);
+    let d = (instruction >> 11) & 0x1f;
+
+    match (instruction >> 21) & 0x1f {
+        0x00 => format!("mfc0 {}, {}", t, COP0_REGISTER_NAMES[d as usize]),
+        0x04 => format!("mtc0 {}, {}", t, COP0_REGISTER_NAMES[d as usize]),
+        0x10 if instruction & 0x3f == 0x10 => "rfe".into(),
+        _ => illegal(instruction),
+    }
+}
+
+/// Disassemble the coprocessor 2 (GTE) opcodes
+fn cop2(instruction: u32) -> String {
+    let t = reg(instruction >> 16);
+    let d = (instruction >> 11) & 0x1f;
+
+    if instruction & (1 << 25) != 0 {
+        // GTE command. The other bits of the instruction contain
+        // the command parameters (shift, saturation, matrix
+        // selection...)
+        let name =
+            match instruction & 0x3f {
+                0x01 => "rtps",
+                0x06 => "nclip",
+                0x0c => "op",
+                0x10 => "dpcs",
+                0x11 => "intpl",
+                0x12 => "mvmva",
+                0x13 => "ncds",
+                0x14 => "cdp",
+                0x16 => "ncdt",
+                0x1b => "nccs",
+                0x1c => "cc",
+                0x1e => "ncs",
+                0x20 => "nct",
+                0x28 => "sqr",
+                0x29 => "dcpl",
+                0x2a => "dpct",
+                0x2d => "avsz3",
+                0x2e => "avsz4",
+                0x30 => "rtpt",
+                0x3d => "gpf",
+                0x3e => "gpl",
+                0x3f => "ncct",
+                _ => "cop2",
+            };
+
+        return format!("{} 0x{:07x}", name, instruction & 0x1ffffff);
+    }
+
+    match (instruction >> 21) & 0x1f {
+        0x00 => format!("mfc2 {}, ${}", t, d),
+        0x02 => format!("cfc2 {}, ${}", t, d),
+        0x04 => format!("mtc2 {}, ${}", t, d),
+        0x06 => format!("ctc2 {}, ${}", t, d),
+        _ => illegal(instruction),
+    }
+}
+
+/// Format a load or store instruction: `name t, offset(base)`
+fn memory(name: &str, t: &str, instruction: u32) -> String {
+    let base = reg(instruction >> 21);
+
+    format!("{} {}, {}({})",
+            name, t, signed_hex(instruction & 0xffff), base)
+}
+
+/// Format SYSCALL and BREAK, displaying the 20bit code if it's not 0
+fn code(name: &str, instruction: u32) -> String {
+    let code = (instruction >> 6) & 0xfffff;
+
+    if code == 0 {
+        name.into()
+    } else {
+        format!("{} 0x{:x}", name, code)
+    }
+}
+
+fn illegal(instruction: u32) -> String {
+    format!("illegal 0x{:08x}", instruction)
+}
+
+/// Return the name of the general purpose register in the 5 low
+/// bits of `index`
+fn reg(index: u32) -> &'static str {
+    REGISTER_NAMES[(index & 0x1f) as usize]
+}
+
+/// Format a sign-extended 16bit immediate value
+fn signed_hex(imm: u32) -> String {
+    let imm = imm as i16;
+
+    if imm < 0 {
+        format!("-0x{:x}", -(imm as i32))
+    } else {
+        format!("0x{:x}", imm)
+    }
+}
+
+/// Branch targets are relative to the delay slot
+fn branch_target(pc: u32, imm: u32) -> u32 {
+    let offset = ((imm as i16) as u32) << 2;
+
+    pc.wrapping_add(4).wrapping_add(offset)
+}
+
+/// Jumps replace the low 28 bits of the address of the delay slot
+fn jump_target(pc: u32, instruction: u32) -> u32 {
+    (pc.wrapping_add(4) & 0xf0000000) | ((instruction & 0x3ffffff) << 2)
+}
+
+/// Names of the COP0 registers
+const COP0_REGISTER_NAMES: [&'static str; 32] = [
+    "$0", "$1", "$2", "$bpc", "$4", "$bda", "$jumpdest", "$dcic",
+    "$badvaddr", "$bdam", "$10", "$bpcm", "$sr", "$cause", "$epc", "$prid",
+    "$16", "$17", "$18", "$19", "$20", "$21", "$22", "$23",
+    "$24", "$25", "$26", "$27", "$28", "$29", "$30", "$31",
+    ];
+
+#[test]
+fn disasm_instructions() {
+    let tests: &[(u32, u32, &str)] = &[
+        (0x00000000, 0, "nop"),
+        (0x25080010, 0, "addiu $t0, $t0, 0x10"),
+        (0x27bdffe8, 0, "addiu $sp, $sp, -0x18"),
+        (0x3c011f80, 0, "lui $at, 0x1f80"),
+        (0x00084080, 0, "sll $t0, $t0, 2"),
+        (0x01095021, 0, "addu $t2, $t0, $t1"),
+        (0x03e00008, 0, "jr $ra"),
+        (0x8fbf0014, 0, "lw $ra, 0x14($sp)"),
+        (0xac20fffc, 0, "sw $zero, -0x4($at)"),
+        (0x1080000a, 0x80001200, "beq $a0, $zero, 0x8000122c"),
+        (0x0411fffe, 0x80001200, "bgezal $zero, 0x800011fc"),
+        (0x0ff00100, 0xbfc00000, "jal 0xbfc00400"),
+        (0x40026000, 0, "mfc0 $v0, $sr"),
+        (0x42000010, 0, "rfe"),
+        (0x4a180001, 0, "rtps 0x0180001"),
+        (0x48c8f800, 0, "ctc2 $t0, $31"),
+        (0xc8a10004, 0, "lwc2 $1, 0x4($a1)"),
+        (0x0000000c, 0, "syscall"),
+        (0xfc000000, 0, "illegal 0xfc000000"),
+        ];
+
+    for &(instruction, pc, expected) in tests {
+        assert!(disasm(instruction, pc) == expected);
+    }
+}
@@ -1,17 +1,25 @@
 mod cop0;
 mod gte;
+pub mod disasm;
+pub mod state;
+
+#[cfg(test)]
+mod tests;
 
 use std::fmt::{Display, Formatter, Error};
 
 use memory::{Interconnect, Addressable, Byte, HalfWord, Word};
 use shared::SharedState;
-use gpu::renderer::Renderer;
+use gpu::renderer::{Renderer, NullRenderer};
 use interrupt::InterruptState;
 use debugger::Debugger;
+use timekeeper::Cycles;
 
-use self::cop0::{Cop0, Exception};
+use self::cop0::Exception;
 use self::gte::Gte;
 
+pub use self::cop0::Cop0;
+
 /// CPU state
 pub struct Cpu {
     /// The program counter register: points to the next instruction
@@ -45,6 +53,12 @@ pub struct Cpu {
     branch: bool,
     /// Set if the current instruction executes in the delay slot
     delay_slot: bool,
+    /// When true instructions are always fetched from memory even if
+    /// the instruction cache is enabled. Only meant for debugging.
+    icache_bypass: bool,
+    /// Date at which the result of the last multiplication or
+    /// division will be available in HI/LO
+    mult_div_end: Cycles,
 }
 
 impl Cpu {
@@ -74,9 +88,19 @@ impl Cpu {
             load:       (RegisterIndex(0), 0),
             branch:     false,
             delay_slot: false,
+            icache_bypass: false,
+            mult_div_end: 0,
         }
     }
 
+    /// Bypass the instruction cache emulation: instructions are
+    /// fetched directly from memory with the uncached timings. Useful
+    /// to check if a bug is caused by the cache emulation. Disabled
+    /// by default.
+    pub fn set_icache_bypass(&mut self, bypass: bool) {
+        self.icache_bypass = bypass;
+    }
+
     /// Return a reference to the interconnect
     pub fn interconnect(&self) -> &Interconnect {
         &self.inter
@@ -94,7 +118,7 @@ impl Cpu {
                                 renderer: &mut Renderer) {
         let frame = shared.frame();
 
-        while frame == shared.frame() {
+        while frame == shared.frame() && !debugger.halted() {
             self.run_next_instruction(debugger, shared, renderer);
         }
     }
@@ -107,7 +131,7 @@ impl Cpu {
 
         // Synchronize the peripherals
         if shared.tk().sync_pending() {
-            self.inter.sync(shared);
+            self.inter.sync(shared, renderer);
             shared.tk().update_sync_pending();
         }
 
@@ -116,11 +140,17 @@ impl Cpu {
         self.current_pc = self.pc;
 
         // Debugger entrypoint: used for code breakpoints and stepping
-        debugger.pc_change(self);
+        debugger.pc_change(self, shared);
+
+        if debugger.halted() {
+            // We hit a breakpoint, don't execute the instruction
+            return;
+        }
 
         if self.current_pc % 4 != 0 {
             // PC is not correctly aligned!
-            self.exception(Exception::LoadAddressError);
+            let pc = self.current_pc;
+            self.address_error(Exception::LoadAddressError, pc);
             return;
         }
 
@@ -166,7 +196,7 @@ impl Cpu {
         // KSEG2 doesn't contain any code
         let cached = pc < 0xa0000000;
 
-        if cached && cc.icache_enabled() {
+        if cached && cc.icache_enabled() && !self.icache_bypass {
             // The MSB is ignored: running from KUSEG or KSEG0 hits
             // the same cachelines. So for instance addresses
             // 0x00000000 and 0x80000000 have the same tag and you can
@@ -192,10 +222,10 @@ impl Cpu {
 
                 // Fetching takes 3 cycles + 1 per instruction on
                 // average.
-                shared.tk().tick(3);
+                shared.tk().advance(3);
 
                 for i in index..4 {
-                    shared.tk().tick(1);
+                    shared.tk().advance(1);
 
                     let instruction =
                         Instruction(self.inter.load_instruction(cpc));
@@ -220,7 +250,7 @@ impl Cpu {
 
             // Cache disabled, fetch directly from memory. Takes 4
             // cycles on average.
-            shared.tk().tick(4);
+            shared.tk().advance(4);
 
             Instruction(self.inter.load_instruction(pc))
         }
@@ -231,16 +261,26 @@ impl Cpu {
                             debugger: &mut Debugger,
                             shared: &mut SharedState,
                             addr: u32) -> u32 {
-        debugger.memory_read(self, addr);
+        debugger.memory_read(self, shared, addr);
 
         self.inter.load::<T>(shared, addr)
     }
 
     /// Memory read with as little side-effect as possible. Used for
     /// debugging.
-    pub fn examine<T: Addressable>(&mut self, addr: u32) -> u32 {
+    pub fn examine<T: Addressable>(&mut self,
+                                   shared: &mut SharedState,
+                                   addr: u32) -> u32 {
+        self.inter.load::<T>(shared, addr)
+    }
 
-        self.inter.load::<T>(&mut SharedState::new(), addr)
+    /// Memory write bypassing the debugger and the cache. Used for
+    /// debugging.
+    pub fn deposit<T: Addressable>(&mut self,
+                                   shared: &mut SharedState,
+                                   addr: u32,
+                                   val: u32) {
+        self.inter.store::<T>(shared, &mut NullRenderer, addr, val);
     }
 
     /// Memory write
@@ -259,7 +299,7 @@ impl Cpu {
                              renderer: &mut Renderer,
                              addr: u32,
                              val: u32) {
-        debugger.memory_write(self, addr);
+        debugger.memory_write(self, shared, addr, val);
 
         if self.cop0.cache_isolated() {
             self.cache_maintenance::<T>(addr, val);
@@ -331,6 +371,14 @@ impl Cpu {
         self.next_pc = self.pc.wrapping_add(4);
     }
 
+    /// Trigger an address error exception for an unaligned access
+    /// to `addr`
+    fn address_error(&mut self, cause: Exception, addr: u32) {
+        self.cop0.set_bad_vaddr(addr);
+
+        self.exception(cause);
+    }
+
     /// Retrieve the value of a general purpose register
     fn reg(&self, index: RegisterIndex) -> u32 {
         self.regs[index.0 as usize]
@@ -357,6 +405,20 @@ impl Cpu {
         self.load = (RegisterIndex(0), 0);
     }
 
+    /// Same as `delayed_load` for instructions which are themselves
+    /// delayed loads into register `target`. If the pending load
+    /// targets the same register it's cancelled: its value is never
+    /// written, even temporarily.
+    fn delayed_load_chain(&mut self, target: RegisterIndex) {
+        let (reg, _) = self.load;
+
+        if reg.0 == target.0 {
+            self.load = (RegisterIndex(0), 0);
+        } else {
+            self.delayed_load();
+        }
+    }
+
     /// Get the value of all general purpose registers
     pub fn regs(&self) -> &[u32] {
         &self.regs
@@ -378,14 +440,36 @@ impl Cpu {
         self.pc
     }
 
+    /// Address of the instruction currently being executed
+    pub fn current_pc(&self) -> u32 {
+        self.current_pc
+    }
+
     pub fn cause(&self, irq_state: InterruptState) -> u32 {
         self.cop0.cause(irq_state)
     }
 
-    pub fn bad(&self) -> u32 {
-        // XXX we don't emulate the "BAD" cop0 register yet. It's
-        // almost useless in the PSX anyway since there's no MMU.
-        0
+    pub fn epc(&self) -> u32 {
+        self.cop0.epc()
+    }
+
+    /// Return a reference to the system control coprocessor
+    pub fn cop0(&self) -> &Cop0 {
+        &self.cop0
+    }
+
+    /// Force the value of general purpose register `index`. Meant
+    /// to be used from the debugger.
+    pub fn force_reg(&mut self, index: u32, val: u32) {
+        self.set_reg(RegisterIndex(index & 0x1f), val);
+    }
+
+    pub fn force_lo(&mut self, lo: u32) {
+        self.lo = lo;
+    }
+
+    pub fn force_hi(&mut self, hi: u32) {
+        self.hi = hi;
     }
 
     /// Force PC address. Meant to be used from the debugger. Use at
@@ -403,7 +487,7 @@ impl Cpu {
                           shared: &mut SharedState,
                           renderer: &mut Renderer) {
         // Simulate instruction execution time.
-        shared.tk().tick(1);
+        shared.tk().advance(1);
 
         match instruction.function() {
             0b000000 => match instruction.subfunction() {
@@ -417,14 +501,14 @@ impl Cpu {
                 0b001001 => self.op_jalr(instruction),
                 0b001100 => self.op_syscall(instruction),
                 0b001101 => self.op_break(instruction),
-                0b010000 => self.op_mfhi(instruction),
+                0b010000 => self.op_mfhi(instruction, shared),
                 0b010001 => self.op_mthi(instruction),
-                0b010010 => self.op_mflo(instruction),
+                0b010010 => self.op_mflo(instruction, shared),
                 0b010011 => self.op_mtlo(instruction),
-                0b011000 => self.op_mult(instruction),
-                0b011001 => self.op_multu(instruction),
-                0b011010 => self.op_div(instruction),
-                0b011011 => self.op_divu(instruction),
+                0b011000 => self.op_mult(instruction, shared),
+                0b011001 => self.op_multu(instruction, shared),
+                0b011010 => self.op_div(instruction, shared),
+                0b011011 => self.op_divu(instruction, shared),
                 0b100000 => self.op_add(instruction),
                 0b100001 => self.op_addu(instruction),
                 0b100010 => self.op_sub(instruction),
@@ -650,10 +734,32 @@ impl Cpu {
         self.exception(Exception::Break);
     }
 
+    /// Stall until the result of the last multiplication or division
+    /// is available in HI/LO
+    fn mult_div_wait(&mut self, shared: &mut SharedState) {
+        let now = shared.tk().now();
+
+        if now < self.mult_div_end {
+            shared.tk().advance(self.mult_div_end - now);
+        }
+    }
+
+    /// Start a multiplication or division taking `cycles` to
+    /// complete. The CPU keeps running in the meantime, only the
+    /// instructions accessing HI/LO have to wait for the result.
+    fn mult_div_start(&mut self, shared: &mut SharedState, cycles: Cycles) {
+        // A new operation can't start before the previous one is done
+        self.mult_div_wait(shared);
+
+        self.mult_div_end = shared.tk().now() + cycles;
+    }
+
     /// Move From HI
-    fn op_mfhi(&mut self, instruction: Instruction) {
+    fn op_mfhi(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let d = instruction.d();
 
+        self.mult_div_wait(shared);
+
         let hi = self.hi;
 
         self.delayed_load();
@@ -671,9 +777,11 @@ impl Cpu {
     }
 
     /// Move From LO
-    fn op_mflo(&mut self, instruction: Instruction) {
+    fn op_mflo(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let d = instruction.d();
 
+        self.mult_div_wait(shared);
+
         let lo = self.lo;
 
         self.delayed_load();
@@ -691,7 +799,7 @@ impl Cpu {
     }
 
     /// Multiply (signed)
-    fn op_mult(&mut self, instruction: Instruction) {
+    fn op_mult(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -700,6 +808,16 @@ impl Cpu {
 
         self.delayed_load();
 
+        // The duration depends on the magnitude of the first operand
+        let magnitude =
+            if a < 0 {
+                !(a as u32)
+            } else {
+                a as u32
+            };
+
+        self.mult_div_start(shared, mult_cycles(magnitude));
+
         let v = (a * b) as u64;
 
         self.hi = (v >> 32) as u32;
@@ -707,7 +825,7 @@ impl Cpu {
     }
 
     /// Multiply Unsigned
-    fn op_multu(&mut self, instruction: Instruction) {
+    fn op_multu(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -716,6 +834,8 @@ impl Cpu {
 
         self.delayed_load();
 
+        self.mult_div_start(shared, mult_cycles(a as u32));
+
         let v = a * b;
 
         self.hi = (v >> 32) as u32;
@@ -723,7 +843,7 @@ impl Cpu {
     }
 
     /// Divide (signed)
-    fn op_div(&mut self, instruction: Instruction) {
+    fn op_div(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -732,6 +852,8 @@ impl Cpu {
 
         self.delayed_load();
 
+        self.mult_div_start(shared, DIV_CYCLES);
+
         if d == 0 {
             // Division by zero, results are bogus
             self.hi = n as u32;
@@ -752,7 +874,7 @@ impl Cpu {
     }
 
     /// Divide Unsigned
-    fn op_divu(&mut self, instruction: Instruction) {
+    fn op_divu(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let s = instruction.s();
         let t = instruction.t();
 
@@ -761,6 +883,8 @@ impl Cpu {
 
         self.delayed_load();
 
+        self.mult_div_start(shared, DIV_CYCLES);
+
         if d == 0 {
             // Division by zero, results are bogus
             self.hi = n;
@@ -1112,13 +1236,14 @@ impl Cpu {
         let cop_r = instruction.d().0;
 
         let v = match cop_r {
+            8 => self.cop0.bad_vaddr(),
             12 => self.cop0.sr(),
             13 => self.cop0.cause(*shared.irq_state()),
             14 => self.cop0.epc(),
             _  => panic!("Unhandled read from cop0r{}", cop_r),
         };
 
-        self.delayed_load();
+        self.delayed_load_chain(cpu_r);
 
         self.load = (cpu_r, v)
     }
@@ -1200,7 +1325,7 @@ impl Cpu {
 
         let v = self.gte.data(cop_r);
 
-        self.delayed_load();
+        self.delayed_load_chain(cpu_r);
 
         self.load = (cpu_r, v)
     }
@@ -1212,7 +1337,7 @@ impl Cpu {
 
         let v = self.gte.control(cop_r);
 
-        self.delayed_load();
+        self.delayed_load_chain(cpu_r);
 
         self.load = (cpu_r, v)
     }
@@ -1264,7 +1389,7 @@ impl Cpu {
         // Cast as i8 to force sign extension
         let v = self.load::<Byte>(debugger, shared, addr) as i8;
 
-        self.delayed_load();
+        self.delayed_load_chain(t);
 
         // Put the load in the delay slot
         self.load = (t, v as u32);
@@ -1285,7 +1410,7 @@ impl Cpu {
         // Cast as i16 to force sign extension
         let v = self.load::<HalfWord>(debugger, shared, addr) as i16;
 
-        self.delayed_load();
+        self.delayed_load_chain(t);
 
         // Put the load in the delay slot
         self.load = (t, v as u32);
@@ -1342,7 +1467,7 @@ impl Cpu {
 
         let addr = self.reg(s).wrapping_add(i);
 
-        self.delayed_load();
+        self.delayed_load_chain(t);
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
@@ -1351,7 +1476,7 @@ impl Cpu {
             // Put the load in the delay slot
             self.load = (t, v);
         } else {
-            self.exception(Exception::LoadAddressError);
+            self.address_error(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1369,7 +1494,7 @@ impl Cpu {
 
         let v = self.load::<Byte>(debugger, shared, addr);
 
-        self.delayed_load();
+        self.delayed_load_chain(t);
 
         // Put the load in the delay slot
         self.load = (t, v as u32);
@@ -1387,7 +1512,7 @@ impl Cpu {
 
         let addr = self.reg(s).wrapping_add(i);
 
-        self.delayed_load();
+        self.delayed_load_chain(t);
 
         // Address must be 16bit aligned
         if addr % 2 == 0 {
@@ -1396,7 +1521,7 @@ impl Cpu {
             // Put the load in the delay slot
             self.load = (t, v as u32);
         } else {
-            self.exception(Exception::LoadAddressError);
+            self.address_error(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1478,7 +1603,7 @@ impl Cpu {
         if addr % 2 == 0 {
             self.store::<HalfWord>(debugger, shared, renderer, addr, v);
         } else {
-            self.exception(Exception::StoreAddressError);
+            self.address_error(Exception::StoreAddressError, addr);
         }
     }
 
@@ -1535,7 +1660,7 @@ impl Cpu {
         if addr % 4 == 0 {
             self.store::<Word>(debugger, shared, renderer, addr, v);
         } else {
-            self.exception(Exception::StoreAddressError);
+            self.address_error(Exception::StoreAddressError, addr);
         }
     }
 
@@ -1609,7 +1734,7 @@ impl Cpu {
             // Send to coprocessor
             self.gte.set_data(cop_r, v);
         } else {
-            self.exception(Exception::LoadAddressError);
+            self.address_error(Exception::LoadAddressError, addr);
         }
     }
 
@@ -1656,7 +1781,7 @@ impl Cpu {
         if addr % 4 == 0 {
             self.store::<Word>(debugger, shared, renderer, addr, v);
         } else {
-            self.exception(Exception::LoadAddressError);
+            self.address_error(Exception::StoreAddressError, addr);
         }
     }
 
@@ -1802,7 +1927,7 @@ impl ICacheLine {
     /// Set the cacheline's tag and valid bits. `pc` is the first
     /// valid PC in the cacheline.
     fn set_tag_valid(&mut self, pc: u32) {
-        self.tag_valid =  pc & 0xfffff00c;
+        self.tag_valid = pc & 0x7ffff00c;
     }
 
     /// Invalidate the entire cacheline by pushing the index out of
@@ -1825,3 +1950,26 @@ impl ICacheLine {
 
 /// PlayStation CPU clock in MHz
 pub const CPU_FREQ_HZ: u32 = 33_868_500;
+
+/// MIPS ABI names of the general purpose registers
+pub const REGISTER_NAMES: [&'static str; 32] = [
+    "$zero", "$at", "$v0", "$v1", "$a0", "$a1", "$a2", "$a3",
+    "$t0", "$t1", "$t2", "$t3", "$t4", "$t5", "$t6", "$t7",
+    "$s0", "$s1", "$s2", "$s3", "$s4", "$s5", "$s6", "$s7",
+    "$t8", "$t9", "$k0", "$k1", "$gp", "$sp", "$fp", "$ra",
+    ];
+
+/// Number of cycles taken by DIV and DIVU, regardless of the operands
+const DIV_CYCLES: Cycles = 36;
+
+/// Number of cycles taken by MULT and MULTU. The multiplier stops
+/// early when the high bits of the first operand's `magnitude` are 0.
+fn mult_cycles(magnitude: u32) -> Cycles {
+    if magnitude < 0x800 {
+        6
+    } else if magnitude < 0x100000 {
+        9
+    } else {
+        13
+    }
+}
@@ -0,0 +1,114 @@
+//! CPU save states. The interconnect and the peripherals behind it are
+//! saved separately.
+
+use savestate::{StateWriter, StateReader, StateError};
+
+use super::{Cpu, Cop0, Instruction, RegisterIndex};
+
+impl Cpu {
+    /// Serialize the CPU registers, COP0, GTE and instruction cache
+    /// in `w`. A multiplication or division still running is
+    /// considered complete.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.pc);
+        w.write_u32(self.next_pc);
+        w.write_u32(self.current_pc);
+
+        for &r in self.regs.iter() {
+            w.write_u32(r);
+        }
+
+        w.write_u32(self.hi);
+        w.write_u32(self.lo);
+        w.write_u8((self.load.0).0 as u8);
+        w.write_u32(self.load.1);
+        w.write_bool(self.branch);
+        w.write_bool(self.delay_slot);
+
+        self.cop0.save_state(w);
+
+        // The GTE is saved through its register interface which
+        // covers its whole state
+        for reg in 0..32 {
+            w.write_u32(self.gte.control(reg));
+        }
+
+        for &reg in GTE_DATA_REGISTERS.iter() {
+            w.write_u32(self.gte.data(reg));
+        }
+
+        for line in self.icache.iter() {
+            w.write_u32(line.tag_valid);
+
+            for i in line.line.iter() {
+                w.write_u32(i.0);
+            }
+        }
+    }
+
+    /// Read back a state serialized by `save_state`. If an error is
+    /// returned the CPU may have been partially modified.
+    pub fn load_state(&mut self,
+                      r: &mut StateReader) -> Result<(), StateError> {
+        self.pc = r.read_u32()?;
+        self.next_pc = r.read_u32()?;
+        self.current_pc = r.read_u32()?;
+
+        for reg in self.regs.iter_mut() {
+            *reg = r.read_u32()?;
+        }
+
+        if self.regs[0] != 0 {
+            return Err(StateError::InvalidValue("CPU R0"));
+        }
+
+        self.hi = r.read_u32()?;
+        self.lo = r.read_u32()?;
+
+        let load_reg = r.read_u8()? as u32;
+
+        if load_reg >= 32 {
+            return Err(StateError::InvalidValue("CPU load register"));
+        }
+
+        self.load = (RegisterIndex(load_reg), r.read_u32()?);
+        self.branch = r.read_bool()?;
+        self.delay_slot = r.read_bool()?;
+
+        self.cop0 = Cop0::load_state(r)?;
+
+        for reg in 0..32 {
+            let v = r.read_u32()?;
+
+            self.gte.set_control(reg, v);
+        }
+
+        for &reg in GTE_DATA_REGISTERS.iter() {
+            let v = r.read_u32()?;
+
+            self.gte.set_data(reg, v);
+        }
+
+        for line in self.icache.iter_mut() {
+            line.tag_valid = r.read_u32()?;
+
+            for i in line.line.iter_mut() {
+                *i = Instruction(r.read_u32()?);
+            }
+        }
+
+        self.mult_div_end = 0;
+
+        Ok(())
+    }
+}
+
+/// GTE data registers holding the state. The others are either read
+/// only or mirror other registers: 15 is the top of the XY FIFO
+/// which is always equal to register 14, 28 and 29 are computed from
+/// IR1-3 and 31 from register 30.
+const GTE_DATA_REGISTERS: [u32; 28] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14,
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    30,
+    ];
@@ -0,0 +1,392 @@
+use debugger::Debugger;
+use gpu::renderer::NullRenderer;
+use interrupt::Interrupt;
+use memory::{Word, test_interconnect};
+use shared::SharedState;
+use timekeeper::Cycles;
+
+use super::{Cpu, ICacheLine};
+
+/// CPU connected to a dummy BIOS running an infinite loop, used to
+/// run small test programs
+struct TestCpu {
+    cpu: Cpu,
+    shared: SharedState,
+    debugger: Debugger,
+    renderer: NullRenderer,
+}
+
+impl TestCpu {
+    fn new() -> TestCpu {
+        let mut debugger = Debugger::new();
+
+        // Never wait for a GDB connection
+        debugger.set_remote_enabled(false);
+
+        TestCpu {
+            cpu: Cpu::new(test_interconnect()),
+            shared: SharedState::new(),
+            debugger,
+            renderer: NullRenderer,
+        }
+    }
+
+    /// Build a CPU about to run `program` from uncached RAM at
+    /// 0xa0001000 with `data` stored at 0x2000
+    fn with_program(program: &[u32], data: &[u32]) -> TestCpu {
+        let mut test = TestCpu::new();
+
+        test.store(0x1000, program);
+        test.store(0x2000, data);
+
+        test.cpu.force_pc(0xa0001000);
+
+        test
+    }
+
+    /// Store `words` in memory starting at `addr`
+    fn store(&mut self, addr: u32, words: &[u32]) {
+        for (i, &w) in words.iter().enumerate() {
+            let addr = addr + i as u32 * 4;
+
+            self.cpu.inter.store::<Word>(&mut self.shared,
+                                         &mut self.renderer,
+                                         addr,
+                                         w);
+        }
+    }
+
+    /// Run a single instruction, returns the number of cycles it took
+    fn step(&mut self) -> Cycles {
+        let start = self.shared.tk().now();
+
+        self.cpu.run_next_instruction(&mut self.debugger,
+                                      &mut self.shared,
+                                      &mut self.renderer);
+
+        self.shared.tk().now() - start
+    }
+}
+
+/// Run `program` from uncached RAM at 0xa0001000 with `data` stored
+/// at 0x2000, one step per instruction. Returns the CPU registers.
+fn run_program(program: &[u32], data: &[u32]) -> Vec<u32> {
+    let mut test = TestCpu::with_program(program, data);
+
+    for _ in 0..program.len() {
+        test.step();
+    }
+
+    test.cpu.regs().to_vec()
+}
+
+#[test]
+fn icache_tag_ignores_msb() {
+    let mut line = ICacheLine::new();
+
+    // Fetch from KSEG0 starting at the 3rd word of the line
+    line.set_tag_valid(0x80001238);
+
+    // KUSEG and KSEG0 share the same cachelines so the tag must match
+    // the one computed in `fetch_instruction` for both
+    assert!(line.tag() == 0x00001238 & 0x7ffff000);
+    assert!(line.tag() == 0x80001238 & 0x7ffff000);
+    assert!(line.valid_index() == 2);
+
+    line.invalidate();
+
+    assert!(line.tag() == 0x00001000);
+    assert!(line.valid_index() > 3);
+}
+
+#[test]
+fn icache_bypass() {
+    let run = |test: &mut TestCpu, bypass: bool| -> u32 {
+        // Enable the instruction cache
+        test.store(0xfffe0130, &[0x800]);
+
+        // Loop in RAM (through the cached KSEG0 region). The
+        // cachelines start with a tag of 0 so we stay away from the
+        // first page to avoid hitting the BREAK "trap" values:
+        //     addiu $t0, $t0, 1
+        //     j     0x80001000
+        //     nop
+        test.store(0x1000, &[0x25080001, 0x08000400, 0x00000000]);
+
+        test.cpu.set_icache_bypass(bypass);
+        test.cpu.force_pc(0x80001000);
+
+        for _ in 0..30 {
+            test.step();
+        }
+
+        // Modify the code in RAM without invalidating the cache
+        test.store(0x1000, &[0x25080100]);
+
+        let before = test.cpu.regs()[8];
+
+        for _ in 0..30 {
+            test.step();
+        }
+
+        test.cpu.regs()[8] - before
+    };
+
+    let mut test = TestCpu::new();
+
+    // With the cache the CPU keeps running the stale instruction
+    assert!(run(&mut test, false) == 10);
+    // Without it the new code is executed immediately
+    assert!(run(&mut test, true) == 10 * 0x100);
+}
+
+#[test]
+fn load_delay() {
+    let regs = run_program(&[
+        // addiu $t0, $zero, 1
+        0x24080001,
+        // lw    $t0, 0x2000($zero)
+        0x8c082000,
+        // move  $t1, $t0
+        0x01004821,
+        // move  $t2, $t0
+        0x01005021,
+        ], &[0x1234]);
+
+    // The load is only visible after the delay slot
+    assert!(regs[9] == 1);
+    assert!(regs[10] == 0x1234);
+
+    let regs = run_program(&[
+        // lw    $t0, 0x2000($zero)
+        0x8c082000,
+        // addiu $t0, $zero, 7
+        0x24080007,
+        // move  $t1, $t0
+        0x01004821,
+        ], &[0x1234]);
+
+    // A register written in the delay slot overrides the load
+    assert!(regs[9] == 7);
+}
+
+#[test]
+fn load_delay_same_register() {
+    let regs = run_program(&[
+        // addiu $t0, $zero, 1
+        0x24080001,
+        // lw    $t0, 0x2000($zero)
+        0x8c082000,
+        // lw    $t0, 0x2004($zero)
+        0x8c082004,
+        // move  $t1, $t0
+        0x01004821,
+        // move  $t2, $t0
+        0x01005021,
+        ], &[0x1234, 0x5678]);
+
+    // The first load is cancelled by the second one and never makes
+    // it to the register
+    assert!(regs[9] == 1);
+    assert!(regs[10] == 0x5678);
+}
+
+#[test]
+fn div_edge_cases() {
+    let regs = run_program(&[
+        // addiu $t0, $zero, 5
+        0x24080005,
+        // div   $t0, $zero
+        0x0100001a,
+        // mflo  $t1
+        0x00004812,
+        // mfhi  $t2
+        0x00005010,
+        // addiu $t0, $zero, -5
+        0x2408fffb,
+        // div   $t0, $zero
+        0x0100001a,
+        // mflo  $t3
+        0x00005812,
+        // mfhi  $t4
+        0x00006010,
+        // divu  $t0, $zero
+        0x0100001b,
+        // mflo  $t5
+        0x00006812,
+        // mfhi  $t6
+        0x00007010,
+        ], &[]);
+
+    // Division by zero: LO is -1 or 1 depending on the sign of the
+    // numerator, HI is the numerator
+    assert!(regs[9] == 0xffffffff);
+    assert!(regs[10] == 5);
+    assert!(regs[11] == 1);
+    assert!(regs[12] == 0xfffffffb);
+    assert!(regs[13] == 0xffffffff);
+    assert!(regs[14] == 0xfffffffb);
+
+    let regs = run_program(&[
+        // lui   $t0, 0x8000
+        0x3c088000,
+        // addiu $t1, $zero, -1
+        0x2409ffff,
+        // div   $t0, $t1
+        0x0109001a,
+        // mflo  $t2
+        0x00005012,
+        // mfhi  $t3
+        0x00005810,
+        ], &[]);
+
+    // 0x80000000 / -1 overflows
+    assert!(regs[10] == 0x80000000);
+    assert!(regs[11] == 0);
+}
+
+#[test]
+fn mult_div_stall() {
+    let mut test = TestCpu::with_program(&[
+        // addiu $t0, $zero, 100
+        0x24080064,
+        // addiu $t1, $zero, 7
+        0x24090007,
+        // div   $t0, $t1
+        0x0109001a,
+        // mflo  $t2
+        0x00005012,
+        // mfhi  $t3
+        0x00005810,
+        // mult  $t0, $t1
+        0x01090018,
+        // mflo  $t4
+        0x00006012,
+        ], &[]);
+
+    test.step();
+    test.step();
+
+    let div = test.step();
+    let mflo = test.step();
+    let mfhi = test.step();
+    let mult = test.step();
+    let mflo_mult = test.step();
+
+    // MFLO waits for the division to complete, MFHI doesn't have
+    // to wait anymore
+    assert!(div + mflo >= 36);
+    assert!(mfhi < mflo);
+    // Small operands: the multiplication is much faster
+    assert!(mult + mflo_mult < 36);
+    assert!(mflo_mult < mflo);
+
+    let regs = test.cpu.regs();
+
+    assert!(regs[10] == 14);
+    assert!(regs[11] == 2);
+    assert!(regs[12] == 700);
+}
+
+#[test]
+fn syscall_in_delay_slot() {
+    let mut test = TestCpu::with_program(&[
+        // beq     $zero, $zero, 0xa000100c
+        0x10000002,
+        // syscall
+        0x0000000c,
+        ], &[]);
+
+    test.step();
+    test.step();
+
+    let cause = test.cpu.cause(test.shared.irq_snapshot());
+
+    // BEV is cleared so we end up in the RAM handler
+    assert!(test.cpu.pc() == 0x80000080);
+    assert!((cause >> 2) & 0x1f == 8);
+    // EPC points to the branch and BD is set
+    assert!(test.cpu.epc() == 0xa0001000);
+    assert!(cause & (1 << 31) != 0);
+}
+
+#[test]
+fn interrupt_exception() {
+    let mut test = TestCpu::with_program(&[
+        // addiu $t0, $zero, 0x401
+        0x24080401,
+        // mtc0  $t0, $12
+        0x40886000,
+        // addiu $t1, $zero, 1
+        0x24090001,
+        ], &[]);
+
+    {
+        let irq_state = test.shared.irq_state();
+
+        irq_state.set_mask(1 << (Interrupt::VBlank as u16));
+        irq_state.assert(Interrupt::VBlank);
+    }
+
+    // The interrupt is pending but masked in SR until the MTC0
+    test.step();
+    test.step();
+
+    assert!(test.cpu.pc() == 0xa0001008);
+
+    test.step();
+
+    let cause = test.cpu.cause(test.shared.irq_snapshot());
+
+    // The instruction is not executed, the CPU jumps to the handler
+    // instead with interrupts disabled
+    assert!(test.cpu.pc() == 0x80000080);
+    assert!((cause >> 2) & 0x1f == 0);
+    assert!(cause & 0x400 != 0);
+    assert!(test.cpu.epc() == 0xa0001008);
+    assert!(test.cpu.sr() & 1 == 0);
+    assert!(test.cpu.regs()[9] == 0xdeadbeef);
+}
+
+#[test]
+fn unaligned_load_exception() {
+    let mut test = TestCpu::with_program(&[
+        // addiu $t0, $zero, 0x2001
+        0x24082001,
+        // lw    $t1, 0($t0)
+        0x8d090000,
+        ], &[]);
+
+    test.step();
+    test.step();
+
+    let cpu = &test.cpu;
+
+    // The load raised an AdEL exception instead of completing
+    assert!(cpu.pc() == 0x80000080);
+    assert!((cpu.cause(test.shared.irq_snapshot()) >> 2) & 0x1f == 4);
+    assert!(cpu.epc() == 0xa0001004);
+    assert!(cpu.cop0().bad_vaddr() == 0x2001);
+    assert!(cpu.regs()[9] == 0xdeadbeef);
+}
+
+#[test]
+fn unaligned_swc2_exception() {
+    let mut test = TestCpu::with_program(&[
+        // addiu $t0, $zero, 0x2002
+        0x24082002,
+        // swc2  $0, 0($t0)
+        0xe9000000,
+        ], &[]);
+
+    test.step();
+    test.step();
+
+    let cpu = &test.cpu;
+
+    // The store raised an AdES exception instead of completing
+    assert!(cpu.pc() == 0x80000080);
+    assert!((cpu.cause(test.shared.irq_snapshot()) >> 2) & 0x1f == 5);
+    assert!(cpu.epc() == 0xa0001004);
+    assert!(cpu.cop0().bad_vaddr() == 0x2002);
+}
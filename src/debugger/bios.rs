@@ -0,0 +1,140 @@
+//! BIOS kernel function tracing. The BIOS exposes its kernel through
+//! three function tables reached by jumping to 0xa0, 0xb0 or 0xc0
+//! with the function number in $t1.
+
+/// Return a human-readable description of the BIOS call being made
+/// if `pc` points at one of the dispatch addresses. `t1` is the value
+/// of the $t1 register containing the function number.
+pub fn describe_call(pc: u32, t1: u32) -> Option<String> {
+    // The dispatchers can be called through any of KUSEG, KSEG0 or
+    // KSEG1
+    let (table, names) =
+        match pc & 0x1fffffff {
+            0xa0 => ('A', A_FUNCTIONS),
+            0xb0 => ('B', B_FUNCTIONS),
+            0xc0 => ('C', C_FUNCTIONS),
+            _ => return None,
+        };
+
+    let name =
+        match names.get(t1 as usize) {
+            Some(n) => n,
+            None => "<unknown>",
+        };
+
+    Some(format!("{}(0x{:02x}): {}", table, t1, name))
+}
+
+/// Names of the functions in the A0 table
+static A_FUNCTIONS: &'static [&'static str] = &[
+    "FileOpen", "FileSeek", "FileRead", "FileWrite",
+    "FileClose", "FileIoctl", "exit", "FileGetDeviceFlag",
+    "FileGetc", "FilePutc", "todigit", "atof",
+    "strtoul", "strtol", "abs", "labs",
+    "atoi", "atol", "atob", "SaveState",
+    "RestoreState", "strcat", "strncat", "strcmp",
+    "strncmp", "strcpy", "strncpy", "strlen",
+    "index", "rindex", "strchr", "strrchr",
+    "strpbrk", "strspn", "strcspn", "strtok",
+    "strstr", "toupper", "tolower", "bcopy",
+    "bzero", "bcmp", "memcpy", "memset",
+    "memmove", "memcmp", "memchr", "rand",
+    "srand", "qsort", "strtod", "malloc",
+    "free", "lsearch", "bsearch", "calloc",
+    "realloc", "InitHeap", "SystemErrorExit", "std_in_getchar",
+    "std_out_putchar", "std_in_gets", "std_out_puts", "printf",
+    "SystemErrorUnresolvedException", "LoadExeHeader", "LoadExeFile",
+    "DoExecute", "FlushCache", "init_a0_b0_c0_vectors",
+    "GPU_dw", "gpu_send_dma", "SendGP1Command", "GPU_cw",
+    "GPU_cwp", "send_gpu_linked_list", "gpu_abort_dma", "GetGPUStatus",
+    "gpu_sync", "SystemError", "SystemError", "LoadAndExecute",
+    "GetSysSp", "SystemError", "CdInit", "_bu_init",
+    "CdRemove", "SystemError", "SystemError", "SystemError",
+    "SystemError", "dev_tty_init", "dev_tty_open", "dev_tty_in_out",
+    "dev_tty_ioctl", "dev_cd_open", "dev_cd_read", "dev_cd_close",
+    "dev_cd_firstfile", "dev_cd_nextfile", "dev_cd_chdir",
+    "dev_card_open", "dev_card_read", "dev_card_write", "dev_card_close",
+    "dev_card_firstfile", "dev_card_nextfile", "dev_card_erase",
+    "dev_card_undelete", "dev_card_format", "dev_card_rename",
+    "card_clear_error", "_bu_init", "CdInit", "CdRemove",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "SystemError", "CdAsyncSeekL", "SystemError", "SystemError",
+    "SystemError", "CdAsyncGetStatus", "SystemError", "CdAsyncReadSector",
+    "SystemError", "SystemError", "CdAsyncSetMode", "SystemError",
+    "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "CdromIoIrqFunc1", "CdromDmaIrqFunc1", "CdromIoIrqFunc2",
+    "CdromDmaIrqFunc2", "CdromGetInt5errCode", "CdInitSubFunc",
+    "AddCDROMDevice", "AddMemCardDevice", "AddDuartTtyDevice",
+    "AddDummyTtyDevice", "SystemError", "SystemError",
+    "SetConf", "GetConf", "SetCdromIrqAutoAbort", "SetMemSize",
+    "WarmBoot", "SystemErrorBootOrDiskFailure", "EnqueueCdIntr",
+    "DequeueCdIntr", "CdGetLbn", "CdReadSector", "CdGetStatus",
+    "bu_callback_okay", "bu_callback_err_write", "bu_callback_err_busy",
+    "bu_callback_err_eject", "_card_info", "_card_async_load_directory",
+    "set_card_auto_format", "bu_callback_err_prev_write",
+    "card_write_test", "SystemError", "SystemError",
+    "ioabort_raw", "SystemError", "GetSystemInfo",
+];
+
+/// Names of the functions in the B0 table
+static B_FUNCTIONS: &'static [&'static str] = &[
+    "alloc_kernel_memory", "free_kernel_memory", "init_timer",
+    "get_timer", "enable_timer_irq", "disable_timer_irq",
+    "restart_timer", "DeliverEvent", "OpenEvent", "CloseEvent",
+    "WaitEvent", "TestEvent", "EnableEvent", "DisableEvent",
+    "OpenThread", "CloseThread", "ChangeThread", "jump_to_00000000h",
+    "InitPad", "StartPad", "StopPad", "OutdatedPadInitAndStart",
+    "OutdatedPadGetButtons", "ReturnFromException",
+    "SetDefaultExitFromException", "SetCustomExitFromException",
+    "SystemError", "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "UnDeliverEvent", "SystemError", "SystemError", "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "SystemError", "SystemError", "SystemError", "SystemError",
+    "SystemError", "SystemError", "open", "lseek",
+    "read", "write", "close", "ioctl",
+    "exit", "isatty", "getc", "putc",
+    "getchar", "putchar", "gets", "puts",
+    "cd", "format", "firstfile", "nextfile",
+    "rename", "erase", "undelete", "AddDrv",
+    "DelDrv", "PrintInstalledDevices", "InitCARD", "StartCARD",
+    "StopCARD", "_card_info_subfunc", "write_card_sector",
+    "read_card_sector", "allow_new_card", "Krom2RawAdd",
+    "SystemError", "Krom2Offset", "GetLastError", "GetLastFileError",
+    "GetC0Table", "GetB0Table", "get_bu_callback_port",
+    "testdevice", "SystemError", "ChangeClearPad",
+    "get_card_status", "wait_card_status",
+];
+
+/// Names of the functions in the C0 table
+static C_FUNCTIONS: &'static [&'static str] = &[
+    "EnqueueTimerAndVblankIrqs", "EnqueueSyscallHandler",
+    "SysEnqIntRP", "SysDeqIntRP", "get_free_EvCB_slot",
+    "get_free_TCB_slot", "ExceptionHandler", "InstallExceptionHandlers",
+    "SysInitMemory", "SysInitKernelVariables", "ChangeClearRCnt",
+    "SystemError", "InitDefInt", "SetIrqAutoAck",
+    "dev_sio_init", "dev_sio_open", "dev_sio_in_out", "dev_sio_ioctl",
+    "InstallDevices", "FlushStdInOutPut", "SystemError",
+    "tty_cdevinput", "tty_cdevscan", "tty_circgetc", "tty_circputc",
+    "ioabort", "set_card_find_mode", "KernelRedirect",
+    "AdjustA0Table", "get_card_find_mode",
+];
+
+#[test]
+fn bios_call_names() {
+    assert!(describe_call(0xa0, 0x3c) ==
+            Some("A(0x3c): std_out_putchar".into()));
+    assert!(describe_call(0xa0, 0x3f) ==
+            Some("A(0x3f): printf".into()));
+    assert!(describe_call(0x800000b0, 0x3d) ==
+            Some("B(0x3d): putchar".into()));
+    assert!(describe_call(0xa00000c0, 0x07) ==
+            Some("C(0x07): InstallExceptionHandlers".into()));
+    assert!(describe_call(0xc0, 0xff) ==
+            Some("C(0xff): <unknown>".into()));
+    assert!(describe_call(0xbfc00000, 0x3c) == None);
+}
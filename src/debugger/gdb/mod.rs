@@ -4,7 +4,7 @@ use std::io::{Read, Write};
 use debugger::Debugger;
 use cpu::Cpu;
 use memory::{Byte, HalfWord, Word};
-use interrupt::InterruptState;
+use shared::SharedState;
 
 use self::reply::Reply;
 
@@ -37,12 +37,13 @@ impl GdbRemote {
     // Serve a single remote request
     pub fn serve(&mut self,
                  debugger: &mut Debugger,
-                 cpu: &mut Cpu) -> GdbResult {
+                 cpu: &mut Cpu,
+                 shared: &mut SharedState) -> GdbResult {
 
         match self.next_packet() {
             PacketResult::Ok(packet) => {
                 try!(self.ack());
-                self.handle_packet(debugger, cpu, &packet)
+                self.handle_packet(debugger, cpu, shared, &packet)
             }
             PacketResult::BadChecksum(_) => {
                 // Request retransmission
@@ -163,6 +164,7 @@ impl GdbRemote {
     fn handle_packet(&mut self,
                      debugger: &mut Debugger,
                      cpu: &mut Cpu,
+                     shared: &mut SharedState,
                      packet: &[u8]) -> GdbResult {
 
         let command = packet[0];
@@ -171,8 +173,10 @@ impl GdbRemote {
         let res =
             match command {
                 b'?' => self.send_status(),
-                b'm' => self.read_memory(cpu, args),
-                b'g' => self.read_registers(cpu),
+                b'm' => self.read_memory(cpu, shared, args),
+                b'M' => self.write_memory(cpu, shared, args),
+                b'g' => self.read_registers(cpu, shared),
+                b'G' => self.write_registers(cpu, args),
                 b'c' => self.resume(debugger, cpu, args),
                 b's' => self.step(debugger, cpu, args),
                 b'Z' => self.add_breakpoint(debugger, args),
@@ -227,7 +231,9 @@ impl GdbRemote {
         self.send_string(b"OK")
     }
 
-    fn read_registers(&mut self, cpu: &mut Cpu) -> GdbResult {
+    fn read_registers(&mut self,
+                      cpu: &mut Cpu,
+                      shared: &mut SharedState) -> GdbResult {
 
         let mut reply = Reply::new();
 
@@ -240,10 +246,8 @@ impl GdbRemote {
         for &r in &[ cpu.sr(),
                      cpu.lo(),
                      cpu.hi(),
-                     cpu.bad(),
-                     // XXX We should figure out a way to get the real
-                     // irq_state over here...
-                     cpu.cause(InterruptState::new()),
+                     cpu.cop0().bad_vaddr(),
+                     cpu.cause(shared.irq_snapshot()),
                      cpu.pc() ] {
             reply.push_u32(r);
         }
@@ -267,7 +271,10 @@ impl GdbRemote {
 
     /// Read a region of memory. The packet format should be
     /// `ADDR,LEN`, both in hexadecimal
-    fn read_memory(&mut self, cpu: &mut Cpu, args: &[u8]) -> GdbResult {
+    fn read_memory(&mut self,
+                   cpu: &mut Cpu,
+                   shared: &mut SharedState,
+                   args: &[u8]) -> GdbResult {
 
         let mut reply = Reply::new();
 
@@ -294,25 +301,32 @@ impl GdbRemote {
                     let count = ::std::cmp::min(len, 4 - align);
 
                     for i in 0..count {
-                        reply.push_u8(cpu.examine::<Byte>(addr + i) as u8);
+                        let addr = addr.wrapping_add(i);
+                        let b = cpu.examine::<Byte>(shared, addr);
+
+                        reply.push_u8(b as u8);
                     }
                     count
                 }
                 2 => {
                     if len == 1 {
                         // Only one byte to read
-                        reply.push_u8(cpu.examine::<Byte>(addr) as u8);
+                        let b = cpu.examine::<Byte>(shared, addr);
+
+                        reply.push_u8(b as u8);
                         1
                     } else {
-                        reply.push_u16(cpu.examine::<HalfWord>(addr) as u16);
+                        let h = cpu.examine::<HalfWord>(shared, addr);
+
+                        reply.push_u16(h as u16);
                         2
                     }
                 }
                 _ => 0,
             };
 
-        let addr = addr + sent;
-        let len = len + sent;
+        let addr = addr.wrapping_add(sent);
+        let len = len - sent;
 
         // We can now deal with the word-aligned portion of the
         // transfer (if any). It's possible that addr is not word
@@ -322,21 +336,25 @@ impl GdbRemote {
         let nwords = len / 4;
 
         for i in 0..nwords {
-            reply.push_u32(cpu.examine::<Word>(addr + i * 4));
+            let addr = addr.wrapping_add(i * 4);
+
+            reply.push_u32(cpu.examine::<Word>(shared, addr));
         }
 
         // See if we have anything remaining
-        let addr = addr + nwords * 4;
+        let addr = addr.wrapping_add(nwords * 4);
         let rem = len - nwords * 4;
 
         match rem {
             1|3 => {
                 for i in 0..rem {
-                    reply.push_u8(cpu.examine::<Byte>(addr + i) as u8);
+                    let b = cpu.examine::<Byte>(shared, addr.wrapping_add(i));
+
+                    reply.push_u8(b as u8);
                 }
             }
             2 => {
-                reply.push_u16(cpu.examine::<HalfWord>(addr) as u16);
+                reply.push_u16(cpu.examine::<HalfWord>(shared, addr) as u16);
             }
             _ => ()
         }
@@ -344,6 +362,67 @@ impl GdbRemote {
         self.send_reply(reply)
     }
 
+    /// Write the registers in the same order as `read_registers`.
+    /// The registers we can't modify are ignored.
+    fn write_registers(&mut self, cpu: &mut Cpu, args: &[u8]) -> GdbResult {
+        // We need at least the 32 GPRs, SR, LO, HI, BAD, CAUSE and PC
+        if args.len() < 38 * 8 {
+            return self.send_error();
+        }
+
+        let mut regs = [0; 38];
+
+        for (i, r) in regs.iter_mut().enumerate() {
+            *r = match parse_hex_u32(&args[i * 8..(i + 1) * 8]) {
+                Ok(v) => v,
+                Err(_) => return self.send_error(),
+            };
+        }
+
+        for i in 0..32 {
+            cpu.force_reg(i as u32, regs[i]);
+        }
+
+        cpu.force_lo(regs[33]);
+        cpu.force_hi(regs[34]);
+        cpu.force_pc(regs[37]);
+
+        self.send_ok()
+    }
+
+    /// Write a region of memory. The packet format should be
+    /// `ADDR,LEN:XX...` with one hexadecimal pair per byte
+    fn write_memory(&mut self,
+                    cpu: &mut Cpu,
+                    shared: &mut SharedState,
+                    args: &[u8]) -> GdbResult {
+        let (header, data) =
+            match args.iter().position(|&b| b == b':') {
+                Some(p) => (&args[..p], &args[p + 1..]),
+                None => return self.send_error(),
+            };
+
+        let (addr, len) = parse_addr_len(header)?;
+
+        if data.len() != len as usize * 2 {
+            return self.send_error();
+        }
+
+        for i in 0..len {
+            let off = i as usize * 2;
+
+            let b =
+                match parse_hex(&data[off..off + 2]) {
+                    Ok(b) => b,
+                    Err(_) => return self.send_error(),
+                };
+
+            cpu.deposit::<Byte>(shared, addr.wrapping_add(i), b);
+        }
+
+        self.send_ok()
+    }
+
     /// Continue execution
     fn resume(&mut self,
               debugger: &mut Debugger,
@@ -370,7 +449,7 @@ impl GdbRemote {
             cpu: &mut Cpu,
             args: &[u8]) -> GdbResult {
 
-        debugger.set_step();
+        debugger.step();
 
         self.resume(debugger, cpu, args)
     }
@@ -424,9 +503,9 @@ impl GdbRemote {
         }
 
         match btype {
-            b'0' => debugger.del_breakpoint(addr),
-            b'2' => debugger.del_write_watchpoint(addr),
-            b'3' => debugger.del_read_watchpoint(addr),
+            b'0' => debugger.remove_breakpoint(addr),
+            b'2' => debugger.remove_write_watchpoint(addr),
+            b'3' => debugger.remove_read_watchpoint(addr),
             // Unsupported breakpoint type
             _ => return self.send_empty_reply(),
         }
@@ -475,6 +554,18 @@ fn parse_hex(hex: &[u8]) -> Result<u32, ()> {
     Ok(v)
 }
 
+/// Parse an u32 encoded as 4 little endian bytes, the format used
+/// by the register packets
+fn parse_hex_u32(hex: &[u8]) -> Result<u32, ()> {
+    let mut v = 0;
+
+    for (i, b) in hex.chunks(2).enumerate() {
+        v |= parse_hex(b)? << (i * 8);
+    }
+
+    Ok(v)
+}
+
 /// Parse a string in the format `addr,len` (both as hexadecimal
 /// strings) and return the values as a tuple. Returns `None` if
 /// the format is bogus.
@@ -531,3 +622,124 @@ fn parse_breakpoint(args: &[u8]) -> Result<(u8, u32, u8), ()> {
 
     Ok((btype, addr, kind))
 }
+
+#[test]
+fn gdb_hex_parsing() {
+    assert!(parse_hex(b"bfc00000") == Ok(0xbfc00000));
+    assert!(parse_hex(b"12g4") == Err(()));
+    assert!(parse_hex_u32(b"0000c0bf") == Ok(0xbfc00000));
+    assert!(parse_addr_len(b"80010000,10") == Ok((0x80010000, 0x10)));
+    assert!(parse_breakpoint(b"0,a0001004,4") == Ok((b'0', 0xa0001004, b'4')));
+}
+
+/// Build a `GdbRemote` connected to a local client stream
+#[cfg(test)]
+fn gdb_session() -> (GdbRemote, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+    (GdbRemote::new(&listener), client)
+}
+
+/// Read a single reply packet from `client`
+#[cfg(test)]
+fn gdb_read_reply(client: &mut TcpStream) -> Vec<u8> {
+    let mut reply = Vec::new();
+
+    // Read until we get the '#' and the two checksum digits
+    while reply.len() < 3 || reply[reply.len() - 3] != b'#' {
+        let mut b = [0];
+
+        client.read_exact(&mut b).unwrap();
+
+        reply.push(b[0]);
+    }
+
+    reply
+}
+
+#[test]
+fn gdb_write_memory() {
+    use machine::looping_machine;
+
+    let mut machine = looping_machine();
+    let mut debugger = Debugger::new();
+    let mut shared = SharedState::new();
+    let (mut remote, mut client) = gdb_session();
+
+    remote.handle_packet(&mut debugger,
+                         machine.cpu_mut(),
+                         &mut shared,
+                         b"M1000,6:785634122301").unwrap();
+
+    assert!(gdb_read_reply(&mut client) == b"$OK#9a");
+
+    let cpu = machine.cpu_mut();
+
+    assert!(cpu.examine::<Word>(&mut shared, 0x1000) == 0x12345678);
+    assert!(cpu.examine::<HalfWord>(&mut shared, 0x1004) == 0x0123);
+
+    // Not enough data
+    remote.handle_packet(&mut debugger,
+                         cpu,
+                         &mut shared,
+                         b"M1000,4:1122").unwrap();
+
+    assert!(gdb_read_reply(&mut client) == b"$E00#a5");
+    assert!(cpu.examine::<Word>(&mut shared, 0x1000) == 0x12345678);
+
+    // Bad hex
+    remote.handle_packet(&mut debugger,
+                         cpu,
+                         &mut shared,
+                         b"M1000,1:zz").unwrap();
+
+    assert!(gdb_read_reply(&mut client) == b"$E00#a5");
+    assert!(cpu.examine::<Byte>(&mut shared, 0x1000) == 0x78);
+}
+
+#[test]
+fn gdb_write_registers() {
+    use machine::looping_machine;
+
+    let mut machine = looping_machine();
+    let mut debugger = Debugger::new();
+    let mut shared = SharedState::new();
+    let (mut remote, mut client) = gdb_session();
+
+    let regs: Vec<u32> =
+        (0..38).map(|i| 0x01010101 * i).collect();
+
+    let mut packet = b"G".to_vec();
+
+    for &r in &regs {
+        for i in 0..4 {
+            packet.extend(format!("{:02x}", (r >> (i * 8)) as u8).bytes());
+        }
+    }
+
+    remote.handle_packet(&mut debugger,
+                         machine.cpu_mut(),
+                         &mut shared,
+                         &packet).unwrap();
+
+    assert!(gdb_read_reply(&mut client) == b"$OK#9a");
+
+    {
+        let cpu = machine.cpu();
+
+        assert!(cpu.regs()[..] == regs[..32]);
+        assert!(cpu.lo() == regs[33]);
+        assert!(cpu.hi() == regs[34]);
+        assert!(cpu.pc() == regs[37]);
+    }
+
+    // Truncated packet, nothing is modified
+    remote.handle_packet(&mut debugger,
+                         machine.cpu_mut(),
+                         &mut shared,
+                         &packet[..packet.len() - 8]).unwrap();
+
+    assert!(gdb_read_reply(&mut client) == b"$E00#a5");
+    assert!(machine.cpu().pc() == regs[37]);
+}
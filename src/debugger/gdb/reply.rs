@@ -25,7 +25,7 @@ impl Reply {
         for &b in data {
             self.csum = self.csum.wrapping_add(b);
 
-            if b == b'$' || b == b'$' {
+            if b == b'$' || b == b'#' {
                 panic!("Invalid char in GDB response");
             }
         }
@@ -1,17 +1,37 @@
+#[cfg(feature = "gdb")]
 use std::net::TcpListener;
+use std::ops::Range;
 
 use cpu::Cpu;
+use cpu::disasm::disasm;
+use interrupt::InterruptState;
+use memory::mask_region;
+use shared::SharedState;
+#[cfg(feature = "gdb")]
 use self::gdb::GdbRemote;
 
+#[cfg(feature = "gdb")]
 mod gdb;
+mod bios;
+
+/// Default address for the GDB server
+#[cfg(feature = "gdb")]
+pub const DEFAULT_BIND_ADDRESS: &'static str = "127.0.0.1:9001";
 
 pub struct Debugger {
-    /// Listener waiting for remote connections
-    listener: TcpListener,
+    /// Listener waiting for remote connections. The socket is only
+    /// bound the first time we enter debug mode.
+    #[cfg(feature = "gdb")]
+    listener: Option<TcpListener>,
+    /// Address the GDB server listener is bound to
+    #[cfg(feature = "gdb")]
+    bind_address: String,
     /// Holds the current client connection
+    #[cfg(feature = "gdb")]
     client: Option<GdbRemote>,
     /// Internal state: set to true when the remote requests that the
     /// execution should resume
+    #[cfg(feature = "gdb")]
     resume: bool,
     /// If a single step is requested this flag is set
     step: bool,
@@ -21,39 +41,103 @@ pub struct Debugger {
     read_watchpoints: Vec<u32>,
     /// Vector containing all active write watchpoints
     write_watchpoints: Vec<u32>,
+    /// If true we log all the calls to the BIOS kernel functions
+    log_bios_calls: bool,
+    /// Physical address ranges whose accesses are logged
+    log_ranges: Vec<Range<u32>>,
+    /// If true breakpoints and watchpoints are handled through the
+    /// GDB remote, otherwise they just halt the CPU and return
+    /// control to the caller.
+    remote: bool,
+    /// Set when a breakpoint or watchpoint has been hit while the
+    /// remote is disabled
+    halted: bool,
+    /// Address of the breakpoint we've just halted on, the next
+    /// instruction at this address will be executed without
+    /// breaking, otherwise we'd never get past it
+    resume_pc: Option<u32>,
 }
 
 impl Debugger {
     pub fn new() -> Debugger {
-        let bind_to = "127.0.0.1:9001";
-
-        // XXX The bind address/port should be configurable
-        let listener =
-            match TcpListener::bind(bind_to) {
-                Ok(l)  => l,
-                Err(e) => panic!("Couldn't bind GDB server TCP socket: {}", e),
-            };
-
-        info!("Waiting for debugger on {}", bind_to);
-
         Debugger {
-            listener: listener,
+            #[cfg(feature = "gdb")]
+            listener: None,
+            #[cfg(feature = "gdb")]
+            bind_address: DEFAULT_BIND_ADDRESS.into(),
+            #[cfg(feature = "gdb")]
             client: None,
+            #[cfg(feature = "gdb")]
             resume: true,
             step: false,
             breakpoints: Vec::new(),
             read_watchpoints: Vec::new(),
             write_watchpoints: Vec::new(),
+            log_bios_calls: false,
+            log_ranges: Vec::new(),
+            remote: cfg!(feature = "gdb"),
+            halted: false,
+            resume_pc: None,
         }
     }
 
     /// Signal a "break" which will put the emulator in debug mode at
     /// the next instruction
     pub fn trigger_break(&mut self) {
-        self.set_step();
+        self.step();
+    }
+
+    /// Select whether breakpoints and watchpoints enter the GDB
+    /// remote (the default if the "gdb" feature is enabled) or
+    /// simply halt the CPU, in which case
+    /// `Machine::run_frame` returns `FrameStatus::Halted`.
+    pub fn set_remote_enabled(&mut self, enable: bool) {
+        self.remote = enable;
+    }
+
+    /// Set the address the GDB server listens on, "127.0.0.1:9001" by
+    /// default. Only takes effect if the server hasn't been started
+    /// yet.
+    #[cfg(feature = "gdb")]
+    pub fn set_bind_address(&mut self, addr: &str) {
+        self.bind_address = addr.into();
+    }
+
+    #[cfg(feature = "gdb")]
+    pub fn bind_address(&self) -> &str {
+        &self.bind_address
+    }
+
+    /// Return true if breakpoints and watchpoints enter the GDB
+    /// remote
+    pub fn remote_enabled(&self) -> bool {
+        self.remote
+    }
+
+    /// Return true if the CPU has been halted by a breakpoint or
+    /// watchpoint
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Clear the halted state so that the execution can resume
+    pub fn clear_halt(&mut self) {
+        self.halted = false;
+    }
+
+    /// Halt the CPU or enter debug mode depending on whether the
+    /// remote is enabled
+    fn halt(&mut self, cpu: &mut Cpu, shared: &mut SharedState) {
+        if self.remote {
+            self.debug(cpu, shared);
+        } else {
+            self.step = false;
+            self.halted = true;
+        }
     }
 
-    pub fn debug(&mut self, cpu: &mut Cpu) {
+    #[cfg(feature = "gdb")]
+    pub fn debug(&mut self, cpu: &mut Cpu, shared: &mut SharedState) {
         // If stepping was requested we can reset the flag here, this
         // way we won't "double step" if we're entering debug mode for
         // an other reason (data watchpoint for instance)
@@ -69,7 +153,11 @@ impl Debugger {
                     let _ = c.send_status();
                     c
                 }
-                None => GdbRemote::new(&self.listener),
+                None =>
+                    match self.listener() {
+                        Some(l) => GdbRemote::new(l),
+                        None => return self.remote_unavailable(),
+                    },
             };
 
         // We loop as long as the remote debugger doesn't tell us to
@@ -80,10 +168,14 @@ impl Debugger {
             // Inner debugger loop: handle client requests until it
             // requests that the execution resumes or an error is
             // encountered
-            if let Err(_) = client.serve(self, cpu) {
+            if let Err(_) = client.serve(self, cpu, shared) {
                 // We encountered an error with the remote client: we
                 // wait for a new connection
-                client = GdbRemote::new(&self.listener);
+                client =
+                    match self.listener() {
+                        Some(l) => GdbRemote::new(l),
+                        None => return self.remote_unavailable(),
+                    };
             }
         }
 
@@ -91,17 +183,62 @@ impl Debugger {
         self.client = Some(client);
     }
 
+    /// Without GDB support there's nothing to talk to, we just halt
+    /// the CPU
+    #[cfg(not(feature = "gdb"))]
+    pub fn debug(&mut self, _cpu: &mut Cpu, _shared: &mut SharedState) {
+        self.step = false;
+        self.halted = true;
+    }
+
+    /// Return the GDB server listener, binding it if necessary.
+    /// Returns `None` if the socket can't be bound.
+    #[cfg(feature = "gdb")]
+    fn listener(&mut self) -> Option<&TcpListener> {
+        if self.listener.is_none() {
+            match TcpListener::bind(&*self.bind_address) {
+                Ok(l) => {
+                    info!("Waiting for debugger on {}", self.bind_address);
+
+                    self.listener = Some(l);
+                }
+                Err(e) => {
+                    error!("Couldn't bind GDB server TCP socket: {}", e);
+                    return None;
+                }
+            }
+        }
+
+        self.listener.as_ref()
+    }
+
+    /// Called when the GDB server can't be started: the remote is
+    /// disabled and the CPU halted instead, as if the "gdb" feature
+    /// was disabled
+    #[cfg(feature = "gdb")]
+    fn remote_unavailable(&mut self) {
+        warn!("GDB remote unavailable, halting the CPU instead");
+
+        self.remote = false;
+        self.halted = true;
+    }
+
+    #[cfg(feature = "gdb")]
     fn resume(&mut self) {
         self.resume = true;
     }
 
-    fn set_step(&mut self) {
+    /// Execute a single instruction: the CPU will halt (or enter
+    /// debug mode if the remote is enabled) before the next
+    /// instruction. If the CPU is not currently halted it stops
+    /// before the instruction about to be executed.
+    pub fn step(&mut self) {
         self.step = true;
     }
 
     /// Add a breakpoint that will trigger when the instruction at
     /// `addr` is about to be executed.
-    fn add_breakpoint(&mut self, addr: u32) {
+    pub fn add_breakpoint(&mut self, addr: u32) {
         // Make sure we're not adding the same address twice
         if !self.breakpoints.contains(&addr) {
             self.breakpoints.push(addr);
@@ -110,24 +247,54 @@ impl Debugger {
 
     /// Delete breakpoint at `addr`. Does nothing if there was no
     /// breakpoint set for this address.
-    fn del_breakpoint(&mut self, addr: u32) {
+    pub fn remove_breakpoint(&mut self, addr: u32) {
         self.breakpoints.retain(|&a| a != addr);
     }
 
+    /// Return true if a breakpoint is set at `pc`
+    pub fn should_break(&self, pc: u32) -> bool {
+        self.breakpoints.contains(&pc)
+    }
+
+    /// Enable or disable the logging of the BIOS kernel function
+    /// calls (the A0, B0 and C0 tables)
+    pub fn set_log_bios_calls(&mut self, enable: bool) {
+        self.log_bios_calls = enable;
+    }
+
     /// Called by the CPU when it's about to execute a new
     /// instruction. This function is called before *all* CPU
     /// instructions so it needs to be as fast as possible.
-    pub fn pc_change(&mut self, cpu: &mut Cpu) {
+    pub fn pc_change(&mut self, cpu: &mut Cpu, shared: &mut SharedState) {
+        if self.log_bios_calls {
+            // The function number is in $t1
+            if let Some(call) = bios::describe_call(cpu.pc(), cpu.regs()[9]) {
+                info!("BIOS call {}", call);
+            }
+        }
+
+        let pc = cpu.pc();
+
+        // If we've just halted on this instruction we let it run
+        // this time around
+        if self.resume_pc.take() == Some(pc) {
+            return;
+        }
+
         // Check if stepping was requested or if we encountered a
         // breakpoint
-        if self.step || self.breakpoints.contains(&cpu.pc()) {
-            self.debug(cpu);
+        if self.step || self.should_break(pc) {
+            self.halt(cpu, shared);
+
+            if self.halted {
+                self.resume_pc = Some(pc);
+            }
         }
     }
 
     /// Add a breakpoint that will trigger when the CPU attempts to
     /// read from `addr`
-    fn add_read_watchpoint(&mut self, addr: u32) {
+    pub fn add_read_watchpoint(&mut self, addr: u32) {
         // Make sure we're not adding the same address twice
         if !self.read_watchpoints.contains(&addr) {
             self.read_watchpoints.push(addr);
@@ -136,25 +303,51 @@ impl Debugger {
 
     /// Delete read watchpoint at `addr`. Does nothing if there was no
     /// breakpoint set for this address.
-    fn del_read_watchpoint(&mut self, addr: u32) {
+    pub fn remove_read_watchpoint(&mut self, addr: u32) {
         self.read_watchpoints.retain(|&a| a != addr);
     }
 
+    /// Log all the CPU accesses to the physical addresses in
+    /// `range` (at trace level)
+    pub fn add_log_range(&mut self, range: Range<u32>) {
+        self.log_ranges.push(range);
+    }
+
+    /// Remove all the logged ranges
+    pub fn clear_log_ranges(&mut self) {
+        self.log_ranges.clear();
+    }
+
+    /// Return true if accesses to `addr` should be logged
+    fn logged(&self, addr: u32) -> bool {
+        let addr = mask_region(addr);
+
+        self.log_ranges.iter().any(|r| addr >= r.start && addr < r.end)
+    }
+
     /// Called by the CPU when it's about to load a value from memory.
-    pub fn memory_read(&mut self, cpu: &mut Cpu, addr: u32) {
+    pub fn memory_read(&mut self,
+                       cpu: &mut Cpu,
+                       shared: &mut SharedState,
+                       addr: u32) {
+        if !self.log_ranges.is_empty() && self.logged(addr) {
+            trace!("Read from 0x{:08x} [PC: 0x{:08x}]",
+                   addr, cpu.current_pc());
+        }
+
         // XXX: how should we handle unaligned watchpoints? For
         // instance if we have a watchpoint on address 1 and the CPU
         // executes a `load32 at` address 0, should we break? Also,
         // should we mask the region?
         if self.read_watchpoints.contains(&addr) {
             info!("Read watchpoint triggered at 0x{:08x}", addr);
-            self.debug(cpu);
+            self.halt(cpu, shared);
         }
     }
 
     /// Add a breakpoint that will trigger when the CPU attempts to
     /// write to `addr`
-    fn add_write_watchpoint(&mut self, addr: u32) {
+    pub fn add_write_watchpoint(&mut self, addr: u32) {
         // Make sure we're not adding the same address twice
         if !self.write_watchpoints.contains(&addr) {
             self.write_watchpoints.push(addr);
@@ -163,16 +356,92 @@ impl Debugger {
 
     /// Delete write watchpoint at `addr`. Does nothing if there was no
     /// breakpoint set for this address.
-    fn del_write_watchpoint(&mut self, addr: u32) {
+    pub fn remove_write_watchpoint(&mut self, addr: u32) {
         self.write_watchpoints.retain(|&a| a != addr);
     }
 
-    /// Called by the CPU when it's about to load a value from memory.
-    pub fn memory_write(&mut self, cpu: &mut Cpu, addr: u32) {
+    /// Called by the CPU when it's about to store `val` to memory.
+    pub fn memory_write(&mut self,
+                        cpu: &mut Cpu,
+                        shared: &mut SharedState,
+                        addr: u32,
+                        val: u32) {
+        if !self.log_ranges.is_empty() && self.logged(addr) {
+            trace!("Write 0x{:08x} to 0x{:08x} [PC: 0x{:08x}]",
+                   val, addr, cpu.current_pc());
+        }
+
         // XXX: same remark as memory_read for unaligned stores
         if self.write_watchpoints.contains(&addr) {
             info!("Write watchpoint triggered at 0x{:08x}", addr);
-            self.debug(cpu);
+            self.halt(cpu, shared);
+        }
+    }
+}
+
+/// Snapshot of the CPU registers, meant to be displayed by a
+/// frontend while the execution is halted
+#[derive(Clone, Copy)]
+pub struct CpuState {
+    /// General purpose registers
+    pub regs: [u32; 32],
+    /// Address of the instruction about to be executed
+    pub pc: u32,
+    pub hi: u32,
+    pub lo: u32,
+    /// COP0 registers
+    pub sr: u32,
+    pub cause: u32,
+    pub epc: u32,
+    pub bad: u32,
+    /// Instruction about to be executed
+    pub instruction: u32,
+}
+
+impl CpuState {
+    pub fn capture(cpu: &Cpu, irq_state: InterruptState) -> CpuState {
+        let mut regs = [0; 32];
+
+        regs.copy_from_slice(cpu.regs());
+
+        let pc = cpu.pc();
+
+        CpuState {
+            regs,
+            pc,
+            hi: cpu.hi(),
+            lo: cpu.lo(),
+            sr: cpu.sr(),
+            cause: cpu.cause(irq_state),
+            epc: cpu.epc(),
+            bad: cpu.cop0().bad_vaddr(),
+            instruction: cpu.interconnect().load_instruction(pc),
         }
     }
+
+    /// Disassembly of the instruction about to be executed
+    pub fn disassembly(&self) -> String {
+        disasm(self.instruction, self.pc)
+    }
+}
+
+#[test]
+fn debugger_log_ranges() {
+    let mut debugger = Debugger::new();
+
+    assert!(!debugger.logged(0x1f801810));
+
+    debugger.add_log_range(0x1f801810..0x1f801818);
+    debugger.add_log_range(0x1000..0x2000);
+
+    // Ranges match physical addresses through any region
+    assert!(debugger.logged(0x1f801810));
+    assert!(debugger.logged(0xbf801814));
+    assert!(debugger.logged(0x80001ffc));
+    assert!(!debugger.logged(0x1f801818));
+    assert!(!debugger.logged(0xa0002000));
+
+    debugger.clear_log_ranges();
+
+    assert!(!debugger.logged(0x1000));
 }
@@ -0,0 +1,47 @@
+//! Error type returned when loading the files needed to build a
+//! `Machine`
+
+use std::fmt;
+use std::io;
+
+use cdimage::CdError;
+
+use bios::BiosError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The BIOS image can't be used
+    Bios(BiosError),
+    /// The disc image can't be read
+    Disc(CdError),
+    /// A file couldn't be read
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Bios(ref e) => write!(f, "BIOS error: {}", e),
+            Error::Disc(ref e) => write!(f, "Disc error: {}", e),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl From<BiosError> for Error {
+    fn from(e: BiosError) -> Error {
+        Error::Bios(e)
+    }
+}
+
+impl From<CdError> for Error {
+    fn from(e: CdError) -> Error {
+        Error::Disc(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,369 @@
+//! C bindings to create and run the emulator from C or C++ code
+//! (for instance a libretro core).
+//!
+//! All the functions take an opaque `Context` pointer created by
+//! `rustation_new` and return one of the `RUSTATION_*` status codes.
+//! Panics are caught at the boundary: once a function has returned
+//! `RUSTATION_ERROR_PANIC` the context can't be used anymore except
+//! to be freed with `rustation_free`.
+//!
+//! The matching C declarations are in `include/rustation.h`.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ffi::CStr;
+use std::path::Path;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+use bios::Bios;
+use cdrom::disc::Disc;
+use gpu::software::SoftwareRenderer;
+use machine::{Machine, MachineConfig, FrameStatus};
+
+/// Success
+pub const RUSTATION_OK: c_int = 0;
+/// `rustation_run_frame` reached the cycle limit before the end of
+/// the frame
+pub const RUSTATION_TIMEOUT: c_int = 1;
+/// A NULL pointer was passed where a valid one was expected
+pub const RUSTATION_ERROR_NULL: c_int = -1;
+/// The BIOS image is invalid
+pub const RUSTATION_ERROR_BAD_BIOS: c_int = -2;
+/// The disc image couldn't be loaded
+pub const RUSTATION_ERROR_BAD_DISC: c_int = -3;
+/// No BIOS has been loaded yet
+pub const RUSTATION_ERROR_NO_BIOS: c_int = -4;
+/// The emulator panicked, the context is unusable
+pub const RUSTATION_ERROR_PANIC: c_int = -5;
+/// The BIOS and disc can't be changed once the emulation is running
+pub const RUSTATION_ERROR_RUNNING: c_int = -6;
+
+/// Opaque emulator context
+pub struct Context {
+    bios: Option<Bios>,
+    disc: Option<Disc>,
+    /// Created the first time `rustation_run_frame` is called
+    machine: Option<Machine>,
+    renderer: SoftwareRenderer,
+    /// Set if a panic occured during a call
+    poisoned: bool,
+}
+
+impl Context {
+    fn new() -> Context {
+        Context {
+            bios: None,
+            disc: None,
+            machine: None,
+            renderer: SoftwareRenderer::new(),
+            poisoned: false,
+        }
+    }
+
+    /// Build the machine if it's not running yet
+    fn start(&mut self) -> Result<(), c_int> {
+        if self.machine.is_none() {
+            let bios =
+                match self.bios.take() {
+                    Some(b) => b,
+                    None => return Err(RUSTATION_ERROR_NO_BIOS),
+                };
+
+            let mut config = MachineConfig::new(bios);
+
+            config.set_disc(self.disc.take());
+            // There's no way to set breakpoints through the C API and
+            // we mustn't block waiting for a GDB connection
+            config.set_gdb_remote(false);
+
+            self.machine = Some(config.build());
+        }
+
+        Ok(())
+    }
+}
+
+/// Run `f` on the context pointed to by `ctx`, catching any panic
+unsafe fn with_context<F>(ctx: *mut Context, f: F) -> c_int
+    where F: FnOnce(&mut Context) -> c_int {
+
+    if ctx.is_null() {
+        return RUSTATION_ERROR_NULL;
+    }
+
+    let ctx = &mut *ctx;
+
+    if ctx.poisoned {
+        return RUSTATION_ERROR_PANIC;
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(|| f(ctx))) {
+        Ok(r) => r,
+        Err(_) => {
+            ctx.poisoned = true;
+            RUSTATION_ERROR_PANIC
+        }
+    }
+}
+
+/// Allocate a new emulator context. Returns NULL on failure.
+#[no_mangle]
+pub extern "C" fn rustation_new() -> *mut Context {
+    match panic::catch_unwind(Context::new) {
+        Ok(ctx) => Box::into_raw(Box::new(ctx)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Free a context allocated by `rustation_new`. Does nothing if
+/// `ctx` is NULL.
+///
+/// # Safety
+///
+/// `ctx` must be NULL or a pointer returned by `rustation_new` that
+/// hasn't been freed yet. It mustn't be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_free(ctx: *mut Context) {
+    if !ctx.is_null() {
+        let ctx = Box::from_raw(ctx);
+
+        // Don't let a panic in a destructor unwind into C
+        let _ = panic::catch_unwind(AssertUnwindSafe(move || drop(ctx)));
+    }
+}
+
+/// Load the BIOS image from the `len` bytes at `data`. The buffer
+/// is copied and can be freed by the caller afterwards.
+///
+/// # Safety
+///
+/// `ctx` must be NULL or a live context returned by `rustation_new`.
+/// `data` must be NULL or point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_load_bios(ctx: *mut Context,
+                                             data: *const u8,
+                                             len: usize) -> c_int {
+    with_context(ctx, |ctx| {
+        if data.is_null() {
+            return RUSTATION_ERROR_NULL;
+        }
+
+        if ctx.machine.is_some() {
+            return RUSTATION_ERROR_RUNNING;
+        }
+
+        let data = slice::from_raw_parts(data, len);
+
+        match Bios::from_data(data) {
+            Ok(bios) => {
+                ctx.bios = Some(bios);
+                RUSTATION_OK
+            }
+            Err(e) => {
+                error!("Can't load BIOS: {}", e);
+                RUSTATION_ERROR_BAD_BIOS
+            }
+        }
+    })
+}
+
+/// Load the disc image described by the `\0`-terminated cue sheet
+/// path `cue`
+///
+/// # Safety
+///
+/// `ctx` must be NULL or a live context returned by `rustation_new`.
+/// `cue` must be NULL or point to a `\0`-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_load_disc(ctx: *mut Context,
+                                             cue: *const c_char) -> c_int {
+    with_context(ctx, |ctx| {
+        if cue.is_null() {
+            return RUSTATION_ERROR_NULL;
+        }
+
+        if ctx.machine.is_some() {
+            return RUSTATION_ERROR_RUNNING;
+        }
+
+        let cue = CStr::from_ptr(cue);
+
+        let path =
+            match cue.to_str() {
+                Ok(p) => Path::new(p),
+                Err(_) => return RUSTATION_ERROR_BAD_DISC,
+            };
+
+        match Disc::from_cue(path) {
+            Ok(disc) => {
+                ctx.disc = Some(disc);
+                RUSTATION_OK
+            }
+            Err(e) => {
+                error!("Can't load {}: {}", path.display(), e);
+                RUSTATION_ERROR_BAD_DISC
+            }
+        }
+    })
+}
+
+/// Run the emulator until the end of the current frame. The
+/// emulation starts the first time this function is called, a BIOS
+/// must have been loaded by then.
+///
+/// Returns `RUSTATION_OK` once the frame is complete or
+/// `RUSTATION_TIMEOUT` if the cycle limit was reached first (see
+/// `Machine::run_frame`).
+///
+/// # Safety
+///
+/// `ctx` must be NULL or a live context returned by `rustation_new`.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_run_frame(ctx: *mut Context) -> c_int {
+    with_context(ctx, |ctx| {
+        if let Err(e) = ctx.start() {
+            return e;
+        }
+
+        let machine = ctx.machine.as_mut().unwrap();
+
+        match machine.run_frame(&mut ctx.renderer) {
+            FrameStatus::Timeout => RUSTATION_TIMEOUT,
+            // Breakpoints can't be set through the C API so the
+            // other statuses can't happen
+            _ => RUSTATION_OK,
+        }
+    })
+}
+
+/// Retrieve the last frame. On success `*pixels` points to
+/// `*width` x `*height` pixels in `0x00RRGGBB` format, line by
+/// line. The buffer remains valid until the next call to
+/// `rustation_run_frame` or `rustation_free`.
+///
+/// # Safety
+///
+/// `ctx` must be NULL or a live context returned by `rustation_new`.
+/// `pixels`, `width` and `height` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rustation_get_framebuffer(ctx: *mut Context,
+                                                   pixels: *mut *const u32,
+                                                   width: *mut u32,
+                                                   height: *mut u32)
+                                                   -> c_int {
+    with_context(ctx, |ctx| {
+        if pixels.is_null() || width.is_null() || height.is_null() {
+            return RUSTATION_ERROR_NULL;
+        }
+
+        let machine =
+            match ctx.machine.as_mut() {
+                Some(m) => m,
+                None => return RUSTATION_ERROR_NO_BIOS,
+            };
+
+        let frame = machine.fetch_frame(&mut ctx.renderer);
+
+        *pixels = frame.pixels.as_ptr();
+        *width = frame.width;
+        *height = frame.height;
+
+        RUSTATION_OK
+    })
+}
+
+#[test]
+fn ffi_errors() {
+    unsafe {
+        let ctx = rustation_new();
+
+        assert!(!ctx.is_null());
+
+        assert!(rustation_run_frame(ptr::null_mut()) == RUSTATION_ERROR_NULL);
+        assert!(rustation_run_frame(ctx) == RUSTATION_ERROR_NO_BIOS);
+
+        let bad_bios = [0u8; 1024];
+
+        assert!(rustation_load_bios(ctx, bad_bios.as_ptr(), bad_bios.len())
+                == RUSTATION_ERROR_BAD_BIOS);
+        assert!(rustation_load_bios(ctx, ptr::null(), 0)
+                == RUSTATION_ERROR_NULL);
+
+        let mut pixels = ptr::null();
+        let mut width = 0;
+        let mut height = 0;
+
+        assert!(rustation_get_framebuffer(ctx,
+                                          &mut pixels,
+                                          &mut width,
+                                          &mut height)
+                == RUSTATION_ERROR_NO_BIOS);
+
+        rustation_free(ctx);
+        rustation_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn ffi_run_frame_status() {
+    use machine::looping_machine;
+
+    unsafe {
+        let ctx = rustation_new();
+
+        // Stuck in an infinite loop, the frame never completes
+        let mut machine = looping_machine();
+
+        machine.set_frame_cycle_limit(10000);
+
+        (*ctx).machine = Some(machine);
+
+        assert!(rustation_run_frame(ctx) == RUSTATION_TIMEOUT);
+
+        rustation_free(ctx);
+    }
+}
+
+#[test]
+fn ffi_start_disables_remote() {
+    use bios::BIOS_SIZE;
+
+    unsafe {
+        let ctx = rustation_new();
+
+        (*ctx).bios = Some(Bios::dummy(box_array![0; BIOS_SIZE]));
+
+        assert!((*ctx).start().is_ok());
+
+        {
+            let machine = (*ctx).machine.as_ref().unwrap();
+
+            assert!(!machine.debugger().remote_enabled());
+        }
+
+        // The BIOS can't be changed anymore
+        let bios = [0u8; BIOS_SIZE];
+
+        assert!(rustation_load_bios(ctx, bios.as_ptr(), bios.len())
+                == RUSTATION_ERROR_RUNNING);
+
+        rustation_free(ctx);
+    }
+}
+
+#[test]
+fn ffi_panic() {
+    unsafe {
+        let ctx = rustation_new();
+
+        let r = with_context(ctx, |_| panic!("Test panic"));
+
+        assert!(r == RUSTATION_ERROR_PANIC);
+
+        // The context is poisoned
+        assert!(rustation_run_frame(ctx) == RUSTATION_ERROR_PANIC);
+
+        rustation_free(ctx);
+    }
+}
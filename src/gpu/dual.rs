@@ -0,0 +1,238 @@
+//! Renderer wrapper forwarding everything to two backends and
+//! comparing their output. Useful to validate a new renderer against
+//! the `SoftwareRenderer`.
+
+use super::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use super::software::{SoftwareRenderer, Frame};
+
+/// Renderers able to export their current frame for comparison
+pub trait FrameSource {
+    fn frame(&mut self) -> &Frame;
+}
+
+impl FrameSource for SoftwareRenderer {
+    fn frame(&mut self) -> &Frame {
+        self.export_frame()
+    }
+}
+
+pub struct DualRenderer<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> DualRenderer<A, B>
+    where A: Renderer + FrameSource,
+          B: Renderer + FrameSource {
+
+    pub fn new(first: A, second: B) -> DualRenderer<A, B> {
+        DualRenderer {
+            first,
+            second,
+        }
+    }
+
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+
+    /// Fetch the current frame from both backends and compare
+    /// them. Returns `None` if they're identical.
+    pub fn display(&mut self) -> Option<FrameDifference> {
+        let a = self.first.frame();
+        let b = self.second.frame();
+
+        let diff = compare_frames(a, b);
+
+        if let Some(ref d) = diff {
+            warn!("Renderer output mismatch: {:?}", d);
+        }
+
+        diff
+    }
+}
+
+impl<A, B> Renderer for DualRenderer<A, B>
+    where A: Renderer,
+          B: Renderer {
+
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.first.set_draw_offset(x, y);
+        self.second.set_draw_offset(x, y);
+    }
+
+    fn set_draw_area(&mut self,
+                     top_left: (u16, u16),
+                     bottom_right: (u16, u16)) {
+        self.first.set_draw_area(top_left, bottom_right);
+        self.second.set_draw_area(top_left, bottom_right);
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.first.set_display_mode(top_left, resolution, depth_24bpp);
+        self.second.set_display_mode(top_left, resolution, depth_24bpp);
+    }
+
+    fn push_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 2]) {
+        self.first.push_line(attributes, vertices);
+        self.second.push_line(attributes, vertices);
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: &[Vertex; 3]) {
+        self.first.push_triangle(attributes, vertices);
+        self.second.push_triangle(attributes, vertices);
+    }
+
+    fn push_quad(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 4]) {
+        self.first.push_quad(attributes, vertices);
+        self.second.push_quad(attributes, vertices);
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.first.fill_rect(color, top_left, dimensions);
+        self.second.fill_rect(color, top_left, dimensions);
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  dimensions: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        self.first.load_image(top_left, dimensions, pixel_buffer);
+        self.second.load_image(top_left, dimensions, pixel_buffer);
+    }
+
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        // The first backend is the reference
+        self.first.store_image(top_left, dimensions, pixel_buffer);
+    }
+
+    fn copy_rect(&mut self,
+                 src: (u16, u16),
+                 dst: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.first.copy_rect(src, dst, dimensions);
+        self.second.copy_rect(src, dst, dimensions);
+    }
+
+    fn clear_texture_cache(&mut self) {
+        self.first.clear_texture_cache();
+        self.second.clear_texture_cache();
+    }
+
+    fn set_mask_settings(&mut self,
+                         set_mask_bit: bool,
+                         check_mask_bit: bool) {
+        self.first.set_mask_settings(set_mask_bit, check_mask_bit);
+        self.second.set_mask_settings(set_mask_bit, check_mask_bit);
+    }
+}
+
+/// Description of the differences between two frames
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameDifference {
+    /// The frames don't have the same dimensions
+    Size((u32, u32), (u32, u32)),
+    /// Some pixels differ
+    Pixels {
+        /// Coordinates of the first differing pixel (in scanning
+        /// order)
+        first: (u32, u32),
+        /// Top-left corner of the smallest rectangle containing all
+        /// the differing pixels
+        top_left: (u32, u32),
+        /// Bottom-right corner of the smallest rectangle containing
+        /// all the differing pixels (inclusive)
+        bottom_right: (u32, u32),
+        /// Number of differing pixels
+        count: u32,
+    },
+}
+
+/// Compare two frames pixel by pixel
+pub fn compare_frames(a: &Frame, b: &Frame) -> Option<FrameDifference> {
+    if a.width != b.width || a.height != b.height {
+        return Some(FrameDifference::Size((a.width, a.height),
+                                          (b.width, b.height)));
+    }
+
+    let mut first = None;
+    let mut top_left = (a.width, a.height);
+    let mut bottom_right = (0, 0);
+    let mut count = 0;
+
+    for y in 0..a.height {
+        for x in 0..a.width {
+            if a.pixel(x, y) == b.pixel(x, y) {
+                continue;
+            }
+
+            if first.is_none() {
+                first = Some((x, y));
+            }
+
+            top_left.0 = ::std::cmp::min(top_left.0, x);
+            top_left.1 = ::std::cmp::min(top_left.1, y);
+            bottom_right.0 = ::std::cmp::max(bottom_right.0, x);
+            bottom_right.1 = ::std::cmp::max(bottom_right.1, y);
+
+            count += 1;
+        }
+    }
+
+    first.map(|first| {
+        FrameDifference::Pixels {
+            first,
+            top_left,
+            bottom_right,
+            count,
+        }
+    })
+}
+
+#[test]
+fn dual_renderer_compare() {
+    let mut dual = DualRenderer::new(SoftwareRenderer::new(),
+                                     SoftwareRenderer::new());
+
+    dual.set_display_mode((0, 0), (320, 240), false);
+    dual.fill_rect([0xff, 0, 0], (10, 10), (100, 50));
+
+    assert!(dual.display() == None);
+
+    // Only modify the second backend
+    dual.second_mut().load_image((40, 20), (2, 2), &[0x7fff; 4]);
+    dual.second_mut().load_image((200, 100), (1, 1), &[0x7fff]);
+
+    let expected = FrameDifference::Pixels {
+        first: (40, 20),
+        top_left: (40, 20),
+        bottom_right: (200, 100),
+        count: 5,
+    };
+
+    assert!(dual.display() == Some(expected));
+
+    dual.first_mut().set_display_mode((0, 0), (256, 240), false);
+
+    assert!(dual.display() ==
+            Some(FrameDifference::Size((256, 240), (320, 240))));
+}
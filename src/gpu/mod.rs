@@ -1,3 +1,5 @@
+use std::cmp;
+
 use memory::Addressable;
 use memory::timers::Timers;
 use shared::SharedState;
@@ -5,9 +7,17 @@ use interrupt::Interrupt;
 use timekeeper::{Peripheral, Cycles, FracCycles};
 
 use self::renderer::{Renderer, Vertex, PrimitiveAttributes};
-use self::renderer::{BlendMode, SemiTransparencyMode, TextureDepth};
+use self::renderer::{BlendMode, SemiTransparencyMode, TextureDepth, Shading};
 
 pub mod renderer;
+pub mod software;
+pub mod dual;
+pub mod trace;
+pub mod state;
+pub mod standalone;
+
+#[cfg(test)]
+mod tests;
 
 pub struct Gpu {
     /// Draw mode for rectangles, dithering enable and a few other
@@ -43,7 +53,8 @@ pub struct Gpu {
     /// Currently displayed field. For progressive output this is
     /// always Top.
     field: Field,
-    /// When true all textures are disabled
+    /// When true textures can be disabled by setting bit 11 of the
+    /// draw mode, see GP1(0x09)
     texture_disable: bool,
     /// Video output horizontal resolution
     hres: HorizontalRes,
@@ -73,14 +84,22 @@ pub struct Gpu {
     display_line_end: u16,
     /// DMA request direction
     dma_direction: DmaDirection,
-    /// Handler function for GP0 writes
-    gp0_handler: fn (&mut Gpu, &mut Renderer, u32),
+    /// Incoming GP0 words waiting to be processed
+    fifo: CommandFifo,
+    /// Number of GPU clock cycles before the GPU is done drawing the
+    /// last primitive. The FIFO isn't processed while it's non-zero.
+    gp0_busy_cycles: u32,
+    /// State of the GP0 parser, says how the next word is handled
+    gp0_state: Gp0State,
     /// Buffer containing the current GP0 command
     gp0_command: CommandBuffer,
     /// Remaining number of words to fetch for the current GP0 command
     gp0_words_remaining: u32,
     /// Current GP0 command attributes
     gp0_attributes: Gp0Attributes,
+    /// Number of GP0 commands received since the start of the
+    /// current frame
+    gp0_frame_commands: u32,
     /// True when the GP0 interrupt has been requested
     gp0_interrupt: bool,
     /// True when the VBLANK interrupt is high
@@ -102,12 +121,26 @@ pub struct Gpu {
     polyline_prev: ([i16; 2], [u8; 3]),
     /// Image buffer for texture uploads
     load_buffer: ImageBuffer,
+    /// Image buffer for VRAM reads through GPUREAD
+    store_buffer: ImageBuffer,
+    /// Number of 32bit words left to be read from `store_buffer`
+    image_store_words_remaining: u32,
+    /// Bitmask of features disabled for debugging purposes, see the
+    /// `force_disable` module.
+    force_disable: u32,
+    /// If true unknown GP1 commands cause a panic instead of being
+    /// ignored
+    strict: bool,
 }
 
 impl Gpu {
     pub fn new(standard: VideoClock) -> Gpu {
         let dummy_gp0 =
-            Gp0Attributes::new(Gpu::gp0_nop, false, BlendMode::None, false);
+            Gp0Attributes::new(Gpu::gp0_nop,
+                               false,
+                               BlendMode::None,
+                               false,
+                               (false, false));
 
         Gpu {
             //renderer: renderer,
@@ -140,10 +173,13 @@ impl Gpu {
             display_line_start: 0x10,
             display_line_end: 0x100,
             dma_direction: DmaDirection::Off,
-            gp0_handler: Gpu::gp0_handle_command,
+            fifo: CommandFifo::new(),
+            gp0_busy_cycles: 0,
+            gp0_state: Gp0State::Command,
             gp0_command: CommandBuffer::new(),
             gp0_words_remaining: 0,
             gp0_attributes: dummy_gp0,
+            gp0_frame_commands: 0,
             gp0_interrupt: false,
             vblank_interrupt: false,
             gpu_clock_phase: 0,
@@ -153,9 +189,40 @@ impl Gpu {
             read_word: 0,
             polyline_prev: ([0; 2], [0; 3]),
             load_buffer: ImageBuffer::new(),
+            store_buffer: ImageBuffer::new(),
+            image_store_words_remaining: 0,
+            force_disable: 0,
+            strict: false,
         }
     }
 
+    /// Force-disable some drawing features regardless of the game's
+    /// configuration in order to help tracking down rendering
+    /// issues. `mask` is a combination of the flags in the
+    /// `force_disable` module, 0 restores the normal behaviour. The
+    /// GPU state seen by the game is not modified.
+    pub fn set_force_disable(&mut self, mask: u32) {
+        self.force_disable = mask;
+    }
+
+    /// Return the current force-disable mask
+    pub fn force_disable(&self) -> u32 {
+        self.force_disable
+    }
+
+    /// When `strict` is true the emulator panics when it receives an
+    /// unknown GP0 or GP1 command or an unsupported display mode
+    /// instead of logging it and moving on. Useful during development
+    /// to catch unimplemented features. Disabled by default.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Return true if the feature `flag` has been force-disabled
+    fn is_force_disabled(&self, flag: u32) -> bool {
+        self.force_disable & flag != 0
+    }
+
     /// Return the number of GPU clock cycles in a line and number of
     /// lines in a frame (or field for interlaced output) depending on
     /// the configured video mode
@@ -169,16 +236,19 @@ impl Gpu {
         }
     }
 
+    /// Return the GPU clock frequency in Hz
+    fn gpu_clock(&self) -> f32 {
+        match self.standard {
+            VideoClock::Ntsc => 53_690_000.,
+            VideoClock::Pal  => 53_222_000.,
+        }
+    }
+
     /// Return the GPU to CPU clock ratio. The value is multiplied by
     /// CLOCK_RATIO_FRAC to get a precise fixed point value.
     fn gpu_to_cpu_clock_ratio(&self) -> FracCycles {
         // First we convert the delta into GPU clock periods.
-        // GPU clock in Hz
-        let gpu_clock =
-            match self.standard {
-                VideoClock::Ntsc => 53_690_000.,
-                VideoClock::Pal  => 53_222_000.,
-            };
+        let gpu_clock = self.gpu_clock();
 
         // CPU clock in Hz
         let cpu_clock = ::cpu::CPU_FREQ_HZ as f32;
@@ -246,6 +316,13 @@ impl Gpu {
         // Conwert delta back to integer
         let delta = delta >> 16;
 
+        self.gp0_busy_cycles =
+            if delta >= self.gp0_busy_cycles as Cycles {
+                0
+            } else {
+                self.gp0_busy_cycles - delta as u32
+            };
+
         // Compute the current line and position within the line.
 
         let (ticks_per_line, lines_per_frame) = self.vmode_timings();
@@ -278,21 +355,30 @@ impl Gpu {
             self.display_line = line as u16;
         }
 
-        let vblank_interrupt = self.in_vblank();
+        let vblank = self.in_vblank();
 
-        if !self.vblank_interrupt && vblank_interrupt {
-            // Rising edge of the vblank interrupt
-            shared.irq_state().assert(Interrupt::VBlank);
+        if vblank != self.vblank_interrupt {
+            self.vblank(shared, vblank);
         }
 
-        if self.vblank_interrupt && !vblank_interrupt {
+        self.predict_next_sync(shared);
+    }
+
+    /// Called when the vertical blanking starts (`active` is true) or
+    /// ends. `predict_next_sync` schedules a sync with the
+    /// `TimeKeeper` at both transitions so this runs once per field
+    /// at the right time.
+    fn vblank(&mut self, shared: &mut SharedState, active: bool) {
+        if active {
+            // The frame is complete, signal the interrupt
+            shared.irq_state().assert(Interrupt::VBlank);
+        } else {
             // End of vertical blanking, we're starting a new frame
             shared.new_frame();
+            self.gp0_frame_commands = 0;
         }
 
-        self.vblank_interrupt = vblank_interrupt;
-
-        self.predict_next_sync(shared);
+        self.vblank_interrupt = active;
     }
 
     /// Predict when the next "forced" sync should take place
@@ -338,6 +424,14 @@ impl Gpu {
             delta += (display_line_end - 1 - cur_line) * ticks_per_line;
         }
 
+        // If words are waiting in the FIFO we need to sync when the
+        // GPU is done drawing in order to process them
+        if !self.fifo.is_empty() {
+            let busy = cmp::max(self.gp0_busy_cycles, 1) as Cycles;
+
+            delta = cmp::min(delta, busy);
+        }
+
         // Convert delta in CPU clock periods.
         delta <<= FracCycles::frac_bits();
         // Remove the current fractional cycle to be more accurate
@@ -351,10 +445,81 @@ impl Gpu {
         shared.tk().set_next_sync_delta(Peripheral::Gpu, delta);
     }
 
+    /// Return the field currently being displayed. For progressive
+    /// output this is always `Field::Top`.
+    pub fn field(&self) -> Field {
+        self.field
+    }
+
     pub fn display_vram_start(&self) -> (u16, u16) {
         (self.display_vram_x_start, self.display_vram_y_start)
     }
 
+    /// Return the horizontal resolution selected through GP1(0x08)
+    pub fn horizontal_res(&self) -> HorizontalRes {
+        self.hres
+    }
+
+    /// Return the portion of the VRAM actually sent to the video
+    /// output. Unlike `HorizontalRes::width` the dimensions are
+    /// computed from the display ranges set by GP1(0x06) and
+    /// GP1(0x07) so they match what a TV would show.
+    pub fn display_rect(&self) -> DisplayRect {
+        let divider = self.hres.dotclock_divider() as u16;
+
+        let dots =
+            self.display_horiz_end.saturating_sub(self.display_horiz_start);
+
+        // The hardware rounds the width to a multiple of 4 pixels
+        let width = (dots / divider + 2) & !3;
+
+        let lines =
+            self.display_line_end.saturating_sub(self.display_line_start);
+
+        let height =
+            match (self.interlaced, self.vres) {
+                (true, VerticalRes::Y480Lines) => lines * 2,
+                _ => lines,
+            };
+
+        DisplayRect {
+            top_left: (self.display_vram_x_start, self.display_vram_y_start),
+            dimensions: (width, height),
+            depth_24bpp: self.display_depth == DisplayDepth::D24Bits,
+        }
+    }
+
+    /// Return the video mode currently selected through GP1(0x08)
+    pub fn video_standard(&self) -> VMode {
+        self.vmode
+    }
+
+    /// Return the video clock of the emulated console
+    pub fn video_clock(&self) -> VideoClock {
+        self.standard
+    }
+
+    /// Return true if the video mode selected by the software
+    /// doesn't match the console's video clock (NTSC output on a PAL
+    /// console for instance)
+    pub fn video_standard_forced(&self) -> bool {
+        match (self.vmode, self.standard) {
+            (VMode::Ntsc, VideoClock::Ntsc) => false,
+            (VMode::Pal, VideoClock::Pal) => false,
+            _ => true,
+        }
+    }
+
+    /// Return the current refresh rate in frames (or fields for
+    /// interlaced output) per second
+    pub fn refresh_rate(&self) -> f32 {
+        let (ticks_per_line, lines_per_frame) = self.vmode_timings();
+
+        let ticks_per_frame = ticks_per_line as f32 * lines_per_frame as f32;
+
+        self.gpu_clock() / ticks_per_frame
+    }
+
     /// Return true if we're currently in the video blanking period
     fn in_vblank(&self) -> bool {
         self.display_line < self.display_line_start ||
@@ -379,7 +544,8 @@ impl Gpu {
                                 offset: u32) -> u32 {
 
         if T::size() != 4 {
-            panic!("Unhandled GPU load ({})", T::size());
+            warn!("Unhandled GPU load ({}) at offset {}", T::size(), offset);
+            return 0;
         }
 
         self.sync(shared);
@@ -402,25 +568,112 @@ impl Gpu {
                                  val: u32) {
 
         if T::size() != 4 {
-            panic!("Unhandled GPU load ({})", T::size());
+            warn!("Unhandled GPU store ({}) at offset {}: 0x{:08x}",
+                  T::size(), offset, val);
+            return;
         }
 
         self.sync(shared);
 
         match offset {
-            0 => self.gp0(renderer, val),
+            0 => self.gp0_write(renderer, val),
             4 => self.gp1(shared, renderer, val, timers),
             _ => unreachable!(),
         }
+
+        // The word might have been queued in the FIFO
+        self.predict_next_sync(shared);
     }
 
-    /// Dispatch to the current GP0 handler method
+    /// Send a word to GP0 and process it right away, cutting the
+    /// drawing of the previous command short if needed. That's what
+    /// the DMA uses since transfers are instantaneous.
+    ///
+    /// The GPU can be driven directly through this method (and `gp1`)
+    /// without the rest of the console, which is handy for tests and
+    /// tools:
+    ///
+    /// ```
+    /// use rustation::gpu::{Gpu, VideoClock};
+    /// use rustation::gpu::renderer::NullRenderer;
+    /// use rustation::gpu::software::SoftwareRenderer;
+    ///
+    /// let mut gpu = Gpu::new(VideoClock::Ntsc);
+    ///
+    /// // GP0(0x28): red monochrome 16x16 quad
+    /// let quad = [0x280000ff, 0x00000000, 0x00000010, 0x00100000, 0x00100010];
+    ///
+    /// for &w in &quad {
+    ///     gpu.gp0(&mut NullRenderer, w);
+    /// }
+    ///
+    /// // Same thing with a renderer we can inspect. We need to set
+    /// // the drawing area first.
+    /// let mut renderer = SoftwareRenderer::new();
+    ///
+    /// gpu.gp0(&mut renderer, 0xe3000000);
+    /// gpu.gp0(&mut renderer, 0xe407fbff);
+    ///
+    /// for &w in &quad {
+    ///     gpu.gp0(&mut renderer, w);
+    /// }
+    ///
+    /// assert_eq!(renderer.vram_pixel(8, 8), 0x001f);
+    /// ```
     pub fn gp0(&mut self, renderer: &mut Renderer, val: u32) {
-        (self.gp0_handler)(self, renderer, val);
+        // Flush the words queued by the CPU first
+        self.gp0_busy_cycles = 0;
+        self.process_fifo(renderer);
+
+        self.gp0_handle_word(renderer, val);
+    }
+
+    /// GP0 write from the CPU: queue the word in the FIFO and process
+    /// the pending words unless the GPU is busy drawing.
+    fn gp0_write(&mut self, renderer: &mut dyn Renderer, val: u32) {
+        if self.fifo.is_full() {
+            // XXX On the real hardware the word would be lost, games
+            // are supposed to check the status register first.
+            warn!("GP0 FIFO overflow, dropping {:08x}", val);
+            return;
+        }
+
+        self.fifo.push(val);
+
+        self.process_fifo(renderer);
+    }
+
+    /// Dispatch the words in the FIFO to the current GP0 handler
+    /// method until it's empty or the GPU becomes busy drawing.
+    pub fn process_fifo(&mut self, renderer: &mut dyn Renderer) {
+        while self.gp0_busy_cycles == 0 {
+            match self.fifo.pop() {
+                Some(val) => self.gp0_handle_word(renderer, val),
+                None => break,
+            }
+        }
+    }
+
+    /// Call the handler method for the current GP0 state
+    fn gp0_handle_word(&mut self, renderer: &mut dyn Renderer, val: u32) {
+        match self.gp0_state {
+            Gp0State::Command =>
+                self.gp0_handle_command(renderer, val),
+            Gp0State::Parameter =>
+                self.gp0_handle_parameter(renderer, val),
+            Gp0State::ImageLoad =>
+                self.gp0_handle_image_load(renderer, val),
+            Gp0State::ShadedPolylineColor =>
+                self.gp0_handle_shaded_polyline_color(renderer, val),
+            Gp0State::ShadedPolylineVertex =>
+                self.gp0_handle_shaded_polyline_vertex(renderer, val),
+            Gp0State::MonochromePolylineVertex =>
+                self.gp0_handle_monochrome_polyline_vertex(renderer, val),
+        }
     }
 
     /// Retrieve value of the status register
-    fn status(&self) -> u32 {
+    pub fn status(&self) -> u32 {
         let mut r = 0u32;
 
         let draw_mode = self.draw_mode as u32;
@@ -440,13 +693,18 @@ impl Gpu {
         r |= (self.display_disabled as u32) << 23;
         r |= (self.gp0_interrupt as u32) << 24;
 
-        // For now we pretend that the GPU is always ready:
-        // Ready to receive command
-        r |= 1 << 26;
-        // Ready to send VRAM to CPU
-        r |= 1 << 27;
-        // Ready to receive DMA block
-        r |= 1 << 28;
+        // Ready to receive a command: we're not in the middle of an
+        // other command, there's nothing left to process in the FIFO
+        // and we're done drawing
+        let ready = self.gp0_idle() &&
+                    self.fifo.is_empty() &&
+                    self.gp0_busy_cycles == 0;
+
+        r |= (ready as u32) << 26;
+        // Ready to send VRAM to CPU: an image store is in progress
+        r |= ((self.image_store_words_remaining > 0) as u32) << 27;
+        // Ready to receive DMA block: the FIFO must be empty
+        r |= (self.fifo.is_empty() as u32) << 28;
 
         r |= (self.dma_direction as u32) << 29;
 
@@ -456,19 +714,17 @@ impl Gpu {
             r |= ((self.displayed_vram_line() & 1) as u32) << 31
         }
 
-        // Not sure about that, I'm guessing that it's the signal
-        // checked by the DMA in when sending data in Request
-        // synchronization mode. For now I blindly follow the Nocash
-        // spec.
+        // DMA request signal, it's the one checked by the DMA when
+        // sending data in Request synchronization mode.
         let dma_request =
             match self.dma_direction {
                 // Always 0
                 DmaDirection::Off => 0,
-                // Should be 0 if FIFO is full, 1 otherwise
-                DmaDirection::Fifo => 1,
-                // Should be the same as status bit 28
+                // 0 if FIFO is full, 1 otherwise
+                DmaDirection::Fifo => (!self.fifo.is_full()) as u32,
+                // Same as status bit 28
                 DmaDirection::CpuToGp0 => (r >> 28) & 1,
-                // Should be the same as status bit 27
+                // Same as status bit 27
                 DmaDirection::VRamToCpu => (r >> 27) & 1,
             };
 
@@ -477,22 +733,48 @@ impl Gpu {
         r
     }
 
+    /// Return true if the GP0 state machine is waiting for a new
+    /// command, false if it's in the middle of one
+    fn gp0_idle(&self) -> bool {
+        self.gp0_state == Gp0State::Command
+    }
+
+    /// Read a word from GPUREAD through the DMA
+    pub fn dma_read_word(&mut self) -> u32 {
+        self.read()
+    }
+
     /// Retrieve value of the "read" register
-    fn read(&self) -> u32 {
+    fn read(&mut self) -> u32 {
         debug!("GPUREAD");
-        // XXX framebuffer read not supported
+
+        if self.image_store_words_remaining > 0 {
+            // Image store in progress, return the next two pixels
+            self.image_store_words_remaining -= 1;
+            self.read_word = self.store_buffer.pop_gp0_word();
+        }
+
         self.read_word
     }
 
     /// GP0 handler method: handle a command word
     fn gp0_handle_command(&mut self, renderer: &mut Renderer, val: u32) {
+        if gp0_opcode((val >> 24) as u8).is_none() {
+            if self.strict {
+                panic!("Unhandled GP0 command {:08x}", val);
+            }
+
+            warn!("Ignoring unhandled GP0 command {:08x}", val);
+        }
+
         let (len, attributes) = self.gp0_parse_command(val);
 
         self.gp0_words_remaining = len;
         self.gp0_attributes = attributes;
         self.gp0_command.clear();
+        self.gp0_frame_commands = self.gp0_frame_commands.wrapping_add(1);
 
-        self.gp0_handler = Gpu::gp0_handle_parameter;
+        self.gp0_state = Gp0State::Parameter;
 
         // Call the parameter handling function for the current word
         self.gp0_handle_parameter(renderer, val);
@@ -500,7 +782,17 @@ impl Gpu {
 
     /// GP0 handler method: handle a command parameter
     fn gp0_handle_parameter(&mut self, renderer: &mut Renderer, val: u32) {
-        self.gp0_command.push_word(val);
+        if !self.gp0_command.push_word(val) {
+            // Can't happen with the command lengths in `gp0_opcode`
+            // since `load_state` rejects inconsistent states. Drop
+            // the command instead of crashing if it ever does.
+            error!("GP0 command {:08x} too long, discarding it",
+                   self.gp0_command[0]);
+            self.gp0_words_remaining = 0;
+            self.gp0_state = Gp0State::Command;
+            return;
+        }
+
         self.gp0_words_remaining -= 1;
 
         if self.gp0_words_remaining == 0 {
@@ -512,23 +804,29 @@ impl Gpu {
 
             // Reset GP0 handler. Can be overriden by the callback in
             // certain cases, for instance for image load commands.
-            self.gp0_handler = Gpu::gp0_handle_command;
+            self.gp0_state = Gp0State::Command;
             (self.gp0_attributes.callback)(self, renderer);
+
+            let opcode = self.gp0_attributes.primitive_attributes.opcode;
+
+            if is_drawing_command(opcode) {
+                self.gp0_busy_cycles = DRAW_BUSY_CYCLES;
+            }
         }
     }
 
     /// GP0 handler method: handle shaded polyline color word
     fn gp0_handle_shaded_polyline_color(&mut self, _: &mut Renderer, val: u32) {
-        self.gp0_handler =
+        self.gp0_state =
             if is_polyline_end_marker(val) {
                 // We found the end-of-polyline marker, we're done.
-                Gpu::gp0_handle_command
+                Gp0State::Command
             } else {
                 // Store the color and wait for the position in the
                 // next word
                 self.gp0_command.clear();
                 self.gp0_command.push_word(val);
-                Gpu::gp0_handle_shaded_polyline_vertex
+                Gp0State::ShadedPolylineVertex
             };
     }
 
@@ -544,7 +842,7 @@ impl Gpu {
 
         // Retrieve color stored in `gp0_handle_shaded_polyline_color`
         let end_color = gp0_color(self.gp0_command[0]);
-        let end_pos = gp0_position(val);
+        let end_pos = gp0_vertex_position(val);
 
         let vertices = [
             Vertex::new(start_pos, start_color),
@@ -558,7 +856,7 @@ impl Gpu {
         self.polyline_prev = (end_pos, end_color);
 
         // We expect the color of the next segment
-        self.gp0_handler = Gpu::gp0_handle_shaded_polyline_color;
+        self.gp0_state = Gp0State::ShadedPolylineColor;
     }
 
     /// GP0 handler method: handle monochrome polyline position word
@@ -567,14 +865,14 @@ impl Gpu {
                                              val: u32) {
         if is_polyline_end_marker(val) {
             // We found the end-of-polyline marker, we're done.
-            self.gp0_handler = Gpu::gp0_handle_command;
+            self.gp0_state = Gp0State::Command;
             return;
         }
 
         // The line starts at the end of the previous segment
         let (start_pos, color) = self.polyline_prev;
 
-        let end_pos = gp0_position(val);
+        let end_pos = gp0_vertex_position(val);
 
         let vertices = [
             Vertex::new(start_pos, color),
@@ -593,72 +891,14 @@ impl Gpu {
     fn gp0_parse_command(&self, gp0: u32) -> (u32, Gp0Attributes) {
         let opcode = gp0 >> 24;
 
-        let dither = self.dither();
-
-        let (len, cback, dither): (u32, fn(&mut Gpu, &mut Renderer), bool) =
-            match opcode {
-                0x00 => (1,  Gpu::gp0_nop, false),
-                0x01 => (1,  Gpu::gp0_clear_cache, false),
-                0x02 => (3,  Gpu::gp0_fill_rect, false),
-                0x20 => (4,  Gpu::gp0_monochrome_triangle, false),
-                0x22 => (4,  Gpu::gp0_monochrome_triangle, false),
-                0x24 => (7,  Gpu::gp0_textured_triangle, dither),
-                0x25 => (7,  Gpu::gp0_textured_triangle, dither),
-                0x26 => (7,  Gpu::gp0_textured_triangle, dither),
-                0x27 => (7,  Gpu::gp0_textured_triangle, dither),
-                0x28 => (5,  Gpu::gp0_monochrome_quad, false),
-                0x2a => (5,  Gpu::gp0_monochrome_quad, false),
-                0x2c => (9,  Gpu::gp0_textured_quad, dither),
-                0x2d => (9,  Gpu::gp0_textured_quad, dither),
-                0x2e => (9,  Gpu::gp0_textured_quad, dither),
-                0x2f => (9,  Gpu::gp0_textured_quad, dither),
-                0x30 => (6,  Gpu::gp0_shaded_triangle, dither),
-                0x32 => (6,  Gpu::gp0_shaded_triangle, dither),
-                0x34 => (9,  Gpu::gp0_textured_shaded_triangle, dither),
-                0x36 => (9,  Gpu::gp0_textured_shaded_triangle, dither),
-                0x38 => (8,  Gpu::gp0_shaded_quad, dither),
-                0x3a => (8,  Gpu::gp0_shaded_quad, dither),
-                0x3c => (12, Gpu::gp0_textured_shaded_quad, dither),
-                0x3e => (12, Gpu::gp0_textured_shaded_quad, dither),
-                0x40 => (3,  Gpu::gp0_monochrome_line, false),
-                0x42 => (3,  Gpu::gp0_monochrome_line, false),
-                0x48 => (3,  Gpu::gp0_monochrome_polyline, false),
-                0x4a => (3,  Gpu::gp0_monochrome_polyline, false),
-                0x50 => (4,  Gpu::gp0_shaded_line, false),
-                0x52 => (4,  Gpu::gp0_shaded_line, false),
-                0x58 => (4,  Gpu::gp0_shaded_polyline, false),
-                0x5a => (4,  Gpu::gp0_shaded_polyline, false),
-                0x60 => (3,  Gpu::gp0_monochrome_rect, false),
-                0x62 => (3,  Gpu::gp0_monochrome_rect, false),
-                0x64 => (4,  Gpu::gp0_textured_rect, false),
-                0x65 => (4,  Gpu::gp0_textured_rect, false),
-                0x66 => (4,  Gpu::gp0_textured_rect, false),
-                0x67 => (4,  Gpu::gp0_textured_rect, false),
-                0x68 => (2,  Gpu::gp0_monochrome_rect_1x1, false),
-                0x6a => (2,  Gpu::gp0_monochrome_rect_1x1, false),
-                0x74 => (3,  Gpu::gp0_textured_rect_8x8, false),
-                0x75 => (3,  Gpu::gp0_textured_rect_8x8, false),
-                0x76 => (3,  Gpu::gp0_textured_rect_8x8, false),
-                0x77 => (3,  Gpu::gp0_textured_rect_8x8, false),
-                0x78 => (2,  Gpu::gp0_monochrome_rect_16x16, false),
-                0x7a => (2,  Gpu::gp0_monochrome_rect_16x16, false),
-                0x7c => (3,  Gpu::gp0_textured_rect_16x16, false),
-                0x7d => (3,  Gpu::gp0_textured_rect_16x16, false),
-                0x7e => (3,  Gpu::gp0_textured_rect_16x16, false),
-                0x7f => (3,  Gpu::gp0_textured_rect_16x16, false),
-                0x80 => (4,  Gpu::gp0_copy_rect, false),
-                0xa0 => (3,  Gpu::gp0_image_load, false),
-                0xc0 => (3,  Gpu::gp0_image_store, false),
-                0xe1 => (1,  Gpu::gp0_draw_mode, false),
-                0xe2 => (1,  Gpu::gp0_texture_window, false),
-                0xe3 => (1,  Gpu::gp0_drawing_area_top_left, false),
-                0xe4 => (1,  Gpu::gp0_drawing_area_bottom_right, false),
-                0xe5 => (1,  Gpu::gp0_drawing_offset, false),
-                0xe6 => (1,  Gpu::gp0_mask_bit_setting, false),
-                _    => panic!("Unhandled GP0 command {:08x}", gp0),
-            };
+        // Unsupported commands are treated as single word NOPs
+        let command = gp0_opcode(opcode as u8).unwrap_or(GP0_NOP);
+
+        let len = command.len;
+        let cback = command.callback;
+        let dither = command.dither && self.dither();
 
-        let textured = opcode & 0x4 != 0;
+        let textured = opcode & 0x4 != 0 && !self.textures_disabled();
 
         let blend_mode =
             if textured {
@@ -673,15 +913,78 @@ impl Gpu {
 
         let semi_transparent = opcode & 2 != 0;
 
-        let attr =
+        let mask = (self.force_set_mask_bit, self.preserve_masked_pixels);
+
+        // Apply the debug overrides
+        let blend_mode =
+            match self.is_force_disabled(force_disable::TEXTURING) {
+                true => BlendMode::None,
+                false => blend_mode,
+            };
+
+        let semi_transparent = semi_transparent &&
+            !self.is_force_disabled(force_disable::SEMI_TRANSPARENCY);
+
+        let dither = dither &&
+            !self.is_force_disabled(force_disable::DITHERING);
+
+        let mask =
+            match self.is_force_disabled(force_disable::MASK_BIT) {
+                true => (false, false),
+                false => mask,
+            };
+
+        let mut attr =
             Gp0Attributes::new(cback,
                                semi_transparent,
                                blend_mode,
-                               dither);
+                               dither,
+                               mask);
+
+        // Untextured primitives use the semi-transparency mode set by
+        // the "Draw Mode" command, textured polygons override it with
+        // their own texture page parameters.
+        attr.set_semi_transparency_mode(self.draw_mode as u32);
+
+        // Drawing to the display area is only prohibited during
+        // active display in 480 line interlaced mode, and then only
+        // for the lines of the field currently being output
+        let protect_display =
+            !self.draw_to_display &&
+            !self.display_disabled &&
+            self.interlaced &&
+            self.vres == VerticalRes::Y480Lines;
+
+        attr.primitive_attributes.displayed_field =
+            match protect_display {
+                true => Some(self.field),
+                false => None,
+            };
+
+        attr.primitive_attributes.texture_window_mask =
+            [self.texture_window_x_mask, self.texture_window_y_mask];
+        attr.primitive_attributes.texture_window_offset =
+            [self.texture_window_x_offset, self.texture_window_y_offset];
+
+        // Polygons and lines have a color per vertex when bit 4 of
+        // the opcode is set. Rectangles are always flat.
+        if opcode >= 0x20 && opcode < 0x60 && opcode & 0x10 != 0 {
+            attr.primitive_attributes.shading = Shading::Gouraud;
+        }
+
+        // Debugging information
+        attr.primitive_attributes.opcode = opcode as u8;
+        attr.primitive_attributes.sequence = self.gp0_frame_commands;
 
         (len, attr)
     }
 
+    /// Return true if the draw mode disables textures, this only
+    /// works if it's been allowed by GP1(0x09)
+    fn textures_disabled(&self) -> bool {
+        self.texture_disable && (self.draw_mode >> 11) & 1 != 0
+    }
+
     fn dither(&self) -> bool {
         (self.draw_mode >> 9) & 1 != 0
     }
@@ -692,8 +995,8 @@ impl Gpu {
     }
 
     /// GP0(0x01): Clear cache
-    fn gp0_clear_cache(&mut self, _: &mut Renderer) {
-        // XXX Not implemented
+    fn gp0_clear_cache(&mut self, renderer: &mut dyn Renderer) {
+        renderer.clear_texture_cache();
     }
 
     /// GP0(0x02): Fill rectangle
@@ -744,14 +1047,26 @@ impl Gpu {
     }
 
     /// Gp0(0x80): Copy rectangle
-    fn gp0_copy_rect(&mut self, _: &mut Renderer) {
-        let size = gp0_position(self.gp0_command[3]);
-        let src_top_left = gp0_position(self.gp0_command[1]);
-        let dst_top_left = gp0_position(self.gp0_command[2]);
+    fn gp0_copy_rect(&mut self, renderer: &mut dyn Renderer) {
+        // The coordinates wrap around the VRAM
+        let src = self.gp0_command[1];
+        let dst = self.gp0_command[2];
 
-        // XXX Implement me
-        debug!("Copy Rectangle {:?} {:?} {:?}",
-               size, src_top_left, dst_top_left);
+        let src_x = (src & 0x3ff) as u16;
+        let src_y = ((src >> 16) & 0x1ff) as u16;
+
+        let dst_x = (dst & 0x3ff) as u16;
+        let dst_y = ((dst >> 16) & 0x1ff) as u16;
+
+        // Same size encoding as the image load command
+        let res = self.gp0_command[3];
+
+        let width  = (((res & 0xffff).wrapping_sub(1)) & 0x3ff) + 1;
+        let height = (((res >> 16).wrapping_sub(1)) & 0x1ff) + 1;
+
+        renderer.copy_rect((src_x, src_y),
+                           (dst_x, dst_y),
+                           (width as u16, height as u16));
     }
 
     /// Draw an untextured unshaded triangle
@@ -759,9 +1074,9 @@ impl Gpu {
         let color = gp0_color(self.gp0_command[0]);
 
         let vertices = [
-            Vertex::new(gp0_position(self.gp0_command[1]), color),
-            Vertex::new(gp0_position(self.gp0_command[2]), color),
-            Vertex::new(gp0_position(self.gp0_command[3]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[1]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[2]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[3]), color),
             ];
 
         renderer.push_triangle(self.gp0_attributes.primitive_attributes(),
@@ -773,10 +1088,10 @@ impl Gpu {
         let color = gp0_color(self.gp0_command[0]);
 
         let vertices = [
-            Vertex::new(gp0_position(self.gp0_command[1]), color),
-            Vertex::new(gp0_position(self.gp0_command[2]), color),
-            Vertex::new(gp0_position(self.gp0_command[3]), color),
-            Vertex::new(gp0_position(self.gp0_command[4]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[1]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[2]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[3]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[4]), color),
             ];
 
         renderer.push_quad(self.gp0_attributes.primitive_attributes(),
@@ -788,8 +1103,8 @@ impl Gpu {
         let color = gp0_color(self.gp0_command[0]);
 
         let vertices = [
-            Vertex::new(gp0_position(self.gp0_command[1]), color),
-            Vertex::new(gp0_position(self.gp0_command[2]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[1]), color),
+            Vertex::new(gp0_vertex_position(self.gp0_command[2]), color),
             ];
 
         renderer.push_line(self.gp0_attributes.primitive_attributes(),
@@ -802,9 +1117,9 @@ impl Gpu {
         // ignored for the first two vertices.
 
         let color = gp0_color(self.gp0_command[0]);
-        let start_pos = gp0_position(self.gp0_command[1]);
+        let start_pos = gp0_vertex_position(self.gp0_command[1]);
 
-        let end_pos = gp0_position(self.gp0_command[2]);
+        let end_pos = gp0_vertex_position(self.gp0_command[2]);
 
         let vertices = [
             Vertex::new(start_pos, color),
@@ -818,7 +1133,7 @@ impl Gpu {
         // the next vertex
         self.polyline_prev = (end_pos, color);
 
-        self.gp0_handler = Gpu::gp0_handle_monochrome_polyline_vertex;
+        self.gp0_state = Gp0State::MonochromePolylineVertex;
     }
 
 
@@ -830,13 +1145,13 @@ impl Gpu {
         self.gp0_attributes.set_draw_params(self.gp0_command[4] >> 16);
 
         let vertices = [
-            Vertex::new_textured(gp0_position(self.gp0_command[1]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[1]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[2])),
-            Vertex::new_textured(gp0_position(self.gp0_command[3]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[3]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[4])),
-            Vertex::new_textured(gp0_position(self.gp0_command[5]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[5]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[6])),
             ];
@@ -853,16 +1168,16 @@ impl Gpu {
         self.gp0_attributes.set_draw_params(self.gp0_command[4] >> 16);
 
         let vertices = [
-            Vertex::new_textured(gp0_position(self.gp0_command[1]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[1]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[2])),
-            Vertex::new_textured(gp0_position(self.gp0_command[3]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[3]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[4])),
-            Vertex::new_textured(gp0_position(self.gp0_command[5]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[5]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[6])),
-            Vertex::new_textured(gp0_position(self.gp0_command[7]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[7]),
                                  color,
                                  gp0_texture_coordinates(self.gp0_command[8])),
             ];
@@ -874,11 +1189,11 @@ impl Gpu {
     /// Draw an untextured shaded triangle
     fn gp0_shaded_triangle(&mut self, renderer: &mut Renderer) {
         let vertices = [
-            Vertex::new(gp0_position(self.gp0_command[1]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[1]),
                         gp0_color(self.gp0_command[0])),
-            Vertex::new(gp0_position(self.gp0_command[3]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[3]),
                         gp0_color(self.gp0_command[2])),
-            Vertex::new(gp0_position(self.gp0_command[5]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[5]),
                         gp0_color(self.gp0_command[4])),
             ];
 
@@ -889,13 +1204,13 @@ impl Gpu {
     /// Draw an untextured shaded quad
     fn gp0_shaded_quad(&mut self, renderer: &mut Renderer) {
         let vertices = [
-            Vertex::new(gp0_position(self.gp0_command[1]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[1]),
                         gp0_color(self.gp0_command[0])),
-            Vertex::new(gp0_position(self.gp0_command[3]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[3]),
                         gp0_color(self.gp0_command[2])),
-            Vertex::new(gp0_position(self.gp0_command[5]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[5]),
                         gp0_color(self.gp0_command[4])),
-            Vertex::new(gp0_position(self.gp0_command[7]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[7]),
                         gp0_color(self.gp0_command[6])),
             ];
 
@@ -906,9 +1221,9 @@ impl Gpu {
     /// Draw a shaded line
     fn gp0_shaded_line(&mut self, renderer: &mut Renderer) {
         let vertices = [
-            Vertex::new(gp0_position(self.gp0_command[1]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[1]),
                         gp0_color(self.gp0_command[0])),
-            Vertex::new(gp0_position(self.gp0_command[3]),
+            Vertex::new(gp0_vertex_position(self.gp0_command[3]),
                         gp0_color(self.gp0_command[2])),
             ];
 
@@ -925,10 +1240,10 @@ impl Gpu {
         // onwards)
 
         let start_color = gp0_color(self.gp0_command[0]);
-        let start_pos = gp0_position(self.gp0_command[1]);
+        let start_pos = gp0_vertex_position(self.gp0_command[1]);
 
         let end_color = gp0_color(self.gp0_command[2]);
-        let end_pos = gp0_position(self.gp0_command[3]);
+        let end_pos = gp0_vertex_position(self.gp0_command[3]);
 
         let vertices = [
             Vertex::new(start_pos, start_color),
@@ -942,7 +1257,7 @@ impl Gpu {
         // the next vertex
         self.polyline_prev = (end_pos, end_color);
 
-        self.gp0_handler = Gpu::gp0_handle_shaded_polyline_color;
+        self.gp0_state = Gp0State::ShadedPolylineColor;
     }
 
     /// Draw a textured shaded triangle
@@ -952,13 +1267,13 @@ impl Gpu {
         self.gp0_attributes.set_draw_params(self.gp0_command[5] >> 16);
 
         let vertices = [
-            Vertex::new_textured(gp0_position(self.gp0_command[1]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[1]),
                                  gp0_color(self.gp0_command[0]),
                                  gp0_texture_coordinates(self.gp0_command[2])),
-            Vertex::new_textured(gp0_position(self.gp0_command[4]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[4]),
                                  gp0_color(self.gp0_command[3]),
                                  gp0_texture_coordinates(self.gp0_command[5])),
-            Vertex::new_textured(gp0_position(self.gp0_command[7]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[7]),
                                  gp0_color(self.gp0_command[6]),
                                  gp0_texture_coordinates(self.gp0_command[8])),
             ];
@@ -974,16 +1289,16 @@ impl Gpu {
         self.gp0_attributes.set_draw_params(self.gp0_command[5] >> 16);
 
         let vertices = [
-            Vertex::new_textured(gp0_position(self.gp0_command[1]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[1]),
                                  gp0_color(self.gp0_command[0]),
                                  gp0_texture_coordinates(self.gp0_command[2])),
-            Vertex::new_textured(gp0_position(self.gp0_command[4]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[4]),
                                  gp0_color(self.gp0_command[3]),
                                  gp0_texture_coordinates(self.gp0_command[5])),
-            Vertex::new_textured(gp0_position(self.gp0_command[7]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[7]),
                                  gp0_color(self.gp0_command[6]),
                                  gp0_texture_coordinates(self.gp0_command[8])),
-            Vertex::new_textured(gp0_position(self.gp0_command[10]),
+            Vertex::new_textured(gp0_vertex_position(self.gp0_command[10]),
                                  gp0_color(self.gp0_command[9]),
                                  gp0_texture_coordinates(self.gp0_command[11])),
             ];
@@ -998,7 +1313,7 @@ impl Gpu {
                       width: i16,
                       height: i16) {
 
-        let top_left = gp0_position(self.gp0_command[1]);
+        let top_left = gp0_vertex_position(self.gp0_command[1]);
         let color = gp0_color(self.gp0_command[0]);
 
         let vertices = [
@@ -1022,28 +1337,35 @@ impl Gpu {
 
         self.gp0_attributes.set_clut(self.gp0_command[2] >> 16);
 
-        let top_left = gp0_position(self.gp0_command[1]);
+        let top_left = gp0_vertex_position(self.gp0_command[1]);
 
         let tex_top_left = gp0_texture_coordinates(self.gp0_command[2]);
 
+        // The draw mode can flip the texture horizontally (bit 12)
+        // and vertically (bit 13)
+        let flip_x = (self.draw_mode >> 12) & 1 != 0;
+        let flip_y = (self.draw_mode >> 13) & 1 != 0;
+
+        let (u_left, u_right) =
+            rect_texture_span(tex_top_left[0], width, flip_x);
+        let (v_top, v_bottom) =
+            rect_texture_span(tex_top_left[1], height, flip_y);
+
         let color = gp0_color(self.gp0_command[0]);
 
         let vertices = [
             Vertex::new_textured(top_left,
                                  color,
-                                 tex_top_left),
+                                 [u_left, v_top]),
             Vertex::new_textured([top_left[0] + width, top_left[1]],
                                  color,
-                                 [tex_top_left[0] + width as u16,
-                                  tex_top_left[1]]),
+                                 [u_right, v_top]),
             Vertex::new_textured([top_left[0], top_left[1] + height],
                                  color,
-                                 [tex_top_left[0],
-                                  tex_top_left[1] + height as u16]),
+                                 [u_left, v_bottom]),
             Vertex::new_textured([top_left[0] + width, top_left[1] + height],
                                  color,
-                                 [tex_top_left[0] + width as u16,
-                                  tex_top_left[1] + height as u16]),
+                                 [u_right, v_bottom]),
         ];
 
         renderer.push_quad(self.gp0_attributes.primitive_attributes(),
@@ -1052,14 +1374,14 @@ impl Gpu {
 
     /// Draw a textured rectangle
     fn gp0_textured_rect(&mut self, renderer: &mut Renderer) {
-        let size = gp0_position(self.gp0_command[3]);
+        let size = gp0_rect_size(self.gp0_command[3]);
 
         self.gp0_rect_sized_textured(renderer, size[0], size[1]);
     }
 
     /// Draw a monochrome rectangle
     fn gp0_monochrome_rect(&mut self, renderer: &mut Renderer) {
-        let size = gp0_position(self.gp0_command[2]);
+        let size = gp0_rect_size(self.gp0_command[2]);
 
         self.gp0_rect_sized(renderer, size[0], size[1]);
     }
@@ -1088,17 +1410,19 @@ impl Gpu {
     /// GP0(0xA0): Image Load
     fn gp0_image_load(&mut self, _: &mut Renderer) {
         // Parameter 1 contains the location of the target location's
-        // top-left corner in VRAM
+        // top-left corner in VRAM. The coordinates wrap around the
+        // VRAM.
         let pos = self.gp0_command[1];
 
-        let x = pos as u16;
-        let y = (pos >> 16) as u16;
+        let x = (pos & 0x3ff) as u16;
+        let y = ((pos >> 16) & 0x1ff) as u16;
 
-        // Parameter 2 contains the image resolution
+        // Parameter 2 contains the image resolution. A dimension of 0
+        // is treated as the maximum size (1024 or 512).
         let res = self.gp0_command[2];
 
-        let width  = res & 0xffff;
-        let height = res >> 16;
+        let width  = (((res & 0xffff).wrapping_sub(1)) & 0x3ff) + 1;
+        let height = (((res >> 16).wrapping_sub(1)) & 0x1ff) + 1;
 
         // Size of the image in 16bit pixels
         let imgsize = width * height;
@@ -1111,15 +1435,10 @@ impl Gpu {
         // Store number of 32bit words expected for this image
         self.gp0_words_remaining = imgsize / 2;
 
-        if self.gp0_words_remaining > 0 {
-            self.load_buffer.reset(x, y, width as u16, height as u16);
-
-            // Use a custom GP0 handler to handle the GP0 image load
-            self.gp0_handler = Gpu::gp0_handle_image_load;
-        } else {
-            warn!("GPU: 0-sized image load");
-        }
+        self.load_buffer.reset(x, y, width as u16, height as u16);
 
+        // Use a custom GP0 handler to handle the GP0 image load
+        self.gp0_state = Gp0State::ImageLoad;
     }
 
     /// GP0 handler method: handle image load
@@ -1134,24 +1453,42 @@ impl Gpu {
                                 self.load_buffer.buffer());
 
             // We're done, wait for the next command
-            self.gp0_handler = Gpu::gp0_handle_command;
+            self.gp0_state = Gp0State::Command;
         }
     }
 
     /// GP0(0xC0): Image Store
-    fn gp0_image_store(&mut self, _: &mut Renderer) {
-        // Parameter 2 contains the image resolution
+    fn gp0_image_store(&mut self, renderer: &mut dyn Renderer) {
+        // Same parameters as the image load command
+        let pos = self.gp0_command[1];
+
+        let x = (pos & 0x3ff) as u16;
+        let y = ((pos >> 16) & 0x1ff) as u16;
+
         let res = self.gp0_command[2];
 
-        let width  = res & 0xffff;
-        let height = res >> 16;
+        let width  = (((res & 0xffff).wrapping_sub(1)) & 0x3ff) + 1;
+        let height = (((res >> 16).wrapping_sub(1)) & 0x1ff) + 1;
+
+        let imgsize = width * height;
+
+        self.store_buffer.reset(x, y, width as u16, height as u16);
 
-        warn!("Unhandled image store: {}x{}", width, height);
+        renderer.store_image(self.store_buffer.top_left(),
+                             self.store_buffer.resolution(),
+                             self.store_buffer.buffer_mut());
+
+        // The pixels are then read two at a time through GPUREAD, if
+        // there's an odd number of pixels the last word is padded
+        self.image_store_words_remaining = (imgsize + 1) / 2;
     }
 
     /// GP0(0xE1): Draw Mode
     fn gp0_draw_mode(&mut self, _: &mut Renderer) {
-        self.draw_mode = self.gp0_command[0] as u16;
+        let val = self.gp0_command[0];
+
+        self.draw_mode = val as u16;
+        self.draw_to_display = (val >> 10) & 1 != 0;
     }
 
     /// GP0(0xE2): Set Texture Window
@@ -1185,7 +1522,7 @@ impl Gpu {
     }
 
     // Called when the drawing area changes to notify the renderer
-    fn update_draw_area(&mut self, renderer: &mut Renderer) {
+    fn update_draw_area(&self, renderer: &mut dyn Renderer) {
         renderer.set_draw_area((self.drawing_area_left,
                                 self.drawing_area_top),
                                (self.drawing_area_right,
@@ -1209,21 +1546,42 @@ impl Gpu {
     }
 
     /// GP0(0xE6): Set Mask Bit Setting
-    fn gp0_mask_bit_setting(&mut self, _: &mut Renderer) {
+    fn gp0_mask_bit_setting(&mut self, renderer: &mut dyn Renderer) {
         let val = self.gp0_command[0];
 
         self.force_set_mask_bit = (val & 1) != 0;
         self.preserve_masked_pixels = (val & 2) != 0;
-    }
 
-    /// Handle writes to the GP1 command register
+        renderer.set_mask_settings(self.force_set_mask_bit,
+                                   self.preserve_masked_pixels);
+    }
+
+    /// Handle writes to the GP1 command register. Some commands
+    /// change the video timings which is why we need the
+    /// `SharedState` and `Timers`, when using the GPU standalone
+    /// fresh instances can be used:
+    ///
+    /// ```
+    /// use rustation::gpu::{Gpu, VideoClock};
+    /// use rustation::gpu::renderer::NullRenderer;
+    /// use rustation::memory::timers::Timers;
+    /// use rustation::shared::SharedState;
+    ///
+    /// let mut gpu = Gpu::new(VideoClock::Ntsc);
+    /// let mut shared = SharedState::new();
+    /// let mut timers = Timers::new();
+    ///
+    /// // GP1(0x00): reset
+    /// gpu.gp1(&mut shared, &mut NullRenderer, 0x00000000, &mut timers);
+    /// ```
     pub fn gp1(&mut self,
                shared: &mut SharedState,
                renderer: &mut Renderer,
                val: u32,
                timers: &mut Timers) {
 
-        let opcode = (val >> 24) & 0xff;
+        // The two MSBs are ignored
+        let opcode = (val >> 24) & 0x3f;
 
         match opcode {
             0x00 => {
@@ -1233,12 +1591,19 @@ impl Gpu {
                 self.update_display_mode(renderer);
                 self.update_draw_area(renderer);
                 renderer.set_draw_offset(0, 0);
+                renderer.set_mask_settings(false, false);
+                // The reset doesn't touch the VRAM but it does
+                // invalidate the texture cache
+                renderer.clear_texture_cache();
             },
             0x01 => self.gp1_reset_command_buffer(),
             0x02 => self.gp1_acknowledge_irq(),
             0x03 => self.gp1_display_enable(val),
             0x04 => self.gp1_dma_direction(val),
-            0x05 => self.gp1_display_vram_start(val),
+            0x05 => {
+                self.gp1_display_vram_start(val);
+                self.update_display_mode(renderer);
+            }
             0x06 => self.gp1_display_horizontal_range(val),
             0x07 => self.gp1_display_vertical_range(shared,val),
             0x08 => {
@@ -1246,20 +1611,34 @@ impl Gpu {
                 timers.video_timings_changed(shared, self);
                 self.update_display_mode(renderer);
             }
-            0x10 => self.gp1_get_info(val),
-            _    => panic!("Unhandled GP1 command {:08x}", val),
+            0x09 => self.gp1_texture_disable(val),
+            // 0x11 to 0x1f are mirrors of 0x10
+            0x10...0x1f => self.gp1_get_info(val),
+            _ => {
+                if self.strict {
+                    panic!("Unhandled GP1 command {:08x}", val);
+                }
+
+                warn!("Ignoring unhandled GP1 command {:08x}", val);
+            }
         }
     }
 
     fn update_display_mode(&self, renderer: &mut Renderer) {
         let top_left = (self.display_vram_x_start, self.display_vram_y_start);
-        let resolution = (self.hres.width(), self.vres.height());
+        let resolution = self.display_resolution();
 
         let depth_24bpp = self.display_depth == DisplayDepth::D24Bits;
 
         renderer.set_display_mode(top_left, resolution, depth_24bpp);
     }
 
+    /// Return the nominal resolution of the video output for the
+    /// current mode
+    fn display_resolution(&self) -> (u16, u16) {
+        (self.hres.width(), self.vres.height(self.vmode))
+    }
+
     /// GP1(0x00): Soft Reset
     fn gp1_reset(&mut self,
                  shared: &mut SharedState) {
@@ -1289,8 +1668,10 @@ impl Gpu {
         self.vres = VerticalRes::Y240Lines;
         self.field = Field::Top;
 
+        // GPUSTAT reads 0x14802000 after a reset: progressive NTSC
+        // 256x240 output regardless of the console's region
         self.vmode = VMode::Ntsc;
-        self.interlaced = true;
+        self.interlaced = false;
         self.display_horiz_start = 0x200;
         self.display_horiz_end = 0xc00;
         self.display_line_start = 0x10;
@@ -1303,17 +1684,24 @@ impl Gpu {
         self.gp1_reset_command_buffer();
         self.gp1_acknowledge_irq();
 
-        self.sync(shared);
+        // Abort the current drawing
+        self.gp0_busy_cycles = 0;
+
+        // Abort any pending VRAM read
+        self.image_store_words_remaining = 0;
+        self.read_word = 0;
 
-        // XXX should also invalidate GPU cache if we ever implement it
+        self.sync(shared);
     }
 
-    /// GP1(0x01): Reset Command Buffer
+    /// GP1(0x01): Reset Command Buffer. Any partially received
+    /// command (including image loads and polylines) is dropped and
+    /// the next GP0 word is decoded as a new command.
     fn gp1_reset_command_buffer(&mut self) {
         self.gp0_command.clear();
         self.gp0_words_remaining = 0;
-        self.gp0_handler = Gpu::gp0_handle_command;
-        // XXX should also clear the command FIFO when we implement it
+        self.gp0_state = Gp0State::Command;
+        self.fifo.clear();
     }
 
     /// GP1(0x02): Acknowledge Interrupt
@@ -1339,6 +1727,8 @@ impl Gpu {
     }
 
     /// GP1(0x05): Display VRAM Start
+    ///
+    /// The horizontal start is always even, bit 0 is ignored.
     fn gp1_display_vram_start(&mut self, val: u32) {
         self.display_vram_x_start = (val & 0x3fe) as u16;
         self.display_vram_y_start = ((val >> 10) & 0x1ff) as u16;
@@ -1361,12 +1751,28 @@ impl Gpu {
         self.sync(shared);
     }
 
+    /// GP1(0x09): Allow texture disable
+    fn gp1_texture_disable(&mut self, val: u32) {
+        self.texture_disable = val & 1 != 0;
+    }
+
     /// Return various GPU state information in the GPUREAD register
     fn gp1_get_info(&mut self, val: u32) {
         // XXX what happens if we're in the middle of a framebuffer
         // read?
         let v =
             match val & 0xf {
+                2 => {
+                    let mask_x = self.texture_window_x_mask as u32;
+                    let mask_y = self.texture_window_y_mask as u32;
+                    let offset_x = self.texture_window_x_offset as u32;
+                    let offset_y = self.texture_window_y_offset as u32;
+
+                    mask_x |
+                    (mask_y << 5) |
+                    (offset_x << 10) |
+                    (offset_y << 15)
+                }
                 3 => {
                     let top = self.drawing_area_top as u32;
                     let left = self.drawing_area_left as u32;
@@ -1389,7 +1795,9 @@ impl Gpu {
                 }
                 // GPU version. Seems to always be 2?
                 7 => 2,
-                _ => panic!("Unsupported GP1 info command {:08x}", val),
+                8 => 0,
+                // The other values don't touch the GPUREAD register
+                _ => return,
             };
 
         self.read_word = v;
@@ -1427,16 +1835,210 @@ impl Gpu {
         self.field = Field::Top;
 
         if val & 0x80 != 0 {
-            panic!("Unsupported display mode {:08x}", val);
+            if self.strict {
+                panic!("Unsupported display mode {:08x}", val);
+            }
+
+            warn!("Unsupported display mode {:08x}", val);
         }
 
         self.sync(shared);
     }
 }
 
+/// Description of a GP0 command
+#[derive(Clone, Copy)]
+struct Gp0Command {
+    /// Length of the command in words, including the opcode
+    len: u32,
+    /// Method called when all the words have been received
+    callback: fn(&mut Gpu, &mut dyn Renderer),
+    /// True if dithering applies to the primitive
+    dither: bool,
+}
+
+macro_rules! gp0 {
+    ($len:expr, $callback:ident, $dither:expr) => (
+        Some(Gp0Command {
+            len: $len,
+            callback: Gpu::$callback,
+            dither: $dither,
+        })
+    )
+}
+
+/// Number of GPU clock cycles the GPU stays busy after a drawing
+/// command. This is a very rough estimate, the real duration depends
+/// on the size of the primitive and the drawing mode.
+const DRAW_BUSY_CYCLES: u32 = 64;
+
+/// Command used in place of unsupported GP0 opcodes
+const GP0_NOP: Gp0Command = Gp0Command {
+    len: 1,
+    callback: Gpu::gp0_nop,
+    dither: false,
+};
+
+/// GP0 commands indexed by opcode, None if the opcode is not
+/// supported
+static GP0_COMMANDS: [Option<Gp0Command>; 0x100] = [
+    gp0!(1,  gp0_nop, false), // 0x00
+    gp0!(1,  gp0_clear_cache, false), // 0x01
+    gp0!(3,  gp0_fill_rect, false), // 0x02
+    // 0x03 - 0x1f
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None,
+    gp0!(4,  gp0_monochrome_triangle, false), // 0x20
+    None, // 0x21
+    gp0!(4,  gp0_monochrome_triangle, false), // 0x22
+    None, // 0x23
+    gp0!(7,  gp0_textured_triangle, true), // 0x24
+    gp0!(7,  gp0_textured_triangle, true), // 0x25
+    gp0!(7,  gp0_textured_triangle, true), // 0x26
+    gp0!(7,  gp0_textured_triangle, true), // 0x27
+    gp0!(5,  gp0_monochrome_quad, false), // 0x28
+    None, // 0x29
+    gp0!(5,  gp0_monochrome_quad, false), // 0x2a
+    None, // 0x2b
+    gp0!(9,  gp0_textured_quad, true), // 0x2c
+    gp0!(9,  gp0_textured_quad, true), // 0x2d
+    gp0!(9,  gp0_textured_quad, true), // 0x2e
+    gp0!(9,  gp0_textured_quad, true), // 0x2f
+    gp0!(6,  gp0_shaded_triangle, true), // 0x30
+    None, // 0x31
+    gp0!(6,  gp0_shaded_triangle, true), // 0x32
+    None, // 0x33
+    gp0!(9,  gp0_textured_shaded_triangle, true), // 0x34
+    None, // 0x35
+    gp0!(9,  gp0_textured_shaded_triangle, true), // 0x36
+    None, // 0x37
+    gp0!(8,  gp0_shaded_quad, true), // 0x38
+    None, // 0x39
+    gp0!(8,  gp0_shaded_quad, true), // 0x3a
+    None, // 0x3b
+    gp0!(12, gp0_textured_shaded_quad, true), // 0x3c
+    None, // 0x3d
+    gp0!(12, gp0_textured_shaded_quad, true), // 0x3e
+    None, // 0x3f
+    gp0!(3,  gp0_monochrome_line, false), // 0x40
+    None, // 0x41
+    gp0!(3,  gp0_monochrome_line, false), // 0x42
+    // 0x43 - 0x47
+    None, None, None, None, None,
+    gp0!(3,  gp0_monochrome_polyline, false), // 0x48
+    None, // 0x49
+    gp0!(3,  gp0_monochrome_polyline, false), // 0x4a
+    // 0x4b - 0x4f
+    None, None, None, None, None,
+    gp0!(4,  gp0_shaded_line, false), // 0x50
+    None, // 0x51
+    gp0!(4,  gp0_shaded_line, false), // 0x52
+    // 0x53 - 0x57
+    None, None, None, None, None,
+    gp0!(4,  gp0_shaded_polyline, false), // 0x58
+    None, // 0x59
+    gp0!(4,  gp0_shaded_polyline, false), // 0x5a
+    // 0x5b - 0x5f
+    None, None, None, None, None,
+    gp0!(3,  gp0_monochrome_rect, false), // 0x60
+    None, // 0x61
+    gp0!(3,  gp0_monochrome_rect, false), // 0x62
+    None, // 0x63
+    gp0!(4,  gp0_textured_rect, false), // 0x64
+    gp0!(4,  gp0_textured_rect, false), // 0x65
+    gp0!(4,  gp0_textured_rect, false), // 0x66
+    gp0!(4,  gp0_textured_rect, false), // 0x67
+    gp0!(2,  gp0_monochrome_rect_1x1, false), // 0x68
+    None, // 0x69
+    gp0!(2,  gp0_monochrome_rect_1x1, false), // 0x6a
+    // 0x6b - 0x73
+    None, None, None, None, None, None, None, None,
+    None,
+    gp0!(3,  gp0_textured_rect_8x8, false), // 0x74
+    gp0!(3,  gp0_textured_rect_8x8, false), // 0x75
+    gp0!(3,  gp0_textured_rect_8x8, false), // 0x76
+    gp0!(3,  gp0_textured_rect_8x8, false), // 0x77
+    gp0!(2,  gp0_monochrome_rect_16x16, false), // 0x78
+    None, // 0x79
+    gp0!(2,  gp0_monochrome_rect_16x16, false), // 0x7a
+    None, // 0x7b
+    gp0!(3,  gp0_textured_rect_16x16, false), // 0x7c
+    gp0!(3,  gp0_textured_rect_16x16, false), // 0x7d
+    gp0!(3,  gp0_textured_rect_16x16, false), // 0x7e
+    gp0!(3,  gp0_textured_rect_16x16, false), // 0x7f
+    gp0!(4,  gp0_copy_rect, false), // 0x80
+    // 0x81 - 0x9f
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None,
+    gp0!(3,  gp0_image_load, false), // 0xa0
+    // 0xa1 - 0xbf
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None,
+    gp0!(3,  gp0_image_store, false), // 0xc0
+    // 0xc1 - 0xe0
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    gp0!(1,  gp0_draw_mode, false), // 0xe1
+    gp0!(1,  gp0_texture_window, false), // 0xe2
+    gp0!(1,  gp0_drawing_area_top_left, false), // 0xe3
+    gp0!(1,  gp0_drawing_area_bottom_right, false), // 0xe4
+    gp0!(1,  gp0_drawing_offset, false), // 0xe5
+    gp0!(1,  gp0_mask_bit_setting, false), // 0xe6
+    // 0xe7 - 0xff
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None, None, None, None, None, None, None, None,
+    None,
+    ];
+
+/// Return the description of the GP0 `opcode`, or None if it's not
+/// supported
+fn gp0_opcode(opcode: u8) -> Option<Gp0Command> {
+    GP0_COMMANDS[opcode as usize]
+}
+
+/// Return true if the GP0 command `opcode` draws to the VRAM and
+/// keeps the GPU busy for a while
+fn is_drawing_command(opcode: u8) -> bool {
+    if gp0_opcode(opcode).is_none() {
+        return false;
+    }
+
+    match opcode {
+        0x02 | 0x20...0x7f | 0x80...0x9f => true,
+        _ => false,
+    }
+}
+
+/// Return the length in words (including the opcode) of the GP0
+/// command `opcode`, or None if it's not supported
+pub fn gp0_command_len(opcode: u8) -> Option<u32> {
+    gp0_opcode(opcode).map(|c| c.len)
+}
+
+/// Area of the VRAM sent to the video output
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DisplayRect {
+    /// Coordinates of the top-left corner in VRAM
+    pub top_left: (u16, u16),
+    /// Width and height in pixels
+    pub dimensions: (u16, u16),
+    /// True if the display is in 24bpp mode, in this case each pixel
+    /// takes 1.5 VRAM halfwords horizontally
+    pub depth_24bpp: bool,
+}
+
 /// Interlaced output splits each frame in two fields
-#[derive(Clone,Copy)]
-enum Field {
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum Field {
     /// Top field (odd lines).
     Top = 1,
     /// Bottom field (even lines)
@@ -1445,7 +2047,7 @@ enum Field {
 
 /// Video output horizontal resolution
 #[derive(Clone,Copy)]
-struct HorizontalRes(u8);
+pub struct HorizontalRes(u8);
 
 impl HorizontalRes {
     /// Create a new HorizontalRes instance from the 2 bit field `hr1`
@@ -1465,7 +2067,7 @@ impl HorizontalRes {
 
     /// Return the divider used to generate the dotclock from the GPU
     /// clock.
-    fn dotclock_divider(self) -> u8 {
+    pub fn dotclock_divider(self) -> u8 {
         let hr1 = (self.0 >> 1) & 0x3;
         let hr2 = self.0 & 1 != 0;
 
@@ -1500,7 +2102,7 @@ impl HorizontalRes {
     /// Return the *approximate* width of the displayed portion of the
     /// framebuffer. The actual visible width depends on the TV
     /// screen.
-    fn width(self) -> u16 {
+    pub fn width(self) -> u16 {
         let hr1 = (self.0 >> 1) & 0x3;
         let hr2 = self.0 & 1 != 0;
 
@@ -1520,7 +2122,7 @@ impl HorizontalRes {
 }
 
 /// Video output vertical resolution
-#[derive(Clone,Copy)]
+#[derive(Clone,Copy,PartialEq,Eq)]
 enum VerticalRes {
     /// 240 lines
     Y240Lines = 0,
@@ -1529,17 +2131,20 @@ enum VerticalRes {
 }
 
 impl VerticalRes {
-    fn height(self) -> u16 {
-        match self {
-            VerticalRes::Y240Lines => 240,
-            VerticalRes::Y480Lines => 480,
+    /// PAL has more visible lines than NTSC
+    fn height(self, vmode: VMode) -> u16 {
+        match (self, vmode) {
+            (VerticalRes::Y240Lines, VMode::Ntsc) => 240,
+            (VerticalRes::Y480Lines, VMode::Ntsc) => 480,
+            (VerticalRes::Y240Lines, VMode::Pal) => 288,
+            (VerticalRes::Y480Lines, VMode::Pal) => 576,
         }
     }
 }
 
 /// Video Modes
-#[derive(Clone,Copy)]
-enum VMode {
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VMode {
     /// NTSC: 480i60H
     Ntsc = 0,
     /// PAL: 576i50Hz
@@ -1564,6 +2169,85 @@ enum DmaDirection {
     VRamToCpu = 3,
 }
 
+/// The GPU command FIFO, 16 words deep
+struct CommandFifo {
+    /// Buffer containing the words
+    buffer: [u32; 16],
+    /// Index of the oldest word in `buffer`
+    read: u8,
+    /// Number of words in the FIFO
+    len: u8,
+}
+
+impl CommandFifo {
+    fn new() -> CommandFifo {
+        CommandFifo {
+            buffer: [0; 16],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len as usize == self.buffer.len()
+    }
+
+    fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+
+    /// Push a new word in the FIFO. Panics if the FIFO is full.
+    fn push(&mut self, word: u32) {
+        if self.is_full() {
+            panic!("GPU FIFO overflow");
+        }
+
+        let write = (self.read + self.len) & 0xf;
+
+        self.buffer[write as usize] = word;
+        self.len += 1;
+    }
+
+    /// Pop the oldest word from the FIFO
+    fn pop(&mut self) -> Option<u32> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let word = self.buffer[self.read as usize];
+
+        self.read = (self.read + 1) & 0xf;
+        self.len -= 1;
+
+        Some(word)
+    }
+}
+
+/// States of the GP0 parser. The values are used in the save states
+/// and mustn't change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Gp0State {
+    /// Waiting for a new command
+    Command = 0,
+    /// Receiving the parameters of a fixed-length command
+    Parameter = 1,
+    /// Receiving the pixels of an image load
+    ImageLoad = 2,
+    /// Waiting for the color of the next shaded polyline vertex (or
+    /// the end marker)
+    ShadedPolylineColor = 3,
+    /// Waiting for the position of the next shaded polyline vertex
+    ShadedPolylineVertex = 4,
+    /// Waiting for the next monochrome polyline vertex (or the end
+    /// marker)
+    MonochromePolylineVertex = 5,
+}
+
 /// Buffer holding multi-word fixed-length GP0 command parameters
 struct CommandBuffer {
     /// Command buffer: the longuest possible command is GP0(0x3E)
@@ -1586,10 +2270,18 @@ impl CommandBuffer {
         self.len = 0;
     }
 
-    fn push_word(&mut self, word: u32) {
+    /// Append `word` to the buffer. Returns false if the buffer is
+    /// already full, in which case `word` is discarded.
+    fn push_word(&mut self, word: u32) -> bool {
+        if self.len as usize >= self.buffer.len() {
+            return false;
+        }
+
         self.buffer[self.len as usize] = word;
 
         self.len += 1;
+
+        true
     }
 }
 
@@ -1623,7 +2315,8 @@ impl Gp0Attributes {
     fn new(callback: fn(&mut Gpu, &mut Renderer),
            semi_transparent: bool,
            blend_mode: BlendMode,
-           dither: bool) -> Gp0Attributes {
+           dither: bool,
+           (set_mask_bit, check_mask_bit): (bool, bool)) -> Gp0Attributes {
 
         Gp0Attributes {
             callback: callback,
@@ -1631,10 +2324,18 @@ impl Gp0Attributes {
                 semi_transparent: semi_transparent,
                 semi_transparency_mode: SemiTransparencyMode::Average,
                 blend_mode: blend_mode,
+                shading: Shading::Flat,
                 texture_page: [0; 2],
                 texture_depth: TextureDepth::T4Bpp,
                 clut: [0, 0],
+                texture_window_mask: [0; 2],
+                texture_window_offset: [0; 2],
                 dither: dither,
+                set_mask_bit,
+                check_mask_bit,
+                displayed_field: None,
+                opcode: 0,
+                sequence: 0,
             }
         }
     }
@@ -1651,7 +2352,21 @@ impl Gp0Attributes {
         self.primitive_attributes.clut = [x as u16, y as u16];
     }
 
+    /// Load the semi-transparency mode from a "Draw Mode" or texture
+    /// page word
+    fn set_semi_transparency_mode(&mut self, params: u32) {
+        self.primitive_attributes.semi_transparency_mode =
+            match (params >> 5) & 3 {
+                0 => SemiTransparencyMode::Average,
+                1 => SemiTransparencyMode::Add,
+                2 => SemiTransparencyMode::SubstractSource,
+                3 => SemiTransparencyMode::AddQuarterSource,
+                _ => unreachable!(),
+            };
+    }
+
     fn set_draw_params(&mut self, params: u32) {
+        self.set_semi_transparency_mode(params);
 
         // Texture page coordinates
         let x = (params & 0xf) << 6;
@@ -1662,15 +2377,6 @@ impl Gp0Attributes {
 
         attrs.texture_page = [x as u16, y as u16];
 
-        attrs.semi_transparency_mode =
-            match (params >> 5) & 3 {
-                0 => SemiTransparencyMode::Average,
-                1 => SemiTransparencyMode::Add,
-                2 => SemiTransparencyMode::SubstractSource,
-                3 => SemiTransparencyMode::AddQuarterSource,
-                _ => unreachable!(),
-            };
-
         attrs.texture_depth =
             match (params >> 7) & 3 {
                 0 => TextureDepth::T4Bpp,
@@ -1697,6 +2403,29 @@ fn gp0_position(pos: u32) -> [i16; 2] {
     [x, y]
 }
 
+/// Parse the dimensions of a variable-size rectangle as written in
+/// the GP0 register. The width is 10 bits and the height 9 bits, the
+/// high bits are ignored.
+fn gp0_rect_size(size: u32) -> [i16; 2] {
+    let width = size & 0x3ff;
+    let height = (size >> 16) & 0x1ff;
+
+    [width as i16, height as i16]
+}
+
+/// Parse a vertex position as written in the GP0 register. The
+/// coordinates are signed 11bit values.
+fn gp0_vertex_position(pos: u32) -> [i16; 2] {
+    let x = pos as u16;
+    let y = (pos >> 16) as u16;
+
+    // Shift the values to 16bits to force sign extension
+    let x = ((x << 5) as i16) >> 5;
+    let y = ((y << 5) as i16) >> 5;
+
+    [x, y]
+}
+
 /// Parse a color as written in the GP0 register and return it as
 /// an array of 3 `u8`
 fn gp0_color(col: u32) -> [u8; 3] {
@@ -1707,6 +2436,23 @@ fn gp0_color(col: u32) -> [u8; 3] {
     [r, g, b]
 }
 
+/// Return the texture coordinates at both ends of a rectangle edge
+/// `len` pixels long starting at texture coordinate `start`. When
+/// `flip` is set the coordinates decrease along the edge. They wrap
+/// around within the texture page so we move the start up by a
+/// multiple of 256 to keep them positive.
+fn rect_texture_span(start: u16, len: i16, flip: bool) -> (u16, u16) {
+    let len = len as u16;
+
+    if flip {
+        let start = start.wrapping_add(len.wrapping_add(0xff) & !0xff);
+
+        (start, start.wrapping_sub(len))
+    } else {
+        (start, start.wrapping_add(len))
+    }
+}
+
 /// Parse a texture coordinate coordinates written in the GP0
 /// register. Values are offset within the current texture page.
 fn gp0_texture_coordinates(gp0: u32) -> [u16; 2] {
@@ -1759,9 +2505,18 @@ impl ImageBuffer {
         // I don't use `index` because it can potentially point one
         // past the end of the buffer if the image has an odd number
         // of pixels.
-        let len = self.resolution.0 as usize * self.resolution.1 as usize;
+        &self.buffer[0..self.len()]
+    }
+
+    fn buffer_mut(&mut self) -> &mut [u16] {
+        let len = self.len();
 
-        &self.buffer[0..len]
+        &mut self.buffer[0..len]
+    }
+
+    /// Size of the image in pixels
+    fn len(&self) -> usize {
+        self.resolution.0 as usize * self.resolution.1 as usize
     }
 
     fn reset(&mut self, x: u16, y: u16, width: u16, height: u16) {
@@ -1780,9 +2535,45 @@ impl ImageBuffer {
         self.buffer[self.index as usize] = (word >> 16) as u16;
         self.index += 1;
     }
+
+    /// Read the next two pixels packed in a GPUREAD word, the first
+    /// pixel is in the low halfword. If the image has an odd number
+    /// of pixels the high halfword of the last word is 0.
+    fn pop_gp0_word(&mut self) -> u32 {
+        let len = self.len();
+        let index = self.index as usize;
+
+        let pixel = |i: usize| -> u32 {
+            if i < len {
+                self.buffer[i] as u32
+            } else {
+                0
+            }
+        };
+
+        let word = pixel(index) | (pixel(index + 1) << 16);
+
+        self.index += 2;
+
+        word
+    }
 }
 
 
+pub mod force_disable {
+    //! Drawing features that can be force-disabled for debugging
+    //! using `Gpu::set_force_disable`
+
+    /// Don't dither primitives
+    pub const DITHERING:         u32 = 1 << 0;
+    /// Draw semi-transparent primitives as opaque
+    pub const SEMI_TRANSPARENCY: u32 = 1 << 1;
+    /// Don't apply textures, only use the primitive's color
+    pub const TEXTURING:         u32 = 1 << 2;
+    /// Ignore the mask bit settings
+    pub const MASK_BIT:          u32 = 1 << 3;
+}
+
 // Width of the VRAM in 16bit pixels
 pub const VRAM_WIDTH_PIXELS: u16 = 1024;
 // Height of the VRAM in lines
@@ -1790,7 +2581,7 @@ pub const VRAM_HEIGHT: u16 = 512;
 
 /// The are a few hardware differences between PAL and NTSC consoles,
 /// in particular the pixelclock runs slightly slower on PAL consoles.
-#[derive(Clone,Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum VideoClock {
     Ntsc,
     Pal,
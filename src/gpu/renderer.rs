@@ -1,6 +1,14 @@
+use super::Field;
+
 pub trait Renderer {
     fn set_draw_offset(&mut self, x: i16, y: i16);
-    fn set_draw_area(&mut self, top_left: (u16, u16), dimensions: (u16, u16));
+    /// Set the drawing area clipping rectangle, both corners are
+    /// inclusive. If `top_left` is below or to the right of
+    /// `bottom_right` the drawing area is empty and primitives
+    /// mustn't draw anything.
+    fn set_draw_area(&mut self,
+                     top_left: (u16, u16),
+                     bottom_right: (u16, u16));
 
     fn set_display_mode(&mut self,
                         top_left: (u16, u16),
@@ -9,6 +17,9 @@ pub trait Renderer {
 
     fn push_line(&mut self, &PrimitiveAttributes, &[Vertex; 2]);
     fn push_triangle(&mut self, &PrimitiveAttributes, &[Vertex; 3]);
+    /// Draw a quadrilateral. The GPU draws it as two triangles
+    /// `[v0, v1, v2]` and `[v1, v2, v3]` and pixels on the shared
+    /// v1-v2 edge must only be drawn once.
     fn push_quad(&mut self, &PrimitiveAttributes, &[Vertex; 4]);
 
     fn fill_rect(&mut self,
@@ -20,6 +31,81 @@ pub trait Renderer {
                   top_left: (u16, u16),
                   dimensions: (u16, u16),
                   pixel_buffer: &[u16]);
+
+    /// Read back the VRAM rectangle at `top_left` into
+    /// `pixel_buffer`. Like `load_image` the rectangle wraps around
+    /// the edges of the VRAM.
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]);
+
+    /// Copy the VRAM rectangle at `src` to `dst`. Both rectangles
+    /// wrap around the edges of the VRAM and the mask settings apply
+    /// like for `load_image`. The GPU copies the rectangle one line
+    /// at a time, the default implementation does the same using
+    /// `store_image` and `load_image`.
+    fn copy_rect(&mut self,
+                 src: (u16, u16),
+                 dst: (u16, u16),
+                 dimensions: (u16, u16)) {
+        let (width, height) = dimensions;
+
+        let mut line = vec![0; width as usize];
+
+        for y in 0..height {
+            self.store_image((src.0, src.1 + y), (width, 1), &mut line);
+            self.load_image((dst.0, dst.1 + y), (width, 1), &line);
+        }
+    }
+
+    /// Called when the GPU's texture cache is flushed (GP0(0x01)).
+    /// Games do that after they've modified textures in VRAM so
+    /// renderers caching texture data must invalidate it. Does
+    /// nothing by default.
+    fn clear_texture_cache(&mut self) {
+    }
+
+    /// Called when the mask bit settings (GP0(0xE6)) change. They
+    /// also apply to image loads, primitives get them through their
+    /// `PrimitiveAttributes`. Does nothing by default.
+    fn set_mask_settings(&mut self,
+                         _set_mask_bit: bool,
+                         _check_mask_bit: bool) {
+    }
+}
+
+/// Renderer that discards everything. Useful to run the emulator
+/// without any video output (for benchmarking or testing).
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self, _: (u16, u16), _: (u16, u16), _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+    }
+
+    fn fill_rect(&mut self, _: [u8; 3], _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self, _: (u16, u16), _: (u16, u16), _: &[u16]) {
+    }
+
+    fn store_image(&mut self, _: (u16, u16), _: (u16, u16), _: &mut [u16]) {
+    }
 }
 
 pub struct Vertex {
@@ -61,6 +147,10 @@ pub struct PrimitiveAttributes {
     /// gouraud shading with the same color on all vertices),
     /// texture-mapped or a mix of both (texture blending).
     pub blend_mode: BlendMode,
+    /// Says if the GP0 command specified a color per vertex or a
+    /// single color for the whole primitive. Renderers can ignore it
+    /// since all the vertices of flat primitives have the same color.
+    pub shading: Shading,
     /// For textured primitives this contains the coordinates of the
     /// top-left coordinates of the texture page. Texture pages are
     /// always 256x256 pixels big and wrap around in case of
@@ -73,8 +163,32 @@ pub struct PrimitiveAttributes {
     /// of the first entry of the palette. The next entries will be at
     /// x + 1, x + 2 etc...
     pub clut: [u16; 2],
+    /// Texture window mask in 8 pixel steps: for each bit set in the
+    /// mask the corresponding texture coordinate bit (multiplied by
+    /// 8) is replaced by the one in `texture_window_offset`. Used to
+    /// repeat a small part of a texture page.
+    pub texture_window_mask: [u8; 2],
+    /// Texture window offset in 8 pixel steps
+    pub texture_window_offset: [u8; 2],
     /// True if the primitive is dithered.
     pub dither: bool,
+    /// If true the "mask" bit of every pixel drawn is set
+    pub set_mask_bit: bool,
+    /// If true the primitive is not drawn over pixels whose "mask"
+    /// bit is set
+    pub check_mask_bit: bool,
+    /// Field currently being displayed if drawing to the display area
+    /// is prohibited in 480 line interlaced mode. The primitive
+    /// mustn't draw over the lines of this field within the displayed
+    /// area of the VRAM. `None` if drawing is unrestricted.
+    pub displayed_field: Option<Field>,
+    /// Opcode of the GP0 command that generated this primitive. Only
+    /// meant for debugging.
+    pub opcode: u8,
+    /// Position of the GP0 command that generated this primitive in
+    /// the current frame (0 for the first command received after the
+    /// vertical blanking). Only meant for debugging.
+    pub sequence: u32,
 }
 
 /// Primitive texturing methods
@@ -88,6 +202,15 @@ pub enum BlendMode {
     Blended,
 }
 
+/// Primitive shading methods
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shading {
+    /// Single color for the whole primitive
+    Flat,
+    /// Color interpolated between the vertices
+    Gouraud,
+}
+
 /// Semi-transparency modes supported by the PlayStation GPU
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum SemiTransparencyMode {
@@ -102,7 +225,7 @@ pub enum SemiTransparencyMode {
 }
 
 /// Depth of the pixel values in a texture page
-#[derive(Clone,Copy)]
+#[derive(Clone,Copy,PartialEq,Eq)]
 pub enum TextureDepth {
     /// 4 bits per pixel, paletted
     T4Bpp = 0,
@@ -0,0 +1,937 @@
+//! Software implementation of the `Renderer` trait. Slow but it
+//! doesn't depend on anything outside of this crate which makes it
+//! useful for testing and for frontends without GPU acceleration.
+
+use super::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use super::renderer::{BlendMode, TextureDepth, SemiTransparencyMode};
+use super::{VRAM_WIDTH_PIXELS, VRAM_HEIGHT, Field};
+
+pub struct SoftwareRenderer {
+    /// Video RAM: 1024x512 16bit pixels
+    vram: Box<[u16; VRAM_SIZE]>,
+    /// Offset added to all vertex coordinates
+    draw_offset: (i16, i16),
+    /// Top-left corner of the drawing area (inclusive)
+    draw_area_top_left: (u16, u16),
+    /// Bottom-right corner of the drawing area (inclusive)
+    draw_area_bottom_right: (u16, u16),
+    /// Top-left corner of the displayed area in VRAM
+    display_top_left: (u16, u16),
+    /// Resolution of the displayed area
+    display_resolution: (u16, u16),
+    /// True if the display is in 24bpp mode
+    display_24bpp: bool,
+    /// If true image loads set the mask bit of every pixel written
+    set_mask_bit: bool,
+    /// If true image loads don't overwrite pixels whose mask bit is
+    /// set
+    check_mask_bit: bool,
+    /// Last exported frame
+    frame: Frame,
+    /// Optional callback called on every exported frame, can be used
+    /// to draw debug overlays on top of the game's output
+    overlay: Option<Box<dyn FnMut(&mut Frame)>>,
+}
+
+impl SoftwareRenderer {
+    pub fn new() -> SoftwareRenderer {
+        SoftwareRenderer {
+            vram: box_array![0; VRAM_SIZE],
+            draw_offset: (0, 0),
+            draw_area_top_left: (0, 0),
+            draw_area_bottom_right: (0, 0),
+            display_top_left: (0, 0),
+            display_resolution: (256, 240),
+            display_24bpp: false,
+            set_mask_bit: false,
+            check_mask_bit: false,
+            frame: Frame::new(0, 0),
+            overlay: None,
+        }
+    }
+
+    /// Install (or remove if `None`) the overlay callback. It will be
+    /// called every time a frame is exported, after the display area
+    /// has been copied from the VRAM. Disabled by default.
+    pub fn set_overlay(&mut self, overlay: Option<Box<dyn FnMut(&mut Frame)>>) {
+        self.overlay = overlay;
+    }
+
+    /// Return the 16bit pixel value at VRAM coordinates `(x, y)`
+    pub fn vram_pixel(&self, x: u16, y: u16) -> u16 {
+        self.vram[vram_index(x, y)]
+    }
+
+    /// Return a copy of the whole VRAM, line by line starting from
+    /// the top-left pixel
+    pub fn dump_vram(&self) -> Vec<u16> {
+        self.vram.to_vec()
+    }
+
+    /// Rasterize a triangle. Pixels are sampled at their top-left
+    /// corner and the right and bottom edges are not drawn so that
+    /// adjacent triangles (and in particular the two halves of a
+    /// quad) don't overlap.
+    fn draw_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: [&Vertex; 3]) {
+        let (off_x, off_y) = self.draw_offset;
+
+        let pos = |v: &Vertex| -> (i32, i32) {
+            (v.position[0] as i32 + off_x as i32,
+             v.position[1] as i32 + off_y as i32)
+        };
+
+        let mut v = vertices;
+
+        let mut area = edge(pos(v[0]), pos(v[1]), pos(v[2]));
+
+        if area == 0 {
+            // Degenerate triangle
+            return;
+        }
+
+        if area < 0 {
+            // Make sure the vertices are always in the same order
+            v.swap(1, 2);
+            area = -area;
+        }
+
+        let p = [pos(v[0]), pos(v[1]), pos(v[2])];
+
+        // Bounding box clipped to the drawing area
+        let (left, top) = self.draw_area_top_left;
+        let (right, bottom) = self.draw_area_bottom_right;
+
+        if left > right || top > bottom {
+            // Inverted drawing area, nothing can be drawn
+            return;
+        }
+
+        let min_x = p.iter().map(|p| p.0).min().unwrap();
+        let max_x = p.iter().map(|p| p.0).max().unwrap();
+        let min_y = p.iter().map(|p| p.1).min().unwrap();
+        let max_y = p.iter().map(|p| p.1).max().unwrap();
+
+        let min_x = ::std::cmp::max(min_x, left as i32);
+        let max_x = ::std::cmp::min(max_x, right as i32);
+        let min_y = ::std::cmp::max(min_y, top as i32);
+        let max_y = ::std::cmp::min(max_y, bottom as i32);
+
+        // Pixels exactly on an edge are only drawn for top and left
+        // edges
+        let bias = [edge_bias(p[1], p[2]),
+                    edge_bias(p[2], p[0]),
+                    edge_bias(p[0], p[1])];
+
+        let textured = attributes.blend_mode != BlendMode::None;
+
+        for y in min_y..max_y + 1 {
+            for x in min_x..max_x + 1 {
+                let w = [edge(p[1], p[2], (x, y)),
+                         edge(p[2], p[0], (x, y)),
+                         edge(p[0], p[1], (x, y))];
+
+                if w.iter().zip(bias.iter()).any(|(&w, &b)| w + b < 0) {
+                    // Outside of the triangle
+                    continue;
+                }
+
+                // Interpolate a vertex attribute
+                let interpolate = |a: [i32; 3]| -> i32 {
+                    let sum = w[0] as i64 * a[0] as i64 +
+                              w[1] as i64 * a[1] as i64 +
+                              w[2] as i64 * a[2] as i64;
+
+                    (sum / area as i64) as i32
+                };
+
+                let mut color = [0; 3];
+
+                for c in 0..3 {
+                    let i = interpolate([v[0].color[c] as i32,
+                                         v[1].color[c] as i32,
+                                         v[2].color[c] as i32]);
+
+                    color[c] = i as u8;
+                }
+
+                let dither =
+                    match attributes.dither {
+                        true => {
+                            let row = DITHER_MATRIX[(y & 3) as usize];
+
+                            row[(x & 3) as usize]
+                        }
+                        false => 0,
+                    };
+
+                let pixel =
+                    if textured {
+                        let u = interpolate([v[0].texture_coord[0] as i32,
+                                             v[1].texture_coord[0] as i32,
+                                             v[2].texture_coord[0] as i32]);
+                        let t = interpolate([v[0].texture_coord[1] as i32,
+                                             v[1].texture_coord[1] as i32,
+                                             v[2].texture_coord[1] as i32]);
+
+                        let texel =
+                            match self.texel(attributes, u as u8, t as u8) {
+                                Some(texel) => texel,
+                                // Fully transparent texel
+                                None => continue,
+                            };
+
+                        match attributes.blend_mode {
+                            BlendMode::Blended =>
+                                texture_blend(texel, color, dither),
+                            _ => texel,
+                        }
+                    } else {
+                        rgb555_dither(color, dither)
+                    };
+
+                self.draw_pixel(attributes, x as u16, y as u16, pixel);
+            }
+        }
+    }
+
+    /// Rasterize a line. Both end points are drawn and the line
+    /// covers exactly one pixel per step along its major axis. Like
+    /// the hardware lines spanning more than 1023 pixels horizontally
+    /// or 511 pixels vertically are not drawn at all.
+    fn draw_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: [&Vertex; 2]) {
+        let (off_x, off_y) = self.draw_offset;
+
+        let pos = |v: &Vertex| -> (i32, i32) {
+            (v.position[0] as i32 + off_x as i32,
+             v.position[1] as i32 + off_y as i32)
+        };
+
+        let (left, top) = self.draw_area_top_left;
+        let (right, bottom) = self.draw_area_bottom_right;
+
+        let (x0, y0) = pos(vertices[0]);
+        let (x1, y1) = pos(vertices[1]);
+
+        let dx = x1 - x0;
+        let dy = y1 - y0;
+
+        if dx.abs() > 1023 || dy.abs() > 511 {
+            return;
+        }
+
+        let steps = ::std::cmp::max(dx.abs(), dy.abs());
+
+        // Positions are stepped in 16.16 fixed point, starting in the
+        // middle of the first pixel
+        let mut x = (x0 << 16) + 0x8000;
+        let mut y = (y0 << 16) + 0x8000;
+
+        let (step_x, step_y) =
+            match steps {
+                0 => (0, 0),
+                _ => ((dx << 16) / steps, (dy << 16) / steps),
+            };
+
+        for i in 0..steps + 1 {
+            let px = x >> 16;
+            let py = y >> 16;
+
+            x += step_x;
+            y += step_y;
+
+            if px < left as i32 || px > right as i32 ||
+               py < top as i32 || py > bottom as i32 {
+                continue;
+            }
+
+            let mut color = [0; 3];
+
+            for c in 0..3 {
+                let c0 = vertices[0].color[c] as i32;
+                let c1 = vertices[1].color[c] as i32;
+
+                color[c] =
+                    match steps {
+                        0 => c0,
+                        _ => c0 + ((c1 - c0) * i) / steps,
+                    } as u8;
+            }
+
+            let dither =
+                match attributes.dither {
+                    true => DITHER_MATRIX[(py & 3) as usize][(px & 3) as usize],
+                    false => 0,
+                };
+
+            let pixel = rgb555_dither(color, dither);
+
+            self.draw_pixel(attributes, px as u16, py as u16, pixel);
+        }
+    }
+
+    /// Fetch the texel at coordinates `(u, v)` in the texture page
+    /// described by `attributes`. Returns None if the texel is fully
+    /// transparent (i.e. it's 0x0000).
+    ///
+    /// The coordinates are 8bit so they wrap around within the
+    /// texture page, that's what the hardware does with primitives
+    /// bigger than 256 pixels.
+    fn texel(&self,
+             attributes: &PrimitiveAttributes,
+             u: u8,
+             v: u8) -> Option<u16> {
+        // Apply the texture window
+        let mask = attributes.texture_window_mask;
+        let offset = attributes.texture_window_offset;
+
+        let u = (u & !(mask[0] << 3)) | ((offset[0] & mask[0]) << 3);
+        let v = (v & !(mask[1] << 3)) | ((offset[1] & mask[1]) << 3);
+
+        let page_x = attributes.texture_page[0];
+        let page_y = attributes.texture_page[1];
+        let clut_x = attributes.clut[0];
+        let clut_y = attributes.clut[1];
+
+        let u = u as u16;
+        let y = page_y + v as u16;
+
+        let texel =
+            match attributes.texture_depth {
+                TextureDepth::T4Bpp => {
+                    let word = self.vram_pixel(page_x + u / 4, y);
+                    let index = (word >> ((u & 3) * 4)) & 0xf;
+
+                    self.vram_pixel(clut_x + index, clut_y)
+                }
+                TextureDepth::T8Bpp => {
+                    let word = self.vram_pixel(page_x + u / 2, y);
+                    let index = (word >> ((u & 1) * 8)) & 0xff;
+
+                    self.vram_pixel(clut_x + index, clut_y)
+                }
+                TextureDepth::T16Bpp => self.vram_pixel(page_x + u, y),
+            };
+
+        if texel == 0 {
+            None
+        } else {
+            Some(texel)
+        }
+    }
+
+    /// Return true if the VRAM pixel at `x`, `y` is on one of the
+    /// lines of `field` within the displayed area
+    fn is_displayed(&self, x: u16, y: u16, field: Field) -> bool {
+        let (left, top) = self.display_top_left;
+        let (width, height) = self.display_resolution;
+
+        // In 24bpp mode each pixel takes 1.5 VRAM halfwords
+        let width =
+            match self.display_24bpp {
+                true => (width * 3) / 2,
+                false => width,
+            };
+
+        // The display area can wrap around the edges of the VRAM
+        let dx = x.wrapping_sub(left) & 0x3ff;
+        let dy = y.wrapping_sub(top) & 0x1ff;
+
+        dx < width && dy < height && dy & 1 == field as u16
+    }
+
+    /// Write a pixel to the VRAM, taking the mask settings into
+    /// account
+    fn draw_pixel(&mut self,
+                  attributes: &PrimitiveAttributes,
+                  x: u16,
+                  y: u16,
+                  pixel: u16) {
+        let index = vram_index(x, y);
+
+        if let Some(field) = attributes.displayed_field {
+            if self.is_displayed(x, y, field) {
+                return;
+            }
+        }
+
+        if attributes.check_mask_bit && self.vram[index] & 0x8000 != 0 {
+            // Masked pixel
+            return;
+        }
+
+        let textured = attributes.blend_mode != BlendMode::None;
+
+        // Textured primitives are only semi-transparent where the
+        // texel's STP bit is set
+        let blend =
+            attributes.semi_transparent && (!textured || pixel & 0x8000 != 0);
+
+        let pixel =
+            if blend {
+                semi_transparency(attributes.semi_transparency_mode,
+                                  self.vram[index],
+                                  pixel)
+            } else {
+                pixel
+            };
+
+        let mask = (attributes.set_mask_bit as u16) << 15;
+
+        self.vram[index] = pixel | mask;
+    }
+
+    /// Convert the currently displayed area of the VRAM into a 24bit
+    /// RGB frame, draw the overlay (if any) and return it.
+    ///
+    /// In 480 line interlaced mode both fields are woven together
+    /// into a single progressive 480 line image, use `export_field`
+    /// to output one field at a time instead.
+    pub fn export_frame(&mut self) -> &Frame {
+        let (_, height) = self.display_resolution;
+
+        self.export_lines(height, 0, 1)
+    }
+
+    /// Same as `export_frame` but only output the lines of `field` in
+    /// 480 line mode, the resulting frame is 240 lines tall. In 240
+    /// line mode both fields display the same lines and this is
+    /// equivalent to `export_frame`.
+    pub fn export_field(&mut self, field: Field) -> &Frame {
+        let (_, height) = self.display_resolution;
+
+        if height > 256 {
+            self.export_lines(height / 2, field as u16, 2)
+        } else {
+            self.export_lines(height, 0, 1)
+        }
+    }
+
+    /// Export `height` lines starting at line `first` of the display
+    /// area and moving down `step` lines at a time
+    fn export_lines(&mut self, height: u16, first: u16, step: u16) -> &Frame {
+        let (width, _) = self.display_resolution;
+        let (left, top) = self.display_top_left;
+
+        self.frame.resize(width as u32, height as u32);
+
+        for y in 0..height {
+            let line = top + first + y * step;
+
+            for x in 0..width {
+                let color =
+                    match self.display_24bpp {
+                        true => self.vram_rgb24(left, line, x),
+                        false => {
+                            let pixel = self.vram[vram_index(left + x, line)];
+
+                            rgb888(pixel)
+                        }
+                    };
+
+                self.frame.set_pixel(x as u32, y as u32, color);
+            }
+        }
+
+        if let Some(ref mut overlay) = self.overlay {
+            overlay(&mut self.frame);
+        }
+
+        &self.frame
+    }
+
+    /// In 24bpp display mode the VRAM halfwords are reinterpreted as
+    /// a packed stream of R, G, B bytes (low byte first). Return the
+    /// `0x00RRGGBB` color of pixel `x` in the line starting at
+    /// `left`, `line`.
+    fn vram_rgb24(&self, left: u16, line: u16, x: u16) -> u32 {
+        let byte = |n: u16| -> u32 {
+            let pixel = self.vram[vram_index(left + n / 2, line)];
+
+            ((pixel >> ((n & 1) * 8)) & 0xff) as u32
+        };
+
+        let offset = x * 3;
+
+        let r = byte(offset);
+        let g = byte(offset + 1);
+        let b = byte(offset + 2);
+
+        (r << 16) | (g << 8) | b
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.draw_offset = (x, y);
+    }
+
+    fn set_draw_area(&mut self, top_left: (u16, u16), bottom_right: (u16, u16)) {
+        self.draw_area_top_left = top_left;
+        self.draw_area_bottom_right = bottom_right;
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.display_top_left = top_left;
+        self.display_resolution = resolution;
+        self.display_24bpp = depth_24bpp;
+    }
+
+    fn push_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 2]) {
+        self.draw_line(attributes, [&vertices[0], &vertices[1]]);
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: &[Vertex; 3]) {
+        self.draw_triangle(attributes,
+                           [&vertices[0], &vertices[1], &vertices[2]]);
+    }
+
+    fn push_quad(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 4]) {
+        // The hardware splits quads along the v1-v2 diagonal. Both
+        // triangles share that edge but it's "top-left" for only one
+        // of them so the fill rule guarantees that the pixels on the
+        // diagonal are drawn exactly once, which matters for
+        // semi-transparent quads.
+        self.draw_triangle(attributes,
+                           [&vertices[0], &vertices[1], &vertices[2]]);
+        self.draw_triangle(attributes,
+                           [&vertices[1], &vertices[2], &vertices[3]]);
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        // Fill rect ignores the drawing area, the drawing offset and
+        // the mask settings
+        let pixel = rgb555(color);
+
+        let (left, top) = top_left;
+        let (width, height) = dimensions;
+
+        for y in 0..height {
+            for x in 0..width {
+                self.vram[vram_index(left + x, top + y)] = pixel;
+            }
+        }
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  dimensions: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        // Rectangles crossing the edge of the VRAM wrap around
+        let (left, top) = top_left;
+        let (width, height) = dimensions;
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = pixel_buffer[y as usize * width as usize +
+                                         x as usize];
+
+                let index = vram_index(left + x, top + y);
+
+                if self.check_mask_bit && self.vram[index] & 0x8000 != 0 {
+                    // Masked pixel
+                    continue;
+                }
+
+                let mask = (self.set_mask_bit as u16) << 15;
+
+                self.vram[index] = pixel | mask;
+            }
+        }
+    }
+
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        let (left, top) = top_left;
+        let (width, height) = dimensions;
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = self.vram[vram_index(left + x, top + y)];
+
+                pixel_buffer[y as usize * width as usize + x as usize] =
+                    pixel;
+            }
+        }
+    }
+
+    fn set_mask_settings(&mut self,
+                         set_mask_bit: bool,
+                         check_mask_bit: bool) {
+        self.set_mask_bit = set_mask_bit;
+        self.check_mask_bit = check_mask_bit;
+    }
+}
+
+/// RGB frame exported by the `SoftwareRenderer`
+pub struct Frame {
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+    /// Pixels in `0x00RRGGBB` format, line by line starting from the
+    /// top-left corner
+    pub pixels: Vec<u32>,
+}
+
+impl Frame {
+    fn new(width: u32, height: u32) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.pixels.resize((width * height) as usize, 0);
+    }
+
+    /// Return the value of the pixel at `(x, y)` in `0x00RRGGBB`
+    /// format
+    pub fn pixel(&self, x: u32, y: u32) -> u32 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Set the value of the pixel at `(x, y)` in `0x00RRGGBB` format
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: u32) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+}
+
+/// Return the index of the pixel at `(x, y)` in the VRAM. The
+/// coordinates wrap around if they're out of bounds.
+fn vram_index(x: u16, y: u16) -> usize {
+    let x = (x % VRAM_WIDTH_PIXELS) as usize;
+    let y = (y % VRAM_HEIGHT) as usize;
+
+    y * VRAM_WIDTH_PIXELS as usize + x
+}
+
+/// Edge function: returns a positive value if `p` is on the inner
+/// side of the edge `a -> b` (assuming the triangle's vertices are
+/// ordered so that its area is positive), 0 if it's on the edge.
+fn edge(a: (i32, i32), b: (i32, i32), p: (i32, i32)) -> i32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+/// Return the bias to add to the edge function to implement the
+/// top-left fill rule: pixels exactly on a top or left edge are drawn,
+/// pixels on a bottom or right edge are not.
+fn edge_bias(a: (i32, i32), b: (i32, i32)) -> i32 {
+    let dx = b.0 - a.0;
+    let dy = b.1 - a.1;
+
+    let top = dy == 0 && dx > 0;
+    let left = dy < 0;
+
+    if top || left {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Modulate `texel` with `color`: each component is multiplied by
+/// the color component divided by 0x80, so 0x80 leaves the texel
+/// untouched and anything above brightens it. The result is computed
+/// with 8bit precision so that `dither` can be applied before
+/// truncating it back to 5 bits. The STP bit is preserved.
+fn texture_blend(texel: u16, color: [u8; 3], dither: i32) -> u16 {
+    let mut pixel = texel & 0x8000;
+
+    for (c, &m) in color.iter().enumerate() {
+        let shift = c * 5;
+
+        let t = ((texel >> shift) & 0x1f) as i32;
+
+        let v = (t * m as i32) >> 4;
+
+        pixel |= dither_component(v, dither) << shift;
+    }
+
+    pixel
+}
+
+/// Blend the `foreground` pixel with the `background` using the
+/// semi-transparency equation `mode`. The mask bit of the foreground
+/// is preserved.
+fn semi_transparency(mode: SemiTransparencyMode,
+                     background: u16,
+                     foreground: u16) -> u16 {
+    let mut pixel = foreground & 0x8000;
+
+    for shift in [0, 5, 10].iter() {
+        let b = ((background >> shift) & 0x1f) as i32;
+        let f = ((foreground >> shift) & 0x1f) as i32;
+
+        let c =
+            match mode {
+                SemiTransparencyMode::Average => (b + f) / 2,
+                SemiTransparencyMode::Add => b + f,
+                SemiTransparencyMode::SubstractSource => b - f,
+                SemiTransparencyMode::AddQuarterSource => b + f / 4,
+            };
+
+        let c =
+            if c > 0x1f {
+                0x1f
+            } else if c < 0 {
+                0
+            } else {
+                c
+            };
+
+        pixel |= (c as u16) << shift;
+    }
+
+    pixel
+}
+
+/// Convert a 24bit RGB color into a 15bit VRAM pixel (with the mask
+/// bit cleared)
+fn rgb555(color: [u8; 3]) -> u16 {
+    let r = (color[0] >> 3) as u16;
+    let g = (color[1] >> 3) as u16;
+    let b = (color[2] >> 3) as u16;
+
+    r | (g << 5) | (b << 10)
+}
+
+/// Convert a 24bit RGB color into a 15bit VRAM pixel after adding
+/// the `dither` offset to each component
+fn rgb555_dither(color: [u8; 3], dither: i32) -> u16 {
+    let r = dither_component(color[0] as i32, dither);
+    let g = dither_component(color[1] as i32, dither);
+    let b = dither_component(color[2] as i32, dither);
+
+    r | (g << 5) | (b << 10)
+}
+
+/// Add the `dither` offset to the 8bit color component `c`, saturate
+/// and truncate the result to 5 bits
+fn dither_component(c: i32, dither: i32) -> u16 {
+    let c = c + dither;
+
+    let c =
+        if c > 0xff {
+            0xff
+        } else if c < 0 {
+            0
+        } else {
+            c
+        };
+
+    (c >> 3) as u16
+}
+
+/// Convert a 15bit VRAM pixel into a `0x00RRGGBB` color
+fn rgb888(pixel: u16) -> u32 {
+    // Replicate the high bits in the low bits to use the full 8bit
+    // range
+    let expand = |c: u16| -> u32 {
+        let c = (c & 0x1f) as u32;
+
+        (c << 3) | (c >> 2)
+    };
+
+    let r = expand(pixel);
+    let g = expand(pixel >> 5);
+    let b = expand(pixel >> 10);
+
+    (r << 16) | (g << 8) | b
+}
+
+/// Ordered dithering offsets added to the 8bit color components,
+/// indexed by the low 2 bits of the VRAM Y and X coordinates
+const DITHER_MATRIX: [[i32; 4]; 4] = [
+    [-4,  0, -3,  1],
+    [ 2, -2,  3, -1],
+    [-3,  1, -4,  0],
+    [ 3, -1,  2, -2],
+    ];
+
+/// Number of 16bit pixels in the VRAM
+const VRAM_SIZE: usize = VRAM_WIDTH_PIXELS as usize * VRAM_HEIGHT as usize;
+
+#[test]
+fn overlay() {
+    let mut renderer = SoftwareRenderer::new();
+
+    renderer.set_display_mode((0, 0), (320, 240), false);
+    renderer.fill_rect([0xff, 0, 0], (0, 0), (320, 240));
+
+    assert!(renderer.export_frame().pixel(10, 20) == 0xff0000);
+
+    renderer.set_overlay(Some(Box::new(|frame: &mut Frame| {
+        frame.set_pixel(10, 20, 0x00ff00);
+    })));
+
+    {
+        let frame = renderer.export_frame();
+
+        assert!(frame.pixel(10, 20) == 0x00ff00);
+        assert!(frame.pixel(11, 20) == 0xff0000);
+    }
+
+    // The overlay must not have touched the VRAM
+    assert!(renderer.vram_pixel(10, 20) == 0x001f);
+
+    renderer.set_overlay(None);
+
+    assert!(renderer.export_frame().pixel(10, 20) == 0xff0000);
+}
+
+#[test]
+fn interlaced_480_lines() {
+    let mut renderer = SoftwareRenderer::new();
+
+    renderer.set_display_mode((0, 0), (640, 480), false);
+
+    // Red on even lines (bottom field), blue on odd lines (top field)
+    for y in 0..480 {
+        let color = match y & 1 {
+            0 => [0xff, 0, 0],
+            _ => [0, 0, 0xff],
+        };
+
+        renderer.fill_rect(color, (0, y), (640, 1));
+    }
+
+    {
+        let frame = renderer.export_frame();
+
+        assert!(frame.height == 480);
+
+        for y in 0..480 {
+            let expected = match y & 1 {
+                0 => 0xff0000,
+                _ => 0x0000ff,
+            };
+
+            assert!(frame.pixel(320, y) == expected);
+        }
+    }
+
+    {
+        let frame = renderer.export_field(Field::Top);
+
+        assert!(frame.height == 240);
+        assert!(frame.pixels.iter().all(|&p| p == 0x0000ff));
+    }
+
+    {
+        let frame = renderer.export_field(Field::Bottom);
+
+        assert!(frame.height == 240);
+        assert!(frame.pixels.iter().all(|&p| p == 0xff0000));
+    }
+}
+
+#[test]
+fn display_24bpp() {
+    let mut renderer = SoftwareRenderer::new();
+
+    // Two 24bpp pixels packed in 3 halfwords: 0x123456 and 0xabcdef
+    renderer.load_image((100, 10), (3, 1), &[0x3412, 0xab56, 0xefcd]);
+
+    renderer.set_display_mode((100, 10), (320, 240), true);
+
+    {
+        let frame = renderer.export_frame();
+
+        assert!(frame.width == 320);
+        assert!(frame.pixel(0, 0) == 0x123456);
+        assert!(frame.pixel(1, 0) == 0xabcdef);
+        assert!(frame.pixel(2, 0) == 0);
+    }
+
+    // Back to 15bpp: the same VRAM is displayed as 15bit pixels
+    renderer.set_display_mode((100, 10), (320, 240), false);
+
+    assert!(renderer.export_frame().pixel(0, 0) == rgb888(0x3412));
+}
+
+#[test]
+fn dump_vram() {
+    let mut renderer = SoftwareRenderer::new();
+
+    renderer.fill_rect([0xff, 0, 0], (16, 2), (16, 1));
+
+    let vram = renderer.dump_vram();
+
+    assert!(vram.len() == 1024 * 512);
+    assert!(vram[2 * 1024 + 15] == 0);
+    assert!(vram[2 * 1024 + 16] == 0x001f);
+    assert!(vram[2 * 1024 + 31] == 0x001f);
+    assert!(vram[2 * 1024 + 32] == 0);
+    assert!(vram[2 * 1024 + 16] == renderer.vram_pixel(16, 2));
+}
+
+#[test]
+fn push_triangle_readback() {
+    use super::renderer::Shading;
+
+    let mut renderer = SoftwareRenderer::new();
+
+    renderer.set_draw_area((0, 0), (1023, 511));
+
+    let attributes = PrimitiveAttributes {
+        semi_transparent: false,
+        semi_transparency_mode: SemiTransparencyMode::Average,
+        blend_mode: BlendMode::None,
+        shading: Shading::Flat,
+        texture_page: [0; 2],
+        texture_depth: TextureDepth::T4Bpp,
+        clut: [0; 2],
+        texture_window_mask: [0; 2],
+        texture_window_offset: [0; 2],
+        dither: false,
+        set_mask_bit: false,
+        check_mask_bit: false,
+        displayed_field: None,
+        opcode: 0x20,
+        sequence: 0,
+    };
+
+    let red = [0xff, 0, 0];
+
+    let vertices = [Vertex::new([10, 20], red),
+                    Vertex::new([14, 20], red),
+                    Vertex::new([10, 24], red)];
+
+    renderer.push_triangle(&attributes, &vertices);
+
+    // The hypotenuse is a bottom-right edge so the pixels on it are
+    // not drawn
+    for y in 0..6 {
+        for x in 0..6 {
+            let expected =
+                match x + y < 4 {
+                    true => 0x001f,
+                    false => 0,
+                };
+
+            assert!(renderer.vram_pixel(10 + x, 20 + y) == expected);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! GPU driven directly with raw GP0 and GP1 words, without the rest of
+//! the console. Meant for tests and tools scripting the GPU.
+
+use memory::timers::Timers;
+use shared::SharedState;
+
+use super::{Gpu, VideoClock};
+use super::renderer::Renderer;
+
+/// GPU bundled with its renderer and the bits of console state it
+/// needs to run on its own.
+///
+/// ```
+/// use rustation::gpu::VideoClock;
+/// use rustation::gpu::renderer::NullRenderer;
+/// use rustation::gpu::standalone::StandaloneGpu;
+///
+/// let mut gpu = StandaloneGpu::new(VideoClock::Ntsc,
+///                                  Box::new(NullRenderer));
+///
+/// // GP0(0x28): red monochrome 16x16 quad
+/// for &w in &[0x280000ff, 0x00000000, 0x00000010, 0x00100000, 0x00100010] {
+///     gpu.gp0(w);
+/// }
+///
+/// // GP1(0x10): request the GPU version, then read it back
+/// gpu.gp1(0x10000007);
+///
+/// assert_eq!(gpu.read(), 2);
+/// ```
+pub struct StandaloneGpu {
+    gpu: Gpu,
+    renderer: Box<dyn Renderer>,
+    shared: SharedState,
+    timers: Timers,
+}
+
+impl StandaloneGpu {
+    /// Create a GPU using the `standard` video clock and drawing
+    /// through `renderer`
+    pub fn new(standard: VideoClock,
+               renderer: Box<dyn Renderer>) -> StandaloneGpu {
+        StandaloneGpu {
+            gpu: Gpu::new(standard),
+            renderer,
+            shared: SharedState::new(),
+            timers: Timers::new(),
+        }
+    }
+
+    /// Send a word to GP0: drawing commands and VRAM transfers
+    pub fn gp0(&mut self, val: u32) {
+        self.gpu.gp0(&mut *self.renderer, val);
+    }
+
+    /// Send a word to GP1: display control
+    pub fn gp1(&mut self, val: u32) {
+        self.gpu.gp1(&mut self.shared,
+                     &mut *self.renderer,
+                     val,
+                     &mut self.timers);
+    }
+
+    /// Read a word from GPUREAD: VRAM data or GPU information
+    pub fn read(&mut self) -> u32 {
+        self.gpu.read()
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn renderer(&self) -> &dyn Renderer {
+        &*self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut dyn Renderer {
+        &mut *self.renderer
+    }
+}
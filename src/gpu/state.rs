@@ -0,0 +1,391 @@
+//! GPU save states. The VRAM is owned by the renderer so it's not
+//! part of the GPU state, frontends must save it separately (using
+//! `Renderer::store_image` and `Renderer::load_image` for instance).
+
+use savestate::{StateWriter, StateReader, StateError};
+
+use super::{Gpu, Field, HorizontalRes, VerticalRes, VMode, DisplayDepth};
+use super::{DmaDirection, VideoClock, ImageBuffer, Gp0State, gp0_opcode};
+use super::renderer::Renderer;
+
+/// Identifier at the beginning of GPU save states
+const MAGIC: &'static [u8; 4] = b"PSXG";
+
+/// Current version of the GPU save state format
+const VERSION: u32 = 2;
+
+/// GP0 parser states indexed by their value in the save state
+const GP0_STATES: [Gp0State; 6] = [
+    Gp0State::Command,
+    Gp0State::Parameter,
+    Gp0State::ImageLoad,
+    Gp0State::ShadedPolylineColor,
+    Gp0State::ShadedPolylineVertex,
+    Gp0State::MonochromePolylineVertex,
+    ];
+
+impl Gpu {
+    /// Serialize the GPU state. The debug settings (force-disabled
+    /// features, strict mode) are not saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(MAGIC, VERSION);
+
+        w.write_u8(match self.standard {
+            VideoClock::Ntsc => 0,
+            VideoClock::Pal => 1,
+        });
+
+        w.write_u16(self.draw_mode);
+        w.write_u8(self.texture_window_x_mask);
+        w.write_u8(self.texture_window_y_mask);
+        w.write_u8(self.texture_window_x_offset);
+        w.write_u8(self.texture_window_y_offset);
+        w.write_bool(self.dithering);
+        w.write_bool(self.draw_to_display);
+        w.write_bool(self.force_set_mask_bit);
+        w.write_bool(self.preserve_masked_pixels);
+        w.write_u16(self.drawing_area_left);
+        w.write_u16(self.drawing_area_top);
+        w.write_u16(self.drawing_area_right);
+        w.write_u16(self.drawing_area_bottom);
+        w.write_i16(self.drawing_offset.0);
+        w.write_i16(self.drawing_offset.1);
+        w.write_u8(self.field as u8);
+        w.write_bool(self.texture_disable);
+        w.write_u8(self.hres.0);
+        w.write_u8(self.vres as u8);
+        w.write_u8(self.vmode as u8);
+        w.write_u8(self.display_depth as u8);
+        w.write_bool(self.interlaced);
+        w.write_bool(self.display_disabled);
+        w.write_u16(self.display_vram_x_start);
+        w.write_u16(self.display_vram_y_start);
+        w.write_u16(self.display_horiz_start);
+        w.write_u16(self.display_horiz_end);
+        w.write_u16(self.display_line_start);
+        w.write_u16(self.display_line_end);
+        w.write_u8(self.dma_direction as u8);
+
+        w.write_u8(self.fifo.read);
+        w.write_u8(self.fifo.len);
+        for &word in self.fifo.buffer.iter() {
+            w.write_u32(word);
+        }
+        w.write_u32(self.gp0_busy_cycles);
+
+        w.write_u8(self.gp0_state as u8);
+
+        w.write_u8(self.gp0_command.len);
+        for i in 0..self.gp0_command.len as usize {
+            w.write_u32(self.gp0_command[i]);
+        }
+
+        w.write_u32(self.gp0_words_remaining);
+
+        // The attributes of the current command are rebuilt from its
+        // opcode when the state is loaded
+        let attributes = self.gp0_attributes.primitive_attributes();
+        w.write_u8(attributes.opcode);
+        w.write_u32(attributes.sequence);
+
+        w.write_u32(self.gp0_frame_commands);
+        w.write_bool(self.gp0_interrupt);
+        w.write_bool(self.vblank_interrupt);
+        w.write_u16(self.gpu_clock_phase);
+        w.write_u16(self.display_line);
+        w.write_u16(self.display_line_tick);
+        w.write_u32(self.read_word);
+
+        let (pos, color) = self.polyline_prev;
+        w.write_i16(pos[0]);
+        w.write_i16(pos[1]);
+        for &c in color.iter() {
+            w.write_u8(c);
+        }
+
+        // Only the pixels received so far are saved for the image
+        // load, the image store needs the whole buffer
+        save_image_buffer(&mut w,
+                          &self.load_buffer,
+                          self.load_buffer.index as usize);
+        save_image_buffer(&mut w,
+                          &self.store_buffer,
+                          self.store_buffer.len());
+
+        w.write_u32(self.image_store_words_remaining);
+
+        w.finish()
+    }
+
+    /// Send the current drawing and display configuration to
+    /// `renderer`. Since the renderer state is not part of the GPU
+    /// state this must be called after `load_state`.
+    pub fn configure_renderer(&self, renderer: &mut dyn Renderer) {
+        let (x, y) = self.drawing_offset;
+
+        self.update_display_mode(renderer);
+        self.update_draw_area(renderer);
+        renderer.set_draw_offset(x, y);
+        renderer.set_mask_settings(self.force_set_mask_bit,
+                                   self.preserve_masked_pixels);
+    }
+
+    /// Restore a state created by `save_state`. If an error is
+    /// returned the GPU is left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(state, MAGIC)?;
+
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let standard =
+            match r.read_u8()? {
+                0 => VideoClock::Ntsc,
+                1 => VideoClock::Pal,
+                _ => return Err(StateError::InvalidValue("video clock")),
+            };
+
+        let mut gpu = Gpu::new(standard);
+
+        gpu.draw_mode = r.read_u16()?;
+        gpu.texture_window_x_mask = r.read_u8()?;
+        gpu.texture_window_y_mask = r.read_u8()?;
+        gpu.texture_window_x_offset = r.read_u8()?;
+        gpu.texture_window_y_offset = r.read_u8()?;
+        gpu.dithering = r.read_bool()?;
+        gpu.draw_to_display = r.read_bool()?;
+        gpu.force_set_mask_bit = r.read_bool()?;
+        gpu.preserve_masked_pixels = r.read_bool()?;
+        gpu.drawing_area_left = r.read_u16()?;
+        gpu.drawing_area_top = r.read_u16()?;
+        gpu.drawing_area_right = r.read_u16()?;
+        gpu.drawing_area_bottom = r.read_u16()?;
+        gpu.drawing_offset = (r.read_i16()?, r.read_i16()?);
+
+        gpu.field =
+            match r.read_u8()? {
+                0 => Field::Bottom,
+                1 => Field::Top,
+                _ => return Err(StateError::InvalidValue("field")),
+            };
+
+        gpu.texture_disable = r.read_bool()?;
+
+        let hres = r.read_u8()?;
+
+        if hres > 7 {
+            return Err(StateError::InvalidValue("hres"));
+        }
+
+        gpu.hres = HorizontalRes(hres);
+
+        gpu.vres =
+            match r.read_u8()? {
+                0 => VerticalRes::Y240Lines,
+                1 => VerticalRes::Y480Lines,
+                _ => return Err(StateError::InvalidValue("vres")),
+            };
+
+        gpu.vmode =
+            match r.read_u8()? {
+                0 => VMode::Ntsc,
+                1 => VMode::Pal,
+                _ => return Err(StateError::InvalidValue("video mode")),
+            };
+
+        gpu.display_depth =
+            match r.read_u8()? {
+                0 => DisplayDepth::D15Bits,
+                1 => DisplayDepth::D24Bits,
+                _ => return Err(StateError::InvalidValue("display depth")),
+            };
+
+        gpu.interlaced = r.read_bool()?;
+        gpu.display_disabled = r.read_bool()?;
+        gpu.display_vram_x_start = r.read_u16()?;
+        gpu.display_vram_y_start = r.read_u16()?;
+        gpu.display_horiz_start = r.read_u16()?;
+        gpu.display_horiz_end = r.read_u16()?;
+        gpu.display_line_start = r.read_u16()?;
+        gpu.display_line_end = r.read_u16()?;
+
+        gpu.dma_direction =
+            match r.read_u8()? {
+                0 => DmaDirection::Off,
+                1 => DmaDirection::Fifo,
+                2 => DmaDirection::CpuToGp0,
+                3 => DmaDirection::VRamToCpu,
+                _ => return Err(StateError::InvalidValue("DMA direction")),
+            };
+
+        gpu.fifo.read = r.read_u8()?;
+        gpu.fifo.len = r.read_u8()?;
+
+        if gpu.fifo.read as usize >= gpu.fifo.buffer.len() ||
+           gpu.fifo.len as usize > gpu.fifo.buffer.len() {
+            return Err(StateError::InvalidValue("GP0 FIFO"));
+        }
+
+        for word in gpu.fifo.buffer.iter_mut() {
+            *word = r.read_u32()?;
+        }
+
+        gpu.gp0_busy_cycles = r.read_u32()?;
+
+        gpu.gp0_state =
+            match GP0_STATES.get(r.read_u8()? as usize) {
+                Some(&s) => s,
+                None => return Err(StateError::InvalidValue("GP0 state")),
+            };
+
+        let command_len = r.read_u8()?;
+
+        if command_len as usize > gpu.gp0_command.buffer.len() {
+            return Err(StateError::InvalidValue("GP0 command length"));
+        }
+
+        for _ in 0..command_len {
+            gpu.gp0_command.push_word(r.read_u32()?);
+        }
+
+        gpu.gp0_words_remaining = r.read_u32()?;
+
+        let opcode = r.read_u8()?;
+        let sequence = r.read_u32()?;
+
+        gpu.gp0_frame_commands = r.read_u32()?;
+        gpu.gp0_interrupt = r.read_bool()?;
+        gpu.vblank_interrupt = r.read_bool()?;
+        gpu.gpu_clock_phase = r.read_u16()?;
+        gpu.display_line = r.read_u16()?;
+        gpu.display_line_tick = r.read_u16()?;
+        gpu.read_word = r.read_u32()?;
+
+        let pos = [r.read_i16()?, r.read_i16()?];
+        let color = [r.read_u8()?, r.read_u8()?, r.read_u8()?];
+
+        gpu.polyline_prev = (pos, color);
+
+        load_image_buffer(&mut r, &mut gpu.load_buffer, true)?;
+        load_image_buffer(&mut r, &mut gpu.store_buffer, false)?;
+
+        gpu.image_store_words_remaining = r.read_u32()?;
+
+        r.finish()?;
+
+        check_gp0_state(&gpu, opcode)?;
+
+        // Rebuild the attributes of the current command now that the
+        // draw mode and mask settings have been restored
+        let (_, attributes) = gpu.gp0_parse_command((opcode as u32) << 24);
+
+        gpu.gp0_attributes = attributes;
+        gpu.gp0_attributes.primitive_attributes.sequence = sequence;
+
+        // The debug settings are not part of the state
+        gpu.force_disable = self.force_disable;
+        gpu.strict = self.strict;
+
+        *self = gpu;
+
+        Ok(())
+    }
+}
+
+/// Make sure the GP0 parser won't run past the end of its buffers
+/// when it resumes from a state. `opcode` is the opcode of the current
+/// command.
+fn check_gp0_state(gpu: &Gpu, opcode: u8) -> Result<(), StateError> {
+    let remaining = gpu.gp0_words_remaining;
+    let command_len = gpu.gp0_command.len as u32;
+
+    let valid =
+        match gpu.gp0_state {
+            Gp0State::Parameter => {
+                // Unsupported opcodes are single word NOPs and never
+                // leave the parser waiting for parameters
+                let len = gp0_opcode(opcode).map(|c| c.len).unwrap_or(0);
+
+                remaining > 0 && command_len + remaining == len
+            }
+            Gp0State::ImageLoad => {
+                let buffer = &gpu.load_buffer;
+                // Pixels are received two at a time
+                let padded_len = (buffer.len() as u32 + 1) & !1;
+
+                remaining > 0 && buffer.index + remaining * 2 == padded_len
+            }
+            // The color is stored in the command buffer
+            Gp0State::ShadedPolylineVertex =>
+                remaining == 0 && command_len == 1,
+            _ => remaining == 0,
+        };
+
+    if !valid {
+        return Err(StateError::InvalidValue("GP0 words remaining"));
+    }
+
+    let store = &gpu.store_buffer;
+    let store_words =
+        ((store.len() as u32 + 1) / 2).saturating_sub(store.index / 2);
+
+    if gpu.image_store_words_remaining > store_words {
+        return Err(StateError::InvalidValue("image store"));
+    }
+
+    Ok(())
+}
+
+fn save_image_buffer(w: &mut StateWriter,
+                     buffer: &ImageBuffer,
+                     npixels: usize) {
+    w.write_u16(buffer.top_left.0);
+    w.write_u16(buffer.top_left.1);
+    w.write_u16(buffer.resolution.0);
+    w.write_u16(buffer.resolution.1);
+    w.write_u32(buffer.index);
+
+    w.write_u32(npixels as u32);
+    for &p in &buffer.buffer[0..npixels] {
+        w.write_u16(p);
+    }
+}
+
+/// Load an image buffer saved by `save_image_buffer`. If `load` is
+/// true the buffer is used for an image load and its index says how
+/// many pixels have been received so far.
+fn load_image_buffer(r: &mut StateReader,
+                     buffer: &mut ImageBuffer,
+                     load: bool) -> Result<(), StateError> {
+    let x = r.read_u16()?;
+    let y = r.read_u16()?;
+    let width = r.read_u16()?;
+    let height = r.read_u16()?;
+
+    if x >= 1024 || y >= 512 || width > 1024 || height > 512 {
+        return Err(StateError::InvalidValue("image buffer"));
+    }
+
+    buffer.reset(x, y, width, height);
+
+    buffer.index = r.read_u32()?;
+
+    let npixels = r.read_u32()? as usize;
+
+    let expected =
+        match load {
+            true => buffer.index as usize,
+            false => buffer.len(),
+        };
+
+    if npixels != expected || npixels > buffer.buffer.len() {
+        return Err(StateError::InvalidValue("image buffer"));
+    }
+
+    for p in buffer.buffer[0..npixels].iter_mut() {
+        *p = r.read_u16()?;
+    }
+
+    Ok(())
+}
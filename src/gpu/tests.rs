@@ -0,0 +1,2125 @@
+use memory::Word;
+use memory::timers::Timers;
+use shared::SharedState;
+use savestate::StateError;
+
+use super::{Gpu, VideoClock, DmaDirection, DisplayRect, force_disable};
+use super::renderer::{Renderer, Vertex, PrimitiveAttributes};
+use super::software::SoftwareRenderer;
+
+/// Dummy renderer recording the primitives it receives
+struct RecordingRenderer {
+    /// Vertex positions of the quads received so far
+    quads: Vec<[[i16; 2]; 4]>,
+    /// Number of texture cache invalidations
+    cache_clears: u32,
+}
+
+impl RecordingRenderer {
+    fn new() -> RecordingRenderer {
+        RecordingRenderer {
+            quads: Vec::new(),
+            cache_clears: 0,
+        }
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self, _: (u16, u16), _: (u16, u16), _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, vertices: &[Vertex; 4]) {
+        self.quads.push([vertices[0].position,
+                         vertices[1].position,
+                         vertices[2].position,
+                         vertices[3].position]);
+    }
+
+    fn fill_rect(&mut self, _: [u8; 3], _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self, _: (u16, u16), _: (u16, u16), _: &[u16]) {
+    }
+
+    fn store_image(&mut self, _: (u16, u16), _: (u16, u16), _: &mut [u16]) {
+    }
+
+    fn clear_texture_cache(&mut self) {
+        self.cache_clears += 1;
+    }
+}
+
+/// GP0(0x28) monochrome quad with vertices at `(x, y)`, `(x + 1, y)`,
+/// `(x, y + 1)` and `(x + 1, y + 1)`
+fn monochrome_quad(x: u32, y: u32) -> [u32; 5] {
+    [0x28ff0000,
+     (y << 16) | x,
+     (y << 16) | (x + 1),
+     ((y + 1) << 16) | x,
+     ((y + 1) << 16) | (x + 1)]
+}
+
+/// Write `val` to GP0 through the CPU bus. Unlike `Gpu::gp0` the word
+/// goes through the FIFO and waits there while the GPU is busy.
+fn cpu_gp0(gpu: &mut Gpu,
+           shared: &mut SharedState,
+           renderer: &mut dyn Renderer,
+           val: u32) {
+    let mut timers = Timers::new();
+
+    gpu.store::<Word>(shared, renderer, &mut timers, 0, val);
+}
+
+#[test]
+fn gp0_split_command() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    let quad = monochrome_quad(10, 20);
+
+    for &w in &quad[0..2] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Reading the status in the middle of the command mustn't disturb
+    // the decoding
+    gpu.status();
+
+    assert!(renderer.quads.is_empty());
+
+    for &w in &quad[2..5] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.quads == vec![[[10, 20], [11, 20], [10, 21], [11, 21]]]);
+}
+
+#[test]
+fn gp0_split_command_reset() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    let quad = monochrome_quad(10, 20);
+
+    for &w in &quad[0..2] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // GP1(0x01): reset command buffer, the partial command must be
+    // discarded
+    gpu.gp1(&mut shared, &mut renderer, 0x01000000, &mut timers);
+
+    let quad = monochrome_quad(30, 40);
+
+    for &w in &quad {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.quads == vec![[[30, 40], [31, 40], [30, 41], [31, 41]]]);
+}
+
+#[test]
+fn gp0_command_overflow() {
+    use super::renderer::NullRenderer;
+    use super::trace::TraceRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = TraceRenderer::new(NullRenderer);
+
+    // Start a GP0(0x20) monochrome triangle and pretend it's much
+    // longer than the command buffer
+    gpu.gp0(&mut renderer, 0x20000000);
+    gpu.gp0_words_remaining = 100;
+
+    for _ in 0..20 {
+        gpu.gp0(&mut renderer, 0);
+    }
+
+    // The command is discarded once the buffer overflows, the
+    // remaining words are parsed as new commands (NOPs)
+    assert!(renderer.primitive_count() == 0);
+    assert!(gpu.gp0_idle());
+
+    for &w in &monochrome_quad(10, 20) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.primitive_count() == 1);
+}
+
+#[test]
+fn gp0_unknown_command() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    // Unsupported opcodes are ignored one word at a time, the
+    // following command must be decoded normally
+    for &w in &[0x03123456, 0x1f000000, 0x21abcdef, 0xe7000000, 0xff000000] {
+        gpu.gp0(&mut renderer, w);
+        assert!(gpu.gp0_idle());
+    }
+
+    for &w in &monochrome_quad(10, 20) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.quads == vec![[[10, 20], [11, 20], [10, 21], [11, 21]]]);
+}
+
+#[test]
+fn gp0_command_lengths() {
+    use super::{gp0_command_len, CommandBuffer};
+
+    assert!(gp0_command_len(0x00) == Some(1));
+    assert!(gp0_command_len(0x02) == Some(3));
+    assert!(gp0_command_len(0x2c) == Some(9));
+    assert!(gp0_command_len(0x3e) == Some(12));
+    assert!(gp0_command_len(0x80) == Some(4));
+    assert!(gp0_command_len(0xe6) == Some(1));
+    assert!(gp0_command_len(0x03) == None);
+    assert!(gp0_command_len(0xff) == None);
+
+    // All the commands must fit in the command buffer
+    let max = CommandBuffer::new().buffer.len() as u32;
+
+    for op in 0..0x100 {
+        if let Some(len) = gp0_command_len(op as u8) {
+            assert!(len >= 1 && len <= max);
+        }
+    }
+}
+
+#[test]
+fn gp0_fifo() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    // Let the GPU finish drawing and process the FIFO, like the
+    // interconnect does when the GPU syncs
+    fn drain(gpu: &mut Gpu,
+             shared: &mut SharedState,
+             renderer: &mut RecordingRenderer) {
+        for _ in 0..16 {
+            shared.tk().advance(100);
+            gpu.sync(shared);
+            gpu.process_fifo(renderer);
+        }
+    }
+
+    // Ready to receive DMA block
+    let fifo_empty = |gpu: &Gpu| (gpu.status() >> 28) & 1 == 1;
+
+    // The first quad is drawn right away, the second one waits in the
+    // FIFO
+    for &w in monochrome_quad(10, 20).iter()
+        .chain(monochrome_quad(30, 40).iter()) {
+        cpu_gp0(&mut gpu, &mut shared, &mut renderer, w);
+    }
+
+    assert!(renderer.quads.len() == 1);
+    assert!(!fifo_empty(&gpu));
+
+    drain(&mut gpu, &mut shared, &mut renderer);
+
+    assert!(renderer.quads.len() == 2);
+    assert!(renderer.quads[1] == [[30, 40], [31, 40], [30, 41], [31, 41]]);
+    assert!(fifo_empty(&gpu));
+
+    // Fill the FIFO with 3 quads and a NOP while the GPU is busy, the
+    // start of the 4th quad is lost
+    for &w in &monochrome_quad(0, 0) {
+        cpu_gp0(&mut gpu, &mut shared, &mut renderer, w);
+    }
+
+    for _ in 0..3 {
+        for &w in &monochrome_quad(50, 60) {
+            cpu_gp0(&mut gpu, &mut shared, &mut renderer, w);
+        }
+    }
+
+    cpu_gp0(&mut gpu, &mut shared, &mut renderer, 0);
+    cpu_gp0(&mut gpu, &mut shared, &mut renderer, 0x28ff0000);
+
+    drain(&mut gpu, &mut shared, &mut renderer);
+
+    assert!(renderer.quads.len() == 6);
+    assert!(gpu.gp0_idle());
+
+    // GP1(0x01) flushes the FIFO
+    for &w in monochrome_quad(0, 0).iter()
+        .chain(monochrome_quad(70, 80).iter()) {
+        cpu_gp0(&mut gpu, &mut shared, &mut renderer, w);
+    }
+
+    assert!(!fifo_empty(&gpu));
+
+    gpu.gp1(&mut shared, &mut renderer, 0x01000000, &mut timers);
+
+    assert!(fifo_empty(&gpu));
+
+    drain(&mut gpu, &mut shared, &mut renderer);
+
+    assert!(renderer.quads.len() == 7);
+}
+
+#[test]
+fn gpustat_dma_request() {
+    let mut shared = SharedState::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    // Return (DMA request, ready to send VRAM, ready to receive DMA
+    // block) status bits
+    fn dma_bits(gpu: &Gpu) -> (u32, u32, u32) {
+        let status = gpu.status();
+
+        ((status >> 25) & 1, (status >> 27) & 1, (status >> 28) & 1)
+    }
+
+    let directions = [DmaDirection::Off,
+                      DmaDirection::Fifo,
+                      DmaDirection::CpuToGp0,
+                      DmaDirection::VRamToCpu];
+
+    // Empty FIFO, no image store in progress so there's nothing to
+    // send to the CPU
+    let expected = [0, 1, 1, 0];
+
+    for (&dir, &req) in directions.iter().zip(expected.iter()) {
+        gpu.dma_direction = dir;
+
+        let (dma_request, _, ready_dma) = dma_bits(&gpu);
+
+        assert!(ready_dma == 1);
+        assert!(dma_request == req);
+    }
+
+    // Draw a quad, the GPU is busy for a while and the next words
+    // pile up in the FIFO until it's full
+    for &w in &monochrome_quad(10, 20) {
+        cpu_gp0(&mut gpu, &mut shared, &mut renderer, w);
+    }
+
+    for _ in 0..16 {
+        cpu_gp0(&mut gpu, &mut shared, &mut renderer, 0);
+    }
+
+    assert!(renderer.quads.len() == 1);
+    assert!(gpu.fifo.is_full());
+
+    for &dir in &directions {
+        gpu.dma_direction = dir;
+
+        let (dma_request, ready_vram, ready_dma) = dma_bits(&gpu);
+
+        let expected =
+            match dir {
+                DmaDirection::Off => 0,
+                DmaDirection::Fifo => 0,
+                DmaDirection::CpuToGp0 => ready_dma,
+                DmaDirection::VRamToCpu => ready_vram,
+            };
+
+        assert!(ready_dma == 0);
+        assert!(dma_request == expected);
+    }
+
+    // GP1(0x01) must flush the FIFO
+    gpu.gp1_reset_command_buffer();
+
+    let (_, _, ready_dma) = dma_bits(&gpu);
+
+    assert!(ready_dma == 1);
+}
+
+#[test]
+fn force_disable_texturing() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Drawing area covers the whole VRAM
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Red 16x16 16bpp texture at 512, 0
+    let texture = [0x001f; 16 * 16];
+    renderer.load_image((512, 0), (16, 16), &texture);
+
+    // Raw-textured quad with a green color, 16bpp texture page at
+    // 512, 0
+    let quad = [0x2d00ff00,
+                0x00000000,
+                0x00000000,
+                0x00000010,
+                0x01080010,
+                0x00100000,
+                0x00001000,
+                0x00100010,
+                0x00001010];
+
+    for &w in &quad {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(8, 8) == 0x001f);
+
+    gpu.set_force_disable(force_disable::TEXTURING);
+
+    for &w in &quad {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 0) == 0x03e0);
+    assert!(renderer.vram_pixel(8, 8) == 0x03e0);
+    assert!(renderer.vram_pixel(15, 15) == 0x03e0);
+    // The right and bottom edges are not drawn
+    assert!(renderer.vram_pixel(16, 8) == 0);
+    assert!(renderer.vram_pixel(8, 16) == 0);
+}
+
+#[test]
+fn gp0_fill_rect_alignment() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Masked pixel in the filled area
+    renderer.load_image((0x20, 5), (1, 1), &[0x8000]);
+
+    // Drawing area limited to 0, 0 - 1, 1 and mask check enabled,
+    // neither applies to the fill
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe4000401);
+    gpu.gp0(&mut renderer, 0xe6000002);
+
+    // GP0(0x02): red fill at 0x13, 0x205 of 0x11x2 pixels. The
+    // position is aligned down to 0x10, 5 and the width rounded up
+    // to 0x20
+    for &w in &[0x020000ff, 0x02050013, 0x00020011] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0x10, 5) == 0x001f);
+    assert!(renderer.vram_pixel(0x20, 5) == 0x001f);
+    assert!(renderer.vram_pixel(0x2f, 6) == 0x001f);
+    assert!(renderer.vram_pixel(0x0f, 5) == 0);
+    assert!(renderer.vram_pixel(0x30, 5) == 0);
+    assert!(renderer.vram_pixel(0x10, 4) == 0);
+    assert!(renderer.vram_pixel(0x10, 7) == 0);
+
+    // Width 0x400 is the same as width 0
+    for &w in &[0x0200ff00, 0x00000000, 0x00010400] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 0) == 0);
+}
+
+#[test]
+fn gp0_copy_rect() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    renderer.load_image((0, 0), (4, 2), &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // GP0(0x80): copy the 4x2 rectangle at 0, 0 to 1022, 511. The
+    // destination wraps around both edges of the VRAM
+    for &w in &[0x80000000, 0x00000000, 0x01ff03fe, 0x00020004] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(1022, 511) == 1);
+    assert!(renderer.vram_pixel(1023, 511) == 2);
+    assert!(renderer.vram_pixel(0, 511) == 3);
+    assert!(renderer.vram_pixel(1, 511) == 4);
+    assert!(renderer.vram_pixel(1022, 0) == 5);
+    assert!(renderer.vram_pixel(1, 0) == 8);
+
+    // The wrapped copy overwrote the source, pixel 0, 0 is now 7.
+    // Overlapping copy one line down: the hardware copies line by
+    // line so the first line is repeated
+    for &w in &[0x80000000, 0x00000000, 0x00010000, 0x00030001] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 0) == 7);
+    assert!(renderer.vram_pixel(0, 1) == 7);
+    assert!(renderer.vram_pixel(0, 2) == 7);
+    assert!(renderer.vram_pixel(0, 3) == 7);
+
+    // Mask settings: set the mask bit, don't overwrite masked pixels
+    renderer.load_image((100, 0), (2, 1), &[0x8000, 0]);
+    gpu.gp0(&mut renderer, 0xe6000003);
+
+    for &w in &[0x80000000, 0x00000002, 0x00000064, 0x00010002] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(100, 0) == 0x8000);
+    assert!(renderer.vram_pixel(101, 0) == 0x8004);
+}
+
+#[test]
+fn gp0_image_load_position() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // 4x2 image at 100, 50
+    let load = [0xa0000000,
+                0x00320064,
+                0x00020004,
+                0x00020001,
+                0x00040003,
+                0x00060005,
+                0x00080007];
+
+    for &w in &load {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for y in 0..2 {
+        for x in 0..4 {
+            let expected = y * 4 + x + 1;
+
+            assert!(renderer.vram_pixel(100 + x, 50 + y) == expected);
+        }
+    }
+
+    // Untouched neighbours
+    assert!(renderer.vram_pixel(99, 50) == 0);
+    assert!(renderer.vram_pixel(104, 50) == 0);
+    assert!(renderer.vram_pixel(100, 52) == 0);
+
+    // 4x2 image at 1022, 511: must wrap around horizontally and
+    // vertically
+    let load = [0xa0000000,
+                0x01ff03fe,
+                0x00020004,
+                0x00020001,
+                0x00040003,
+                0x00060005,
+                0x00080007];
+
+    for &w in &load {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(1022, 511) == 1);
+    assert!(renderer.vram_pixel(1023, 511) == 2);
+    assert!(renderer.vram_pixel(0, 511) == 3);
+    assert!(renderer.vram_pixel(1, 511) == 4);
+    assert!(renderer.vram_pixel(1022, 0) == 5);
+    assert!(renderer.vram_pixel(1023, 0) == 6);
+    assert!(renderer.vram_pixel(0, 0) == 7);
+    assert!(renderer.vram_pixel(1, 0) == 8);
+}
+
+#[test]
+fn semi_transparency_modes() {
+    // Background: (16, 8, 31)
+    let background = 16 | (8 << 5) | (31 << 10);
+
+    // Monochrome semi-transparent quad color (8, 20, 4), expected
+    // result for each of the 4 modes
+    let expected = [
+        // B / 2 + F / 2
+        (12, 14, 17),
+        // B + F
+        (24, 28, 31),
+        // B - F
+        (8, 0, 27),
+        // B + F / 4
+        (18, 13, 31),
+        ];
+
+    for (mode, &(r, g, b)) in expected.iter().enumerate() {
+        let mut gpu = Gpu::new(VideoClock::Ntsc);
+        let mut renderer = SoftwareRenderer::new();
+
+        gpu.gp0(&mut renderer, 0xe3000000);
+        gpu.gp0(&mut renderer, 0xe407fbff);
+
+        renderer.load_image((0, 0), (8, 8), &[background; 64]);
+
+        // Draw mode: select the semi-transparency equation
+        gpu.gp0(&mut renderer, 0xe1000000 | ((mode as u32) << 5));
+
+        let mut quad = monochrome_quad(0, 0);
+
+        // GP0(0x2a): semi-transparent monochrome quad
+        quad[0] = 0x2a20a040;
+        quad[2] = 0x00000004;
+        quad[3] = 0x00040000;
+        quad[4] = 0x00040004;
+
+        for &w in &quad {
+            gpu.gp0(&mut renderer, w);
+        }
+
+        let pixel = r | (g << 5) | (b << 10);
+
+        assert!(renderer.vram_pixel(1, 1) == pixel);
+        // Outside of the quad
+        assert!(renderer.vram_pixel(4, 4) == background);
+    }
+}
+
+#[test]
+fn semi_transparency_textured() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Green background
+    renderer.load_image((0, 0), (16, 16), &[0x03e0; 16 * 16]);
+
+    // Red 16x16 16bpp texture at 512, 0. The left half has the STP
+    // bit set.
+    let mut texture = [0x001f; 16 * 16];
+
+    for (i, t) in texture.iter_mut().enumerate() {
+        if i % 16 < 8 {
+            *t |= 0x8000;
+        }
+    }
+
+    renderer.load_image((512, 0), (16, 16), &texture);
+
+    // Semi-transparent raw-textured quad, 16bpp texture page at 512,
+    // 0 with the "B + F" semi-transparency mode
+    let quad = [0x2f000000,
+                0x00000000,
+                0x00000000,
+                0x00000010,
+                0x01280010,
+                0x00100000,
+                0x00001000,
+                0x00100010,
+                0x00001010];
+
+    for &w in &quad {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Texels with the STP bit set are blended
+    assert!(renderer.vram_pixel(4, 8) == 0x83ff);
+    // The others are opaque
+    assert!(renderer.vram_pixel(12, 8) == 0x001f);
+}
+
+#[test]
+fn gpustat_ready_for_command() {
+    let mut shared = SharedState::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    let ready = |gpu: &Gpu| (gpu.status() >> 26) & 1 == 1;
+
+    assert!(ready(&gpu));
+
+    let quad = monochrome_quad(10, 20);
+
+    for &w in &quad[0..3] {
+        gpu.gp0(&mut renderer, w);
+
+        // Waiting for the rest of the command
+        assert!(!ready(&gpu));
+    }
+
+    for &w in &quad[3..5] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Busy drawing the quad
+    assert!(!ready(&gpu));
+
+    shared.tk().advance(100);
+    gpu.sync(&mut shared);
+
+    assert!(ready(&gpu));
+
+    // Image load: not ready until all the pixels have been received
+    for &w in &[0xa0000000, 0x00000000, 0x00010002] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(!ready(&gpu));
+
+    gpu.gp0(&mut renderer, 0x00020001);
+
+    assert!(ready(&gpu));
+
+    // Monochrome polyline: not ready until the end marker
+    for &w in &[0x48ffffff, 0x00000000, 0x00100010, 0x00200000] {
+        gpu.gp0(&mut renderer, w);
+
+        assert!(!ready(&gpu));
+    }
+
+    gpu.gp0(&mut renderer, 0x55555555);
+
+    assert!(ready(&gpu));
+}
+
+#[test]
+fn inverted_drawing_area() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Left > right
+    gpu.gp0(&mut renderer, 0xe3000064);
+    gpu.gp0(&mut renderer, 0xe4032032);
+
+    for &w in &[0x20ffffff, 0x00000000, 0x00000200, 0x01000000] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for y in 0..0x100 {
+        for x in 0..0x200 {
+            assert!(renderer.vram_pixel(x, y) == 0);
+        }
+    }
+
+    // Top > bottom
+    gpu.gp0(&mut renderer, 0xe3019000);
+    gpu.gp0(&mut renderer, 0xe40161ff);
+
+    for &w in &[0x20ffffff, 0x00000000, 0x00000200, 0x01000000] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for y in 0..0x100 {
+        for x in 0..0x200 {
+            assert!(renderer.vram_pixel(x, y) == 0);
+        }
+    }
+
+    // Sanity check: the same triangle is drawn with a valid drawing
+    // area
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    for &w in &[0x20ffffff, 0x00000000, 0x00000200, 0x01000000] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(50, 50) == 0x7fff);
+}
+
+#[test]
+fn gp0_clear_cache() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = RecordingRenderer::new();
+
+    // GP0(0x00): NOP
+    gpu.gp0(&mut renderer, 0x00000000);
+
+    assert!(renderer.cache_clears == 0);
+
+    // GP0(0x01): clear cache
+    gpu.gp0(&mut renderer, 0x01000000);
+
+    assert!(renderer.cache_clears == 1);
+}
+
+#[test]
+fn drawing_offset() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Drawing offset: 100, 50
+    gpu.gp0(&mut renderer, 0xe5019064);
+
+    // 4x4 monochrome rectangle at 10, 10
+    for &w in &[0x60ffffff, 0x000a000a, 0x00040004] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(110, 60) == 0x7fff);
+    assert!(renderer.vram_pixel(113, 63) == 0x7fff);
+    assert!(renderer.vram_pixel(109, 60) == 0);
+    assert!(renderer.vram_pixel(114, 63) == 0);
+    assert!(renderer.vram_pixel(10, 10) == 0);
+
+    // Monochrome triangle. The first vertex is at -8, -8 (signed
+    // 11bit coordinates)
+    for &w in &[0x200000ff, 0x07f807f8, 0x07f80010, 0x001007f8] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(92, 42) == 0x001f);
+    assert!(renderer.vram_pixel(100, 42) == 0x001f);
+    assert!(renderer.vram_pixel(91, 42) == 0);
+    assert!(renderer.vram_pixel(92, 41) == 0);
+
+    // Fill rectangle isn't affected by the drawing offset
+    for &w in &[0x0200ff00, 0x01000200, 0x00100010] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(512, 256) == 0x03e0);
+    assert!(renderer.vram_pixel(612, 306) == 0);
+}
+
+#[test]
+fn display_vram_start_alignment() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    let red = 0x001f;
+    let green = 0x03e0;
+    let blue = 0x7c00;
+
+    renderer.load_image((0xff, 0), (3, 1), &[red, green, blue]);
+    renderer.load_image((0x3fd, 0), (3, 1), &[red, green, blue]);
+    renderer.load_image((0, 0), (1, 1), &[red]);
+
+    // GP1(0x05): X start = 0x101, Y start = 0. Must be rounded down to
+    // 0x100
+    gpu.gp1(&mut shared, &mut renderer, 0x05000101, &mut timers);
+
+    {
+        let frame = renderer.export_frame();
+
+        assert!(frame.pixel(0, 0) == 0x00ff00);
+        assert!(frame.pixel(1, 0) == 0x0000ff);
+    }
+
+    // X start = 0x3ff: rounded down to 0x3fe, the display wraps
+    // around
+    gpu.gp1(&mut shared, &mut renderer, 0x050003ff, &mut timers);
+
+    let frame = renderer.export_frame();
+
+    assert!(frame.pixel(0, 0) == 0x00ff00);
+    assert!(frame.pixel(1, 0) == 0x0000ff);
+    assert!(frame.pixel(2, 0) == 0xff0000);
+}
+
+#[test]
+fn trace_renderer() {
+    use super::renderer::NullRenderer;
+    use super::trace::TraceRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = TraceRenderer::new(NullRenderer);
+
+    // GP0(0xe1): draw mode, doesn't draw anything
+    gpu.gp0(&mut renderer, 0xe1000000);
+
+    for &w in &monochrome_quad(10, 20) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    let log = renderer.log();
+
+    assert!(log.len() == 1);
+    assert!(log[0].opcode == 0x28);
+    assert!(log[0].sequence == 1);
+    assert!(log[0].positions == vec![[10, 20], [11, 20], [10, 21], [11, 21]]);
+}
+
+#[test]
+fn trace_renderer_draw_offset() {
+    use super::renderer::NullRenderer;
+    use super::trace::TraceRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = TraceRenderer::new(NullRenderer);
+
+    // GP0(0x20): monochrome triangle
+    let triangle = [0x200000ff, 0x00000000, 0x00000010, 0x00100000];
+
+    for &w in &triangle {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Drawing offset: 100, -8
+    gpu.gp0(&mut renderer, 0xe53fc064);
+
+    for &w in &triangle {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for &w in &monochrome_quad(10, 20) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.primitive_count() == 3);
+
+    let log = renderer.log();
+
+    assert!(log[0].draw_offset == [0, 0]);
+    assert!(log[0].positions == vec![[0, 0], [16, 0], [0, 16]]);
+    assert!(log[0].colors == vec![[0xff, 0, 0]; 3]);
+
+    assert!(log[1].opcode == 0x20);
+    assert!(log[1].draw_offset == [100, -8]);
+    assert!(log[1].positions == log[0].positions);
+
+    assert!(log[2].opcode == 0x28);
+    assert!(log[2].draw_offset == [100, -8]);
+    assert!(log[2].colors == vec![[0, 0, 0xff]; 4]);
+
+    renderer.clear();
+
+    assert!(renderer.primitive_count() == 0);
+}
+
+#[test]
+fn primitive_shading() {
+    use super::renderer::{NullRenderer, Shading};
+    use super::trace::TraceRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = TraceRenderer::new(NullRenderer);
+
+    let commands: &[&[u32]] = &[
+        // GP0(0x20): monochrome triangle
+        &[0x20000000, 0, 0x10, 0x100000],
+        // GP0(0x30): shaded triangle
+        &[0x30000000, 0, 0, 0x10, 0, 0x100000],
+        // GP0(0x40): monochrome line
+        &[0x40000000, 0, 0x10],
+        // GP0(0x50): shaded line
+        &[0x50000000, 0, 0, 0x10],
+        // GP0(0x74): 8x8 textured rectangle
+        &[0x74000000, 0, 0],
+        ];
+
+    for c in commands {
+        for &w in c.iter() {
+            gpu.gp0(&mut renderer, w);
+        }
+    }
+
+    let shading: Vec<_> = renderer.log().iter().map(|p| p.shading).collect();
+
+    assert!(shading == vec![Shading::Flat,
+                            Shading::Gouraud,
+                            Shading::Flat,
+                            Shading::Gouraud,
+                            Shading::Flat]);
+}
+
+#[test]
+fn textured_rect_uv_wrap() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Draw mode: 16bpp texture page at 512, 0
+    gpu.gp0(&mut renderer, 0xe1000108);
+
+    let red = 0x001f;
+    let green = 0x03e0;
+    let blue = 0x7c00;
+    let white = 0x7fff;
+
+    let mut texture = [blue; 16];
+    texture[0] = red;
+    texture[1] = green;
+    texture[8] = white;
+
+    renderer.load_image((512, 0), (16, 1), &texture);
+
+    // GP0(0x65): raw textured rectangle 300x1 at 0, 100
+    let rect = [0x65000000, 0x00640000, 0x00000000, 0x0001012c];
+
+    for &w in &rect {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 100) == red);
+    assert!(renderer.vram_pixel(8, 100) == white);
+    // The texture coordinates wrap around within the page
+    assert!(renderer.vram_pixel(256, 100) == red);
+    assert!(renderer.vram_pixel(257, 100) == green);
+    assert!(renderer.vram_pixel(264, 100) == white);
+
+    // Texture window: X mask 1, offset 0. Bit 3 of U is forced to 0
+    gpu.gp0(&mut renderer, 0xe2000001);
+
+    for &w in &rect {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(8, 100) == red);
+    assert!(renderer.vram_pixel(9, 100) == green);
+    assert!(renderer.vram_pixel(264, 100) == red);
+}
+
+#[test]
+fn gp0_image_store_roundtrip() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // 3x3 image at 1022, 100: odd number of pixels and wraps around
+    // horizontally. The high half of the last word is padding.
+    let pixels = [0x0001, 0x1234, 0x7fff,
+                  0x8000, 0x4321, 0x0f0f,
+                  0xf0f0, 0x00ff, 0xabcd];
+
+    let load = [0xa0000000,
+                0x006403fe,
+                0x00030003,
+                0x12340001,
+                0x80007fff,
+                0x0f0f4321,
+                0x00fff0f0,
+                0xdeadabcd];
+
+    for &w in &load {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(1022, 100) == pixels[0]);
+    assert!(renderer.vram_pixel(0, 100) == pixels[2]);
+    // The padding must not be written to VRAM
+    assert!(renderer.vram_pixel(1, 102) == 0);
+
+    for &w in &[0xc0000000, 0x006403fe, 0x00030003] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    let mut stored = Vec::new();
+
+    for _ in 0..5 {
+        let w = gpu.read();
+
+        stored.push(w as u16);
+        stored.push((w >> 16) as u16);
+    }
+
+    assert!(&stored[0..9] == &pixels[..]);
+    // Padding of the last word is 0
+    assert!(stored[9] == 0);
+
+    // Once the transfer is over GPUREAD keeps returning the last word
+    assert!(gpu.read() == 0x0000abcd);
+}
+
+#[test]
+fn semi_transparent_quad_diagonal() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Draw mode: B + F
+    gpu.gp0(&mut renderer, 0xe1000020);
+
+    // GP0(0x2a): semi-transparent monochrome 8x8 quad, color (4, 4,
+    // 4). The v1-v2 diagonal goes from (8, 0) to (0, 8).
+    let quad = [0x2a202020,
+                0x00000000,
+                0x00000008,
+                0x00080000,
+                0x00080008];
+
+    for &w in &quad {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    let once = 4 | (4 << 5) | (4 << 10);
+
+    // Every pixel, including the ones on the diagonal, must be
+    // blended exactly once
+    for y in 0..8 {
+        for x in 0..8 {
+            assert!(renderer.vram_pixel(x, y) == once);
+        }
+    }
+
+    assert!(renderer.vram_pixel(8, 0) == 0);
+    assert!(renderer.vram_pixel(0, 8) == 0);
+}
+
+#[test]
+fn gp1_reset_state() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut renderer = SoftwareRenderer::new();
+
+    for &clock in &[VideoClock::Ntsc, VideoClock::Pal] {
+        let mut fresh = Gpu::new(clock);
+        let mut gpu = Gpu::new(clock);
+
+        assert!(fresh.status() == 0x14802000);
+
+        // The reset synchronizes the GPU, which latches the VBLANK
+        // signal. Do the same on the fresh instance.
+        fresh.sync(&mut shared);
+
+        gpu.gp1(&mut shared, &mut renderer, 0x00000000, &mut timers);
+
+        assert!(gpu.save_state() == fresh.save_state());
+
+        // Change the drawing and display configuration: PAL 480i
+        // 24bpp, draw mode, drawing area and offset, mask settings
+        for &w in &[0xe100060f, 0xe3000401, 0xe407fbff,
+                    0xe5019064, 0xe6000003] {
+            gpu.gp0(&mut renderer, w);
+        }
+
+        for &w in &[0x08000037, 0x03000000, 0x05000100, 0x04000002] {
+            gpu.gp1(&mut shared, &mut renderer, w, &mut timers);
+        }
+
+        assert!(gpu.status() != fresh.status());
+
+        gpu.gp1(&mut shared, &mut renderer, 0x00000000, &mut timers);
+
+        assert!(gpu.status() == fresh.status());
+    }
+}
+
+#[test]
+fn gp1_reset_keeps_vram() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    renderer.load_image((100, 50), (2, 1), &[0x1234, 0x4321]);
+
+    // 2x1 image store, never read back
+    for &w in &[0xc0000000, 0x00320064, 0x00010002] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Partial 2x1 image load at 100, 50: the pixel data never arrives
+    for &w in &[0xa0000000, 0x00320064, 0x00010002] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(gpu.status() & (1 << 26) == 0);
+
+    gpu.gp1(&mut shared, &mut renderer, 0x00000000, &mut timers);
+
+    // Ready to receive a new command
+    assert!(gpu.status() & (1 << 26) != 0);
+    // The image store has been aborted
+    assert!(gpu.read() == 0);
+
+    // GP0(0x02): fill 16x1 rectangle at 16, 0. If the reset didn't
+    // work this would be taken as pixel data for the image load.
+    for &w in &[0x0200001f, 0x00000010, 0x00010010] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(16, 0) == 0x0003);
+    assert!(renderer.vram_pixel(31, 0) == 0x0003);
+
+    // VRAM contents survive the reset
+    assert!(renderer.vram_pixel(100, 50) == 0x1234);
+    assert!(renderer.vram_pixel(101, 50) == 0x4321);
+}
+
+#[test]
+fn draw_to_display_area() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Display enabled, 256x480 interlaced at 0, 0. The top field
+    // (odd lines) is being displayed.
+    gpu.gp1(&mut shared, &mut renderer, 0x03000000, &mut timers);
+    gpu.gp1(&mut shared, &mut renderer, 0x05000000, &mut timers);
+    gpu.gp1(&mut shared, &mut renderer, 0x08000024, &mut timers);
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Draw mode: drawing to the display area prohibited
+    gpu.gp0(&mut renderer, 0xe1000000);
+
+    // Inside of the display area, on a line of the displayed field
+    // and on a line of the other field
+    for &w in &monochrome_quad(10, 11) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for &w in &monochrome_quad(10, 10) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Outside of the display area
+    for &w in &monochrome_quad(300, 11) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(10, 11) == 0);
+    assert!(renderer.vram_pixel(10, 10) != 0);
+    assert!(renderer.vram_pixel(300, 11) != 0);
+
+    // Draw mode: drawing to the display area allowed
+    gpu.gp0(&mut renderer, 0xe1000400);
+
+    for &w in &monochrome_quad(10, 11) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(10, 11) != 0);
+    // GPUSTAT bit 10 reflects the setting
+    assert!(gpu.status() & (1 << 10) != 0);
+}
+
+#[test]
+fn draw_to_display_area_progressive() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Display enabled, 256x240 progressive at 0, 0
+    gpu.gp1(&mut shared, &mut renderer, 0x03000000, &mut timers);
+    gpu.gp1(&mut shared, &mut renderer, 0x05000000, &mut timers);
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Draw mode: drawing to the display area prohibited
+    gpu.gp0(&mut renderer, 0xe1000000);
+
+    for &w in &monochrome_quad(10, 10) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for &w in &monochrome_quad(10, 11) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // The restriction only applies to 480 line interlaced mode
+    assert!(renderer.vram_pixel(10, 10) != 0);
+    assert!(renderer.vram_pixel(10, 11) != 0);
+}
+
+#[test]
+fn textured_rect_flip() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // 4x4 16bpp texture at 512, 0: texel value is 0x100 * v + u + 1
+    let mut texture = [0; 16];
+
+    for v in 0..4 {
+        for u in 0..4 {
+            texture[v * 4 + u] = 0x100 * v as u16 + u as u16 + 1;
+        }
+    }
+
+    renderer.load_image((512, 0), (4, 4), &texture);
+
+    // Draw mode: 16bpp texture page at 512, 0 with horizontal flip
+    gpu.gp0(&mut renderer, 0xe1001108);
+
+    // GP0(0x65): raw textured 4x1 rectangle at 0, 100, U = 3
+    for &w in &[0x65000000, 0x00640000, 0x00000003, 0x00010004] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 100) == 4);
+    assert!(renderer.vram_pixel(1, 100) == 3);
+    assert!(renderer.vram_pixel(2, 100) == 2);
+    assert!(renderer.vram_pixel(3, 100) == 1);
+
+    // Vertical flip
+    gpu.gp0(&mut renderer, 0xe1002108);
+
+    // GP0(0x65): raw textured 1x4 rectangle at 0, 200, V = 3
+    for &w in &[0x65000000, 0x00c80000, 0x00000300, 0x00040001] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 200) == 0x301);
+    assert!(renderer.vram_pixel(0, 201) == 0x201);
+    assert!(renderer.vram_pixel(0, 202) == 0x101);
+    assert!(renderer.vram_pixel(0, 203) == 0x001);
+}
+
+#[test]
+fn rect_max_size() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // GP0(0x60): monochrome rectangle, the size is truncated to
+    // 1023x511
+    for &w in &[0x60ff0000, 0x00000000, 0xffffffff] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 0) == 0x7c00);
+    assert!(renderer.vram_pixel(1022, 510) == 0x7c00);
+    assert!(renderer.vram_pixel(1023, 510) == 0);
+    assert!(renderer.vram_pixel(1022, 511) == 0);
+
+    // White 16bpp texture page at 512, 0
+    let texture = [0x7fff; 256 * 256];
+    renderer.load_image((512, 0), (256, 256), &texture);
+
+    // Draw mode: 16bpp texture page at 512, 0 with horizontal and
+    // vertical flip
+    gpu.gp0(&mut renderer, 0xe1003108);
+
+    // GP0(0x65): raw textured rectangle with the maximum size
+    for &w in &[0x65000000, 0x00000000, 0x00000000, 0xffffffff] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(0, 0) == 0x7fff);
+    assert!(renderer.vram_pixel(1022, 510) == 0x7fff);
+    assert!(renderer.vram_pixel(1023, 510) == 0);
+    assert!(renderer.vram_pixel(1022, 511) == 0);
+}
+
+#[test]
+fn video_standard() {
+    use super::VMode;
+
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Pal);
+    let mut renderer = SoftwareRenderer::new();
+
+    // The GPU starts in NTSC mode even on PAL consoles
+    assert!(gpu.video_standard() == VMode::Ntsc);
+    assert!(gpu.video_standard_forced());
+
+    let ntsc_rate = gpu.refresh_rate();
+
+    // GP1(0x08): 320x240, PAL
+    gpu.gp1(&mut shared, &mut renderer, 0x08000009, &mut timers);
+
+    assert!(gpu.video_standard() == VMode::Pal);
+    assert!(!gpu.video_standard_forced());
+    assert!(gpu.video_clock() == VideoClock::Pal);
+
+    let pal_rate = gpu.refresh_rate();
+
+    assert!(pal_rate > 49. && pal_rate < 51.);
+    assert!(ntsc_rate > 59. && ntsc_rate < 61.);
+}
+
+#[test]
+fn textured_quad_clut() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    let white = 0x7fff;
+    let red = 0x001f;
+    let green = 0x03e0;
+    let blue = 0x7c00;
+
+    // CLUT at 0, 256
+    renderer.load_image((0, 256), (4, 1), &[white, red, green, blue]);
+
+    // 4bpp texture at 512, 0: texels 0, 1, 2, 3
+    renderer.load_image((512, 0), (1, 1), &[0x3210]);
+    // 8bpp texture at 576, 0: texels 3, 2, 1, 0
+    renderer.load_image((576, 0), (2, 1), &[0x0203, 0x0001]);
+
+    // GP0(0x2c): blended textured 4x1 quad at `y`
+    let quad = |color: u32, y: u32, page: u32| -> [u32; 9] {
+        [0x2c000000 | color,
+         y << 16, 0x40000000,
+         (y << 16) | 4, (page << 16) | 4,
+         ((y + 1) << 16), 0x00000100,
+         ((y + 1) << 16) | 4, 0x00000104]
+    };
+
+    let draw = |gpu: &mut Gpu, renderer: &mut SoftwareRenderer, q: [u32; 9]| {
+        for &w in &q {
+            gpu.gp0(renderer, w);
+        }
+    };
+
+    // Neutral color: the raw texels are drawn
+    draw(&mut gpu, &mut renderer, quad(0x808080, 0, 0x0008));
+
+    assert!(renderer.vram_pixel(0, 0) == white);
+    assert!(renderer.vram_pixel(1, 0) == red);
+    assert!(renderer.vram_pixel(2, 0) == green);
+    assert!(renderer.vram_pixel(3, 0) == blue);
+
+    // 8bpp page
+    draw(&mut gpu, &mut renderer, quad(0x808080, 2, 0x0089));
+
+    assert!(renderer.vram_pixel(0, 2) == blue);
+    assert!(renderer.vram_pixel(1, 2) == green);
+    assert!(renderer.vram_pixel(2, 2) == red);
+    assert!(renderer.vram_pixel(3, 2) == white);
+
+    // Half intensity red, full intensity green, double intensity
+    // blue
+    draw(&mut gpu, &mut renderer, quad(0xff8040, 4, 0x0008));
+
+    assert!(renderer.vram_pixel(0, 4) == 0x0f | (0x1f << 5) | (0x1f << 10));
+    assert!(renderer.vram_pixel(1, 4) == 0x000f);
+    assert!(renderer.vram_pixel(2, 4) == green);
+    assert!(renderer.vram_pixel(3, 4) == blue);
+}
+
+#[test]
+fn gpustat_line_parity() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Run for a bit more than a frame, returning the (active display,
+    // display line, field, GPUSTAT bit 31) observed
+    fn run(gpu: &mut Gpu,
+           shared: &mut SharedState) -> Vec<(bool, u16, u32, u32)> {
+        (0..6000).map(|_| {
+            shared.tk().advance(100);
+            gpu.sync(shared);
+
+            let line = gpu.display_line;
+            let active = line >= gpu.display_line_start &&
+                         line < gpu.display_line_end;
+
+            (active, line, gpu.field as u32, gpu.status() >> 31)
+        }).collect()
+    }
+
+    // 320x240 progressive
+    gpu.gp1(&mut shared, &mut renderer, 0x08000001, &mut timers);
+
+    let samples = run(&mut gpu, &mut shared);
+
+    assert!(samples.iter().any(|&(_, _, _, b)| b == 0));
+    assert!(samples.iter().any(|&(_, _, _, b)| b == 1));
+
+    for &(active, line, _, bit) in &samples {
+        if active {
+            assert!(bit == (line & 1) as u32);
+        } else {
+            // Always 0 in the vertical blanking
+            assert!(bit == 0);
+        }
+    }
+
+    // 320x480 interlaced: the parity of the VRAM line depends on the
+    // field
+    gpu.gp1(&mut shared, &mut renderer, 0x08000025, &mut timers);
+
+    let samples = run(&mut gpu, &mut shared);
+
+    assert!(samples.iter().any(|&(a, _, _, b)| a && b == 0));
+    assert!(samples.iter().any(|&(a, _, _, b)| a && b == 1));
+
+    for &(active, _, field, bit) in &samples {
+        if active {
+            assert!(bit == field);
+        } else {
+            assert!(bit == 0);
+        }
+    }
+}
+
+#[test]
+fn gpustat_480i() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // GP1(0x08): 640x480 interlaced
+    gpu.gp1(&mut shared, &mut renderer, 0x08000027, &mut timers);
+
+    let status = gpu.status();
+
+    // Vertical resolution: 480 lines
+    assert!(status & (1 << 19) != 0);
+    // Interlaced
+    assert!(status & (1 << 22) != 0);
+
+    // The vertical timings must keep running: software waiting for
+    // the vblank or for the field to change mustn't hang
+    let mut fields = [false; 2];
+
+    for _ in 0..20_000 {
+        shared.tk().advance(100);
+        gpu.sync(&mut shared);
+
+        let status = gpu.status();
+
+        assert!(status & (1 << 19) != 0);
+
+        fields[((status >> 13) & 1) as usize] = true;
+    }
+
+    assert!(shared.frame() >= 3);
+    assert!(fields == [true, true]);
+}
+
+#[test]
+fn dithering() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Drawing area covers the whole VRAM
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // 4x4 gouraud shaded quad with the same grey on all vertices
+    let quad = |x: u32| [0x38868686, x,
+                         0x00868686, x + 4,
+                         0x00868686, x + 0x40000,
+                         0x00868686, x + 0x40004];
+
+    // Dithering disabled
+    for &w in &quad(0) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for y in 0..4 {
+        for x in 0..4 {
+            assert!(renderer.vram_pixel(x, y) == 0x4210);
+        }
+    }
+
+    // Draw mode: dithering enabled
+    gpu.gp0(&mut renderer, 0xe1000200);
+
+    for &w in &quad(16) {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // 0x86 + offset rounds up to the next 5bit value when the dither
+    // offset is at least 2
+    let expected = [[0x10, 0x10, 0x10, 0x10],
+                    [0x11, 0x10, 0x11, 0x10],
+                    [0x10, 0x10, 0x10, 0x10],
+                    [0x11, 0x10, 0x11, 0x10]];
+
+    for y in 0..4 {
+        for x in 0..4 {
+            let c = expected[y as usize][x as usize];
+
+            assert!(renderer.vram_pixel(16 + x, y) ==
+                    c | (c << 5) | (c << 10));
+        }
+    }
+
+    // Monochrome primitives are never dithered
+    let quad = [0x28868686, 32, 36, 0x40020, 0x40024];
+
+    for &w in &quad {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for y in 0..4 {
+        for x in 0..4 {
+            assert!(renderer.vram_pixel(32 + x, y) == 0x4210);
+        }
+    }
+}
+
+#[test]
+fn gp1_get_info() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Texture window
+    gpu.gp0(&mut renderer, 0xe20abcde);
+    // Drawing area: 16, 8 to 335, 247
+    gpu.gp0(&mut renderer, 0xe3002010);
+    gpu.gp0(&mut renderer, 0xe403dd4f);
+    // Drawing offset: -2, 5
+    gpu.gp0(&mut renderer, 0xe5002ffe);
+
+    let mut info = |command: u32| {
+        gpu.gp1(&mut shared, &mut renderer, 0x10000000 | command, &mut timers);
+        gpu.read()
+    };
+
+    assert!(info(2) == 0x0abcde);
+    assert!(info(3) == 0x002010);
+    assert!(info(4) == 0x03dd4f);
+    assert!(info(5) == 0x002ffe);
+    // GPU version
+    assert!(info(7) == 2);
+    // The upper bits of the command are ignored
+    assert!(info(0xf3) == 0x002010);
+
+    // These don't modify the GPUREAD register
+    assert!(info(0) == 0x002010);
+    assert!(info(6) == 0x002010);
+
+    assert!(info(8) == 0);
+}
+
+#[test]
+fn lines() {
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Drawing area: 0, 0 to 255, 255
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe403fcff);
+
+    // Red horizontal line from 2, 1 to 9, 1
+    for &w in &[0x400000ff, 0x00010002, 0x00010009] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Both end points are drawn
+    assert!(renderer.vram_pixel(1, 1) == 0);
+    for x in 2..10 {
+        assert!(renderer.vram_pixel(x, 1) == 0x001f);
+    }
+    assert!(renderer.vram_pixel(10, 1) == 0);
+
+    // Green diagonal from 20, 10 to 10, 20
+    for &w in &[0x4000ff00, 0x000a0014, 0x0014000a] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for i in 0..11 {
+        assert!(renderer.vram_pixel(20 - i, 10 + i) == 0x03e0);
+        assert!(renderer.vram_pixel(20 - i, 11 + i) == 0);
+    }
+
+    // Shaded vertical line from black to blue, the colors are
+    // interpolated
+    for &w in &[0x50000000, 0x00200040, 0x00f80000, 0x00270040] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    assert!(renderer.vram_pixel(64, 32) == 0);
+    assert!(renderer.vram_pixel(64, 35) == 13 << 10);
+    assert!(renderer.vram_pixel(64, 39) == 0x1f << 10);
+
+    // Monochrome polyline: 100, 100 -> 110, 100 -> 110, 105 -> end
+    for &w in &[0x48ffffff,
+                0x00640064, 0x0064006e, 0x0069006e,
+                0x55555555] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    for x in 100..111 {
+        assert!(renderer.vram_pixel(x, 100) == 0x7fff);
+    }
+    for y in 100..106 {
+        assert!(renderer.vram_pixel(110, y) == 0x7fff);
+    }
+    assert!(renderer.vram_pixel(110, 106) == 0);
+
+    // The GPU is ready to receive a new command
+    gpu.gp0(&mut renderer, 0x400000ff);
+    gpu.gp0(&mut renderer, 0x00c80000);
+    gpu.gp0(&mut renderer, 0x00c80000);
+
+    assert!(renderer.vram_pixel(0, 200) == 0x001f);
+
+    // Lines are clipped to the drawing area
+    gpu.gp0(&mut renderer, 0x400000ff);
+    gpu.gp0(&mut renderer, 0x00f000f8);
+    gpu.gp0(&mut renderer, 0x00f00108);
+
+    assert!(renderer.vram_pixel(255, 240) == 0x001f);
+    assert!(renderer.vram_pixel(256, 240) == 0);
+}
+
+#[test]
+fn mask_bit() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Drawing area covers the whole VRAM
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Load a single pixel at `x`, 0
+    let load = |gpu: &mut Gpu, renderer: &mut SoftwareRenderer, x, p| {
+        for &w in &[0xa0000000, x, 0x00010001, p] {
+            gpu.gp0(renderer, w);
+        }
+    };
+
+    // Force the mask bit
+    gpu.gp0(&mut renderer, 0xe6000001);
+
+    for &w in &monochrome_quad(0, 0) {
+        gpu.gp0(&mut renderer, w);
+    }
+    load(&mut gpu, &mut renderer, 1, 0x03e0);
+
+    assert!(renderer.vram_pixel(0, 0) == 0xfc00);
+    assert!(renderer.vram_pixel(1, 0) == 0x83e0);
+
+    // Preserve the masked pixels
+    gpu.gp0(&mut renderer, 0xe6000002);
+
+    for &w in &[0x28000038, 0, 3, 0x10000, 0x10003] {
+        gpu.gp0(&mut renderer, w);
+    }
+    load(&mut gpu, &mut renderer, 1, 0x7c00);
+    load(&mut gpu, &mut renderer, 3, 0x7c00);
+
+    assert!(renderer.vram_pixel(0, 0) == 0xfc00);
+    assert!(renderer.vram_pixel(1, 0) == 0x83e0);
+    assert!(renderer.vram_pixel(2, 0) == 0x0007);
+    assert!(renderer.vram_pixel(3, 0) == 0x7c00);
+
+    // GP1(0x00) resets the mask settings
+    gpu.gp1(&mut shared, &mut renderer, 0x00000000, &mut timers);
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    load(&mut gpu, &mut renderer, 1, 0x7c00);
+
+    assert!(renderer.vram_pixel(1, 0) == 0x7c00);
+}
+
+#[test]
+fn gp1_texture_disable() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // Drawing area covers the whole VRAM
+    gpu.gp0(&mut renderer, 0xe3000000);
+    gpu.gp0(&mut renderer, 0xe407fbff);
+
+    // Red 16x16 16bpp texture at 512, 0
+    let texture = [0x001f; 16 * 16];
+    renderer.load_image((512, 0), (16, 16), &texture);
+
+    // Raw-textured quad with a green color, 16bpp texture page at
+    // 512, 0
+    let quad = [0x2d00ff00,
+                0x00000000,
+                0x00000000,
+                0x00000010,
+                0x01080010,
+                0x00100000,
+                0x00001000,
+                0x00100010,
+                0x00001010];
+
+    let draw = |gpu: &mut Gpu, renderer: &mut SoftwareRenderer| {
+        // Draw mode with the "texture disable" bit set
+        gpu.gp0(renderer, 0xe1000800);
+
+        for &w in &quad {
+            gpu.gp0(renderer, w);
+        }
+
+        renderer.vram_pixel(8, 8)
+    };
+
+    // Texture disable hasn't been allowed yet
+    assert!(draw(&mut gpu, &mut renderer) == 0x001f);
+
+    gpu.gp1(&mut shared, &mut renderer, 0x09000001, &mut timers);
+
+    assert!(draw(&mut gpu, &mut renderer) == 0x03e0);
+
+    gpu.gp1(&mut shared, &mut renderer, 0x09000000, &mut timers);
+
+    assert!(draw(&mut gpu, &mut renderer) == 0x001f);
+
+    // Unknown commands are ignored
+    gpu.gp1(&mut shared, &mut renderer, 0x20000000, &mut timers);
+    gpu.gp1(&mut shared, &mut renderer, 0x3f000000, &mut timers);
+
+    // The two MSBs of the opcode are ignored and 0x11 to 0x1f mirror
+    // GP1(0x10): get the GPU version
+    gpu.gp1(&mut shared, &mut renderer, 0xdf000007, &mut timers);
+
+    assert!(gpu.read() == 2);
+}
+
+#[test]
+#[should_panic]
+fn gp1_strict() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.set_strict(true);
+
+    gpu.gp1(&mut shared, &mut renderer, 0x20000000, &mut timers);
+}
+
+#[test]
+fn gp1_display_mode_bit7() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // GP1(0x08): 320x240 with the unsupported "reverse" flag set,
+    // only logged when not in strict mode
+    gpu.gp1(&mut shared, &mut renderer, 0x08000081, &mut timers);
+
+    assert!(gpu.display_resolution() == (320, 240));
+}
+
+#[test]
+#[should_panic]
+fn gp1_display_mode_bit7_strict() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    gpu.set_strict(true);
+
+    gpu.gp1(&mut shared, &mut renderer, 0x08000081, &mut timers);
+}
+
+#[test]
+fn display_rect() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    let expected = DisplayRect {
+        top_left: (0, 0),
+        dimensions: (256, 240),
+        depth_24bpp: false,
+    };
+
+    assert!(gpu.display_rect() == expected);
+    assert!(gpu.horizontal_res().width() == 256);
+
+    let mut gp1 = |gpu: &mut Gpu, val: u32| {
+        gpu.gp1(&mut shared, &mut renderer, val, &mut timers);
+    };
+
+    // Display start: 320, 16
+    gp1(&mut gpu, 0x05004140);
+    // Typical NTSC horizontal range: 0x260 to 0xc60
+    gp1(&mut gpu, 0x06c60260);
+    // Vertical range: 0x10 to 0x100
+    gp1(&mut gpu, 0x07040010);
+    // 320x240, 24bpp
+    gp1(&mut gpu, 0x08000011);
+
+    let expected = DisplayRect {
+        top_left: (320, 16),
+        dimensions: (320, 240),
+        depth_24bpp: true,
+    };
+
+    assert!(gpu.display_rect() == expected);
+    assert!(gpu.horizontal_res().width() == 320);
+
+    // 368x480 interlaced: the width is rounded to a multiple of 4
+    gp1(&mut gpu, 0x08000064);
+
+    let rect = gpu.display_rect();
+
+    assert!(rect.dimensions == (364, 480));
+    assert!(gpu.horizontal_res().width() == 368);
+
+    // Shorter vertical range
+    gp1(&mut gpu, 0x07038020);
+
+    assert!(gpu.display_rect().dimensions == (364, 384));
+}
+
+#[test]
+fn save_state() {
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = SoftwareRenderer::new();
+
+    // 320x240 PAL, display at 0, 256
+    gpu.gp1(&mut shared, &mut renderer, 0x08000009, &mut timers);
+    gpu.gp1(&mut shared, &mut renderer, 0x05040000, &mut timers);
+
+    // Drawing configuration
+    for &w in &[0xe1000608, 0xe20abcde, 0xe3000000, 0xe403fcff,
+                0xe5002ffe, 0xe6000001] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    // Start an image load: 4x2 at 16, 8, only send half of the data
+    for &w in &[0xa0000000, 0x00080010, 0x00020004,
+                0x11112222, 0x33334444] {
+        gpu.gp0(&mut renderer, w);
+    }
+
+    let state = gpu.save_state();
+
+    let mut restored = Gpu::new(VideoClock::Pal);
+    let mut restored_renderer = SoftwareRenderer::new();
+
+    assert!(restored.load_state(&state) == Ok(()));
+    restored.configure_renderer(&mut restored_renderer);
+
+    assert!(restored.save_state() == state);
+    assert!(restored.status() == gpu.status());
+    assert!(restored.video_clock() == VideoClock::Ntsc);
+    assert!(restored.display_rect() == gpu.display_rect());
+
+    // Both GPUs must finish the image load and draw the same thing
+    let finish = |gpu: &mut Gpu, renderer: &mut SoftwareRenderer| {
+        for &w in &[0x55556666, 0x77778888, 0x30ff0000, 0x00000000,
+                    0x0000ff00, 0x00000020, 0x000000ff, 0x00200000] {
+            gpu.gp0(renderer, w);
+        }
+    };
+
+    finish(&mut gpu, &mut renderer);
+    finish(&mut restored, &mut restored_renderer);
+
+    assert!(restored_renderer.dump_vram() == renderer.dump_vram());
+    // Mask bit forced by GP0(0xE6)
+    assert!(restored_renderer.vram_pixel(19, 9) & 0x8000 != 0);
+
+    // Invalid states are rejected and leave the GPU untouched
+    let mut gpu = Gpu::new(VideoClock::Pal);
+    let before = gpu.save_state();
+
+    assert!(gpu.load_state(&state[0..state.len() - 1]) ==
+            Err(StateError::Truncated));
+    assert!(gpu.load_state(b"junk") == Err(StateError::BadMagic));
+    assert!(gpu.save_state() == before);
+}
+
+#[test]
+fn load_corrupted_state() {
+    use super::Gp0State;
+
+    let corrupt = |f: &Fn(&mut Gpu)| {
+        let mut gpu = Gpu::new(VideoClock::Ntsc);
+
+        f(&mut gpu);
+
+        let state = gpu.save_state();
+
+        let mut gpu = Gpu::new(VideoClock::Ntsc);
+        let before = gpu.save_state();
+
+        let res = gpu.load_state(&state);
+
+        assert!(gpu.save_state() == before);
+
+        res
+    };
+
+    let invalid = |what| Err(StateError::InvalidValue(what));
+
+    // Image load bigger than the VRAM
+    assert!(corrupt(&|gpu| {
+        gpu.load_buffer.reset(0, 0, 2048, 512);
+    }) == invalid("image buffer"));
+
+    // More parameters than the current command takes
+    assert!(corrupt(&|gpu| {
+        gpu.gp0(&mut SoftwareRenderer::new(), 0x20000000);
+        gpu.gp0_words_remaining = 20;
+    }) == invalid("GP0 words remaining"));
+
+    // More pixels than the image load buffer holds
+    assert!(corrupt(&|gpu| {
+        for &w in &[0xa0000000, 0x00000000, 0x00010002] {
+            gpu.gp0(&mut SoftwareRenderer::new(), w);
+        }
+        gpu.gp0_words_remaining = 2;
+    }) == invalid("GP0 words remaining"));
+
+    // Shaded polyline vertex without the color word
+    assert!(corrupt(&|gpu| {
+        gpu.gp0_state = Gp0State::ShadedPolylineVertex;
+    }) == invalid("GP0 words remaining"));
+
+    // FIFO longer than 16 words
+    assert!(corrupt(&|gpu| {
+        gpu.fifo.len = 17;
+    }) == invalid("GP0 FIFO"));
+
+    // Sanity check: an untouched state loads fine
+    assert!(corrupt(&|_| ()) == Ok(()));
+}
+
+#[test]
+fn vblank_frame_start() {
+    use cpu::CPU_FREQ_HZ;
+    use interrupt::Interrupt;
+    use timekeeper::Cycles;
+    use super::renderer::NullRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+
+    // GP1(0x08): NTSC timings
+    gpu.gp1(&mut shared, &mut NullRenderer, 0x08000000, &mut timers);
+
+    let vblank = 1 << (Interrupt::VBlank as u16);
+    let frame = shared.frame();
+
+    // Start of the vertical blanking: the interrupt fires but the
+    // next frame only starts at the end of the blanking
+    gpu.vblank(&mut shared, true);
+
+    assert!(shared.irq_state().status() & vblank != 0);
+    assert!(shared.frame() == frame);
+
+    gpu.vblank(&mut shared, false);
+
+    assert!(shared.frame() == frame.wrapping_add(1));
+
+    // When driven by `sync` we get exactly one frame per interrupt
+    let mut irqs = 0;
+    let frame = shared.frame();
+
+    shared.irq_state().ack(!vblank);
+
+    while shared.tk().now() < CPU_FREQ_HZ as Cycles {
+        shared.tk().advance(10_000);
+        gpu.sync(&mut shared);
+
+        if shared.irq_state().status() & vblank != 0 {
+            irqs += 1;
+            shared.irq_state().ack(!vblank);
+        }
+    }
+
+    let frames = shared.frame().wrapping_sub(frame);
+
+    assert!(irqs == 60 || irqs == 59);
+    assert!(frames == irqs || frames + 1 == irqs);
+}
+
+#[test]
+fn gpu_bad_access_width() {
+    use memory::{Byte, HalfWord};
+    use super::renderer::NullRenderer;
+
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut renderer = NullRenderer;
+
+    let status = gpu.status();
+
+    // Only word accesses are supported: this GP0(0xE1) and GP1(0x03)
+    // are ignored
+    gpu.store::<HalfWord>(&mut shared, &mut renderer, &mut timers,
+                          0, 0xe1000400);
+    gpu.store::<Byte>(&mut shared, &mut renderer, &mut timers,
+                      4, 0x03000000);
+
+    assert!(gpu.status() == status);
+    assert!(gpu.load::<HalfWord>(&mut shared, 4) == 0);
+    assert!(gpu.load::<Word>(&mut shared, 4) == status);
+}
+
+#[test]
+fn vblank_interrupt_rate() {
+    use cpu::CPU_FREQ_HZ;
+    use interrupt::Interrupt;
+    use timekeeper::Cycles;
+    use super::renderer::NullRenderer;
+
+    for &(clock, display_mode, rate) in &[(VideoClock::Ntsc, 0, 60),
+                                          (VideoClock::Pal, 0x8, 50)] {
+        let mut gpu = Gpu::new(clock);
+        let mut shared = SharedState::new();
+        let mut timers = Timers::new();
+
+        // GP1(0x08): select NTSC or PAL timings
+        gpu.gp1(&mut shared,
+                &mut NullRenderer,
+                0x08000000 | display_mode,
+                &mut timers);
+
+        let vblank = 1 << (Interrupt::VBlank as u16);
+        let mut count = 0;
+
+        // Run for one second of emulated time
+        while shared.tk().now() < CPU_FREQ_HZ as Cycles {
+            shared.tk().advance(10_000);
+            gpu.sync(&mut shared);
+
+            if shared.irq_state().status() & vblank != 0 {
+                count += 1;
+                shared.irq_state().ack(!vblank);
+            }
+        }
+
+        assert!(count == rate || count == rate - 1);
+    }
+}
+
+#[test]
+fn video_standard_resolution() {
+    use super::VMode;
+    use super::renderer::NullRenderer;
+
+    let mut gpu = Gpu::new(VideoClock::Pal);
+    let mut shared = SharedState::new();
+    let mut timers = Timers::new();
+
+    // GP1(0x08): 320x240, NTSC
+    gpu.gp1(&mut shared, &mut NullRenderer, 0x08000001, &mut timers);
+
+    assert!(gpu.video_standard() == VMode::Ntsc);
+    assert!(gpu.video_standard_forced());
+    assert!(gpu.display_resolution() == (320, 240));
+    assert!(gpu.refresh_rate() > 59. && gpu.refresh_rate() < 60.);
+
+    // GP1(0x08): 320x576, PAL, interlaced
+    gpu.gp1(&mut shared, &mut NullRenderer, 0x0800002d, &mut timers);
+
+    assert!(gpu.video_standard() == VMode::Pal);
+    assert!(!gpu.video_standard_forced());
+    assert!(gpu.display_resolution() == (320, 576));
+    assert!(gpu.refresh_rate() > 49. && gpu.refresh_rate() < 50.);
+
+    // GP1(0x08): 640x288, PAL
+    gpu.gp1(&mut shared, &mut NullRenderer, 0x0800000b, &mut timers);
+
+    assert!(gpu.display_resolution() == (640, 288));
+}
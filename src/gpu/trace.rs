@@ -0,0 +1,158 @@
+//! Renderer wrapper logging every primitive along with the GP0
+//! command that generated it. Useful to figure out which draw call
+//! is responsible for a glitch on screen. Wrapping a `NullRenderer`
+//! makes it possible to check the primitives generated by a GP0
+//! command sequence without any video output.
+
+use super::renderer::{Renderer, Vertex, PrimitiveAttributes, Shading};
+
+pub struct TraceRenderer<R> {
+    /// Renderer doing the actual work
+    inner: R,
+    /// Primitives received so far
+    log: Vec<TracedPrimitive>,
+    /// Last drawing offset set by the GPU
+    draw_offset: [i16; 2],
+}
+
+impl<R: Renderer> TraceRenderer<R> {
+    pub fn new(inner: R) -> TraceRenderer<R> {
+        TraceRenderer {
+            inner,
+            log: Vec::new(),
+            draw_offset: [0, 0],
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Return the primitives logged since the last call to `clear`
+    pub fn log(&self) -> &[TracedPrimitive] {
+        &self.log
+    }
+
+    /// Return the number of primitives logged since the last call to
+    /// `clear`
+    pub fn primitive_count(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Empty the log
+    pub fn clear(&mut self) {
+        self.log.clear();
+    }
+
+    fn trace(&mut self, attributes: &PrimitiveAttributes, vertices: &[Vertex]) {
+        self.log.push(TracedPrimitive {
+            opcode: attributes.opcode,
+            sequence: attributes.sequence,
+            shading: attributes.shading,
+            positions: vertices.iter().map(|v| v.position).collect(),
+            colors: vertices.iter().map(|v| v.color).collect(),
+            draw_offset: self.draw_offset,
+        });
+    }
+}
+
+impl<R: Renderer> Renderer for TraceRenderer<R> {
+    fn set_draw_offset(&mut self, x: i16, y: i16) {
+        self.draw_offset = [x, y];
+        self.inner.set_draw_offset(x, y);
+    }
+
+    fn set_draw_area(&mut self,
+                     top_left: (u16, u16),
+                     bottom_right: (u16, u16)) {
+        self.inner.set_draw_area(top_left, bottom_right);
+    }
+
+    fn set_display_mode(&mut self,
+                        top_left: (u16, u16),
+                        resolution: (u16, u16),
+                        depth_24bpp: bool) {
+        self.inner.set_display_mode(top_left, resolution, depth_24bpp);
+    }
+
+    fn push_line(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 2]) {
+        self.trace(attributes, vertices);
+        self.inner.push_line(attributes, vertices);
+    }
+
+    fn push_triangle(&mut self,
+                     attributes: &PrimitiveAttributes,
+                     vertices: &[Vertex; 3]) {
+        self.trace(attributes, vertices);
+        self.inner.push_triangle(attributes, vertices);
+    }
+
+    fn push_quad(&mut self,
+                 attributes: &PrimitiveAttributes,
+                 vertices: &[Vertex; 4]) {
+        self.trace(attributes, vertices);
+        self.inner.push_quad(attributes, vertices);
+    }
+
+    fn fill_rect(&mut self,
+                 color: [u8; 3],
+                 top_left: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.inner.fill_rect(color, top_left, dimensions);
+    }
+
+    fn load_image(&mut self,
+                  top_left: (u16, u16),
+                  dimensions: (u16, u16),
+                  pixel_buffer: &[u16]) {
+        self.inner.load_image(top_left, dimensions, pixel_buffer);
+    }
+
+    fn store_image(&mut self,
+                   top_left: (u16, u16),
+                   dimensions: (u16, u16),
+                   pixel_buffer: &mut [u16]) {
+        self.inner.store_image(top_left, dimensions, pixel_buffer);
+    }
+
+    fn copy_rect(&mut self,
+                 src: (u16, u16),
+                 dst: (u16, u16),
+                 dimensions: (u16, u16)) {
+        self.inner.copy_rect(src, dst, dimensions);
+    }
+
+    fn clear_texture_cache(&mut self) {
+        self.inner.clear_texture_cache();
+    }
+
+    fn set_mask_settings(&mut self,
+                         set_mask_bit: bool,
+                         check_mask_bit: bool) {
+        self.inner.set_mask_settings(set_mask_bit, check_mask_bit);
+    }
+}
+
+/// Primitive logged by the `TraceRenderer`
+#[derive(Clone, Debug)]
+pub struct TracedPrimitive {
+    /// Opcode of the GP0 command that generated the primitive
+    pub opcode: u8,
+    /// Position of the command in the frame
+    pub sequence: u32,
+    /// Flat or gouraud shading
+    pub shading: Shading,
+    /// Vertex positions: 2 for lines, 3 for triangles and 4 for quads
+    pub positions: Vec<[i16; 2]>,
+    /// Vertex colors, in the same order as `positions`
+    pub colors: Vec<[u8; 3]>,
+    /// Drawing offset in effect when the primitive was received. It
+    /// hasn't been added to `positions`.
+    pub draw_offset: [i16; 2],
+}
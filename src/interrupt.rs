@@ -1,8 +1,13 @@
-/// The PlayStation supports 10 interrupts
+use savestate::{StateWriter, StateReader, StateError};
+
+/// The PlayStation supports 11 interrupts
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Interrupt {
     /// Display in vertical blanking
     VBlank = 0,
+    /// GPU interrupt. Real hardware raises it through GP0(0x1f) but
+    /// that command isn't emulated so it's never asserted
+    Gpu = 1,
     /// CDROM controller
     CdRom = 2,
     /// DMA transfer done
@@ -15,6 +20,12 @@ pub enum Interrupt {
     Timer2 = 6,
     /// Gamepad and Memory Card controller interrupt
     PadMemCard = 7,
+    /// Serial port interrupt
+    Sio = 8,
+    /// SPU interrupt
+    Spu = 9,
+    /// Parallel port (and lightgun) interrupt
+    Pio = 10,
 }
 
 #[derive(Clone,Copy)]
@@ -35,8 +46,8 @@ impl InterruptState {
     }
 
     /// Return true if at least one interrupt is asserted and not
-    /// masked
-    pub fn active(self) -> bool {
+    /// masked, i.e. if the CPU interrupt line is high
+    pub fn irq_pending(&self) -> bool {
         (self.status & self.mask) != 0
     }
 
@@ -46,6 +57,9 @@ impl InterruptState {
 
     /// Acknowledge interrupts by writing 0 to the corresponding bit
     pub fn ack(&mut self, ack: u16) {
+         debug!("IRQ acknowledge {:03x}, status {:03x}",
+                !ack & IRQ_MASK, self.status);
+
          self.status &= ack;
     }
 
@@ -54,29 +68,117 @@ impl InterruptState {
     }
 
     pub fn set_mask(&mut self, mask: u16) {
-        // Temporary hack: trigger an error if a non-implemented
-        // interrupt is requested
-        let supported = [ Interrupt::VBlank,
-                          Interrupt::CdRom,
-                          Interrupt::Dma,
-                          Interrupt::Timer0,
-                          Interrupt::Timer1,
-                          Interrupt::Timer2,
-                          Interrupt::PadMemCard];
-
-        let rem = supported.iter().fold(mask,
-                                        |mask, &it| mask & !(1 << it as u16));
-
-        if rem != 0 {
-            panic!("Unsupported interrupt: {:04x}", rem);
+        // Only the 11 low bits are used
+        self.mask = mask & IRQ_MASK;
+
+        debug!("IRQ mask {:03x}", self.mask);
+    }
+
+    /// Return the interrupts which are both asserted and not masked,
+    /// i.e. the ones which drive the CPU interrupt line
+    pub fn pending(self) -> u16 {
+        self.status & self.mask
+    }
+
+    /// Serialize the interrupt state in `w`
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.status);
+        w.write_u16(self.mask);
+    }
+
+    /// Read back a state serialized by `save_state`
+    pub fn load_state(r: &mut StateReader)
+                      -> Result<InterruptState, StateError> {
+        let status = r.read_u16()?;
+        let mask = r.read_u16()?;
+
+        if (status | mask) & !IRQ_MASK != 0 {
+            return Err(StateError::InvalidValue("interrupt state"));
         }
 
-        self.mask = mask;
+        Ok(InterruptState {
+            status,
+            mask,
+        })
     }
 
     /// Trigger the interrupt `which`, must be called on the rising
-    /// edge of the interrupt signal.
+    /// edge of the interrupt signal. The status bit latches the
+    /// edge: asserting an interrupt which is already set has no
+    /// effect, it can only trigger again once it's been acknowledged.
     pub fn assert(&mut self, which: Interrupt) {
-        self.status |= 1 << (which as usize);
+        let bit = 1 << (which as usize);
+
+        if self.status & bit != 0 {
+            // Already latched
+            return;
+        }
+
+        debug!("IRQ {:?} asserted", which);
+
+        self.status |= bit;
     }
 }
+
+/// Mask of all the valid interrupt bits
+const IRQ_MASK: u16 = 0x7ff;
+
+#[test]
+fn interrupt_mask_status() {
+    let mut irq_state = InterruptState::new();
+
+    irq_state.assert(Interrupt::Sio);
+    irq_state.assert(Interrupt::CdRom);
+
+    // Nothing reaches the CPU while masked
+    assert!(irq_state.status() == 0x104);
+    assert!(irq_state.pending() == 0);
+    assert!(!irq_state.irq_pending());
+
+    // Unused bits are ignored
+    irq_state.set_mask(0xffff);
+    assert!(irq_state.mask() == 0x7ff);
+    assert!(irq_state.pending() == 0x104);
+    assert!(irq_state.irq_pending());
+
+    // Acknowledge the CDROM interrupt only
+    irq_state.ack(!(1 << (Interrupt::CdRom as u16)));
+    assert!(irq_state.status() == 0x100);
+
+    irq_state.set_mask(1 << (Interrupt::Pio as u16));
+    assert!(irq_state.pending() == 0);
+    assert!(!irq_state.irq_pending());
+}
+
+#[test]
+fn interrupt_vblank_edge() {
+    let vblank = 1 << (Interrupt::VBlank as u16);
+
+    let mut irq_state = InterruptState::new();
+
+    irq_state.set_mask(vblank);
+
+    // Rising edge
+    irq_state.assert(Interrupt::VBlank);
+    assert!(irq_state.status() == vblank);
+    assert!(irq_state.irq_pending());
+
+    // Re-asserting a latched interrupt doesn't re-trigger it: a
+    // single acknowledge clears it
+    irq_state.assert(Interrupt::VBlank);
+    assert!(irq_state.status() == vblank);
+    irq_state.ack(!vblank);
+    assert!(irq_state.status() == 0);
+    assert!(!irq_state.irq_pending());
+
+    // Asserting it again after the acknowledge does
+    irq_state.assert(Interrupt::VBlank);
+    assert!(irq_state.status() == vblank);
+    assert!(irq_state.irq_pending());
+
+    // Other interrupts are latched independently
+    irq_state.assert(Interrupt::Timer0);
+    irq_state.ack(!vblank);
+    assert!(irq_state.status() == 1 << (Interrupt::Timer0 as u16));
+    assert!(!irq_state.irq_pending());
+}
@@ -15,10 +15,20 @@ pub mod cpu;
 pub mod shared;
 pub mod padmemcard;
 pub mod debugger;
+pub mod spu;
+pub mod machine;
+pub mod prelude;
+pub mod logfilter;
+pub mod savestate;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "libretro")]
+pub mod libretro;
 
 mod interrupt;
+mod bytes;
 mod timekeeper;
-mod spu;
 mod mdec;
 
 /// Version of the rustation library set in Cargo.toml
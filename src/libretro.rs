@@ -0,0 +1,775 @@
+//! libretro core implementation, to run rustation in RetroArch and
+//! other libretro frontends. Only built with the "libretro" feature.
+//!
+//! The core expects a cue sheet path as game and looks for a valid
+//! BIOS image in the frontend's system directory.
+
+use std::ffi::CStr;
+use std::fs;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process;
+use std::ptr;
+
+use bios::{Bios, BIOS_SIZE};
+use cdrom::disc::{Disc, Region};
+use gpu::software::SoftwareRenderer;
+use machine::{Machine, MachineConfig};
+use padmemcard::gamepad::{self, Button, ButtonState, Axis, Profile};
+use spu::Spu;
+
+/// Emulator state, created when a game is loaded
+struct Core {
+    machine: Machine,
+    renderer: SoftwareRenderer,
+}
+
+static mut CORE: *mut Core = 0 as *mut Core;
+
+static mut ENVIRONMENT: Option<EnvironmentFn> = None;
+static mut VIDEO_REFRESH: Option<VideoRefreshFn> = None;
+static mut AUDIO_SAMPLE_BATCH: Option<AudioSampleBatchFn> = None;
+static mut INPUT_POLL: Option<InputPollFn> = None;
+static mut INPUT_STATE: Option<InputStateFn> = None;
+
+type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = extern "C" fn(data: *const c_void,
+                                    width: c_uint,
+                                    height: c_uint,
+                                    pitch: usize);
+type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = extern "C" fn(data: *const i16,
+                                        frames: usize) -> usize;
+type InputPollFn = extern "C" fn();
+type InputStateFn = extern "C" fn(port: c_uint,
+                                  device: c_uint,
+                                  index: c_uint,
+                                  id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct InputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+/// libretro API version implemented by this core
+const API_VERSION: c_uint = 1;
+
+const ENVIRONMENT_GET_SYSTEM_DIRECTORY: c_uint = 9;
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+
+/// 0x00RRGGBB pixels, the format used by `Frame`
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const DEVICE_JOYPAD: c_uint = 1;
+const DEVICE_ANALOG: c_uint = 5;
+
+const DEVICE_INDEX_ANALOG_LEFT: c_uint = 0;
+const DEVICE_INDEX_ANALOG_RIGHT: c_uint = 1;
+
+const DEVICE_ID_ANALOG_X: c_uint = 0;
+const DEVICE_ID_ANALOG_Y: c_uint = 1;
+
+const REGION_NTSC: c_uint = 0;
+const REGION_PAL: c_uint = 1;
+
+/// Mapping between the libretro joypad button IDs and the
+/// PlayStation buttons along with their description. The libretro
+/// layout uses the SNES button positions.
+const BUTTON_MAP: [(c_uint, Button, &'static [u8]); 16] = [
+    (0, Button::Cross, b"Cross\0"),
+    (1, Button::Square, b"Square\0"),
+    (2, Button::Select, b"Select\0"),
+    (3, Button::Start, b"Start\0"),
+    (4, Button::DUp, b"D-Pad Up\0"),
+    (5, Button::DDown, b"D-Pad Down\0"),
+    (6, Button::DLeft, b"D-Pad Left\0"),
+    (7, Button::DRight, b"D-Pad Right\0"),
+    (8, Button::Circle, b"Circle\0"),
+    (9, Button::Triangle, b"Triangle\0"),
+    (10, Button::L1, b"L1\0"),
+    (11, Button::R1, b"R1\0"),
+    (12, Button::L2, b"L2\0"),
+    (13, Button::R2, b"R2\0"),
+    (14, Button::L3, b"L3\0"),
+    (15, Button::R3, b"R3\0"),
+    ];
+
+/// Mapping between the libretro analog stick (index, id) pairs and
+/// the DualShock axes along with their description
+const AXIS_MAP: [(c_uint, c_uint, Axis, &'static [u8]); 4] = [
+    (DEVICE_INDEX_ANALOG_LEFT, DEVICE_ID_ANALOG_X, Axis::LeftX,
+     b"Left Analog X\0"),
+    (DEVICE_INDEX_ANALOG_LEFT, DEVICE_ID_ANALOG_Y, Axis::LeftY,
+     b"Left Analog Y\0"),
+    (DEVICE_INDEX_ANALOG_RIGHT, DEVICE_ID_ANALOG_X, Axis::RightX,
+     b"Right Analog X\0"),
+    (DEVICE_INDEX_ANALOG_RIGHT, DEVICE_ID_ANALOG_Y, Axis::RightY,
+     b"Right Analog Y\0"),
+    ];
+
+/// Audio output sample rate
+const SAMPLE_RATE: f64 = 44_100.;
+
+fn core() -> Option<&'static mut Core> {
+    unsafe {
+        if CORE.is_null() {
+            None
+        } else {
+            Some(&mut *CORE)
+        }
+    }
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match unsafe { ENVIRONMENT } {
+        Some(env) => env(cmd, data),
+        None => false,
+    }
+}
+
+/// Return the frontend's system directory, where we look for the
+/// BIOS
+fn system_directory() -> Option<String> {
+    let mut dir: *const c_char = ptr::null();
+
+    let dir_ptr = &mut dir as *mut *const c_char as *mut c_void;
+
+    if !environment(ENVIRONMENT_GET_SYSTEM_DIRECTORY, dir_ptr) ||
+        dir.is_null() {
+        return None;
+    }
+
+    let dir = unsafe { CStr::from_ptr(dir) };
+
+    dir.to_str().ok().map(|d| d.to_owned())
+}
+
+/// Look for a valid BIOS image in `dir`, preferably one matching
+/// `region`
+fn find_bios(dir: &Path, region: Region) -> Option<Bios> {
+    let entries =
+        match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(e) => {
+                error!("Can't read {}: {}", dir.display(), e);
+                return None;
+            }
+        };
+
+    let mut fallback = None;
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+
+        match fs::metadata(&path) {
+            Ok(md) => if md.len() != BIOS_SIZE as u64 {
+                continue;
+            },
+            Err(_) => continue,
+        }
+
+        if let Ok(bios) = Bios::from_file(&path) {
+            info!("Found BIOS {} in {}", bios.version(), path.display());
+
+            if bios.region() == region {
+                return Some(bios);
+            }
+
+            if fallback.is_none() {
+                fallback = Some(bios);
+            }
+        }
+    }
+
+    fallback
+}
+
+fn load_game(path: &Path) -> Option<Core> {
+    let disc =
+        match Disc::from_cue(path) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Can't load {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+    let region = disc.region();
+
+    let bios =
+        match system_directory()
+            .and_then(|dir| find_bios(Path::new(&dir), region)) {
+            Some(b) => b,
+            None => {
+                error!("Couldn't find a BIOS in the system directory");
+                return None;
+            }
+        };
+
+    let mut format = PIXEL_FORMAT_XRGB8888;
+
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT,
+                    &mut format as *mut c_uint as *mut c_void) {
+        error!("Frontend doesn't support XRGB8888 output");
+        return None;
+    }
+
+    // The frontend copies the descriptors, it's fine if it doesn't
+    // support them
+    let mut descriptors = input_descriptors();
+
+    environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+                descriptors.as_mut_ptr() as *mut c_void);
+
+    let mut config = MachineConfig::new(bios);
+
+    config.set_disc(Some(disc));
+    // Breakpoints can't be set from the frontend and we mustn't block
+    // waiting for a GDB connection
+    config.set_gdb_remote(false);
+
+    let mut machine = config.build();
+
+    {
+        // DualShocks start in digital mode so they work with all
+        // games
+        let inter = machine.cpu_mut().interconnect_mut();
+        let pad_memcard = inter.pad_memcard_mut();
+
+        for slot in 0..2 {
+            pad_memcard.set_pad_type(slot, gamepad::Type::DualShock);
+        }
+    }
+
+    Some(Core {
+        machine,
+        renderer: SoftwareRenderer::new(),
+    })
+}
+
+/// Build the input descriptors for the two ports, terminated by an
+/// entry with a NULL description
+fn input_descriptors() -> Vec<InputDescriptor> {
+    let mut descriptors = Vec::new();
+
+    for port in 0..2 {
+        for &(id, _, description) in BUTTON_MAP.iter() {
+            descriptors.push(InputDescriptor {
+                port,
+                device: DEVICE_JOYPAD,
+                index: 0,
+                id,
+                description: description.as_ptr() as *const c_char,
+            });
+        }
+
+        for &(index, id, _, description) in AXIS_MAP.iter() {
+            descriptors.push(InputDescriptor {
+                port,
+                device: DEVICE_ANALOG,
+                index,
+                id,
+                description: description.as_ptr() as *const c_char,
+            });
+        }
+    }
+
+    descriptors.push(InputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: ptr::null(),
+    });
+
+    descriptors
+}
+
+/// Convert a libretro analog axis value (-0x8000 to 0x7fff) into a
+/// DualShock one (0x00 to 0xff, 0x80 is the center)
+fn analog_to_axis(value: i16) -> u8 {
+    ((value as i32 + 0x8000) >> 8) as u8
+}
+
+/// Update the gamepad state from the libretro input callbacks
+fn poll_input(core: &mut Core) {
+    let (poll, state) =
+        match unsafe { (INPUT_POLL, INPUT_STATE) } {
+            (Some(p), Some(s)) => (p, s),
+            _ => return,
+        };
+
+    poll();
+
+    let inter = core.machine.cpu_mut().interconnect_mut();
+    let mut profiles = inter.pad_memcard_mut().pad_profiles();
+
+    for (port, profile) in profiles.iter_mut().enumerate() {
+        update_pad(*profile, port as c_uint, state);
+    }
+}
+
+/// Update the state of the gamepad in `port` using the libretro
+/// input `state` callback
+fn update_pad(profile: &mut dyn Profile, port: c_uint, state: InputStateFn) {
+    for &(id, button, _) in BUTTON_MAP.iter() {
+        let pressed = state(port, DEVICE_JOYPAD, 0, id) != 0;
+
+        let button_state =
+            match pressed {
+                true => ButtonState::Pressed,
+                false => ButtonState::Released,
+            };
+
+        profile.set_button_state(button, button_state);
+    }
+
+    for &(index, id, axis, _) in AXIS_MAP.iter() {
+        let value = state(port, DEVICE_ANALOG, index, id);
+
+        profile.set_axis_state(axis, analog_to_axis(value));
+    }
+}
+
+/// Send the SPU output to the frontend
+fn send_audio(core: &mut Core) {
+    let batch =
+        match unsafe { AUDIO_SAMPLE_BATCH } {
+            Some(b) => b,
+            None => return,
+        };
+
+    let spu = core.machine.cpu_mut().interconnect_mut().spu_mut();
+
+    drain_audio(spu, batch);
+}
+
+/// Send all the samples buffered by `spu` to the frontend's `batch`
+/// callback
+fn drain_audio(spu: &mut Spu, batch: AudioSampleBatchFn) {
+    let mut buf = [0i16; 1024];
+
+    loop {
+        let n = spu.drain(&mut buf);
+
+        if n > 0 {
+            batch(buf.as_ptr(), n / 2);
+        }
+
+        if n < buf.len() {
+            break;
+        }
+    }
+}
+
+fn run_frame(core: &mut Core) {
+    poll_input(core);
+
+    core.machine.run_frame(&mut core.renderer);
+
+    send_audio(core);
+
+    let frame = core.machine.fetch_frame(&mut core.renderer);
+
+    if let Some(refresh) = unsafe { VIDEO_REFRESH } {
+        refresh(frame.pixels.as_ptr() as *const c_void,
+                frame.width,
+                frame.height,
+                frame.width as usize * 4);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    retro_unload_game();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    unsafe { ENVIRONMENT = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    unsafe { VIDEO_REFRESH = Some(cb) }
+}
+
+/// We only use the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    unsafe { AUDIO_SAMPLE_BATCH = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    unsafe { INPUT_POLL = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    unsafe { INPUT_STATE = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_: c_uint, _: c_uint) {
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_info` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    let info = &mut *info;
+
+    info.library_name = b"Rustation\0".as_ptr() as *const c_char;
+    info.library_version = ::VERSION_CSTR.as_ptr() as *const c_char;
+    info.valid_extensions = b"cue\0".as_ptr() as *const c_char;
+    info.need_fullpath = true;
+    info.block_extract = false;
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_av_info` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let info = &mut *info;
+
+    let (width, height, fps) =
+        match core() {
+            Some(core) => {
+                let gpu = core.machine.cpu().interconnect().gpu();
+                let (w, h) = gpu.display_rect().dimensions;
+
+                (w as c_uint, h as c_uint, gpu.refresh_rate() as f64)
+            }
+            None => (640, 480, 60.),
+        };
+
+    info.geometry = GameGeometry {
+        base_width: width,
+        base_height: height,
+        max_width: 640,
+        max_height: 576,
+        aspect_ratio: 4. / 3.,
+    };
+
+    info.timing = SystemTiming {
+        fps,
+        sample_rate: SAMPLE_RATE,
+    };
+}
+
+/// # Safety
+///
+/// `info` must be NULL or point to a valid `retro_game_info` whose
+/// `path` is NULL or a `\0`-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    if info.is_null() {
+        return false;
+    }
+
+    let path = (*info).path;
+
+    if path.is_null() {
+        return false;
+    }
+
+    let path =
+        match CStr::from_ptr(path).to_str() {
+            Ok(p) => p.to_owned(),
+            Err(_) => return false,
+        };
+
+    retro_unload_game();
+
+    let res = panic::catch_unwind(|| load_game(Path::new(&path)));
+
+    match res {
+        Ok(Some(core)) => {
+            CORE = Box::into_raw(Box::new(core));
+            true
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_: c_uint,
+                                          _: *const GameInfo,
+                                          _: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    unsafe {
+        if !CORE.is_null() {
+            drop(Box::from_raw(CORE));
+            CORE = ptr::null_mut();
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    if let Some(core) = core() {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| run_frame(core)));
+
+        if res.is_err() {
+            // We can't unwind into the frontend and the emulator
+            // state is probably inconsistent anyway
+            error!("Emulator panicked, aborting");
+            process::abort();
+        }
+    }
+}
+
+/// Not implemented yet: the frontend has to restart the game
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    match core().map(|c| c.machine.region()) {
+        Some(Region::Europe) => REGION_PAL,
+        _ => REGION_NTSC,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize(_: *mut c_void, _: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unserialize(_: *const c_void, _: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_: c_uint, _: bool, _: *const c_char) {
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_: c_uint) -> usize {
+    0
+}
+
+#[test]
+fn input_mapping() {
+    /// Profile recording the state it's given
+    struct TestProfile {
+        pressed: Vec<u16>,
+        axes: Vec<(u8, u8)>,
+    }
+
+    impl Profile for TestProfile {
+        fn handle_command(&mut self, _: u8, _: u8) -> (u8, bool) {
+            (0xff, false)
+        }
+
+        fn set_button_state(&mut self, button: Button, state: ButtonState) {
+            if let ButtonState::Pressed = state {
+                self.pressed.push(button as u16);
+            }
+        }
+
+        fn set_axis_state(&mut self, axis: Axis, value: u8) {
+            self.axes.push((axis as u8, value));
+        }
+    }
+
+    extern "C" fn state(port: c_uint,
+                        device: c_uint,
+                        index: c_uint,
+                        id: c_uint) -> i16 {
+        match (port, device, index, id) {
+            // B and Start
+            (1, DEVICE_JOYPAD, 0, 0) => 1,
+            (1, DEVICE_JOYPAD, 0, 3) => 1,
+            // Left stick all the way to the left, right stick all
+            // the way down
+            (1, DEVICE_ANALOG, DEVICE_INDEX_ANALOG_LEFT,
+             DEVICE_ID_ANALOG_X) => -0x8000,
+            (1, DEVICE_ANALOG, DEVICE_INDEX_ANALOG_RIGHT,
+             DEVICE_ID_ANALOG_Y) => 0x7fff,
+            _ => 0,
+        }
+    }
+
+    let mut profile = TestProfile {
+        pressed: Vec::new(),
+        axes: Vec::new(),
+    };
+
+    update_pad(&mut profile, 1, state);
+
+    assert!(profile.pressed == vec![Button::Cross as u16,
+                                    Button::Start as u16]);
+    assert!(profile.axes == vec![(Axis::LeftX as u8, 0x00),
+                                 (Axis::LeftY as u8, 0x80),
+                                 (Axis::RightX as u8, 0x80),
+                                 (Axis::RightY as u8, 0xff)]);
+
+    let descriptors = input_descriptors();
+
+    assert!(descriptors.len() == 2 * (16 + 4) + 1);
+    assert!(descriptors[descriptors.len() - 1].description.is_null());
+
+    for d in &descriptors[..descriptors.len() - 1] {
+        let description = unsafe { CStr::from_ptr(d.description) };
+
+        assert!(!description.to_bytes().is_empty());
+    }
+}
+
+#[test]
+fn audio_batches() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use shared::SharedState;
+
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+    static BATCHES: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn batch(data: *const i16, frames: usize) -> usize {
+        assert!(!data.is_null());
+        // The buffer holds 1024 interleaved samples
+        assert!(frames > 0 && frames <= 512);
+
+        FRAMES.fetch_add(frames, Ordering::SeqCst);
+        BATCHES.fetch_add(1, Ordering::SeqCst);
+
+        frames
+    }
+
+    let mut shared = SharedState::new();
+    let mut spu = Spu::new();
+
+    // Generate 1000 stereo samples, one every 768 CPU cycles
+    shared.tk().advance(768 * 1000);
+    spu.sync(&mut shared);
+
+    assert!(spu.buffer_fill() == 1000);
+
+    drain_audio(&mut spu, batch);
+
+    assert!(FRAMES.load(Ordering::SeqCst) == 1000);
+    assert!(BATCHES.load(Ordering::SeqCst) == 2);
+    assert!(spu.buffer_fill() == 0);
+}
+
+#[test]
+fn av_info() {
+    use std::mem;
+    use machine::looping_machine;
+
+    unsafe {
+        let mut info: SystemAvInfo = mem::zeroed();
+
+        // No game loaded
+        retro_get_system_av_info(&mut info);
+
+        assert!(info.geometry.base_width == 640);
+        assert!(info.geometry.base_height == 480);
+        assert!(info.timing.fps == 60.);
+        assert!(info.timing.sample_rate == 44_100.);
+
+        let core = Core {
+            machine: looping_machine(),
+            renderer: SoftwareRenderer::new(),
+        };
+
+        let (width, height, fps) = {
+            let gpu = core.machine.cpu().interconnect().gpu();
+            let (w, h) = gpu.display_rect().dimensions;
+
+            (w as c_uint, h as c_uint, gpu.refresh_rate() as f64)
+        };
+
+        CORE = Box::into_raw(Box::new(core));
+
+        retro_get_system_av_info(&mut info);
+
+        retro_unload_game();
+
+        assert!(info.geometry.base_width == width);
+        assert!(info.geometry.base_height == height);
+        assert!(info.geometry.max_width == 640);
+        assert!(info.geometry.max_height == 576);
+        assert!(info.timing.fps == fps);
+    }
+}
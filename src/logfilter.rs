@@ -0,0 +1,138 @@
+//! Per-component log filtering. The emulator logs through the `log`
+//! crate using the module path as target (`rustation::gpu`,
+//! `rustation::cdrom::disc`...), the `LogFilter` lets frontends
+//! select the verbosity of each component independently from their
+//! logger implementation.
+
+use log::{LogLevel, LogLevelFilter};
+
+/// Emulator components with their own log target
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Component {
+    Gpu,
+    Cpu,
+    CdRom,
+    Spu,
+    Dma,
+    Interrupt,
+}
+
+impl Component {
+    /// Return the log target prefix used by this component
+    pub fn target(self) -> &'static str {
+        match self {
+            Component::Gpu => "rustation::gpu",
+            Component::Cpu => "rustation::cpu",
+            Component::CdRom => "rustation::cdrom",
+            Component::Spu => "rustation::spu",
+            Component::Dma => "rustation::memory::dma",
+            Component::Interrupt => "rustation::interrupt",
+        }
+    }
+
+    /// Return true if `target` belongs to this component
+    fn matches(self, target: &str) -> bool {
+        let prefix = self.target();
+
+        target.starts_with(prefix) &&
+            (target.len() == prefix.len() ||
+             target[prefix.len()..].starts_with("::"))
+    }
+}
+
+pub struct LogFilter {
+    /// Level used for the targets without a specific setting
+    default: LogLevelFilter,
+    /// Per-component overrides
+    components: Vec<(Component, LogLevelFilter)>,
+}
+
+impl LogFilter {
+    /// Create a filter using `default` for all components
+    pub fn new(default: LogLevelFilter) -> LogFilter {
+        LogFilter {
+            default,
+            components: Vec::new(),
+        }
+    }
+
+    /// Set the level used for the targets without a specific setting
+    pub fn set_default(&mut self, level: LogLevelFilter) {
+        self.default = level;
+    }
+
+    /// Set the level for `component`, overriding the default
+    pub fn set_level(&mut self, component: Component, level: LogLevelFilter) {
+        self.components.retain(|&(c, _)| c != component);
+        self.components.push((component, level));
+    }
+
+    /// Remove the override for `component`, it will use the default
+    /// level again
+    pub fn reset_level(&mut self, component: Component) {
+        self.components.retain(|&(c, _)| c != component);
+    }
+
+    /// Return the level for `target`
+    pub fn level(&self, target: &str) -> LogLevelFilter {
+        for &(component, level) in &self.components {
+            if component.matches(target) {
+                return level;
+            }
+        }
+
+        self.default
+    }
+
+    /// Return true if a message with the given `target` and `level`
+    /// should be logged. Meant to be called from the frontend's
+    /// `Log::enabled` implementation.
+    pub fn enabled(&self, target: &str, level: LogLevel) -> bool {
+        level.to_log_level_filter() <= self.level(target)
+    }
+
+    /// Return the most verbose level of all the components, loggers
+    /// should be installed with at least this maximum level.
+    pub fn max_level(&self) -> LogLevelFilter {
+        self.components.iter()
+            .map(|&(_, l)| l)
+            .fold(self.default, ::std::cmp::max)
+    }
+}
+
+#[test]
+fn log_filter_components() {
+    let mut filter = LogFilter::new(LogLevelFilter::Warn);
+
+    filter.set_level(Component::Gpu, LogLevelFilter::Debug);
+
+    assert!(filter.max_level() == LogLevelFilter::Debug);
+
+    assert!(filter.enabled("rustation::gpu", LogLevel::Debug));
+    assert!(filter.enabled("rustation::gpu::software", LogLevel::Debug));
+    assert!(!filter.enabled("rustation::gpu", LogLevel::Trace));
+
+    for &target in &["rustation::cpu",
+                     "rustation::cdrom::disc",
+                     "rustation::spu",
+                     "rustation::memory::dma",
+                     "rustation::interrupt"] {
+        assert!(!filter.enabled(target, LogLevel::Debug));
+        assert!(!filter.enabled(target, LogLevel::Info));
+        assert!(filter.enabled(target, LogLevel::Warn));
+        assert!(filter.enabled(target, LogLevel::Error));
+    }
+
+    // Not a GPU target
+    assert!(!filter.enabled("rustation::gpux", LogLevel::Debug));
+
+    filter.set_level(Component::Dma, LogLevelFilter::Off);
+
+    assert!(!filter.enabled("rustation::memory::dma", LogLevel::Error));
+    assert!(filter.enabled("rustation::memory", LogLevel::Error));
+
+    filter.reset_level(Component::Gpu);
+
+    assert!(!filter.enabled("rustation::gpu", LogLevel::Debug));
+    assert!(filter.max_level() == LogLevelFilter::Warn);
+}
@@ -0,0 +1,953 @@
+//! Top-level emulator state: bundles the CPU (and through it the
+//! rest of the hardware) with the state shared between modules and
+//! the debugger.
+
+use cpu::{Cpu, CPU_FREQ_HZ, REGISTER_NAMES};
+use cpu::disasm::disasm;
+use memory::{Interconnect, IoHandler};
+use memory::dma::Port;
+use bios::Bios;
+use gpu::{Gpu, VMode, VideoClock, VRAM_WIDTH_PIXELS, VRAM_HEIGHT};
+use gpu::renderer::Renderer;
+use gpu::software::{SoftwareRenderer, Frame};
+use cdrom::disc::{Disc, Region};
+use shared::SharedState;
+use debugger::{Debugger, CpuState};
+use timekeeper::Cycles;
+use interrupt::InterruptState;
+use savestate::{StateWriter, StateReader, StateError};
+
+use std::ops::Range;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+pub struct Machine {
+    cpu: Cpu,
+    shared: SharedState,
+    debugger: Debugger,
+    /// Maximum number of CPU cycles `run_frame` will run before
+    /// giving up on waiting for the end of the frame
+    frame_cycle_limit: Cycles,
+    /// Frame number `run_frame` should stop at
+    frame_breakpoint: Option<u32>,
+    /// Callback invoked at the end of every frame
+    frame_callback: Option<Box<dyn FnMut(u64, Duration)>>,
+    /// Frame count and host time when `frame_callback` was last
+    /// checked
+    last_frame: (u64, Instant),
+    /// Number of the last frame fetched with `fetch_frame`
+    fetched_frame: u64,
+}
+
+impl Machine {
+    pub fn new(bios: Bios, gpu: Gpu, disc: Option<Disc>) -> Machine {
+        let inter = Interconnect::new(bios, gpu, disc);
+
+        Machine {
+            cpu: Cpu::new(inter),
+            shared: SharedState::new(),
+            debugger: Debugger::new(),
+            frame_cycle_limit: DEFAULT_FRAME_CYCLE_LIMIT,
+            frame_breakpoint: None,
+            frame_callback: None,
+            last_frame: (0, Instant::now()),
+            fetched_frame: 0,
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn shared(&self) -> &SharedState {
+        &self.shared
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Route all the CPU loads and stores within the physical address
+    /// `range` to `handler` instead of the emulated hardware. See
+    /// `Interconnect::register_io_handler`.
+    pub fn register_io_handler(&mut self,
+                               range: Range<u32>,
+                               handler: Box<dyn IoHandler>) {
+        self.cpu.interconnect_mut().register_io_handler(range, handler);
+    }
+
+    /// Region of the loaded disc or, if there's no disc, of the BIOS
+    pub fn region(&self) -> Region {
+        let inter = self.cpu.interconnect();
+
+        match inter.cdrom().disc() {
+            Some(disc) => disc.region(),
+            None => inter.bios().metadata().region,
+        }
+    }
+
+    /// Video mode currently used by the GPU
+    pub fn video_standard(&self) -> VMode {
+        self.cpu.interconnect().gpu().video_standard()
+    }
+
+    /// Video mode matching `region`
+    pub fn region_video_standard(&self) -> VMode {
+        match self.region() {
+            Region::Europe => VMode::Pal,
+            Region::Japan | Region::NorthAmerica => VMode::Ntsc,
+        }
+    }
+
+    /// Refresh rate the frontend should use for its output. This
+    /// uses the GPU's current timings which don't necessarily match
+    /// the region since games can force NTSC output on a PAL console
+    /// and vice-versa.
+    pub fn refresh_rate(&self) -> f32 {
+        self.cpu.interconnect().gpu().refresh_rate()
+    }
+
+    /// Return true if a new frame has been completed since the last
+    /// call to `fetch_frame`. Frontends running their own loop can
+    /// use this to poll for new frames instead of blocking in
+    /// `run_frame`.
+    pub fn frame_ready(&self) -> bool {
+        self.shared.frames_rendered() != self.fetched_frame
+    }
+
+    /// Fetch the current framebuffer from `renderer`, `frame_ready`
+    /// returns false until the next frame is completed
+    pub fn fetch_frame<'a>(&mut self,
+                           renderer: &'a mut SoftwareRenderer) -> &'a Frame {
+        self.fetched_frame = self.shared.frames_rendered();
+
+        renderer.export_frame()
+    }
+
+    /// Run a single CPU instruction
+    pub fn step(&mut self, renderer: &mut dyn Renderer) {
+        self.cpu.run_next_instruction(&mut self.debugger,
+                                      &mut self.shared,
+                                      renderer);
+
+        let frames = self.shared.frames_rendered();
+
+        if frames != self.last_frame.0 {
+            let now = Instant::now();
+            let elapsed = now - self.last_frame.1;
+
+            self.last_frame = (frames, now);
+
+            if let Some(ref mut callback) = self.frame_callback {
+                callback(frames, elapsed);
+            }
+        }
+    }
+
+    /// Number of frames emulated since the machine was created
+    pub fn frames_rendered(&self) -> u64 {
+        self.shared.frames_rendered()
+    }
+
+    /// Call `callback` at the end of every emulated frame with the
+    /// total frame count and the host time elapsed since the
+    /// previous frame. Frontends can use it to compute the emulation
+    /// speed and detect when it falls behind realtime.
+    pub fn set_frame_callback(&mut self,
+                              callback: Box<dyn FnMut(u64, Duration)>) {
+        self.frame_callback = Some(callback);
+    }
+
+    pub fn clear_frame_callback(&mut self) {
+        self.frame_callback = None;
+    }
+
+    /// Capture the current state of the CPU registers
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState::capture(&self.cpu, self.shared.irq_snapshot())
+    }
+
+    /// Build a human-readable summary of the state of the machine,
+    /// meant to be pasted in bug reports
+    pub fn dump_state(&self) -> String {
+        let mut s = String::new();
+
+        // Writing to a String can't fail
+        self.write_state(&mut s).unwrap();
+
+        s
+    }
+
+    fn write_state(&self, s: &mut String) -> ::std::fmt::Result {
+        let cpu = &self.cpu;
+        let inter = cpu.interconnect();
+
+        writeln!(s, "== CPU ==")?;
+
+        let pc = cpu.pc();
+
+        let instruction = inter.load_instruction(pc);
+
+        writeln!(s, "PC: {:08x} [{:08x}] {}",
+                 pc, instruction, disasm(instruction, pc))?;
+
+        for (i, r) in cpu.regs().iter().enumerate() {
+            write!(s, "{:>6}: {:08x}", REGISTER_NAMES[i], r)?;
+
+            if i % 4 == 3 {
+                writeln!(s, "")?;
+            }
+        }
+
+        let irq_state = self.shared.irq_snapshot();
+
+        writeln!(s, "   $hi: {:08x}    $lo: {:08x}",
+                 cpu.hi(), cpu.lo())?;
+        writeln!(s, "   $sr: {:08x} $cause: {:08x}",
+                 cpu.sr(), cpu.cause(irq_state))?;
+
+        writeln!(s, "== Interrupts ==")?;
+        writeln!(s, "Status: {:04x} Mask: {:04x} Active: {}",
+                 irq_state.status(),
+                 irq_state.mask(),
+                 irq_state.irq_pending())?;
+
+        let gpu = inter.gpu();
+        let stat = gpu.status();
+        let (x_start, y_start) = gpu.display_vram_start();
+
+        writeln!(s, "== GPU ==")?;
+        writeln!(s, "GPUSTAT: {:08x}", stat)?;
+        writeln!(s, "Display: {}, VRAM start ({}, {}), {}, {}bpp{}",
+                 if stat & (1 << 23) != 0 { "off" } else { "on" },
+                 x_start, y_start,
+                 if stat & (1 << 20) != 0 { "PAL" } else { "NTSC" },
+                 if stat & (1 << 21) != 0 { 24 } else { 15 },
+                 if stat & (1 << 22) != 0 {
+                     ", interlaced"
+                 } else {
+                     ""
+                 })?;
+        writeln!(s, "Ready: command {} VRAM-to-CPU {} DMA {}",
+                 (stat >> 26) & 1, (stat >> 27) & 1, (stat >> 28) & 1)?;
+        writeln!(s, "DMA direction: {} IRQ: {}",
+                 (stat >> 29) & 3, (stat >> 24) & 1)?;
+
+        let dma = inter.dma();
+
+        writeln!(s, "== DMA ==")?;
+        writeln!(s, "DPCR: {:08x} DICR: {:08x}",
+                 dma.control(), dma.interrupt())?;
+
+        for i in 0..7 {
+            let port = Port::from_index(i);
+            let channel = dma.channel(port);
+
+            writeln!(s, "{:<8} base: {:06x} block: {:08x} \
+                         control: {:08x}{}",
+                     format!("{:?}", port),
+                     channel.base(),
+                     channel.block_control(),
+                     channel.control(),
+                     if channel.active() { " (active)" } else { "" })?;
+        }
+
+        let cdrom = inter.cdrom();
+
+        writeln!(s, "== CD-ROM ==")?;
+
+        if cdrom.disc_present() {
+            let speed =
+                match cdrom.double_speed() {
+                    true => "double",
+                    false => "single",
+                };
+
+            writeln!(s, "Position: {} ({} speed){}",
+                     cdrom.position(),
+                     speed,
+                     if cdrom.reading() { ", reading" } else { "" })?;
+        } else {
+            writeln!(s, "No disc")?;
+        }
+
+        writeln!(s, "Command: {} IRQ flags: {:02x}",
+                 if cdrom.command_idle() { "idle" } else { "pending" },
+                 cdrom.irq_flags())?;
+
+        Ok(())
+    }
+
+    /// Set the maximum number of CPU cycles `run_frame` will run
+    /// before returning `FrameStatus::Timeout`
+    pub fn set_frame_cycle_limit(&mut self, limit: Cycles) {
+        self.frame_cycle_limit = limit;
+    }
+
+    pub fn frame_cycle_limit(&self) -> Cycles {
+        self.frame_cycle_limit
+    }
+
+    /// Make `run_frame` return `FrameStatus::Breakpoint` when the
+    /// frame counter reaches `frame`. The breakpoint is removed once
+    /// it's been hit.
+    pub fn break_at_frame(&mut self, frame: u32) {
+        self.frame_breakpoint = Some(frame);
+    }
+
+    pub fn clear_frame_breakpoint(&mut self) {
+        self.frame_breakpoint = None;
+    }
+
+    pub fn frame_breakpoint(&self) -> Option<u32> {
+        self.frame_breakpoint
+    }
+
+    /// Run the emulator until the end of the current frame. If the
+    /// frame doesn't end within the cycle limit (because the video
+    /// timings are misconfigured for instance) we bail out instead
+    /// of hanging forever.
+    pub fn run_frame(&mut self, renderer: &mut dyn Renderer) -> FrameStatus {
+        let frame = self.shared.frame();
+        let start = self.shared.tk().now();
+
+        while frame == self.shared.frame() {
+            if self.shared.tk().now() - start >= self.frame_cycle_limit {
+                warn!("Frame timeout after {} cycles",
+                      self.shared.tk().now() - start);
+                return FrameStatus::Timeout;
+            }
+
+            self.step(renderer);
+
+            if self.debugger.halted() {
+                self.debugger.clear_halt();
+                return FrameStatus::Halted;
+            }
+        }
+
+        if self.frame_breakpoint == Some(self.shared.frame()) {
+            self.frame_breakpoint = None;
+            return FrameStatus::Breakpoint;
+        }
+
+        FrameStatus::Frame
+    }
+
+    /// Run the emulator for `cycles` CPU cycles. The peripherals are
+    /// synchronized along the way whenever their next event is
+    /// reached. Since instructions can't be interrupted we may run a
+    /// few cycles too many. Returns the number of cycles actually
+    /// emulated, which is less than `cycles` if the execution was
+    /// halted by the debugger.
+    pub fn run_for(&mut self,
+                   renderer: &mut dyn Renderer,
+                   cycles: Cycles) -> Cycles {
+        let start = self.shared.tk().now();
+
+        while self.shared.tk().now() - start < cycles {
+            self.step(renderer);
+
+            if self.debugger.halted() {
+                self.debugger.clear_halt();
+                break;
+            }
+        }
+
+        self.shared.tk().now() - start
+    }
+
+    /// Serialize the state of the CPU, RAM, interrupt controller, GPU
+    /// (including the VRAM read back from `renderer`) and SPU. The
+    /// other peripherals are not saved yet so a game using the DMA,
+    /// timers or CDROM at that point may not resume correctly.
+    pub fn save_state(&self, renderer: &mut dyn Renderer) -> Vec<u8> {
+        let mut w = StateWriter::new(MAGIC, VERSION);
+
+        self.cpu.save_state(&mut w);
+        self.shared.irq_snapshot().save_state(&mut w);
+        self.cpu.interconnect().save_state(&mut w);
+
+        let mut vram = vec![0; VRAM_PIXELS];
+
+        renderer.store_image((0, 0),
+                             (VRAM_WIDTH_PIXELS, VRAM_HEIGHT),
+                             &mut vram);
+
+        for &p in vram.iter() {
+            w.write_u16(p);
+        }
+
+        w.finish()
+    }
+
+    /// Restore a state created by `save_state` and upload its VRAM to
+    /// `renderer`. If an error is returned the machine is left
+    /// untouched.
+    pub fn load_state(&mut self,
+                      renderer: &mut dyn Renderer,
+                      state: &[u8]) -> Result<(), StateError> {
+        // The components are modified as the state is read so we
+        // need a way to roll back if it turns out to be invalid
+        let backup = self.save_state(renderer);
+
+        match self.restore_state(renderer, state) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.restore_state(renderer, &backup)
+                    .expect("Couldn't restore the machine state");
+                Err(e)
+            }
+        }
+    }
+
+    fn restore_state(&mut self,
+                     renderer: &mut dyn Renderer,
+                     state: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(state, MAGIC)?;
+
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        self.cpu.load_state(&mut r)?;
+        *self.shared.irq_state() = InterruptState::load_state(&mut r)?;
+        self.cpu.interconnect_mut().load_state(&mut r)?;
+
+        let mut vram = vec![0; VRAM_PIXELS];
+
+        for p in vram.iter_mut() {
+            *p = r.read_u16()?;
+        }
+
+        r.finish()?;
+
+        renderer.load_image((0, 0),
+                            (VRAM_WIDTH_PIXELS, VRAM_HEIGHT),
+                            &vram);
+
+        self.cpu.interconnect().gpu().configure_renderer(renderer);
+
+        Ok(())
+    }
+}
+
+/// Settings used to build a `Machine`
+pub struct MachineConfig {
+    bios: Bios,
+    disc: Option<Disc>,
+    video_clock: VideoClock,
+    gdb_remote: bool,
+}
+
+impl MachineConfig {
+    /// Create a configuration for a console running `bios` without
+    /// a disc. The video clock matches the region of the BIOS.
+    pub fn new(bios: Bios) -> MachineConfig {
+        let video_clock =
+            match bios.region() {
+                Region::Europe => VideoClock::Pal,
+                _ => VideoClock::Ntsc,
+            };
+
+        MachineConfig {
+            bios,
+            disc: None,
+            video_clock,
+            gdb_remote: cfg!(feature = "gdb"),
+        }
+    }
+
+    /// Insert `disc` in the console (or leave the tray empty if
+    /// `None`)
+    pub fn set_disc(&mut self, disc: Option<Disc>) {
+        self.disc = disc;
+    }
+
+    pub fn disc(&self) -> Option<&Disc> {
+        self.disc.as_ref()
+    }
+
+    /// Override the video clock picked from the BIOS region
+    pub fn set_video_clock(&mut self, clock: VideoClock) {
+        self.video_clock = clock;
+    }
+
+    pub fn video_clock(&self) -> VideoClock {
+        self.video_clock
+    }
+
+    /// Select whether debugger breakpoints enter the GDB remote, see
+    /// `Debugger::set_remote_enabled`. Frontends embedding the
+    /// emulator should disable it so that the execution never blocks
+    /// waiting for a connection.
+    pub fn set_gdb_remote(&mut self, enable: bool) {
+        self.gdb_remote = enable;
+    }
+
+    /// Build the machine
+    pub fn build(self) -> Machine {
+        let gpu = Gpu::new(self.video_clock);
+
+        let mut machine = Machine::new(self.bios, gpu, self.disc);
+
+        machine.debugger_mut().set_remote_enabled(self.gdb_remote);
+
+        machine
+    }
+}
+
+/// Value returned by `Machine::run_frame`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameStatus {
+    /// A new frame has been completed
+    Frame,
+    /// The cycle limit was reached before the end of the frame
+    Timeout,
+    /// A new frame has been completed and its number matches the
+    /// one set with `break_at_frame`
+    Breakpoint,
+    /// The execution was stopped by a debugger breakpoint or
+    /// watchpoint (only when the debugger remote is disabled)
+    Halted,
+}
+
+/// Identifier at the beginning of machine save states
+const MAGIC: &'static [u8; 4] = b"PSXM";
+
+/// Current version of the machine save state format
+const VERSION: u32 = 1;
+
+/// Number of VRAM pixels stored in the machine save states
+const VRAM_PIXELS: usize = VRAM_WIDTH_PIXELS as usize * VRAM_HEIGHT as usize;
+
+/// Default value for the frame cycle limit: one second worth of
+/// emulated time, that's way more than any frame should take even in
+/// PAL mode.
+pub const DEFAULT_FRAME_CYCLE_LIMIT: Cycles = CPU_FREQ_HZ as Cycles;
+
+/// Build a machine running an infinite loop
+#[cfg(test)]
+pub fn looping_machine() -> Machine {
+    use bios::looping_bios;
+
+    let gpu = Gpu::new(VideoClock::Ntsc);
+
+    Machine::new(looping_bios(), gpu, None)
+}
+
+#[test]
+fn frame_timeout() {
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    // The GPU starts in the vertical blanking so the first frame
+    // ends quickly
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Frame);
+
+    // Not enough time to reach the next vblank
+    machine.set_frame_cycle_limit(10_000);
+
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Timeout);
+    assert!(machine.shared().frame() == 1);
+}
+
+#[test]
+fn frame_ready() {
+    let mut machine = looping_machine();
+    let mut renderer = SoftwareRenderer::new();
+
+    assert!(!machine.frame_ready());
+
+    // Step until the end of the first vblank
+    while machine.shared().frame() == 0 {
+        machine.step(&mut renderer);
+    }
+
+    assert!(machine.frame_ready());
+
+    // Polling doesn't clear the flag
+    machine.step(&mut renderer);
+    assert!(machine.frame_ready());
+
+    machine.fetch_frame(&mut renderer);
+
+    assert!(!machine.frame_ready());
+}
+
+#[test]
+fn frame_breakpoint() {
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    machine.break_at_frame(3);
+
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Frame);
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Frame);
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Breakpoint);
+    assert!(machine.shared().frame() == 3);
+
+    // The breakpoint is only hit once
+    assert!(machine.frame_breakpoint() == None);
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Frame);
+    assert!(machine.shared().frame() == 4);
+}
+
+#[test]
+fn frame_callback() {
+    use gpu::renderer::NullRenderer;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    let frames = Rc::new(RefCell::new(Vec::new()));
+
+    {
+        let frames = frames.clone();
+
+        machine.set_frame_callback(Box::new(move |count, _| {
+            frames.borrow_mut().push(count);
+        }));
+    }
+
+    assert!(machine.frames_rendered() == 0);
+
+    for _ in 0..3 {
+        machine.run_frame(&mut renderer);
+    }
+
+    assert!(machine.frames_rendered() == 3);
+    assert!(*frames.borrow() == vec![1, 2, 3]);
+
+    machine.clear_frame_callback();
+    machine.run_frame(&mut renderer);
+
+    assert!(machine.frames_rendered() == 4);
+    assert!(frames.borrow().len() == 3);
+}
+
+#[test]
+fn io_handler() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use memory::{Word, HalfWord};
+    use gpu::renderer::NullRenderer;
+
+    struct Mock {
+        stores: Rc<RefCell<Vec<(u32, u8, u32)>>>,
+    }
+
+    impl IoHandler for Mock {
+        fn load(&mut self, offset: u32, size: u8) -> u32 {
+            0xab000000 | ((size as u32) << 16) | offset
+        }
+
+        fn store(&mut self, offset: u32, size: u8, val: u32) {
+            self.stores.borrow_mut().push((offset, size, val));
+        }
+    }
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    let stores = Rc::new(RefCell::new(Vec::new()));
+
+    // Unused area in the expansion 3 region
+    machine.register_io_handler(0x1fa00000..0x1fa00100,
+                                Box::new(Mock { stores: stores.clone() }));
+
+    let shared = &mut machine.shared;
+    let inter = machine.cpu.interconnect_mut();
+
+    // Accesses through KSEG1 are masked to the physical address
+    assert!(inter.load::<Word>(shared, 0xbfa00010) == 0xab040010);
+    assert!(inter.load::<HalfWord>(shared, 0x1fa000fe) == 0xab0200fe);
+
+    inter.store::<Word>(shared, &mut renderer, 0x9fa00020, 0x12345678);
+    inter.store::<HalfWord>(shared, &mut renderer, 0x1fa00004, 0xbeef);
+
+    assert!(*stores.borrow() == vec![(0x20, 4, 0x12345678),
+                                     (0x04, 2, 0xbeef)]);
+
+    // Addresses outside of the range still go to the hardware
+    inter.store::<Word>(shared, &mut renderer, 0x00000100, 0xcafe);
+    assert!(inter.load::<Word>(shared, 0x00000100) == 0xcafe);
+    assert!(stores.borrow().len() == 2);
+}
+
+#[test]
+fn dump_state() {
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    for _ in 0..3 {
+        machine.step(&mut renderer);
+    }
+
+    let pc = machine.cpu().pc();
+
+    let dump = machine.dump_state();
+
+    for header in &["== CPU ==", "== Interrupts ==", "== GPU ==",
+                    "== DMA ==", "== CD-ROM =="] {
+        assert!(dump.contains(header));
+    }
+
+    assert!(dump.contains(&format!("PC: {:08x} [", pc)));
+    assert!(dump.contains("$zero: 00000000"));
+    assert!(dump.contains("No disc"));
+    assert!(dump.contains("Otc"));
+}
+
+#[test]
+fn region_video_standard() {
+    let machine = looping_machine();
+
+    // The dummy BIOS uses the metadata of a japanese BIOS
+    assert!(machine.region() == Region::Japan);
+    assert!(machine.region_video_standard() == VMode::Ntsc);
+    assert!(machine.video_standard() == VMode::Ntsc);
+}
+
+#[test]
+fn debugger_breakpoints() {
+    use memory::Word;
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    {
+        let shared = &mut machine.shared;
+        let inter = machine.cpu.interconnect_mut();
+
+        let program = [
+            // lw    $t0, 0x2000($zero)
+            0x8c082000,
+            // addiu $t1, $zero, 1
+            0x24090001,
+            // sw    $t1, 0x2004($zero)
+            0xac092004,
+            // j     0x1000
+            0x08000400,
+            // nop
+            0x00000000,
+            ];
+
+        for (i, &w) in program.iter().enumerate() {
+            let addr = 0x1000 + i as u32 * 4;
+
+            inter.store::<Word>(shared, &mut renderer, addr, w);
+        }
+    }
+
+    machine.cpu.force_pc(0xa0001000);
+
+    let debugger = &mut machine.debugger;
+
+    debugger.set_remote_enabled(false);
+    debugger.add_breakpoint(0xa0001004);
+    debugger.add_write_watchpoint(0x2004);
+
+    assert!(debugger.should_break(0xa0001004));
+    assert!(!debugger.should_break(0xa0001000));
+
+    // The code breakpoint stops the CPU before executing the
+    // instruction
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+    assert!(machine.cpu().pc() == 0xa0001004);
+    assert!(machine.cpu().regs()[9] == 0xdeadbeef);
+
+    // The watchpoint stops the CPU after the store
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+    assert!(machine.cpu().pc() == 0xa000100c);
+    assert!(machine.cpu().regs()[9] == 1);
+
+    machine.debugger_mut().remove_breakpoint(0xa0001004);
+    machine.debugger_mut().remove_write_watchpoint(0x2004);
+
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Frame);
+}
+
+#[test]
+fn debugger_step() {
+    use memory::Word;
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    {
+        let shared = &mut machine.shared;
+        let inter = machine.cpu.interconnect_mut();
+
+        // addiu $t0, $zero, 1
+        inter.store::<Word>(shared, &mut renderer, 0x1000, 0x24080001);
+        // addiu $t0, $t0, 1
+        inter.store::<Word>(shared, &mut renderer, 0x1004, 0x25080001);
+    }
+
+    machine.cpu.force_pc(0xa0001000);
+
+    machine.debugger_mut().set_remote_enabled(false);
+
+    // Halt before the first instruction
+    machine.debugger_mut().step();
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+
+    let state = machine.cpu_state();
+
+    assert!(state.pc == 0xa0001000);
+    assert!(state.instruction == 0x24080001);
+    assert!(state.disassembly() == "addiu $t0, $zero, 0x1");
+    assert!(state.regs[8] == 0xdeadbeef);
+
+    machine.debugger_mut().step();
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+
+    let state = machine.cpu_state();
+
+    assert!(state.pc == 0xa0001004);
+    assert!(state.instruction == 0x25080001);
+    assert!(state.regs[8] == 1);
+
+    machine.debugger_mut().step();
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+    assert!(machine.cpu_state().regs[8] == 2);
+}
+
+#[test]
+#[cfg(feature = "gdb")]
+fn gdb_bind_failure() {
+    use std::net::TcpListener;
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    // Point the GDB server to an address which is already in use
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    machine.debugger_mut().set_bind_address(&addr);
+    machine.debugger_mut().step();
+
+    // The debugger can't start the remote so it just halts
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+
+    // And keeps doing so on the next break
+    machine.debugger_mut().clear_halt();
+    machine.debugger_mut().step();
+    assert!(machine.run_frame(&mut renderer) == FrameStatus::Halted);
+}
+
+#[test]
+fn run_for() {
+    use gpu::renderer::NullRenderer;
+
+    let mut machine = looping_machine();
+    let mut renderer = NullRenderer;
+
+    let ran = machine.run_for(&mut renderer, 100_000);
+
+    assert!(ran >= 100_000 && ran < 100_100);
+
+    // Breakpoint in the BIOS loop
+    machine.debugger_mut().set_remote_enabled(false);
+    machine.debugger_mut().add_breakpoint(0xbfc00000);
+
+    let ran = machine.run_for(&mut renderer, 100_000);
+
+    assert!(ran < 100_000);
+    assert!(machine.cpu().pc() == 0xbfc00000);
+}
+
+#[test]
+fn save_state() {
+    use memory::{Word, HalfWord};
+
+    let mut machine = looping_machine();
+    let mut renderer = SoftwareRenderer::new();
+
+    {
+        let shared = &mut machine.shared;
+        let inter = machine.cpu.interconnect_mut();
+
+        let program = [
+            // addiu $t0, $t0, 1
+            0x25080001,
+            // sw    $t0, 0x2000($zero)
+            0xac082000,
+            // j     0x1000
+            0x08000400,
+            // nop
+            0x00000000,
+            ];
+
+        for (i, &w) in program.iter().enumerate() {
+            let addr = 0x1000 + i as u32 * 4;
+
+            inter.store::<Word>(shared, &mut renderer, addr, w);
+        }
+
+        // GP0(0x02): fill a 16x16 rectangle at the top of the VRAM
+        inter.store::<Word>(shared, &mut renderer, 0x1f801810, 0x0200ff00);
+        inter.store::<Word>(shared, &mut renderer, 0x1f801810, 0x00000000);
+        inter.store::<Word>(shared, &mut renderer, 0x1f801810, 0x00100010);
+        // GP0(0xe1): draw mode
+        inter.store::<Word>(shared, &mut renderer, 0x1f801810, 0xe1000205);
+        // SPU main volume
+        inter.store::<HalfWord>(shared, &mut renderer, 0x1f801d80, 0x1234);
+        // IRQ mask
+        inter.store::<Word>(shared, &mut renderer, 0x1f801074, 0x5);
+    }
+
+    machine.cpu.force_pc(0xa0001000);
+
+    for _ in 0..100 {
+        machine.step(&mut renderer);
+    }
+
+    let state = machine.save_state(&mut renderer);
+
+    let mut restored = looping_machine();
+    let mut restored_renderer = SoftwareRenderer::new();
+
+    assert!(restored.load_state(&mut restored_renderer, &state) == Ok(()));
+    assert!(restored.save_state(&mut restored_renderer) == state);
+
+    // Both machines carry on the same way
+    for _ in 0..100 {
+        machine.step(&mut renderer);
+        restored.step(&mut restored_renderer);
+    }
+
+    assert!(restored.cpu_state().regs == machine.cpu_state().regs);
+    assert!(restored.cpu().pc() == machine.cpu().pc());
+
+    // Invalid states are rejected without touching the machine
+    let before = restored.save_state(&mut restored_renderer);
+
+    assert!(restored.load_state(&mut restored_renderer,
+                                &state[..state.len() - 1]) ==
+            Err(StateError::Truncated));
+    assert!(restored.load_state(&mut restored_renderer, b"junk") ==
+            Err(StateError::BadMagic));
+    assert!(restored.save_state(&mut restored_renderer) == before);
+}
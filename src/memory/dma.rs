@@ -110,6 +110,8 @@ impl Dma {
 
         self.channel_mut(port).done();
 
+        debug!("{:?} DMA transfer done", port);
+
         let prev_irq = self.irq();
 
         // Set interrupt flag if the channel's interrupt is enabled
@@ -311,7 +313,7 @@ pub enum Step {
 }
 
 /// DMA transfer synchronization mode
-#[derive(Clone,Copy)]
+#[derive(Clone,Copy,Debug)]
 pub enum Sync {
     /// Transfer starts when the CPU writes to the Trigger bit and
     /// transfers everything at once
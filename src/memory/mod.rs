@@ -1,14 +1,18 @@
 pub mod timers;
 mod ram;
-mod dma;
+pub mod dma;
+pub mod state;
 
 use self::ram::{Ram, ScratchPad};
 use self::dma::{Dma, Port, Direction, Step, Sync};
 use self::timers::Timers;
 
+use std::fmt;
+use std::ops::Range;
+
 use shared::SharedState;
 use bios::Bios;
-use timekeeper::Peripheral;
+use timekeeper::{Peripheral, Cycles};
 use gpu::Gpu;
 use gpu::renderer::Renderer;
 use spu::Spu;
@@ -17,6 +21,8 @@ use cdrom::disc::Disc;
 use padmemcard::PadMemCard;
 use mdec::MDec;
 
+pub use self::map::mask_region;
+
 /// Global interconnect
 pub struct Interconnect {
     /// Basic Input/Output memory
@@ -46,6 +52,13 @@ pub struct Interconnect {
     ram_size: u32,
     /// Memory control registers
     mem_control: [u32; 9],
+    /// If capture is enabled this contains the GPU linked list DMA
+    /// transfers that took place since the last call to
+    /// `take_gpu_dma_capture`
+    gpu_dma_capture: Option<Vec<Vec<LinkedListNode>>>,
+    /// Custom handlers registered with `register_io_handler`. They're
+    /// consulted before the regular memory map.
+    io_handlers: Vec<(Range<u32>, Box<dyn IoHandler>)>,
 }
 
 impl Interconnect {
@@ -64,12 +77,19 @@ impl Interconnect {
             mdec: MDec::new(),
             ram_size: 0,
             mem_control: [0; 9],
+            gpu_dma_capture: None,
+            io_handlers: Vec::new(),
         }
     }
 
-    pub fn sync(&mut self, shared: &mut SharedState) {
+    pub fn sync(&mut self,
+                shared: &mut SharedState,
+                renderer: &mut dyn Renderer) {
         if shared.tk().needs_sync(Peripheral::Gpu) {
             self.gpu.sync(shared);
+            // The GPU might be done drawing
+            self.gpu.process_fifo(renderer);
+            self.gpu.predict_next_sync(shared);
         }
 
         if shared.tk().needs_sync(Peripheral::PadMemCard) {
@@ -81,6 +101,10 @@ impl Interconnect {
         if shared.tk().needs_sync(Peripheral::CdRom) {
             self.cdrom.sync(shared);
         }
+
+        if shared.tk().needs_sync(Peripheral::Spu) {
+            self.spu.sync(shared);
+        }
     }
 
     pub fn cache_control(&self) -> CacheControl {
@@ -92,11 +116,57 @@ impl Interconnect {
         &self.gpu
     }
 
+    /// Return a reference to the BIOS
+    pub fn bios(&self) -> &Bios {
+        &self.bios
+    }
+
+    /// Return a reference to the DMA controller
+    pub fn dma(&self) -> &Dma {
+        &self.dma
+    }
+
+    /// Return a reference to the CDROM controller
+    pub fn cdrom(&self) -> &CdRom {
+        &self.cdrom
+    }
+
+    /// Return a reference to the SPU instance
+    pub fn spu(&self) -> &Spu {
+        &self.spu
+    }
+
+    /// Return a mutable reference to the SPU instance
+    pub fn spu_mut(&mut self) -> &mut Spu {
+        &mut self.spu
+    }
+
     /// Return a mutable reference to the PadMemCard instance
     pub fn pad_memcard_mut(&mut self) -> &mut PadMemCard {
         &mut self.pad_memcard
     }
 
+    /// Enable or disable the capture of GPU linked list DMA
+    /// transfers. Disabling the capture discards any transfer
+    /// captured so far.
+    pub fn set_gpu_dma_capture(&mut self, enable: bool) {
+        self.gpu_dma_capture =
+            match enable {
+                true => Some(Vec::new()),
+                false => None,
+            };
+    }
+
+    /// Return the GPU linked list DMA transfers captured since the
+    /// last call, one `Vec` of nodes per transfer. Returns an empty
+    /// `Vec` if the capture is disabled.
+    pub fn take_gpu_dma_capture(&mut self) -> Vec<Vec<LinkedListNode>> {
+        match self.gpu_dma_capture {
+            Some(ref mut c) => ::std::mem::replace(c, Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
     /// Interconnect: load instruction at `PC`. Only the RAM and BIOS
     /// are supported, would it make sense to fetch instructions from
     /// anything else?
@@ -114,6 +184,29 @@ impl Interconnect {
         panic!("unhandled instruction load at address {:08x}", pc);
     }
 
+    /// Register `handler` for all the accesses within the physical
+    /// address `range`. The handler takes precedence over the regular
+    /// memory map (and over the handlers registered before it if the
+    /// ranges overlap). Instruction fetches are not affected.
+    pub fn register_io_handler(&mut self,
+                               range: Range<u32>,
+                               handler: Box<dyn IoHandler>) {
+        self.io_handlers.insert(0, (range, handler));
+    }
+
+    /// Return the handler for `abs_addr` if any, along with the
+    /// offset of `abs_addr` within its range
+    fn io_handler(&mut self,
+                  abs_addr: u32) -> Option<(&mut dyn IoHandler, u32)> {
+        for &mut (ref range, ref mut handler) in &mut self.io_handlers {
+            if abs_addr >= range.start && abs_addr < range.end {
+                return Some((&mut **handler, abs_addr - range.start));
+            }
+        }
+
+        None
+    }
+
     /// Interconnect: load value at `addr`
     pub fn load<T: Addressable>(&mut self,
                                 shared: &mut SharedState,
@@ -121,10 +214,16 @@ impl Interconnect {
         // XXX Average RAM load delay, needs to do per-device tests
         // XXX This does not take the CPU pipelining into account so
         // it might be a little too slow in some cases actually.
-        shared.tk().tick(5);
+        shared.tk().advance(5);
 
         let abs_addr = map::mask_region(addr);
 
+        if !self.io_handlers.is_empty() {
+            if let Some((handler, offset)) = self.io_handler(abs_addr) {
+                return handler.load(offset, T::size());
+            }
+        }
+
         if let Some(offset) = map::RAM.contains(abs_addr) {
             return self.ram.load::<T>(offset);
         }
@@ -171,6 +270,8 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::SPU.contains(abs_addr) {
+            self.spu.sync(shared);
+
             return self.spu.load::<T>(offset);
         }
 
@@ -191,7 +292,9 @@ impl Interconnect {
         if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
 
             if T::size() != 4 {
-                panic!("Unhandled MEM_CONTROL access ({})", T::size());
+                warn!("Unhandled MEM_CONTROL load ({}) at offset {:x}",
+                      T::size(), offset);
+                return 0;
             }
 
             let index = (offset >> 2) as usize;
@@ -211,6 +314,13 @@ impl Interconnect {
 
         let abs_addr = map::mask_region(addr);
 
+        if !self.io_handlers.is_empty() {
+            if let Some((handler, offset)) = self.io_handler(abs_addr) {
+                handler.store(offset, T::size(), val);
+                return;
+            }
+        }
+
         if let Some(offset) = map::RAM.contains(abs_addr) {
             self.ram.store::<T>(offset, val);
             return;
@@ -264,6 +374,7 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::SPU.contains(abs_addr) {
+            self.spu.sync(shared);
             self.spu.store::<T>(offset, val);
             return;
         }
@@ -286,7 +397,10 @@ impl Interconnect {
         if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
 
             if T::size() != 4 {
-                panic!("Unhandled MEM_CONTROL access ({})", T::size());
+                warn!("Unhandled MEM_CONTROL store ({}) at offset {:x}: \
+                       0x{:08x}",
+                      T::size(), offset, val);
+                return;
             }
 
             let val = val;
@@ -433,16 +547,30 @@ impl Interconnect {
         // process everything in one pass (i.e. no
         // chopping or priority handling)
 
-        match self.dma.channel(port).sync() {
+        // Log with the target of the DMA module so that the transfers
+        // can be filtered with the rest of the DMA messages
+        debug!(target: "rustation::memory::dma",
+               "{:?} DMA transfer, {:?} sync",
+               port, self.dma.channel(port).sync());
+
+        let words =
+            match self.dma.channel(port).sync() {
                 Sync::LinkedList => self.do_dma_linked_list(renderer, port),
                 _                => self.do_dma_block(renderer, port),
-        }
+            };
+
+        // The DMA holds the bus for the duration of the transfer so
+        // the CPU is stalled. Each word takes roughly one cycle.
+        shared.tk().advance(words);
 
         self.dma.done(shared, port);
     }
 
     /// Emulate DMA transfer for linked list synchronization mode.
-    fn do_dma_linked_list(&mut self, renderer: &mut Renderer, port: Port) {
+    /// Returns the number of words read from RAM, headers included.
+    fn do_dma_linked_list(&mut self,
+                          renderer: &mut dyn Renderer,
+                          port: Port) -> Cycles {
         let channel = self.dma.channel_mut(port);
 
         let mut addr = channel.base() & 0x1ffffc;
@@ -457,25 +585,44 @@ impl Interconnect {
             panic!("Attempted linked list DMA on port {:?}", port);
         }
 
+        let capture = self.gpu_dma_capture.is_some();
+        let mut nodes = Vec::new();
+        let mut words = 0;
+
         loop {
             // In linked list mode, each entry starts with a "header"
             // word. The high byte contains the number of words in the
             // "packet" (not counting the header word)
             let header = self.ram.load::<Word>(addr);
 
+            let mut node = LinkedListNode {
+                address: addr,
+                packet: Vec::new(),
+            };
+
             let mut remsz = header >> 24;
 
+            words += 1 + remsz as Cycles;
+
             while remsz > 0 {
                 addr = (addr + 4) & 0x1ffffc;
 
                 let command = self.ram.load::<Word>(addr);
 
+                if capture {
+                    node.packet.push(command);
+                }
+
                 // Send command to the GPU
                 self.gpu.gp0(renderer, command);
 
                 remsz -= 1;
             }
 
+            if capture {
+                nodes.push(node);
+            }
+
             // The end-of-table marker is usually 0xffffff but
             // mednafen only checks for the MSB so maybe that's what
             // the hardware does? Since this bit is not part of any
@@ -487,11 +634,19 @@ impl Interconnect {
 
             addr = header & 0x1ffffc;
         }
+
+        if let Some(ref mut c) = self.gpu_dma_capture {
+            c.push(nodes);
+        }
+
+        words
     }
 
     /// Emulate DMA transfer for Manual and Request synchronization
-    /// modes.
-    fn do_dma_block(&mut self, renderer: &mut Renderer, port: Port) {
+    /// modes. Returns the number of words transferred.
+    fn do_dma_block(&mut self,
+                    renderer: &mut dyn Renderer,
+                    port: Port) -> Cycles {
         let channel = self.dma.channel_mut(port);
 
         let increment = match channel.step() {
@@ -509,6 +664,8 @@ impl Interconnect {
             None    => panic!("Couldn't figure out DMA block transfer size"),
         };
 
+        let words = remsz as Cycles;
+
         while remsz > 0 {
             // Not sure what happens if address is
             // bogus... Mednafen just masks addr this way, maybe
@@ -540,11 +697,7 @@ impl Interconnect {
                             // Pointer to the previous entry
                             _ => addr.wrapping_sub(4) & 0x1fffff,
                         },
-                        Port::Gpu => {
-                            // XXX to be implemented
-                            debug!("DMA GPU READ");
-                            0
-                        }
+                        Port::Gpu => self.gpu.dma_read_word(),
                         Port::CdRom => self.cdrom.dma_read_word(),
                         _ => panic!("Unhandled DMA source port {:?}", port),
                     };
@@ -556,9 +709,44 @@ impl Interconnect {
             addr = addr.wrapping_add(increment);
             remsz -= 1;
         }
+
+        words
     }
 }
 
+/// One node of a linked list DMA transfer
+#[derive(Clone, Debug)]
+pub struct LinkedListNode {
+    /// Address of the node's header in RAM
+    pub address: u32,
+    /// Words sent to the device
+    pub packet: Vec<u32>,
+}
+
+impl fmt::Display for LinkedListNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:06x} ({} words):", self.address, self.packet.len())?;
+
+        for w in &self.packet {
+            write!(f, " {:08x}", w)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Custom memory-mapped device, see
+/// `Interconnect::register_io_handler`. Can be used to mock
+/// peripherals or to trace accesses to a given address range.
+pub trait IoHandler {
+    /// Load `size` bytes (1, 2 or 4) at `offset` within the handler's
+    /// range
+    fn load(&mut self, offset: u32, size: u8) -> u32;
+    /// Store `size` bytes (1, 2 or 4) of `val` at `offset` within the
+    /// handler's range
+    fn store(&mut self, offset: u32, size: u8, val: u32);
+}
+
 #[derive(Clone,Copy)]
 pub struct CacheControl(u32);
 
@@ -689,3 +877,155 @@ mod map {
     /// Cache control register. Full address since it's in KSEG2
     pub const CACHE_CONTROL: Range = Range(0xfffe0130, 4);
 }
+
+/// Build an interconnect for the tests with a dummy BIOS running an
+/// infinite loop, an NTSC GPU and no disc
+#[cfg(test)]
+pub fn test_interconnect() -> Interconnect {
+    use bios::looping_bios;
+    use gpu::VideoClock;
+
+    Interconnect::new(looping_bios(), Gpu::new(VideoClock::Ntsc), None)
+}
+
+#[test]
+fn gpu_dma_linked_list_capture() {
+    use gpu::renderer::NullRenderer;
+
+    let mut inter = test_interconnect();
+    let mut shared = SharedState::new();
+    let mut renderer = NullRenderer;
+
+    // First node at 0x100: monochrome triangle, next node at 0x200
+    let triangle = [0x20ff0000, 0x00000000, 0x00000010, 0x00100000];
+
+    inter.ram.store::<Word>(0x100, 0x04000200);
+    for (i, &w) in triangle.iter().enumerate() {
+        inter.ram.store::<Word>(0x104 + i as u32 * 4, w);
+    }
+
+    // Second node at 0x200: draw mode, end of list
+    inter.ram.store::<Word>(0x200, 0x01ffffff);
+    inter.ram.store::<Word>(0x204, 0xe1000000);
+
+    inter.set_gpu_dma_capture(true);
+
+    let start = shared.tk().now();
+
+    {
+        let channel = inter.dma.channel_mut(Port::Gpu);
+
+        channel.set_base(0x100);
+        // From RAM, linked list mode
+        channel.set_control(0x00000401);
+    }
+
+    inter.do_dma(&mut shared, &mut renderer, Port::Gpu);
+
+    // 2 headers + 5 commands
+    assert!(shared.tk().now() - start == 7);
+
+    let capture = inter.take_gpu_dma_capture();
+
+    assert!(capture.len() == 1);
+
+    let nodes = &capture[0];
+
+    assert!(nodes.len() == 2);
+    assert!(nodes[0].address == 0x100);
+    assert!(nodes[0].packet == triangle.to_vec());
+    assert!(nodes[1].address == 0x200);
+    assert!(nodes[1].packet == vec![0xe1000000]);
+
+    // The capture has been consumed
+    assert!(inter.take_gpu_dma_capture().is_empty());
+}
+
+#[test]
+fn gpu_dma_image_store() {
+    use gpu::software::SoftwareRenderer;
+
+    let mut inter = test_interconnect();
+    let mut shared = SharedState::new();
+    let mut renderer = SoftwareRenderer::new();
+
+    let pixels = [0x1111, 0x2222, 0x3333, 0x4444, 0x5555, 0x6666];
+
+    renderer.load_image((32, 16), (3, 2), &pixels);
+
+    // GPUSTAT bit 27: not ready to send VRAM to CPU
+    assert!(inter.load::<Word>(&mut shared, 0x1f801814) & (1 << 27) == 0);
+
+    // GP0(0xc0): 3x2 image store at 32, 16
+    for &w in &[0xc0000000, 0x00100020, 0x00020003] {
+        inter.store::<Word>(&mut shared, &mut renderer, 0x1f801810, w);
+    }
+
+    assert!(inter.load::<Word>(&mut shared, 0x1f801814) & (1 << 27) != 0);
+
+    {
+        let channel = inter.dma.channel_mut(Port::Gpu);
+
+        channel.set_base(0x100);
+        channel.set_block_control(3);
+        // To RAM, manual sync, enabled and triggered
+        channel.set_control(0x11000000);
+    }
+
+    inter.do_dma(&mut shared, &mut renderer, Port::Gpu);
+
+    assert!(inter.ram.load::<Word>(0x100) == 0x22221111);
+    assert!(inter.ram.load::<Word>(0x104) == 0x44443333);
+    assert!(inter.ram.load::<Word>(0x108) == 0x66665555);
+
+    // The whole image has been read
+    assert!(inter.load::<Word>(&mut shared, 0x1f801814) & (1 << 27) == 0);
+}
+
+#[test]
+fn memory_mirrors() {
+    use gpu::renderer::NullRenderer;
+
+    let mut inter = test_interconnect();
+    let mut shared = SharedState::new();
+    let mut renderer = NullRenderer;
+
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1000, 0x12345678);
+
+    // The 2MB of RAM are mirrored over the first 8MB, in all the
+    // regions
+    for &addr in &[0x00001000, 0x00201000, 0x00401000, 0x00601000,
+                   0x80001000, 0x80601000, 0xa0001000, 0xa0201000] {
+        assert!(inter.load::<Word>(&mut shared, addr) == 0x12345678);
+    }
+
+    inter.store::<HalfWord>(&mut shared, &mut renderer, 0xa0601002, 0xabcd);
+    assert!(inter.load::<Word>(&mut shared, 0x1000) == 0xabcd5678);
+
+    // The ScratchPad is only reachable through KUSEG and KSEG0
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f8003fc, 0xcafef00d);
+    assert!(inter.load::<Word>(&mut shared, 0x9f8003fc) == 0xcafef00d);
+    assert!(inter.load::<Byte>(&mut shared, 0x1f8003ff) == 0xca);
+
+    // And it doesn't alias RAM
+    assert!(inter.load::<Word>(&mut shared, 0x3fc) != 0xcafef00d);
+}
+
+#[test]
+fn mem_control_bad_access_width() {
+    use gpu::renderer::NullRenderer;
+
+    let mut inter = test_interconnect();
+    let mut shared = SharedState::new();
+    let mut renderer = NullRenderer;
+
+    // Expansion 1 delay/size
+    inter.store::<Word>(&mut shared, &mut renderer, 0x1f801008, 0x0013243f);
+
+    // Only word accesses are supported, the others are ignored
+    inter.store::<HalfWord>(&mut shared, &mut renderer, 0x1f801008, 0xffff);
+    inter.store::<Byte>(&mut shared, &mut renderer, 0x1f801008, 0xff);
+
+    assert!(inter.load::<Word>(&mut shared, 0x1f801008) == 0x0013243f);
+    assert!(inter.load::<HalfWord>(&mut shared, 0x1f801008) == 0);
+}
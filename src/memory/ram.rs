@@ -1,3 +1,5 @@
+use savestate::{StateWriter, StateReader, StateError};
+
 use super::Addressable;
 
 /// RAM
@@ -41,6 +43,17 @@ impl Ram {
             self.data[offset + i] = (val >> (i * 8)) as u8;
         }
     }
+
+    /// Serialize the memory contents in `w`
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data[..]);
+    }
+
+    /// Read back the memory contents serialized by `save_state`
+    pub fn load_state(&mut self,
+                      r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.data[..])
+    }
 }
 
 /// ScratchPad memory
@@ -76,6 +89,17 @@ impl ScratchPad {
             self.data[offset + i] = (val >> (i * 8)) as u8;
         }
     }
+
+    /// Serialize the memory contents in `w`
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data[..]);
+    }
+
+    /// Read back the memory contents serialized by `save_state`
+    pub fn load_state(&mut self,
+                      r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.data[..])
+    }
 }
 
 /// Main PlayStation RAM: 2Megabytes
@@ -0,0 +1,43 @@
+//! Interconnect save states: the RAM, the memory control registers,
+//! the GPU and the SPU. The other peripherals (DMA, timers, CDROM,
+//! gamepads, MDEC) are not saved yet.
+
+use savestate::{StateWriter, StateReader, StateError};
+
+use super::{Interconnect, CacheControl};
+
+impl Interconnect {
+    /// Serialize the interconnect state in `w`
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.ram.save_state(w);
+        self.scratch_pad.save_state(w);
+
+        w.write_u32(self.cache_control.0);
+        w.write_u32(self.ram_size);
+
+        for &r in self.mem_control.iter() {
+            w.write_u32(r);
+        }
+
+        w.write_blob(&self.gpu.save_state());
+        w.write_blob(&self.spu.save_state());
+    }
+
+    /// Read back a state serialized by `save_state`. If an error is
+    /// returned the interconnect may have been partially modified.
+    pub fn load_state(&mut self,
+                      r: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load_state(r)?;
+        self.scratch_pad.load_state(r)?;
+
+        self.cache_control = CacheControl(r.read_u32()?);
+        self.ram_size = r.read_u32()?;
+
+        for reg in self.mem_control.iter_mut() {
+            *reg = r.read_u32()?;
+        }
+
+        self.gpu.load_state(r.read_blob()?)?;
+        self.spu.load_state(r.read_blob()?)
+    }
+}
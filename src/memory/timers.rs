@@ -156,6 +156,10 @@ struct Timer {
     phase: FracCycles,
     /// True if interrupt signal is active
     interrupt: bool,
+    /// True if the interrupt has been triggered since the mode was
+    /// last written. Used to inhibit further interrupts in one-shot
+    /// mode (when `repeat_irq` is false).
+    irq_fired: bool,
 }
 
 impl Timer {
@@ -177,6 +181,7 @@ impl Timer {
             period: FracCycles::from_cycles(1),
             phase: FracCycles::from_cycles(0),
             interrupt: false,
+            irq_fired: false,
         }
     }
 
@@ -276,7 +281,14 @@ impl Timer {
         }
 
         self.counter = count as u16;
-        if (self.wrap_irq && overflow) || (self.target_irq && target_passed) {
+
+        // In one-shot mode only the first interrupt condition
+        // triggers the IRQ
+        let irq_enabled = self.repeat_irq || !self.irq_fired;
+
+        if irq_enabled &&
+            ((self.wrap_irq && overflow) ||
+             (self.target_irq && target_passed)) {
             let interrupt =
                 match self.instance {
                     Peripheral::Timer0 => Interrupt::Timer0,
@@ -291,6 +303,7 @@ impl Timer {
                 // Pulse interrupt
                 shared.irq_state().assert(interrupt);
                 self.interrupt = true;
+                self.irq_fired = true;
             }
         } else if !self.negate_irq {
             // Pulse is over
@@ -301,21 +314,42 @@ impl Timer {
     }
 
     fn predict_next_sync(&mut self, shared: &mut SharedState) {
-        // XXX add support for wrap IRQ
+        let irq_enabled = self.repeat_irq || !self.irq_fired;
 
-        if !self.target_irq {
-            // No IRQ enabled, we don't need to be called back.
-            shared.tk().no_sync_needed(self.instance);
-            return;
-        }
+        // The counter only reaches 0xffff if it's not reset by the
+        // target first
+        let can_overflow = !self.target_wrap || self.target == 0xffff;
 
-        let countdown =
+        let target_countdown =
             if self.counter <= self.target {
                 self.target - self.counter
             } else {
                 0xffff - self.counter + self.target
             };
 
+        let overflow_countdown = 0xffff - self.counter;
+
+        let countdown =
+            match (self.target_irq, self.wrap_irq && can_overflow) {
+                _ if !irq_enabled => None,
+                (true, true) =>
+                    Some(::std::cmp::min(target_countdown,
+                                         overflow_countdown)),
+                (true, false) => Some(target_countdown),
+                (false, true) => Some(overflow_countdown),
+                (false, false) => None,
+            };
+
+        let countdown =
+            match countdown {
+                Some(c) => c,
+                None => {
+                    // No IRQ enabled, we don't need to be called back.
+                    shared.tk().no_sync_needed(self.instance);
+                    return;
+                }
+            };
+
         // Convert from timer count to CPU cycles. I add + 1 to the
         // countdown because the interrupt is generated on the
         // following cycle (I think?)
@@ -372,20 +406,14 @@ impl Timer {
         self.negate_irq = (val >> 7) & 1 != 0;
         self.clock_source = ClockSource::from_field((val >> 8) & 3);
 
-        // Writing to mode resets the interrupt flag
+        // Writing to mode resets the interrupt flag and re-arms
+        // one-shot interrupts
         self.interrupt = false;
+        self.irq_fired = false;
 
         // Writing to mode resets the counter
         self.counter = 0;
 
-        if self.wrap_irq {
-            panic!("Wrap IRQ not supported");
-        }
-
-        if (self.wrap_irq || self.target_irq) && !self.repeat_irq {
-            panic!("One shot timer interrupts are not supported: {:?}", self);
-        }
-
         if self.negate_irq {
             panic!("Only pulse interrupts are supported: {:?}", self);
         }
@@ -507,3 +535,70 @@ impl Clock {
         }
     }
 }
+
+#[test]
+fn timer_interrupts() {
+    use gpu::VideoClock;
+    use interrupt::Interrupt;
+    use memory::HalfWord;
+
+    let mut timers = Timers::new();
+    let mut gpu = Gpu::new(VideoClock::Ntsc);
+    let mut shared = SharedState::new();
+
+    let timer2_irq = 1 << (Interrupt::Timer2 as usize);
+
+    // Advance the time and synchronize like the interconnect does
+    let run = |timers: &mut Timers, shared: &mut SharedState, cycles| {
+        shared.tk().advance(cycles);
+
+        if shared.tk().sync_pending() {
+            timers.sync(shared);
+            shared.tk().update_sync_pending();
+        }
+    };
+
+    // Timer 2: system clock, reset on target, repeated target IRQ
+    timers.store::<HalfWord>(&mut shared, &mut gpu, 0x28, 100);
+    timers.store::<HalfWord>(&mut shared, &mut gpu, 0x24, 0x58);
+
+    run(&mut timers, &mut shared, 50);
+
+    // The counter is up to date when read
+    assert!(timers.load::<HalfWord>(&mut shared, 0x20) == 50);
+    assert!(shared.irq_state().status() & timer2_irq == 0);
+
+    run(&mut timers, &mut shared, 51);
+
+    assert!(shared.irq_state().status() & timer2_irq != 0);
+    assert!(timers.load::<HalfWord>(&mut shared, 0x20) == 0);
+
+    shared.irq_state().ack(!timer2_irq);
+
+    // Repeat mode: the interrupt triggers again
+    run(&mut timers, &mut shared, 101);
+
+    assert!(shared.irq_state().status() & timer2_irq != 0);
+
+    shared.irq_state().ack(!timer2_irq);
+
+    // One-shot overflow IRQ
+    timers.store::<HalfWord>(&mut shared, &mut gpu, 0x24, 0x20);
+    timers.store::<HalfWord>(&mut shared, &mut gpu, 0x20, 0xfff0);
+
+    run(&mut timers, &mut shared, 0x10);
+
+    assert!(shared.irq_state().status() & timer2_irq != 0);
+
+    // Overflow flag, reset on read
+    assert!(timers.load::<HalfWord>(&mut shared, 0x24) & 0x1000 != 0);
+    assert!(timers.load::<HalfWord>(&mut shared, 0x24) & 0x1000 == 0);
+
+    shared.irq_state().ack(!timer2_irq);
+
+    run(&mut timers, &mut shared, 0x10000);
+
+    // The flag is set but not the interrupt
+    assert!(shared.irq_state().status() & timer2_irq == 0);
+    assert!(timers.load::<HalfWord>(&mut shared, 0x24) & 0x1000 != 0);
+}
@@ -16,6 +16,8 @@ impl GamePad {
                     Box::new(DisconnectedProfile),
                 Type::Digital =>
                     Box::new(DigitalProfile::new()),
+                Type::DualShock =>
+                    Box::new(DualShockProfile::new()),
             };
 
         GamePad {
@@ -66,11 +68,19 @@ pub enum Type {
     Disconnected,
     /// SCPH-1080: original gamepad without analog sticks
     Digital,
+    /// SCPH-1200: DualShock gamepad with two analog sticks and
+    /// rumble. Starts in digital mode, games can switch it to analog
+    /// mode.
+    DualShock,
 }
 
 #[derive(Clone,Copy,Debug)]
 pub enum Button {
     Select = 0,
+    /// Left stick button, DualShock only
+    L3 = 1,
+    /// Right stick button, DualShock only
+    R3 = 2,
     Start = 3,
     DUp = 4,
     DRight = 5,
@@ -92,6 +102,16 @@ pub enum ButtonState {
     Released,
 }
 
+/// Analog stick axes. For all axes 0x00 is the leftmost/topmost
+/// position, 0x80 is the center and 0xff the rightmost/bottommost.
+#[derive(Clone,Copy,Debug)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+}
+
 /// Trait used to abstract away the various controller types.
 pub trait Profile {
     /// Handle a command byte sent by the console. `seq` is the byte
@@ -108,6 +128,11 @@ pub trait Profile {
     /// in a row with the same button and the same state, it should be
     /// idempotent.
     fn set_button_state(&mut self, button: Button, state: ButtonState);
+
+    /// Set the position of an analog stick axis. Does nothing by
+    /// default, for controllers without analog sticks.
+    fn set_axis_state(&mut self, _axis: Axis, _value: u8) {
+    }
 }
 
 /// Dummy profile emulating an empty pad slot
@@ -159,14 +184,250 @@ impl Profile for DigitalProfile {
     }
 
     fn set_button_state(&mut self, button: Button, state: ButtonState) {
-        let s = self.0;
+        match button {
+            // The digital pad doesn't have those, the bits always
+            // read as 1
+            Button::L3 | Button::R3 => (),
+            _ => self.0 = update_buttons(self.0, button, state),
+        }
+    }
+}
+
+/// SCPH-1200: DualShock gamepad. In digital mode it behaves like the
+/// SCPH-1080, in analog mode it also returns the position of the two
+/// sticks. The mode is changed by the game through the "config"
+/// mode commands.
+struct DualShockProfile {
+    /// Button state, same format as `DigitalProfile`
+    buttons: u16,
+    /// Stick positions in the order they're sent: right X, right Y,
+    /// left X, left Y
+    axes: [u8; 4],
+    /// True if the pad is in analog mode
+    analog: bool,
+    /// True if the pad is in configuration mode
+    config: bool,
+    /// Command being processed
+    command: u8,
+    /// Data bytes returned for the current command
+    reply: [u8; 6],
+    /// Number of valid bytes in `reply`
+    reply_len: u8,
+}
+
+impl DualShockProfile {
+    pub fn new() -> DualShockProfile {
+        DualShockProfile {
+            buttons: 0xffff,
+            axes: [0x80; 4],
+            analog: false,
+            config: false,
+            command: 0,
+            reply: [0; 6],
+            reply_len: 0,
+        }
+    }
+
+    /// Controller ID returned as the response to the command byte
+    fn id(&self) -> u8 {
+        if self.config {
+            0xf3
+        } else if self.analog {
+            0x73
+        } else {
+            0x41
+        }
+    }
+
+    /// Latch the reply to `cmd`. Returns false if the command is not
+    /// supported in the current mode.
+    fn start_command(&mut self, cmd: u8) -> bool {
+        let b = self.buttons;
+        let a = self.axes;
+
+        let poll = [b as u8, (b >> 8) as u8, a[0], a[1], a[2], a[3]];
 
-        let mask = 1 << (button as usize);
+        // In config mode the replies are always 6 bytes long
+        self.reply_len = if self.config || self.analog { 6 } else { 2 };
+        self.command = cmd;
 
-        self.0 =
-            match state {
-                ButtonState::Pressed  => s & !mask,
-                ButtonState::Released => s | mask,
+        self.reply =
+            match (self.config, cmd) {
+                // Read buttons. In analog mode the command bytes
+                // control the rumble motors, we ignore them.
+                (_, 0x42) => poll,
+                // Enter config mode, otherwise behaves like 0x42
+                (false, 0x43) => poll,
+                // Exit config mode, set analog mode
+                (true, 0x43) | (true, 0x44) => [0; 6],
+                // Get status
+                (true, 0x45) => [0x01, 0x02, self.analog as u8,
+                                 0x02, 0x01, 0x00],
+                // Constant responses of unknown meaning, 0x46 and
+                // 0x4c depend on the parameter byte
+                (true, 0x46) => [0x00, 0x00, 0x01, 0x02, 0x00, 0x0a],
+                (true, 0x47) => [0x00, 0x00, 0x02, 0x00, 0x01, 0x00],
+                (true, 0x4c) => [0x00, 0x00, 0x00, 0x04, 0x00, 0x00],
+                // Rumble configuration: accepted but ignored
+                (true, 0x4d) => [0xff; 6],
+                _ => return false,
             };
+
+        true
+    }
+
+    /// Handle the first parameter byte `param` of the current command
+    fn parameter(&mut self, param: u8) {
+        match (self.command, param) {
+            (0x43, p) => self.config = p == 0x01,
+            (0x44, 0x00) => self.analog = false,
+            (0x44, 0x01) => self.analog = true,
+            (0x46, 0x01) => self.reply[3..6].copy_from_slice(&[1, 1, 0x14]),
+            (0x4c, 0x01) => self.reply[3] = 0x07,
+            _ => (),
+        }
+    }
+}
+
+impl Profile for DualShockProfile {
+    fn handle_command(&mut self, seq: u8, cmd: u8) -> (u8, bool) {
+        match seq {
+            // First byte should be 0x01 if the command targets
+            // the controller
+            0 => (0xff, (cmd == 0x01)),
+            // The reply mode is latched here, changes made by this
+            // command only take effect in the next transaction
+            1 => {
+                let id = self.id();
+
+                (id, self.start_command(cmd))
+            }
+            2 => (0x5a, true),
+            _ => {
+                let index = seq - 3;
+
+                if index == 0 {
+                    self.parameter(cmd);
+                }
+
+                if index < self.reply_len {
+                    // We don't assert DSR for the last byte
+                    (self.reply[index as usize], index + 1 < self.reply_len)
+                } else {
+                    // Shouldn't be reached
+                    (0xff, false)
+                }
+            }
+        }
     }
+
+    fn set_button_state(&mut self, button: Button, state: ButtonState) {
+        self.buttons = update_buttons(self.buttons, button, state);
+    }
+
+    fn set_axis_state(&mut self, axis: Axis, value: u8) {
+        let index =
+            match axis {
+                Axis::RightX => 0,
+                Axis::RightY => 1,
+                Axis::LeftX => 2,
+                Axis::LeftY => 3,
+            };
+
+        self.axes[index] = value;
+    }
+}
+
+/// Update `button` in the active-low button mask `buttons`
+fn update_buttons(buttons: u16, button: Button, state: ButtonState) -> u16 {
+    let mask = 1 << (button as usize);
+
+    match state {
+        ButtonState::Pressed  => buttons & !mask,
+        ButtonState::Released => buttons | mask,
+    }
+}
+
+#[test]
+fn digital_pad_read() {
+    let mut pad = GamePad::new(Type::Digital);
+
+    pad.profile().set_button_state(Button::Start, ButtonState::Pressed);
+    pad.profile().set_button_state(Button::Cross, ButtonState::Pressed);
+
+    pad.select();
+
+    let response: Vec<(u8, bool)> =
+        [0x01, 0x42, 0x00, 0x00, 0x00].iter()
+        .map(|&c| pad.send_command(c))
+        .collect();
+
+    assert!(response == vec![(0xff, true),
+                             (0x41, true),
+                             (0x5a, true),
+                             (0xf7, true),
+                             (0xbf, false)]);
+
+    // Unsupported command: the pad stops responding
+    pad.select();
+
+    assert!(pad.send_command(0x01) == (0xff, true));
+    assert!(pad.send_command(0x43) == (0x41, false));
+    assert!(pad.send_command(0x00) == (0xff, false));
+}
+
+#[test]
+fn dualshock_analog_mode() {
+    let mut pad = GamePad::new(Type::DualShock);
+
+    let transaction = |pad: &mut GamePad, cmd: &[u8]| -> Vec<u8> {
+        pad.select();
+
+        let mut response = Vec::new();
+
+        for &c in cmd {
+            let (r, dsr) = pad.send_command(c);
+
+            response.push(r);
+
+            if !dsr {
+                break;
+            }
+        }
+
+        response
+    };
+
+    pad.profile().set_button_state(Button::L3, ButtonState::Pressed);
+    pad.profile().set_axis_state(Axis::LeftX, 0x12);
+    pad.profile().set_axis_state(Axis::RightY, 0xef);
+
+    let poll = [0x01, 0x42, 0, 0, 0, 0, 0, 0, 0];
+
+    // Starts in digital mode
+    assert!(transaction(&mut pad, &poll) == vec![0xff, 0x41, 0x5a,
+                                                 0xfd, 0xff]);
+
+    // Enter config mode
+    assert!(transaction(&mut pad, &[0x01, 0x43, 0x00, 0x01, 0x00])
+            == vec![0xff, 0x41, 0x5a, 0xfd, 0xff]);
+
+    // Set analog mode and lock it
+    assert!(transaction(&mut pad, &[0x01, 0x44, 0x00, 0x01, 0x03,
+                                    0x00, 0x00, 0x00, 0x00])
+            == vec![0xff, 0xf3, 0x5a, 0, 0, 0, 0, 0, 0]);
+
+    // Get status
+    assert!(transaction(&mut pad, &[0x01, 0x45, 0, 0, 0, 0, 0, 0, 0])
+            == vec![0xff, 0xf3, 0x5a, 0x01, 0x02, 0x01, 0x02, 0x01, 0x00]);
+
+    // Exit config mode
+    assert!(transaction(&mut pad, &[0x01, 0x43, 0x00, 0x00, 0x00,
+                                    0x00, 0x00, 0x00, 0x00])
+            == vec![0xff, 0xf3, 0x5a, 0, 0, 0, 0, 0, 0]);
+
+    // The sticks are now returned, rumble bytes are ignored
+    assert!(transaction(&mut pad, &[0x01, 0x42, 0x00, 0xff, 0xff,
+                                    0, 0, 0, 0])
+            == vec![0xff, 0x73, 0x5a, 0xfd, 0xff, 0x80, 0xef, 0x12, 0x80]);
 }
@@ -0,0 +1,814 @@
+//! Memory card image handling. The memory card contains 128KB of
+//! flash split in 16 blocks of 8KB. The first block holds the
+//! directory: a header frame followed by 15 directory frames, one for
+//! each of the remaining 15 data blocks. Saves larger than 8KB use
+//! several blocks linked together by the directory frames, they're
+//! not necessarily contiguous.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::{read_u32_le, write_u32_le};
+
+pub struct MemoryCard {
+    /// Raw memory card contents
+    data: Box<[u8; MEMORY_CARD_SIZE]>,
+    /// File backing the card, if any
+    path: Option<PathBuf>,
+    /// True if the contents changed since the last `flush`
+    dirty: bool,
+    /// Flag byte returned with each command. Bit 3 is set on power
+    /// up and cleared by the first successful write.
+    flag: u8,
+    /// False if the card is done processing the current transaction
+    active: bool,
+    /// Position in the current transaction
+    seq: u8,
+    /// Current command
+    command: u8,
+    /// Last byte received
+    previous: u8,
+    /// Sector (frame) being accessed
+    sector: u16,
+    /// Running checksum of the current sector transfer
+    checksum: u8,
+    /// Data received by the write command
+    buffer: [u8; FRAME_SIZE],
+}
+
+impl MemoryCard {
+    /// Create a new, freshly formatted memory card
+    pub fn new() -> MemoryCard {
+        let mut card = MemoryCard::blank();
+
+        card.format();
+
+        card
+    }
+
+    /// Create a memory card from a raw 128KB image. Returns None if
+    /// `data` doesn't have the right size.
+    pub fn from_data(data: &[u8]) -> Option<MemoryCard> {
+        if data.len() != MEMORY_CARD_SIZE {
+            return None;
+        }
+
+        let mut card = MemoryCard::blank();
+
+        card.data.copy_from_slice(data);
+
+        Some(card)
+    }
+
+    /// Load the memory card image stored at `path`. If the file
+    /// doesn't exist a new formatted card is created, the file will
+    /// be written by the first call to `flush`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MemoryCard> {
+        let path = path.as_ref();
+
+        let mut card =
+            match File::open(path) {
+                Ok(mut f) => {
+                    let mut data = Vec::new();
+
+                    f.read_to_end(&mut data)?;
+
+                    match MemoryCard::from_data(&data) {
+                        Some(c) => c,
+                        None => return Err(
+                            io::Error::new(io::ErrorKind::InvalidData,
+                                           "Invalid memory card size")),
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                    MemoryCard::new(),
+                Err(e) => return Err(e),
+            };
+
+        card.path = Some(path.to_path_buf());
+
+        Ok(card)
+    }
+
+    /// Return the path of the file backing this card, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|p| p.as_ref())
+    }
+
+    /// True if the card has been modified since the last `flush`
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Write the card contents to its backing file if they've been
+    /// modified. Does nothing for cards without a backing file.
+    /// Frontends should call it regularly (once per frame for
+    /// instance) and before exiting.
+    ///
+    /// The contents are written to a temporary file in the same
+    /// directory which then replaces the backing file, that way an
+    /// interrupted flush can't leave a truncated image behind.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(ref path) = self.path {
+            let mut tmp_name = path.as_os_str().to_os_string();
+
+            tmp_name.push(".tmp");
+
+            let tmp_path = PathBuf::from(tmp_name);
+
+            {
+                let mut f = File::create(&tmp_path)?;
+
+                f.write_all(&self.data[..])?;
+                f.sync_all()?;
+            }
+
+            fs::rename(&tmp_path, path)?;
+        }
+
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    /// Called when the "select" line goes down.
+    pub fn select(&mut self) {
+        self.active = true;
+        self.seq = 0;
+    }
+
+    /// Handle a byte sent by the console. Returns the response byte
+    /// and whether the card asserts DSR (i.e. expects more bytes)
+    pub fn send_command(&mut self, cmd: u8) -> (u8, bool) {
+        if !self.active {
+            return (0xff, false);
+        }
+
+        let (resp, dsr) =
+            match self.seq {
+                // The memory cards are addressed with 0x81, the pads
+                // with 0x01
+                0 => (0xff, cmd == 0x81),
+                1 => {
+                    self.command = cmd;
+
+                    (self.flag, cmd == b'R' || cmd == b'W' || cmd == b'S')
+                }
+                // Memory card ID
+                2 => (0x5a, true),
+                3 => (0x5d, true),
+                seq => {
+                    let index = seq as usize - 4;
+
+                    match self.command {
+                        b'R' => self.read_command(index, cmd),
+                        b'W' => self.write_command(index, cmd),
+                        _ => self.get_id_command(index),
+                    }
+                }
+            };
+
+        self.active = dsr;
+        self.previous = cmd;
+        self.seq = self.seq.saturating_add(1);
+
+        (resp, dsr)
+    }
+
+    /// Read sector: the console sends the sector number and the card
+    /// replies with an acknowledge, the sector number, the 128 data
+    /// bytes, a checksum and a status byte.
+    fn read_command(&mut self, index: usize, cmd: u8) -> (u8, bool) {
+        match index {
+            0 => {
+                self.sector = (cmd as u16) << 8;
+
+                (0x00, true)
+            }
+            1 => {
+                self.sector |= cmd as u16;
+
+                (self.previous, true)
+            }
+            2 => (0x5c, true),
+            3 => (0x5d, true),
+            _ if self.sector as usize >= SECTOR_COUNT => {
+                // Invalid sector, the card aborts the transfer
+                (0xff, index < 5)
+            }
+            4 => {
+                let msb = (self.sector >> 8) as u8;
+
+                self.checksum = msb;
+
+                (msb, true)
+            }
+            5 => {
+                let lsb = self.sector as u8;
+
+                self.checksum ^= lsb;
+
+                (lsb, true)
+            }
+            6...133 => {
+                let b = self.data[self.sector_offset() + index - 6];
+
+                self.checksum ^= b;
+
+                (b, true)
+            }
+            134 => (self.checksum, true),
+            // Good
+            135 => (b'G', false),
+            _ => (0xff, false),
+        }
+    }
+
+    /// Write sector: the console sends the sector number, the 128
+    /// data bytes and the checksum. The card echoes each byte then
+    /// replies with an acknowledge and a status byte.
+    fn write_command(&mut self, index: usize, cmd: u8) -> (u8, bool) {
+        match index {
+            0 => {
+                self.sector = (cmd as u16) << 8;
+                self.checksum = cmd;
+
+                (0x00, true)
+            }
+            1...129 => {
+                if index == 1 {
+                    self.sector |= cmd as u16;
+                } else {
+                    self.buffer[index - 2] = cmd;
+                }
+
+                self.checksum ^= cmd;
+
+                (self.previous, true)
+            }
+            // Checksum
+            130 => {
+                self.checksum ^= cmd;
+
+                (self.previous, true)
+            }
+            131 => (0x5c, true),
+            132 => (0x5d, true),
+            133 => {
+                let status =
+                    if self.sector as usize >= SECTOR_COUNT {
+                        0xff
+                    } else if self.checksum != 0 {
+                        // The checksum byte cancels the XOR of the
+                        // rest if it's valid
+                        b'N'
+                    } else {
+                        let offset = self.sector_offset();
+
+                        self.data[offset..offset + FRAME_SIZE]
+                            .copy_from_slice(&self.buffer);
+
+                        self.dirty = true;
+                        self.flag &= !0x08;
+
+                        b'G'
+                    };
+
+                (status, false)
+            }
+            _ => (0xff, false),
+        }
+    }
+
+    /// Get ID: returns a constant sequence describing the card
+    fn get_id_command(&mut self, index: usize) -> (u8, bool) {
+        let id = [0x5c, 0x5d, 0x04, 0x00, 0x00, 0x80];
+
+        match id.get(index) {
+            Some(&b) => (b, index + 1 < id.len()),
+            None => (0xff, false),
+        }
+    }
+
+    /// Offset of the current sector in the card's data
+    fn sector_offset(&self) -> usize {
+        self.sector as usize * FRAME_SIZE
+    }
+
+    /// Create a card filled with zeroes, not formatted
+    fn blank() -> MemoryCard {
+        MemoryCard {
+            data: box_array![0; MEMORY_CARD_SIZE],
+            path: None,
+            dirty: false,
+            flag: 0x08,
+            active: false,
+            seq: 0,
+            command: 0,
+            previous: 0,
+            sector: 0,
+            checksum: 0,
+            buffer: [0; FRAME_SIZE],
+        }
+    }
+
+    /// Return the raw contents of the memory card
+    pub fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    /// Erase the entire card and rebuild an empty directory
+    pub fn format(&mut self) {
+        for b in self.data.iter_mut() {
+            *b = 0;
+        }
+
+        // Header frame
+        {
+            let header = self.frame_mut(0);
+
+            header[0] = b'M';
+            header[1] = b'C';
+        }
+        self.update_checksum(0);
+
+        for block in 1..BLOCK_COUNT {
+            self.free_block(block);
+        }
+
+        // Broken sector list: no broken sector
+        for frame in 16..36 {
+            {
+                let f = self.frame_mut(frame);
+
+                for b in f[0..4].iter_mut() {
+                    *b = 0xff;
+                }
+
+                f[8] = 0xff;
+                f[9] = 0xff;
+            }
+            self.update_checksum(frame);
+        }
+
+        // The "write test" frame is a copy of the header
+        let header = self.data[0..FRAME_SIZE].to_vec();
+
+        self.frame_mut(63).copy_from_slice(&header);
+    }
+
+    /// Enumerate all the saves on the card
+    pub fn saves(&self) -> Vec<SaveInfo> {
+        let mut saves = Vec::new();
+
+        for block in 1..BLOCK_COUNT {
+            let entry = self.directory_frame(block);
+
+            if entry[0] != block_state::FIRST {
+                continue;
+            }
+
+            saves.push(SaveInfo {
+                name: entry_name(entry),
+                size: read_u32_le(&entry[4..]),
+                blocks: self.block_chain(block),
+            });
+        }
+
+        saves
+    }
+
+    /// Return the number of unallocated blocks
+    pub fn free_blocks(&self) -> usize {
+        (1..BLOCK_COUNT)
+            .filter(|&b| self.directory_frame(b)[0] & 0xf0 == 0xa0)
+            .count()
+    }
+
+    /// Return the contents of the save called `name`, or None if no
+    /// such save exists.
+    pub fn read_save(&self, name: &str) -> Option<Vec<u8>> {
+        let save = match self.find_save(name) {
+            Some(s) => s,
+            None => return None,
+        };
+
+        let mut data = Vec::with_capacity(save.blocks.len() * BLOCK_SIZE);
+
+        for &block in &save.blocks {
+            let start = block * BLOCK_SIZE;
+
+            data.extend_from_slice(&self.data[start..start + BLOCK_SIZE]);
+        }
+
+        Some(data)
+    }
+
+    /// Write a new save called `name` containing `data`. The length
+    /// of `data` must be a non-zero multiple of the block size
+    /// (8KB). If a save with the same name already exists it's
+    /// replaced.
+    pub fn write_save(&mut self,
+                      name: &str,
+                      data: &[u8]) -> Result<(), MemoryCardError> {
+        if data.is_empty() || data.len() % BLOCK_SIZE != 0 {
+            return Err(MemoryCardError::BadSize(data.len()));
+        }
+
+        if name.is_empty() || name.len() > MAX_NAME_LEN || !name.is_ascii() {
+            return Err(MemoryCardError::BadName);
+        }
+
+        let nblocks = data.len() / BLOCK_SIZE;
+
+        // The blocks of the save we're replacing (if any) can be
+        // reused
+        let replaced = self.find_save(name).map(|s| s.blocks.len());
+
+        let available = self.free_blocks() + replaced.unwrap_or(0);
+
+        if available < nblocks {
+            return Err(MemoryCardError::NoSpace);
+        }
+
+        if replaced.is_some() {
+            self.delete_save(name);
+        }
+
+        // Allocate the first free blocks. If the card has been used
+        // for a while they might not be contiguous.
+        let blocks: Vec<usize> =
+            (1..BLOCK_COUNT)
+            .filter(|&b| self.directory_frame(b)[0] & 0xf0 == 0xa0)
+            .take(nblocks)
+            .collect();
+
+        for (i, &block) in blocks.iter().enumerate() {
+            let start = block * BLOCK_SIZE;
+            let chunk = &data[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
+
+            self.data[start..start + BLOCK_SIZE].copy_from_slice(chunk);
+            self.dirty = true;
+
+            let state =
+                if i == 0 {
+                    block_state::FIRST
+                } else if i == nblocks - 1 {
+                    block_state::LAST
+                } else {
+                    block_state::MIDDLE
+                };
+
+            // For single block saves the first block is also the last
+            let next =
+                match blocks.get(i + 1) {
+                    Some(&n) => (n - 1) as u16,
+                    None => 0xffff,
+                };
+
+            {
+                let entry = self.frame_mut(block);
+
+                for b in entry.iter_mut() {
+                    *b = 0;
+                }
+
+                entry[0] = state;
+
+                if i == 0 {
+                    write_u32_le(&mut entry[4..], data.len() as u32);
+
+                    entry[0xa..0xa + name.len()]
+                        .copy_from_slice(name.as_bytes());
+                }
+
+                entry[8] = next as u8;
+                entry[9] = (next >> 8) as u8;
+            }
+
+            self.update_checksum(block);
+        }
+
+        Ok(())
+    }
+
+    /// Delete the save called `name`. Returns false if no such save
+    /// exists.
+    pub fn delete_save(&mut self, name: &str) -> bool {
+        match self.find_save(name) {
+            Some(save) => {
+                for &block in &save.blocks {
+                    self.free_block(block);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find_save(&self, name: &str) -> Option<SaveInfo> {
+        self.saves().into_iter().find(|s| s.name == name)
+    }
+
+    /// Follow the chain of blocks starting at `first`
+    fn block_chain(&self, first: usize) -> Vec<usize> {
+        let mut blocks = vec![first];
+        let mut block = first;
+
+        loop {
+            let entry = self.directory_frame(block);
+
+            let next = entry[8] as usize | ((entry[9] as usize) << 8);
+
+            if next == 0xffff {
+                break;
+            }
+
+            block = next + 1;
+
+            // Make sure we don't loop forever on a corrupted
+            // directory
+            if block >= BLOCK_COUNT || blocks.contains(&block) {
+                warn!("Corrupted memory card directory entry {}", first);
+                break;
+            }
+
+            blocks.push(block);
+        }
+
+        blocks
+    }
+
+    /// Mark `block` as free in the directory
+    fn free_block(&mut self, block: usize) {
+        {
+            let entry = self.frame_mut(block);
+
+            for b in entry.iter_mut() {
+                *b = 0;
+            }
+
+            entry[0] = block_state::FREE;
+            entry[8] = 0xff;
+            entry[9] = 0xff;
+        }
+
+        self.update_checksum(block);
+    }
+
+    /// Return the directory frame for data block `block`
+    fn directory_frame(&self, block: usize) -> &[u8] {
+        let start = block * FRAME_SIZE;
+
+        &self.data[start..start + FRAME_SIZE]
+    }
+
+    /// Return a mutable reference to the frame at index `frame` in
+    /// the directory block
+    fn frame_mut(&mut self, frame: usize) -> &mut [u8] {
+        let start = frame * FRAME_SIZE;
+
+        self.dirty = true;
+
+        &mut self.data[start..start + FRAME_SIZE]
+    }
+
+    /// Recompute the checksum of the directory frame `frame`: the XOR
+    /// of the first 127 bytes stored in the last byte
+    fn update_checksum(&mut self, frame: usize) {
+        let f = self.frame_mut(frame);
+
+        let checksum = f[0..FRAME_SIZE - 1].iter().fold(0, |c, &b| c ^ b);
+
+        f[FRAME_SIZE - 1] = checksum;
+    }
+}
+
+/// Description of a save in a memory card
+#[derive(Clone, Debug)]
+pub struct SaveInfo {
+    /// Save file name as stored in the directory
+    pub name: String,
+    /// Size in bytes
+    pub size: u32,
+    /// Data blocks used by the save, in order
+    pub blocks: Vec<usize>,
+}
+
+/// Error returned by the memory card manipulation methods
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryCardError {
+    /// Save size is not a non-zero multiple of the block size
+    BadSize(usize),
+    /// Save name is empty, too long or not ASCII
+    BadName,
+    /// Not enough free blocks on the card
+    NoSpace,
+}
+
+impl fmt::Display for MemoryCardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryCardError::BadSize(s) => write!(f, "Invalid save size {}", s),
+            MemoryCardError::BadName => write!(f, "Invalid save name"),
+            MemoryCardError::NoSpace => write!(f, "Memory card is full"),
+        }
+    }
+}
+
+/// Extract the file name from a directory entry
+fn entry_name(entry: &[u8]) -> String {
+    let name = &entry[0xa..0xa + MAX_NAME_LEN];
+
+    let len = name.iter().position(|&b| b == 0).unwrap_or(MAX_NAME_LEN);
+
+    String::from_utf8_lossy(&name[0..len]).into_owned()
+}
+
+mod block_state {
+    //! Values of the first byte of directory frames
+
+    /// First block of a save
+    pub const FIRST:  u8 = 0x51;
+    /// Block in the middle of a save
+    pub const MIDDLE: u8 = 0x52;
+    /// Last block of a multi-block save
+    pub const LAST:   u8 = 0x53;
+    /// Free block
+    pub const FREE:   u8 = 0xa0;
+}
+
+/// Total size of a memory card: 128KB
+pub const MEMORY_CARD_SIZE: usize = 128 * 1024;
+
+/// Size of a block
+pub const BLOCK_SIZE: usize = 8 * 1024;
+
+/// Number of blocks on the card (including the directory block)
+const BLOCK_COUNT: usize = MEMORY_CARD_SIZE / BLOCK_SIZE;
+
+/// The card is accessed 128 bytes at a time
+const FRAME_SIZE: usize = 128;
+
+/// Number of frames on the card
+const SECTOR_COUNT: usize = MEMORY_CARD_SIZE / FRAME_SIZE;
+
+/// Maximum length of a save file name
+const MAX_NAME_LEN: usize = 20;
+
+#[test]
+fn memory_card_multi_block_save() {
+    let mut card = MemoryCard::new();
+
+    assert!(card.saves().is_empty());
+    assert!(card.free_blocks() == 15);
+
+    let block = vec![0x11; BLOCK_SIZE];
+
+    card.write_save("BASLUS-00001A", &block).unwrap();
+    card.write_save("BASLUS-00002B", &block).unwrap();
+
+    // Free the first block to force the next save to be fragmented
+    assert!(card.delete_save("BASLUS-00001A"));
+
+    let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+
+    card.write_save("BESCES-01234C", &data).unwrap();
+
+    let saves = card.saves();
+
+    assert!(saves.len() == 2);
+
+    let save = saves.iter().find(|s| s.name == "BESCES-01234C").unwrap();
+
+    assert!(save.size == 3 * BLOCK_SIZE as u32);
+    assert!(save.blocks == vec![1, 3, 4]);
+
+    assert!(card.read_save("BESCES-01234C").unwrap() == data);
+    assert!(card.free_blocks() == 11);
+
+    // Make sure the directory checksums are valid
+    for frame in 0..16 {
+        let f = &card.data()[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE];
+
+        let checksum = f[0..FRAME_SIZE - 1].iter().fold(0, |c, &b| c ^ b);
+
+        assert!(checksum == f[FRAME_SIZE - 1]);
+    }
+
+    // Not enough room left
+    let big = vec![0; 12 * BLOCK_SIZE];
+
+    assert!(card.write_save("BIG", &big) == Err(MemoryCardError::NoSpace));
+}
+
+#[test]
+fn memory_card_protocol() {
+    let mut card = MemoryCard::new();
+
+    let transaction = |card: &mut MemoryCard, cmd: &[u8]| -> Vec<u8> {
+        card.select();
+
+        let mut response = Vec::new();
+
+        for (i, &c) in cmd.iter().enumerate() {
+            let (r, dsr) = card.send_command(c);
+
+            response.push(r);
+
+            // DSR must be asserted for all the bytes but the last
+            assert!(dsr == (i + 1 < cmd.len()));
+        }
+
+        response
+    };
+
+    let data: Vec<u8> = (0..FRAME_SIZE).map(|i| (i * 3) as u8).collect();
+
+    // Write sector 0x123
+    let checksum = data.iter().fold(0x01 ^ 0x23, |c, &b| c ^ b);
+
+    let mut write = vec![0x81, b'W', 0x00, 0x00, 0x01, 0x23];
+    write.extend_from_slice(&data);
+    write.extend_from_slice(&[checksum, 0x00, 0x00, 0x00]);
+
+    let response = transaction(&mut card, &write);
+
+    // Flag, ID, echo of the sector number and data
+    assert!(response[1] == 0x08);
+    assert!(response[2..6] == [0x5a, 0x5d, 0x00, 0x01]);
+    assert!(response[6] == 0x23);
+    assert!(response[7..135] == data[..]);
+    assert!(response[135..] == [0x5c, 0x5d, b'G']);
+
+    // Read it back. The flag has been cleared by the write.
+    let mut read = vec![0x81, b'R', 0x00, 0x00, 0x01, 0x23];
+    read.extend_from_slice(&[0; 4 + FRAME_SIZE + 2]);
+
+    let response = transaction(&mut card, &read);
+
+    assert!(response[1] == 0x00);
+    assert!(response[2..10] == [0x5a, 0x5d, 0x00, 0x01,
+                                0x5c, 0x5d, 0x01, 0x23]);
+    assert!(response[10..138] == data[..]);
+    assert!(response[138..] == [checksum, b'G']);
+
+    // Bad checksum: the sector isn't modified
+    write[6] ^= 0xff;
+
+    let response = transaction(&mut card, &write);
+
+    assert!(response[135..] == [0x5c, 0x5d, b'N']);
+    assert!(card.data()[0x123 * FRAME_SIZE] == data[0]);
+
+    // Pads are addressed with 0x01, the card must not answer
+    card.select();
+
+    assert!(card.send_command(0x01) == (0xff, false));
+    assert!(card.send_command(0x42) == (0xff, false));
+}
+
+#[test]
+fn memory_card_file() {
+    use std::process;
+
+    let name = format!("rustation-memory-card-test-{}.mcr", process::id());
+    let path = ::std::env::temp_dir().join(name);
+
+    let _ = fs::remove_file(&path);
+
+    // The file is created by the first flush
+    let mut card = MemoryCard::open(&path).unwrap();
+
+    assert!(card.path() == Some(path.as_path()));
+    assert!(card.dirty());
+
+    card.write_save("BASLUS-00001A", &[0x42; BLOCK_SIZE]).unwrap();
+    card.flush().unwrap();
+
+    assert!(!card.dirty());
+
+    // The temporary file has been renamed
+    let mut tmp_name = path.as_os_str().to_os_string();
+
+    tmp_name.push(".tmp");
+
+    assert!(!Path::new(&tmp_name).exists());
+
+    let card = MemoryCard::open(&path).unwrap();
+
+    assert!(card.read_save("BASLUS-00001A") == Some(vec![0x42; BLOCK_SIZE]));
+
+    // Invalid images are rejected
+    File::create(&path).unwrap().write_all(b"not a memory card").unwrap();
+
+    assert!(MemoryCard::open(&path).is_err());
+
+    fs::remove_file(&path).unwrap();
+}
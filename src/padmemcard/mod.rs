@@ -6,8 +6,10 @@ use timekeeper::{Peripheral, Cycles};
 use shared::SharedState;
 
 use self::gamepad::GamePad;
+use self::memorycard::MemoryCard;
 
 pub mod gamepad;
+pub mod memorycard;
 
 pub struct PadMemCard {
     /// Serial clock divider. The LSB is read/write but is not used,
@@ -54,6 +56,10 @@ pub struct PadMemCard {
     pad1: GamePad,
     /// Gamepad in slot 2
     pad2: GamePad,
+    /// Memory card in slot 1, if any
+    memcard1: Option<MemoryCard>,
+    /// Memory card in slot 2, if any
+    memcard2: Option<MemoryCard>,
     /// Bus state machine
     bus: BusState,
 }
@@ -75,6 +81,8 @@ impl PadMemCard {
             rx_not_empty: false,
             pad1: GamePad::new(gamepad::Type::Digital),
             pad2: GamePad::new(gamepad::Type::Disconnected),
+            memcard1: None,
+            memcard2: None,
             bus: BusState::Idle,
         }
     }
@@ -221,6 +229,43 @@ impl PadMemCard {
         }
     }
 
+    /// Replace the gamepad in `slot` (0 or 1) with a new one of type
+    /// `pad_type`. The button state is reset.
+    pub fn set_pad_type(&mut self, slot: usize, pad_type: gamepad::Type) {
+        let pad = GamePad::new(pad_type);
+
+        match slot {
+            0 => self.pad1 = pad,
+            1 => self.pad2 = pad,
+            _ => panic!("Invalid gamepad slot {}", slot),
+        }
+    }
+
+    /// Insert `card` in `slot` (0 or 1), replacing the previous one
+    /// if any. Use `None` to remove the card. Returns the card
+    /// previously inserted, the caller should `flush` it.
+    pub fn set_memory_card(&mut self,
+                           slot: usize,
+                           card: Option<MemoryCard>) -> Option<MemoryCard> {
+        let cur = self.memory_card_slot(slot);
+
+        ::std::mem::replace(cur, card)
+    }
+
+    /// Return a mutable reference to the memory card in `slot` (0 or
+    /// 1) if any. Can be used to `flush` the card.
+    pub fn memory_card_mut(&mut self, slot: usize) -> Option<&mut MemoryCard> {
+        self.memory_card_slot(slot).as_mut()
+    }
+
+    fn memory_card_slot(&mut self, slot: usize) -> &mut Option<MemoryCard> {
+        match slot {
+            0 => &mut self.memcard1,
+            1 => &mut self.memcard2,
+            _ => panic!("Invalid memory card slot {}", slot),
+        }
+    }
+
     /// Return a mutable reference to the gamepad profiles being used.
     pub fn pad_profiles(&mut self) -> [&mut gamepad::Profile; 2] {
         [ self.pad1.profile(), self.pad2.profile() ]
@@ -240,10 +285,26 @@ impl PadMemCard {
 
         let (response, dsr) =
             if self.select {
-                match self.target {
-                    Target::PadMemCard1 => self.pad1.send_command(cmd),
-                    Target::PadMemCard2 => self.pad2.send_command(cmd),
-                }
+                let (pad, card) =
+                    match self.target {
+                        Target::PadMemCard1 => (&mut self.pad1,
+                                                &mut self.memcard1),
+                        Target::PadMemCard2 => (&mut self.pad2,
+                                                &mut self.memcard2),
+                    };
+
+                // The pad and the memory card share the bus, only the
+                // one addressed by the first byte answers. The other
+                // one leaves the lines high (0xff, no DSR).
+                let (pad_resp, pad_dsr) = pad.send_command(cmd);
+
+                let (card_resp, card_dsr) =
+                    match *card {
+                        Some(ref mut c) => c.send_command(cmd),
+                        None => (0xff, false),
+                    };
+
+                (pad_resp & card_resp, pad_dsr || card_dsr)
             } else {
                 // No response
                 (0xff, false)
@@ -363,10 +424,23 @@ impl PadMemCard {
             }
 
             if !prev_select && self.select {
-                // XXX Should probably also check self.target, not
-                // sure how it influences the select line. I assume
-                // only the targeted slot is selected?
-                self.pad1.select();
+                // XXX I assume only the targeted slot is selected
+                match self.target {
+                    Target::PadMemCard1 => {
+                        self.pad1.select();
+
+                        if let Some(ref mut c) = self.memcard1 {
+                            c.select();
+                        }
+                    }
+                    Target::PadMemCard2 => {
+                        self.pad2.select();
+
+                        if let Some(ref mut c) = self.memcard2 {
+                            c.select();
+                        }
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,38 @@
+//! Re-exports of the types most frontends need, to be glob-imported:
+//!
+//! ```
+//! use rustation::prelude::*;
+//!
+//! // A real BIOS would be loaded with `Bios::from_file`, for this
+//! // example we use a dummy image running an infinite loop at the
+//! // reset vector
+//! let mut binary = Box::new([0; BIOS_SIZE]);
+//!
+//! // j 0xbfc00000
+//! binary[..4].copy_from_slice(&[0x00, 0x00, 0xf0, 0x0b]);
+//!
+//! let mut config = MachineConfig::new(Bios::dummy(binary));
+//!
+//! // Games would be loaded with `Disc::from_cue`
+//! config.set_disc(None);
+//! config.set_gdb_remote(false);
+//!
+//! let mut machine = config.build();
+//! let mut renderer = SoftwareRenderer::new();
+//!
+//! assert!(machine.run_frame(&mut renderer) == FrameStatus::Frame);
+//!
+//! let frame = machine.fetch_frame(&mut renderer);
+//!
+//! println!("{}x{}", frame.width, frame.height);
+//! ```
+
+pub use machine::{Machine, MachineConfig, FrameStatus};
+pub use error::Error;
+pub use bios::{Bios, BiosError, BIOS_SIZE};
+pub use cdrom::disc::{Disc, Region};
+pub use gpu::{Gpu, VideoClock};
+pub use gpu::renderer::{Renderer, NullRenderer};
+pub use gpu::software::{SoftwareRenderer, Frame};
+pub use padmemcard::gamepad::{GamePad, Button, ButtonState, Axis};
+pub use padmemcard::memorycard::MemoryCard;
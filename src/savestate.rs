@@ -0,0 +1,249 @@
+//! Minimal binary serialization used for the save states. Values are
+//! stored in little endian without any padding or type information,
+//! the reader must load them in the same order they were written.
+
+use std::fmt;
+
+/// Serializer building a save state blob
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Create a new writer, the state starts with `magic` followed by
+    /// `version`
+    pub fn new(magic: &[u8; 4], version: u32) -> StateWriter {
+        let mut writer = StateWriter {
+            data: Vec::new(),
+        };
+
+        writer.data.extend_from_slice(magic);
+        writer.write_u32(version);
+
+        writer
+    }
+
+    /// Return the serialized state
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_u8(v as u8);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.write_u8(v as u8);
+        self.write_u8((v >> 8) as u8);
+    }
+
+    pub fn write_i16(&mut self, v: i16) {
+        self.write_u16(v as u16);
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.write_u16(v as u16);
+        self.write_u16((v >> 16) as u16);
+    }
+
+    /// Write raw bytes, the reader must know how many to expect
+    pub fn write_bytes(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// Write `blob` preceded by its length. Used to embed a state
+    /// serialized by an other writer.
+    pub fn write_blob(&mut self, blob: &[u8]) {
+        self.write_u32(blob.len() as u32);
+        self.write_bytes(blob);
+    }
+}
+
+/// Deserializer reading back a blob created by a `StateWriter`
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header of `data` and return a reader for the
+    /// values that follow it along with the version of the state.
+    pub fn new(data: &'a [u8],
+               magic: &[u8; 4]) -> Result<(StateReader<'a>, u32), StateError> {
+        if data.len() < 4 || &data[0..4] != magic {
+            return Err(StateError::BadMagic);
+        }
+
+        let mut reader = StateReader {
+            data: &data[4..],
+        };
+
+        let version = reader.read_u32()?;
+
+        Ok((reader, version))
+    }
+
+    /// Make sure the whole state has been consumed
+    pub fn finish(self) -> Result<(), StateError> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::TrailingData),
+        }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        match self.data.split_first() {
+            Some((&b, rest)) => {
+                self.data = rest;
+                Ok(b)
+            }
+            None => Err(StateError::Truncated),
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue("bool")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+
+        Ok(lo | (hi << 8))
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, StateError> {
+        self.read_u16().map(|v| v as i16)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let lo = self.read_u16()? as u32;
+        let hi = self.read_u16()? as u32;
+
+        Ok(lo | (hi << 16))
+    }
+
+    /// Fill `buf` with raw bytes written by `write_bytes`
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), StateError> {
+        if self.data.len() < buf.len() {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(buf.len());
+
+        buf.copy_from_slice(bytes);
+        self.data = rest;
+
+        Ok(())
+    }
+
+    /// Read a blob written by `write_blob`
+    pub fn read_blob(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.read_u32()? as usize;
+
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (blob, rest) = self.data.split_at(len);
+
+        self.data = rest;
+
+        Ok(blob)
+    }
+}
+
+/// Error returned when a save state can't be loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The state doesn't start with the expected header, it's
+    /// corrupted or belongs to something else
+    BadMagic,
+    /// The state was created by an incompatible version of the
+    /// emulator
+    UnsupportedVersion(u32),
+    /// The state ended unexpectedly
+    Truncated,
+    /// The state contains data past its end
+    TrailingData,
+    /// A value is out of range, the string describes which one
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "Invalid save state header"),
+            StateError::UnsupportedVersion(v) =>
+                write!(f, "Unsupported save state version {}", v),
+            StateError::Truncated => write!(f, "Truncated save state"),
+            StateError::TrailingData =>
+                write!(f, "Unexpected data at the end of the save state"),
+            StateError::InvalidValue(what) =>
+                write!(f, "Invalid {} in save state", what),
+        }
+    }
+}
+
+#[test]
+fn roundtrip() {
+    let mut writer = StateWriter::new(b"TEST", 3);
+
+    writer.write_u8(0xab);
+    writer.write_bool(true);
+    writer.write_u16(0x1234);
+    writer.write_i16(-2);
+    writer.write_u32(0xdeadbeef);
+
+    let state = writer.finish();
+
+    assert!(state.len() == 4 + 4 + 1 + 1 + 2 + 2 + 4);
+
+    let (mut reader, version) = StateReader::new(&state, b"TEST").unwrap();
+
+    assert!(version == 3);
+    assert!(reader.read_u8() == Ok(0xab));
+    assert!(reader.read_bool() == Ok(true));
+    assert!(reader.read_u16() == Ok(0x1234));
+    assert!(reader.read_i16() == Ok(-2));
+    assert!(reader.read_u32() == Ok(0xdeadbeef));
+    assert!(reader.read_u8() == Err(StateError::Truncated));
+    assert!(reader.finish() == Ok(()));
+
+    assert!(StateReader::new(&state, b"NOPE").is_err());
+}
+
+#[test]
+fn blobs() {
+    let inner = StateWriter::new(b"INNR", 1).finish();
+
+    let mut writer = StateWriter::new(b"TEST", 1);
+
+    writer.write_blob(&inner);
+    writer.write_bytes(&[1, 2, 3]);
+
+    let state = writer.finish();
+
+    let (mut reader, _) = StateReader::new(&state, b"TEST").unwrap();
+
+    assert!(reader.read_blob() == Ok(&inner[..]));
+
+    let mut buf = [0; 3];
+
+    assert!(reader.read_bytes(&mut buf) == Ok(()));
+    assert!(buf == [1, 2, 3]);
+    assert!(reader.read_bytes(&mut buf) == Err(StateError::Truncated));
+    assert!(reader.finish() == Ok(()));
+
+    // Blob length past the end of the state
+    let (mut reader, _) = StateReader::new(&state[..12], b"TEST").unwrap();
+
+    assert!(reader.read_blob() == Err(StateError::Truncated));
+}
@@ -5,7 +5,8 @@ use interrupt::InterruptState;
 pub struct SharedState {
     tk: TimeKeeper,
     irq_state: InterruptState,
-    frame: u32,
+    /// Total number of frames since power on
+    frame: u64,
 }
 
 impl SharedState {
@@ -25,12 +26,24 @@ impl SharedState {
         &mut self.irq_state
     }
 
+    /// Return a copy of the interrupt state, for read-only accesses
+    pub fn irq_snapshot(&self) -> InterruptState {
+        self.irq_state
+    }
+
+    /// Return the frame number truncated to 32 bits. It wraps in a
+    /// little more than 2 years at 60Hz.
     pub fn frame(&self) -> u32 {
-        self.frame
+        self.frame as u32
     }
 
     pub fn new_frame(&mut self) {
-        // It will wrap in a little more than 2 years at 60Hz
-        self.frame = self.frame.wrapping_add(1);
+        self.frame += 1;
+    }
+
+    /// Total number of frames since power on. Unlike `frame` it
+    /// never wraps in practice.
+    pub fn frames_rendered(&self) -> u64 {
+        self.frame
     }
 }
@@ -1,4 +1,16 @@
 use memory::Addressable;
+use shared::SharedState;
+use savestate::StateError;
+use timekeeper::{Peripheral, Cycles};
+
+use self::output::OutputBuffer;
+use self::voice::Voice;
+
+pub use self::voice::AdsrPhase;
+
+pub mod output;
+pub mod state;
+mod voice;
 
 /// Sound Processing Unit
 pub struct Spu {
@@ -9,23 +21,231 @@ pub struct Spu {
     shadow_registers: [u16; 0x100],
 
     /// SPU RAM: 256k 16bit samples
-    ram: Box<[u16; 256 * 1024]>,
+    ram: Box<[u16; RAM_SIZE / 2]>,
     /// Write pointer in the SPU RAM
     ram_index: u32,
+    /// Samples waiting to be fetched by the frontend
+    output: OutputBuffer,
+    /// The 24 voices
+    voices: [Voice; 24],
+    /// Voices keyed on since the last sample, one bit per voice
+    key_on_pending: u32,
+    /// Voices keyed off since the last sample, one bit per voice
+    key_off_pending: u32,
+    /// CPU cycles elapsed since the last generated sample
+    sample_cycles: Cycles,
 }
 
 impl Spu {
+    /// Create a new SPU instance with the default output buffer size
     pub fn new() -> Spu {
+        Spu::with_buffer_size(DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Create a new SPU instance whose output buffer can hold
+    /// `buffer_size` stereo samples. See `OutputBuffer` for the
+    /// latency tradeoff.
+    pub fn with_buffer_size(buffer_size: usize) -> Spu {
         Spu {
             shadow_registers: [0; 0x100],
-            ram: box_array![0xbad; 256 * 1024],
+            ram: box_array![0xbad; RAM_SIZE / 2],
             ram_index: 0,
+            output: OutputBuffer::new(buffer_size),
+            voices: [Voice::new(); 24],
+            key_on_pending: 0,
+            key_off_pending: 0,
+            sample_cycles: 0,
+        }
+    }
+
+    /// Generate the samples for the time elapsed since the last sync
+    /// and push them to the output buffer
+    pub fn sync(&mut self, shared: &mut SharedState) {
+        let delta = shared.tk().sync(Peripheral::Spu);
+
+        self.sample_cycles += delta;
+
+        while self.sample_cycles >= CYCLES_PER_SAMPLE {
+            self.sample_cycles -= CYCLES_PER_SAMPLE;
+
+            let (left, right) = self.sample();
+
+            self.output.push([left, right]);
+        }
+
+        // We don't need to be synchronized for every single sample,
+        // register accesses force a sync anyway.
+        let next_sync =
+            SYNC_SAMPLES * CYCLES_PER_SAMPLE - self.sample_cycles;
+
+        shared.tk().set_next_sync_delta(Peripheral::Spu, next_sync);
+    }
+
+    /// Move buffered samples to `out` as interleaved left and right
+    /// values, `out` should have an even length. If there aren't
+    /// enough samples available the rest of `out` is filled with
+    /// silence. Returns the number of values actually taken from the
+    /// buffer, a value less than `out.len()` means that an underrun
+    /// occured.
+    pub fn drain(&mut self, out: &mut [i16]) -> usize {
+        let mut drained = 0;
+
+        for pair in out.chunks_mut(2) {
+            let sample =
+                match self.output.pop() {
+                    Some(s) => s,
+                    None => break,
+                };
+
+            for (o, &s) in pair.iter_mut().zip(sample.iter()) {
+                *o = s;
+            }
+
+            drained += pair.len();
+        }
+
+        for o in &mut out[drained..] {
+            *o = 0;
+        }
+
+        drained
+    }
+
+    /// Generate one stereo sample. Should be called at 44.1kHz.
+    pub fn sample(&mut self) -> (i16, i16) {
+        // Key on and key off are edge triggered: writing to KON/KOFF
+        // only takes effect at the beginning of the next sample. If a
+        // voice is both keyed on and off it ends up in release.
+        let key_on = self.key_on_pending;
+        let key_off = self.key_off_pending;
+
+        self.key_on_pending = 0;
+        self.key_off_pending = 0;
+
+        let mut left = 0i32;
+        let mut right = 0i32;
+
+        for v in 0..24 {
+            let regs = &self.shadow_registers[v * 8..(v + 1) * 8];
+
+            let voice = &mut self.voices[v];
+
+            if key_on & (1 << v) != 0 {
+                let adsr_config =
+                    (regs[regmap::voice::ADPCM_ADSR_LOW] as u32) |
+                    ((regs[regmap::voice::ADPCM_ADSR_HIGH] as u32) << 16);
+
+                voice.key_on(&self.ram[..],
+                             regs[regmap::voice::ADPCM_START_INDEX],
+                             adsr_config);
+            }
+
+            if key_off & (1 << v) != 0 {
+                voice.key_off();
+            }
+
+            let pitch = regs[regmap::voice::ADPCM_SAMPLE_RATE];
+
+            let sample = voice.next_sample(&self.ram[..], pitch) as i32;
+
+            left += (sample * volume(regs[regmap::voice::VOLUME_LEFT])) >> 15;
+            right += (sample * volume(regs[regmap::voice::VOLUME_RIGHT])) >> 15;
+        }
+
+        let left = saturate(left);
+        let right = saturate(right);
+
+        let main_left =
+            volume(self.shadow_registers[regmap::MAIN_VOLUME_LEFT]);
+        let main_right =
+            volume(self.shadow_registers[regmap::MAIN_VOLUME_RIGHT]);
+
+        let left = saturate((left as i32 * main_left) >> 15);
+        let right = saturate((right as i32 * main_right) >> 15);
+
+        if !self.enabled() {
+            // The voices keep running but the output is muted, that
+            // way playback resumes seamlessly when the SPU is
+            // re-enabled.
+            return (0, 0);
+        }
+
+        (left, right)
+    }
+
+    /// Return the current ADSR phase of `voice` (0 to 23)
+    pub fn voice_phase(&self, voice: usize) -> AdsrPhase {
+        self.voices[voice].phase()
+    }
+
+    /// Move all the voices to the release phase immediately, without
+    /// waiting for the next sample. Can be used to silence stuck
+    /// voices.
+    pub fn force_key_off_all(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.key_off();
+        }
+
+        // Drop any pending key on, otherwise the voices would start
+        // playing again at the next sample
+        self.key_on_pending = 0;
+    }
+
+    /// Replace the output buffer with a new one able to hold
+    /// `buffer_size` stereo samples. Any sample currently buffered is
+    /// discarded.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        self.output = OutputBuffer::new(buffer_size);
+    }
+
+    /// Maximum number of stereo samples the output buffer can hold
+    pub fn buffer_size(&self) -> usize {
+        self.output.capacity()
+    }
+
+    /// Number of stereo samples currently waiting in the output
+    /// buffer. Frontends can monitor this value to adjust their audio
+    /// pacing.
+    pub fn buffer_fill(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Return a little endian copy of the entire SPU RAM
+    pub fn dump_ram(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RAM_SIZE);
+
+        for &v in self.ram.iter() {
+            data.push(v as u8);
+            data.push((v >> 8) as u8);
+        }
+
+        data
+    }
+
+    /// Replace the contents of the SPU RAM with `data` (in the format
+    /// returned by `dump_ram`). Returns an error and leaves the RAM
+    /// untouched if `data` isn't exactly `RAM_SIZE` bytes long.
+    pub fn load_ram(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() < RAM_SIZE {
+            return Err(StateError::Truncated);
+        }
+
+        if data.len() > RAM_SIZE {
+            return Err(StateError::TrailingData);
         }
+
+        for (v, b) in self.ram.iter_mut().zip(data.chunks(2)) {
+            *v = (b[0] as u16) | ((b[1] as u16) << 8);
+        }
+
+        Ok(())
     }
 
     pub fn store<T: Addressable>(&mut self, offset: u32, val: u32) {
         if T::size() != 2 {
-            panic!("Unhandled SPU store ({})", T::size());
+            warn!("Unhandled SPU store ({}) at offset {:x}: 0x{:08x}",
+                  T::size(), offset, val);
+            return;
         }
 
         let val = val as u16;
@@ -43,7 +263,8 @@ impl Spu {
                 regmap::voice::ADPCM_ADSR_HIGH => (),
                 // XXX change current volume?
                 regmap::voice::CURRENT_ADSR_VOLUME => (),
-                regmap::voice::ADPCM_REPEAT_INDEX => (),
+                regmap::voice::ADPCM_REPEAT_INDEX =>
+                    self.voices[index >> 3].set_repeat_address(val),
                 _ => unreachable!(),
             }
         } else {
@@ -53,19 +274,20 @@ impl Spu {
                 regmap::REVERB_VOLUME_LEFT => (),
                 regmap::REVERB_VOLUME_RIGHT => (),
                 regmap::VOICE_ON_LOW =>
-                    self.shadow_registers[regmap::VOICE_STATUS_LOW] |= val,
+                    self.key_on_pending |= val as u32,
                 regmap::VOICE_ON_HIGH =>
-                    self.shadow_registers[regmap::VOICE_STATUS_HIGH] |= val,
+                    self.key_on_pending |= (val as u32) << 16,
                 regmap::VOICE_OFF_LOW =>
-                    self.shadow_registers[regmap::VOICE_STATUS_LOW] &= !val,
+                    self.key_off_pending |= val as u32,
                 regmap::VOICE_OFF_HIGH =>
-                    self.shadow_registers[regmap::VOICE_STATUS_HIGH] &= !val,
+                    self.key_off_pending |= (val as u32) << 16,
                 regmap::VOICE_PITCH_MOD_EN_LOW => (),
                 regmap::VOICE_PITCH_MOD_EN_HIGH => (),
                 regmap::VOICE_NOISE_EN_LOW => (),
                 regmap::VOICE_NOISE_EN_HIGH => (),
                 regmap::VOICE_REVERB_EN_LOW => (),
                 regmap::VOICE_REVERB_EN_HIGH => (),
+                // ENDX is read-only
                 regmap::VOICE_STATUS_LOW => (),
                 regmap::VOICE_STATUS_HIGH => (),
                 regmap::REVERB_BASE => (),
@@ -124,7 +346,8 @@ impl Spu {
 
     pub fn load<T: Addressable>(&mut self, offset: u32) -> u32 {
         if T::size() != 2 {
-            panic!("Unhandled SPU load ({})", T::size());
+            warn!("Unhandled SPU load ({}) at offset {:x}", T::size(), offset);
+            return 0;
         }
 
         let index = (offset >> 1) as usize;
@@ -143,11 +366,9 @@ impl Spu {
             if index < 0xc0 {
                 match index & 7 {
                     regmap::voice::CURRENT_ADSR_VOLUME =>
-                        // XXX return current volume
-                        shadow,
+                        self.voices[index >> 3].envelope_level() as u16,
                     regmap::voice::ADPCM_REPEAT_INDEX =>
-                        // XXX return current repeat index
-                        shadow,
+                        self.voices[index >> 3].repeat_address(),
                     _ => shadow,
                 }
             } else {
@@ -162,8 +383,8 @@ impl Spu {
                     regmap::VOICE_NOISE_EN_LOW => shadow,
                     regmap::VOICE_REVERB_EN_LOW => shadow,
                     regmap::VOICE_REVERB_EN_HIGH => shadow,
-                    regmap::VOICE_STATUS_LOW => shadow,
-                    regmap::VOICE_STATUS_HIGH => shadow,
+                    regmap::VOICE_STATUS_LOW => self.endx() as u16,
+                    regmap::VOICE_STATUS_HIGH => (self.endx() >> 16) as u16,
                     regmap::TRANSFER_START_INDEX => shadow,
                     regmap::CONTROL => shadow,
                     regmap::TRANSFER_CONTROL => shadow,
@@ -185,10 +406,23 @@ impl Spu {
         r as u32
     }
 
+    /// Return the ENDX register: one bit per voice, set when the
+    /// voice reached the end of its sample
+    fn endx(&self) -> u32 {
+        self.voices.iter()
+            .enumerate()
+            .fold(0, |endx, (i, v)| endx | ((v.endx() as u32) << i))
+    }
+
     fn control(&self) -> u16 {
         self.shadow_registers[regmap::CONTROL]
     }
 
+    /// True if the SPU master enable bit is set in SPUCNT
+    fn enabled(&self) -> bool {
+        self.control() & 0x8000 != 0
+    }
+
     fn set_control(&mut self, ctrl: u16) {
         // XXX if a game enables the SPU IRQ we're probably going to
         // be in trouble
@@ -220,6 +454,43 @@ impl Spu {
     }
 }
 
+/// Convert a volume register value into a signed 16bit volume
+fn volume(reg: u16) -> i32 {
+    if reg & 0x8000 != 0 {
+        // XXX volume sweep not implemented, use the maximum volume
+        0x7fff
+    } else {
+        // Fixed volume, the value is in the 15 LSBs and is half the
+        // effective volume
+        ((reg << 1) as i16) as i32
+    }
+}
+
+/// Clamp `v` to the signed 16bit range
+fn saturate(v: i32) -> i16 {
+    if v > 0x7fff {
+        0x7fff
+    } else if v < -0x8000 {
+        -0x8000
+    } else {
+        v as i16
+    }
+}
+
+/// Size of the SPU RAM in bytes
+pub const RAM_SIZE: usize = 512 * 1024;
+
+/// Number of CPU cycles between two samples: the SPU runs at
+/// 44.1kHz, exactly 768 times slower than the CPU.
+const CYCLES_PER_SAMPLE: Cycles = 768;
+
+/// Number of samples generated between two forced synchronizations
+const SYNC_SAMPLES: Cycles = 32;
+
+/// Default output buffer size in stereo samples: about 100ms worth
+/// of audio at 44.1kHz.
+pub const DEFAULT_BUFFER_SIZE: usize = 4410;
+
 mod regmap {
     //! SPU register map: offset from the base in number of
     //! *halfwords*
@@ -299,3 +570,399 @@ mod regmap {
     pub const REVERB_INPUT_VOLUME_LEFT:   usize = 0xfe;
     pub const REVERB_INPUT_VOLUME_RIGHT:  usize = 0xff;
 }
+
+#[test]
+fn spu_buffer_size() {
+    let mut spu = Spu::with_buffer_size(16);
+
+    assert!(spu.buffer_size() == 16);
+    assert!(spu.buffer_fill() == 0);
+
+    for i in 0..10 {
+        spu.output.push([i, i]);
+    }
+
+    assert!(spu.buffer_fill() == 10);
+
+    for i in 0..10 {
+        spu.output.push([i, i]);
+    }
+
+    // We can't go above the configured capacity
+    assert!(spu.buffer_fill() == 16);
+
+    spu.set_buffer_size(32);
+
+    assert!(spu.buffer_size() == 32);
+    assert!(spu.buffer_fill() == 0);
+}
+
+#[test]
+fn spu_key_on_off() {
+    use memory::HalfWord;
+
+    let mut spu = Spu::new();
+
+    // Voice 0: start address 0x1000, linear attack with the fastest
+    // rate (+0x3800 per sample), release shift 0
+    spu.store::<HalfWord>(0x004, 0x1000);
+    spu.store::<HalfWord>(0x006, 0x0200);
+    spu.store::<HalfWord>(0x008, 0x000f);
+    spu.store::<HalfWord>(0x00a, 0x0000);
+
+    assert!(spu.voices[0].phase() == AdsrPhase::Off);
+
+    // KON voice 0
+    spu.store::<HalfWord>(0x188, 1);
+
+    // Not taken into account before the next sample
+    assert!(spu.voices[0].phase() == AdsrPhase::Off);
+
+    spu.sample();
+
+    assert!(spu.voices[0].phase() == AdsrPhase::Attack);
+    assert!(spu.voices[0].envelope_level() == 0x3800);
+    // Readback through the current ADSR volume register
+    assert!(spu.load::<HalfWord>(0x00c) == 0x3800);
+
+    spu.sample();
+
+    assert!(spu.voices[0].envelope_level() == 0x7000);
+
+    spu.sample();
+
+    assert!(spu.voices[0].phase() == AdsrPhase::Decay);
+    assert!(spu.voices[0].envelope_level() == 0x7fff);
+
+    // KOFF voice 0
+    spu.store::<HalfWord>(0x18c, 1);
+
+    assert!(spu.voices[0].phase() == AdsrPhase::Decay);
+
+    spu.sample();
+
+    assert!(spu.voices[0].phase() == AdsrPhase::Release);
+
+    // Key on and key off during the same sample: key off wins. Since
+    // the level is reset to 0 by the key on the release ends
+    // immediately.
+    spu.store::<HalfWord>(0x188, 1);
+    spu.store::<HalfWord>(0x18c, 1);
+
+    spu.sample();
+
+    assert!(spu.voices[0].phase() == AdsrPhase::Off);
+    assert!(spu.voices[0].envelope_level() == 0);
+}
+
+#[test]
+fn spu_ram_dump_load() {
+    use memory::HalfWord;
+
+    // Upload an ADPCM block at 0x1000 and play it on voice 0
+    fn play(spu: &mut Spu) -> Vec<(i16, i16)> {
+        spu.store::<HalfWord>(0x000, 0x3fff);
+        spu.store::<HalfWord>(0x002, 0x3fff);
+        spu.store::<HalfWord>(0x004, 0x1000);
+        spu.store::<HalfWord>(0x006, 0x0200);
+        spu.store::<HalfWord>(0x008, 0x000f);
+        spu.store::<HalfWord>(0x00a, 0x0000);
+        spu.store::<HalfWord>(0x180, 0x3fff);
+        spu.store::<HalfWord>(0x182, 0x3fff);
+
+        spu.store::<HalfWord>(0x188, 1);
+
+        (0..28).map(|_| spu.sample()).collect()
+    }
+
+    let mut spu = Spu::new();
+
+    // SPUCNT: enable the SPU
+    spu.store::<HalfWord>(0x1aa, 0x8000);
+
+    spu.store::<HalfWord>(0x1ac, 0x4);
+    spu.store::<HalfWord>(0x1a6, 0x200);
+
+    let block = [0x0001, 0x1234, 0x5678, 0x9abc, 0xdef0,
+                 0x7777, 0x8888, 0x0f0f];
+
+    for &v in &block {
+        spu.store::<HalfWord>(0x1a8, v);
+    }
+
+    let dump = spu.dump_ram();
+
+    assert!(dump.len() == RAM_SIZE);
+    assert!(dump[0x1000] == 0x01);
+    assert!(dump[0x1002] == 0x34);
+    assert!(dump[0x1003] == 0x12);
+
+    let expected = play(&mut spu);
+
+    assert!(expected.iter().any(|&s| s != (0, 0)));
+
+    // Size validation
+    assert!(spu.load_ram(&dump[1..]) == Err(StateError::Truncated));
+
+    let mut long = dump.clone();
+    long.push(0);
+
+    assert!(spu.load_ram(&long) == Err(StateError::TrailingData));
+    assert!(spu.dump_ram() == dump);
+
+    // Clear the RAM
+    assert!(spu.load_ram(&vec![0; RAM_SIZE]).is_ok());
+    assert!(spu.dump_ram().iter().all(|&b| b == 0));
+
+    assert!(spu.load_ram(&dump).is_ok());
+    assert!(spu.dump_ram() == dump);
+
+    assert!(play(&mut spu) == expected);
+}
+
+#[test]
+fn spu_force_key_off() {
+    use memory::HalfWord;
+
+    let mut spu = Spu::new();
+
+    // SPUCNT: enable the SPU
+    spu.store::<HalfWord>(0x1aa, 0x8000);
+
+    // Play the same sample on voices 0 to 3 with a sustain level at
+    // the maximum and a slow release (shift 10)
+    spu.store::<HalfWord>(0x1ac, 0x4);
+    spu.store::<HalfWord>(0x1a6, 0x200);
+
+    for i in 0..8 {
+        let v = if i == 0 { 0x0000 } else { 0x7777 };
+
+        spu.store::<HalfWord>(0x1a8, v);
+    }
+
+    spu.store::<HalfWord>(0x180, 0x3fff);
+    spu.store::<HalfWord>(0x182, 0x3fff);
+
+    for v in 0..4 {
+        let base = v * 0x10;
+
+        spu.store::<HalfWord>(base + 0x0, 0x3fff);
+        spu.store::<HalfWord>(base + 0x2, 0x3fff);
+        spu.store::<HalfWord>(base + 0x4, 0);
+        spu.store::<HalfWord>(base + 0x6, 0x0200);
+        spu.store::<HalfWord>(base + 0x8, 0x000f);
+        spu.store::<HalfWord>(base + 0xa, 0x000a);
+    }
+
+    spu.store::<HalfWord>(0x188, 0xf);
+
+    for _ in 0..4 {
+        spu.sample();
+    }
+
+    assert!(spu.sample() != (0, 0));
+
+    spu.force_key_off_all();
+
+    for v in 0..4 {
+        assert!(spu.voice_phase(v) == AdsrPhase::Release);
+    }
+
+    // Voices that weren't playing stay off
+    assert!(spu.voice_phase(4) == AdsrPhase::Off);
+
+    // The mix decays to zero
+    let mut samples = 0;
+
+    while (0..4).any(|v| spu.voice_phase(v) != AdsrPhase::Off) {
+        spu.sample();
+
+        samples += 1;
+
+        assert!(samples < 44100);
+    }
+
+    assert!(spu.sample() == (0, 0));
+}
+
+#[test]
+fn spu_adpcm_loop() {
+    use memory::HalfWord;
+
+    let mut spu = Spu::new();
+
+    // SPUCNT: enable the SPU
+    spu.store::<HalfWord>(0x1aa, 0x8000);
+
+    // Upload ADPCM blocks at 0x1000 (8 byte unit 0x200)
+    fn upload(spu: &mut Spu, blocks: &[[u16; 8]]) {
+        spu.store::<HalfWord>(0x1ac, 0x4);
+        spu.store::<HalfWord>(0x1a6, 0x200);
+
+        for block in blocks {
+            for &v in block {
+                spu.store::<HalfWord>(0x1a8, v as u32);
+            }
+        }
+    }
+
+    // Configure voice 0 to play from 0x1000 with an envelope that
+    // reaches the maximum level quickly and stays there, then key it
+    // on
+    fn key_on(spu: &mut Spu) {
+        spu.store::<HalfWord>(0x000, 0x3fff);
+        spu.store::<HalfWord>(0x002, 0x3fff);
+        spu.store::<HalfWord>(0x004, 0x1000);
+        spu.store::<HalfWord>(0x006, 0x0200);
+        spu.store::<HalfWord>(0x008, 0x000f);
+        spu.store::<HalfWord>(0x00a, 0x0000);
+        spu.store::<HalfWord>(0x180, 0x3fff);
+        spu.store::<HalfWord>(0x182, 0x3fff);
+
+        spu.store::<HalfWord>(0x188, 1);
+    }
+
+    // Looping sample: the first block sets the loop start, the
+    // second one loops back
+    upload(&mut spu, &[[0x0400, 0x1234, 0x5678, 0x9abc,
+                        0xdef0, 0x0123, 0x4567, 0x89ab],
+                       [0x0300, 0x7531, 0x8642, 0x1357,
+                        0x2468, 0x7777, 0x1111, 0x9999]]);
+
+    key_on(&mut spu);
+
+    let samples: Vec<_> = (0..28 * 6).map(|_| spu.sample()).collect();
+
+    assert!(spu.voice_phase(0) != AdsrPhase::Off);
+    // ENDX is set even though the voice keeps playing
+    assert!(spu.load::<HalfWord>(0x19c) == 1);
+    // The loop start flag of the first block set the repeat address
+    assert!(spu.load::<HalfWord>(0x00e) == 0x200);
+
+    // Once the envelope is at its maximum the two block sequence
+    // repeats
+    assert!(samples[56..112] == samples[112..168]);
+    assert!(samples[56..84] != samples[84..112]);
+
+    // Key on resets ENDX
+    key_on(&mut spu);
+    spu.sample();
+
+    assert!(spu.load::<HalfWord>(0x19c) == 0);
+
+    // One-shot sample: a single block with the loop end flag and no
+    // repeat
+    upload(&mut spu, &[[0x0100, 0x1234, 0x5678, 0x9abc, 0xdef0,
+                        0x0123, 0x4567, 0x89ab]]);
+
+    key_on(&mut spu);
+
+    for _ in 0..27 {
+        spu.sample();
+    }
+
+    assert!(spu.load::<HalfWord>(0x19c) == 0);
+    assert!(spu.sample() != (0, 0));
+
+    // End of the block
+    assert!(spu.load::<HalfWord>(0x19c) == 1);
+
+    assert!(spu.sample() == (0, 0));
+    assert!(spu.voice_phase(0) == AdsrPhase::Off);
+}
+
+#[test]
+fn spu_master_enable() {
+    use memory::HalfWord;
+
+    let mut spu = Spu::new();
+
+    // Upload a block of noise and play it in loop on voice 0 at the
+    // maximum volume
+    spu.store::<HalfWord>(0x1ac, 0x4);
+    spu.store::<HalfWord>(0x1a6, 0x200);
+
+    for &v in &[0x0300, 0x1234, 0x5678, 0x9abc,
+                0xdef0, 0x7777, 0x8888, 0x0f0f] {
+        spu.store::<HalfWord>(0x1a8, v);
+    }
+
+    spu.store::<HalfWord>(0x000, 0x3fff);
+    spu.store::<HalfWord>(0x002, 0x3fff);
+    spu.store::<HalfWord>(0x004, 0x1000);
+    spu.store::<HalfWord>(0x006, 0x0200);
+    spu.store::<HalfWord>(0x008, 0x000f);
+    spu.store::<HalfWord>(0x00a, 0x0000);
+    spu.store::<HalfWord>(0x00e, 0x0200);
+    spu.store::<HalfWord>(0x180, 0x3fff);
+    spu.store::<HalfWord>(0x182, 0x3fff);
+
+    // SPUCNT: enable the SPU
+    spu.store::<HalfWord>(0x1aa, 0x8000);
+
+    spu.store::<HalfWord>(0x188, 1);
+
+    let enabled: Vec<_> = (0..28).map(|_| spu.sample()).collect();
+
+    assert!(enabled.iter().any(|&s| s != (0, 0)));
+
+    // Disable the SPU: the output is muted
+    spu.store::<HalfWord>(0x1aa, 0x0000);
+
+    assert!((0..28).all(|_| spu.sample() == (0, 0)));
+    // The voice keeps running in the background
+    assert!(spu.voice_phase(0) != AdsrPhase::Off);
+
+    // Re-enable it: the output resumes
+    spu.store::<HalfWord>(0x1aa, 0x8000);
+
+    let resumed: Vec<_> = (0..28).map(|_| spu.sample()).collect();
+
+    assert!(resumed.iter().any(|&s| s != (0, 0)));
+}
+
+#[test]
+fn spu_sync_drain() {
+    let mut spu = Spu::with_buffer_size(16);
+    let mut shared = SharedState::new();
+
+    spu.sync(&mut shared);
+
+    // 10.5 samples worth of cycles
+    shared.tk().advance(CYCLES_PER_SAMPLE * 21 / 2);
+    spu.sync(&mut shared);
+
+    assert!(spu.buffer_fill() == 10);
+
+    let mut out = [0x7fff; 24];
+
+    // Underrun: only 10 stereo samples are available, the rest is
+    // silence
+    assert!(spu.drain(&mut out) == 20);
+    assert!(spu.buffer_fill() == 0);
+    assert!(out.iter().all(|&s| s == 0));
+
+    // The leftover half sample is not lost
+    shared.tk().advance(CYCLES_PER_SAMPLE / 2);
+    spu.sync(&mut shared);
+
+    assert!(spu.buffer_fill() == 1);
+}
+
+#[test]
+fn spu_bad_access_width() {
+    use memory::{Byte, HalfWord, Word};
+
+    let mut spu = Spu::new();
+
+    // Main volume left
+    spu.store::<HalfWord>(0x180, 0x1234);
+
+    // Only halfword accesses are supported, the others are ignored
+    spu.store::<Word>(0x180, 0xffffffff);
+    spu.store::<Byte>(0x180, 0xff);
+
+    assert!(spu.load::<HalfWord>(0x180) == 0x1234);
+    assert!(spu.load::<Word>(0x180) == 0);
+    assert!(spu.load::<Byte>(0x180) == 0);
+}
@@ -0,0 +1,121 @@
+//! Ring buffer holding the stereo samples generated by the SPU until
+//! the frontend fetches them.
+
+/// Fixed-capacity FIFO of stereo samples. The capacity is the main
+/// knob controlling audio latency: a small buffer means the frontend
+/// gets the samples shortly after they've been generated but it's more
+/// likely to run dry (underrun) if the emulator is late, causing
+/// crackling. A bigger buffer absorbs more jitter at the cost of
+/// added lag.
+pub struct OutputBuffer {
+    /// Sample storage, each entry is a `[left, right]` pair
+    samples: Box<[[i16; 2]]>,
+    /// Index of the oldest sample in the buffer
+    read: usize,
+    /// Number of samples currently buffered
+    len: usize,
+}
+
+impl OutputBuffer {
+    /// Create a new buffer able to hold `capacity` stereo
+    /// samples. Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> OutputBuffer {
+        if capacity == 0 {
+            panic!("Invalid SPU output buffer capacity");
+        }
+
+        OutputBuffer {
+            samples: vec![[0; 2]; capacity].into_boxed_slice(),
+            read: 0,
+            len: 0,
+        }
+    }
+
+    /// Maximum number of stereo samples the buffer can hold
+    pub fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Number of stereo samples currently buffered
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    /// Append a new sample at the end of the buffer. If the buffer is
+    /// full the oldest sample is discarded: the frontend is not
+    /// keeping up so we might as well keep the most recent audio.
+    pub fn push(&mut self, sample: [i16; 2]) {
+        let capacity = self.capacity();
+
+        if self.is_full() {
+            self.read = (self.read + 1) % capacity;
+            self.len -= 1;
+        }
+
+        let write = (self.read + self.len) % capacity;
+
+        self.samples[write] = sample;
+        self.len += 1;
+    }
+
+    /// Remove and return the oldest sample in the buffer
+    pub fn pop(&mut self) -> Option<[i16; 2]> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let sample = self.samples[self.read];
+
+        self.read = (self.read + 1) % self.capacity();
+        self.len -= 1;
+
+        Some(sample)
+    }
+
+    /// Discard all buffered samples
+    pub fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+}
+
+#[test]
+fn output_buffer_capacity() {
+    let mut buf = OutputBuffer::new(4);
+
+    assert!(buf.capacity() == 4);
+    assert!(buf.len() == 0);
+    assert!(buf.is_empty());
+
+    buf.push([1, -1]);
+    buf.push([2, -2]);
+
+    assert!(buf.len() == 2);
+
+    buf.push([3, -3]);
+    buf.push([4, -4]);
+
+    assert!(buf.len() == 4);
+    assert!(buf.is_full());
+
+    // Overflow: the oldest sample must be dropped
+    buf.push([5, -5]);
+
+    assert!(buf.len() == 4);
+    assert!(buf.pop() == Some([2, -2]));
+    assert!(buf.len() == 3);
+
+    assert!(buf.pop() == Some([3, -3]));
+    assert!(buf.pop() == Some([4, -4]));
+    assert!(buf.pop() == Some([5, -5]));
+    assert!(buf.pop() == None);
+    assert!(buf.len() == 0);
+}
@@ -0,0 +1,145 @@
+//! SPU save states, including the whole SPU RAM. The samples waiting
+//! in the output buffer are not saved.
+
+use savestate::{StateWriter, StateReader, StateError};
+use timekeeper::Cycles;
+
+use super::{Spu, CYCLES_PER_SAMPLE};
+use super::voice::Voice;
+
+/// Identifier at the beginning of SPU save states
+const MAGIC: &'static [u8; 4] = b"PSXS";
+
+/// Current version of the SPU save state format
+const VERSION: u32 = 1;
+
+impl Spu {
+    /// Serialize the SPU state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(MAGIC, VERSION);
+
+        for &r in self.shadow_registers.iter() {
+            w.write_u16(r);
+        }
+
+        for &v in self.ram.iter() {
+            w.write_u16(v);
+        }
+
+        w.write_u32(self.ram_index);
+
+        for voice in self.voices.iter() {
+            voice.save_state(&mut w);
+        }
+
+        w.write_u32(self.key_on_pending);
+        w.write_u32(self.key_off_pending);
+        // Always less than CYCLES_PER_SAMPLE after a sync
+        w.write_u32(self.sample_cycles as u32);
+
+        w.finish()
+    }
+
+    /// Restore a state created by `save_state`. If an error is
+    /// returned the SPU is left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let (mut r, version) = StateReader::new(state, MAGIC)?;
+
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut spu = Spu::with_buffer_size(self.buffer_size());
+
+        for reg in spu.shadow_registers.iter_mut() {
+            *reg = r.read_u16()?;
+        }
+
+        for v in spu.ram.iter_mut() {
+            *v = r.read_u16()?;
+        }
+
+        spu.ram_index = r.read_u32()?;
+
+        if spu.ram_index > 0x3ffff {
+            return Err(StateError::InvalidValue("SPU RAM index"));
+        }
+
+        for voice in spu.voices.iter_mut() {
+            *voice = Voice::load_state(&mut r)?;
+        }
+
+        spu.key_on_pending = r.read_u32()?;
+        spu.key_off_pending = r.read_u32()?;
+        spu.sample_cycles = r.read_u32()? as Cycles;
+
+        if spu.sample_cycles >= CYCLES_PER_SAMPLE {
+            return Err(StateError::InvalidValue("SPU sample cycles"));
+        }
+
+        r.finish()?;
+
+        *self = spu;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn save_state() {
+    use memory::HalfWord;
+
+    let mut spu = Spu::new();
+
+    // SPUCNT: enable the SPU
+    spu.store::<HalfWord>(0x1aa, 0x8000);
+
+    // Upload an ADPCM block at 0x1000 and key it on for voice 0
+    spu.store::<HalfWord>(0x1a6, 0x200);
+
+    for &v in &[0x0001, 0x1234, 0x5678, 0x9abc, 0xdef0,
+                0x7777, 0x8888, 0x0f0f] {
+        spu.store::<HalfWord>(0x1a8, v);
+    }
+
+    spu.store::<HalfWord>(0x000, 0x3fff);
+    spu.store::<HalfWord>(0x002, 0x3fff);
+    spu.store::<HalfWord>(0x004, 0x0800);
+    spu.store::<HalfWord>(0x006, 0x0200);
+    spu.store::<HalfWord>(0x008, 0x000f);
+    spu.store::<HalfWord>(0x180, 0x3fff);
+    spu.store::<HalfWord>(0x182, 0x3fff);
+    spu.store::<HalfWord>(0x188, 1);
+
+    // Save while the block is being played
+    for _ in 0..10 {
+        spu.sample();
+    }
+
+    let state = spu.save_state();
+
+    let expected: Vec<_> = (0..20).map(|_| spu.sample()).collect();
+
+    assert!(expected.iter().any(|&s| s != (0, 0)));
+
+    let mut restored = Spu::new();
+
+    assert!(restored.load_state(&state).is_ok());
+    assert!(restored.dump_ram() == spu.dump_ram());
+
+    let samples: Vec<_> = (0..20).map(|_| restored.sample()).collect();
+
+    assert!(samples == expected);
+
+    // Corrupted states are rejected without touching the SPU
+    let mut bad = state.clone();
+    let len = bad.len();
+    // Sample cycles
+    bad[len - 3] = 0xff;
+
+    assert!(restored.load_state(&bad) ==
+            Err(StateError::InvalidValue("SPU sample cycles")));
+    assert!(restored.load_state(&state[..len - 1]) ==
+            Err(StateError::Truncated));
+    assert!(restored.dump_ram() == spu.dump_ram());
+}
@@ -0,0 +1,446 @@
+//! SPU voice emulation: ADPCM decoding and ADSR envelope
+
+use savestate::{StateWriter, StateReader, StateError};
+
+/// ADSR phases indexed by their value in the save state
+const ADSR_PHASES: [AdsrPhase; 5] = [
+    AdsrPhase::Attack,
+    AdsrPhase::Decay,
+    AdsrPhase::Sustain,
+    AdsrPhase::Release,
+    AdsrPhase::Off,
+    ];
+
+/// One of the 24 SPU voices
+#[derive(Clone, Copy)]
+pub struct Voice {
+    /// Current address in SPU RAM, in halfwords. Always points at the
+    /// beginning of the block being played.
+    address: u32,
+    /// Address the voice jumps to at the end of a block with the
+    /// "loop end" flag, in halfwords
+    repeat_address: u32,
+    /// Flags of the block being played
+    flags: u8,
+    /// Set when a block with the "loop end" flag has been played,
+    /// cleared on key on
+    endx: bool,
+    /// Pitch counter: the 12 LSBs are the fractional position between
+    /// two samples, the rest is the index of the current sample in
+    /// `decoded`
+    pitch_counter: u32,
+    /// Samples decoded from the current ADPCM block
+    decoded: [i16; 28],
+    /// Last two decoded samples, used by the ADPCM prediction filters
+    history: [i16; 2],
+    /// Envelope generator
+    adsr: Adsr,
+}
+
+impl Voice {
+    pub fn new() -> Voice {
+        Voice {
+            address: 0,
+            repeat_address: 0,
+            flags: 0,
+            endx: false,
+            pitch_counter: 0,
+            decoded: [0; 28],
+            history: [0; 2],
+            adsr: Adsr::new(),
+        }
+    }
+
+    /// Start playing the sample at `start_address` (in 8 byte units)
+    /// from the beginning of the attack phase
+    pub fn key_on(&mut self,
+                  ram: &[u16],
+                  start_address: u16,
+                  adsr_config: u32) {
+        self.address = (start_address as u32) << 2;
+        self.pitch_counter = 0;
+        self.endx = false;
+        self.history = [0; 2];
+
+        self.adsr.key_on(adsr_config);
+
+        self.decode_block(ram);
+    }
+
+    /// Switch to the release phase
+    pub fn key_off(&mut self) {
+        self.adsr.key_off();
+    }
+
+    /// Return the repeat address (in 8 byte units)
+    pub fn repeat_address(&self) -> u16 {
+        (self.repeat_address >> 2) as u16
+    }
+
+    /// Set the repeat address (in 8 byte units)
+    pub fn set_repeat_address(&mut self, address: u16) {
+        self.repeat_address = (address as u32) << 2;
+    }
+
+    /// True if the voice reached the end of a sample since the last
+    /// key on
+    pub fn endx(&self) -> bool {
+        self.endx
+    }
+
+    /// Serialize the voice state in `w`
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.address);
+        w.write_u32(self.repeat_address);
+        w.write_u8(self.flags);
+        w.write_bool(self.endx);
+        w.write_u32(self.pitch_counter);
+
+        for &s in self.decoded.iter().chain(self.history.iter()) {
+            w.write_i16(s);
+        }
+
+        w.write_u8(self.adsr.phase as u8);
+        w.write_i16(self.adsr.level);
+        w.write_u32(self.adsr.divider);
+        w.write_u32(self.adsr.config);
+    }
+
+    /// Read back a voice serialized by `save_state`
+    pub fn load_state(r: &mut StateReader) -> Result<Voice, StateError> {
+        let mut voice = Voice::new();
+
+        voice.address = r.read_u32()?;
+        voice.repeat_address = r.read_u32()?;
+
+        if voice.address > 0x3ffff || voice.repeat_address > 0x3ffff {
+            return Err(StateError::InvalidValue("SPU voice address"));
+        }
+
+        voice.flags = r.read_u8()?;
+        voice.endx = r.read_bool()?;
+        voice.pitch_counter = r.read_u32()?;
+
+        if voice.pitch_counter >= 28 << 12 {
+            return Err(StateError::InvalidValue("SPU voice pitch counter"));
+        }
+
+        for s in voice.decoded.iter_mut().chain(voice.history.iter_mut()) {
+            *s = r.read_i16()?;
+        }
+
+        voice.adsr.phase =
+            match ADSR_PHASES.get(r.read_u8()? as usize) {
+                Some(&p) => p,
+                None => return Err(StateError::InvalidValue("ADSR phase")),
+            };
+
+        voice.adsr.level = r.read_i16()?;
+        voice.adsr.divider = r.read_u32()?;
+        voice.adsr.config = r.read_u32()?;
+
+        Ok(voice)
+    }
+
+    /// Current ADSR phase
+    pub fn phase(&self) -> AdsrPhase {
+        self.adsr.phase
+    }
+
+    /// Current envelope level
+    pub fn envelope_level(&self) -> i16 {
+        self.adsr.level
+    }
+
+    /// Return the current sample (with the envelope applied) and move
+    /// on to the next one
+    pub fn next_sample(&mut self, ram: &[u16], pitch: u16) -> i16 {
+        if self.adsr.phase == AdsrPhase::Off {
+            return 0;
+        }
+
+        // XXX No interpolation, the real hardware uses a 4-point
+        // gaussian filter
+        let index = (self.pitch_counter >> 12) as usize;
+
+        let raw = self.decoded[index] as i32;
+
+        let sample = (raw * self.adsr.level as i32) >> 15;
+
+        self.adsr.step();
+
+        // The step is clamped to 0x4000, that is 4 times the base
+        // frequency (176.4kHz)
+        let step = ::std::cmp::min(pitch, 0x4000) as u32;
+
+        self.pitch_counter += step;
+
+        while self.pitch_counter >= 28 << 12 {
+            // End of block
+            self.pitch_counter -= 28 << 12;
+
+            if self.flags & block_flags::LOOP_END != 0 {
+                self.endx = true;
+                self.address = self.repeat_address;
+
+                if self.flags & block_flags::LOOP_REPEAT == 0 {
+                    // One-shot sample: the voice is silenced
+                    // immediately
+                    self.adsr.key_off();
+                    self.adsr.level = 0;
+                }
+            } else {
+                self.address = (self.address + 8) & 0x3ffff;
+            }
+
+            self.decode_block(ram);
+        }
+
+        sample as i16
+    }
+
+    /// Decode the 16 byte ADPCM block at `address`
+    fn decode_block(&mut self, ram: &[u16]) {
+        let address = self.address as usize;
+
+        let header = ram[address];
+
+        self.flags = (header >> 8) as u8;
+
+        if self.flags & block_flags::LOOP_START != 0 {
+            self.repeat_address = self.address;
+        }
+
+        let mut shift = header & 0xf;
+        let mut filter = ((header >> 4) & 7) as usize;
+
+        if shift > 12 {
+            // Invalid shift values behave like 9
+            shift = 9;
+        }
+
+        if filter > 4 {
+            warn!("Invalid ADPCM filter {}", filter);
+            filter = 4;
+        }
+
+        let f0 = ADPCM_FILTERS[filter].0;
+        let f1 = ADPCM_FILTERS[filter].1;
+
+        for i in 0..28 {
+            let word = ram[(address + 1 + i / 4) & 0x3ffff];
+
+            let nibble = (word >> ((i & 3) * 4)) & 0xf;
+
+            // Sign-extend the nibble and apply the shift
+            let sample = (((nibble << 12) as i16) >> shift) as i32;
+
+            let old = self.history[0] as i32;
+            let older = self.history[1] as i32;
+
+            let sample = sample + (old * f0 + older * f1 + 32) / 64;
+
+            let sample =
+                if sample > 0x7fff {
+                    0x7fff
+                } else if sample < -0x8000 {
+                    -0x8000
+                } else {
+                    sample
+                } as i16;
+
+            self.decoded[i] = sample;
+            self.history = [sample, self.history[0]];
+        }
+    }
+}
+
+/// ADSR envelope generator
+#[derive(Clone, Copy)]
+struct Adsr {
+    /// Current phase
+    phase: AdsrPhase,
+    /// Current envelope level
+    level: i16,
+    /// Number of samples to wait until the next level update
+    divider: u32,
+    /// ADSR configuration registers (high halfword in the upper 16
+    /// bits)
+    config: u32,
+}
+
+impl Adsr {
+    fn new() -> Adsr {
+        Adsr {
+            phase: AdsrPhase::Off,
+            level: 0,
+            divider: 0,
+            config: 0,
+        }
+    }
+
+    fn key_on(&mut self, config: u32) {
+        self.config = config;
+        self.level = 0;
+        self.divider = 0;
+        self.phase = AdsrPhase::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.phase != AdsrPhase::Off {
+            self.divider = 0;
+            self.phase = AdsrPhase::Release;
+        }
+    }
+
+    /// Sustain level, the decay phase ends when it's reached
+    fn sustain_level(&self) -> i32 {
+        let level = (self.config & 0xf) as i32;
+
+        ::std::cmp::min((level + 1) << 11, 0x7fff)
+    }
+
+    /// Return the current envelope slope parameters: (exponential,
+    /// decrease, shift, step)
+    fn parameters(&self) -> (bool, bool, u32, i32) {
+        let c = self.config;
+
+        match self.phase {
+            AdsrPhase::Attack => {
+                let exp = (c >> 15) & 1 != 0;
+                let shift = (c >> 10) & 0x1f;
+                let step = 7 - ((c >> 8) & 3) as i32;
+
+                (exp, false, shift, step)
+            }
+            AdsrPhase::Decay => {
+                let shift = (c >> 4) & 0xf;
+
+                (true, true, shift, -8)
+            }
+            AdsrPhase::Sustain => {
+                let exp = (c >> 31) & 1 != 0;
+                let decrease = (c >> 30) & 1 != 0;
+                let shift = (c >> 24) & 0x1f;
+                let step = ((c >> 22) & 3) as i32;
+
+                let step =
+                    match decrease {
+                        false => 7 - step,
+                        true => -8 + step,
+                    };
+
+                (exp, decrease, shift, step)
+            }
+            AdsrPhase::Release => {
+                let exp = (c >> 21) & 1 != 0;
+                let shift = (c >> 16) & 0x1f;
+
+                (exp, true, shift, -8)
+            }
+            AdsrPhase::Off => unreachable!(),
+        }
+    }
+
+    /// Run the envelope for one sample
+    fn step(&mut self) {
+        if self.phase == AdsrPhase::Off {
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        let (exp, decrease, shift, step) = self.parameters();
+
+        let level = self.level as i32;
+
+        let mut cycles = 1 << shift.saturating_sub(11);
+        let mut step = step << 11u32.saturating_sub(shift);
+
+        if exp {
+            if !decrease && level > 0x6000 {
+                // Exponential increase slows down near the top
+                cycles *= 4;
+            }
+
+            if decrease {
+                step = (step * level) >> 15;
+            }
+        }
+
+        self.divider = cycles - 1;
+
+        let level = level + step;
+
+        let level =
+            if level > 0x7fff {
+                0x7fff
+            } else if level < 0 {
+                0
+            } else {
+                level
+            };
+
+        self.level = level as i16;
+
+        // Phase transitions
+        match self.phase {
+            AdsrPhase::Attack => {
+                if level == 0x7fff {
+                    self.phase = AdsrPhase::Decay;
+                    self.divider = 0;
+                }
+            }
+            AdsrPhase::Decay => {
+                if level <= self.sustain_level() {
+                    self.phase = AdsrPhase::Sustain;
+                    self.divider = 0;
+                }
+            }
+            // Sustain lasts until key off
+            AdsrPhase::Sustain => (),
+            AdsrPhase::Release => {
+                if level == 0 {
+                    self.phase = AdsrPhase::Off;
+                }
+            }
+            AdsrPhase::Off => unreachable!(),
+        }
+    }
+}
+
+/// Phase of the ADSR envelope. The values are used in the save
+/// states and mustn't change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AdsrPhase {
+    Attack = 0,
+    Decay = 1,
+    Sustain = 2,
+    Release = 3,
+    /// Voice is silent
+    Off = 4,
+}
+
+mod block_flags {
+    //! Flags stored in the second byte of ADPCM block headers
+
+    /// Jump to the repeat address at the end of this block
+    pub const LOOP_END:    u8 = 1;
+    /// If LOOP_END is set, keep playing from the repeat address.
+    /// Otherwise the voice is silenced.
+    pub const LOOP_REPEAT: u8 = 2;
+    /// Set the repeat address to the start of this block
+    pub const LOOP_START:  u8 = 4;
+}
+
+/// ADPCM prediction filter coefficients (multiplied by 64)
+const ADPCM_FILTERS: [(i32, i32); 5] = [
+    (0, 0),
+    (60, 0),
+    (115, -52),
+    (98, -55),
+    (122, -60),
+    ];
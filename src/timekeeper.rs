@@ -7,7 +7,7 @@ use std::{fmt};
 
 /// List of all peripherals requiring a TimeSheet. The value of the
 /// enum is used as the index in the timesheet table
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Peripheral {
     /// Graphics Processing Unit
     Gpu,
@@ -19,48 +19,117 @@ pub enum Peripheral {
     PadMemCard,
     /// CD-ROM controller
     CdRom,
+    /// Sound Processing Unit
+    Spu,
+}
+
+
+/// Source of the current date. The TimeKeeper uses `CpuClock` by
+/// default, tests can substitute their own implementation to control
+/// the passing of time.
+pub trait Clock {
+    /// Return the current date in CPU cycles
+    fn now(&self) -> Cycles;
+
+    /// Move the current date forward by `cycles`
+    fn advance(&mut self, cycles: Cycles);
+}
+
+/// Default clock: a counter moved forward as the CPU runs. The date
+/// is never derived from the host's clock so the emulation is fully
+/// deterministic.
+pub struct CpuClock {
+    now: Cycles,
+}
+
+impl CpuClock {
+    pub fn new() -> CpuClock {
+        CpuClock {
+            now: 0,
+        }
+    }
 }
 
+impl Clock for CpuClock {
+    fn now(&self) -> Cycles {
+        self.now
+    }
+
+    fn advance(&mut self, cycles: Cycles) {
+        self.now += cycles;
+    }
+}
 
 /// Struct keeping track of the various peripheral's emulation advancement.
-pub struct TimeKeeper {
-    /// Counter keeping track of the current date. Unit is a period of
+pub struct TimeKeeper<C: Clock = CpuClock> {
+    /// Clock keeping track of the current date. Unit is a period of
     /// the CPU clock at 33.8685MHz (~29.5ns)
-    now: Cycles,
+    clock: C,
     /// Next time a peripheral needs an update
     next_sync: Cycles,
     /// Time sheets for keeping track of the various peripherals
-    timesheets: [TimeSheet; 6],
+    timesheets: [TimeSheet; 7],
 }
 
 impl TimeKeeper {
     pub fn new() -> TimeKeeper {
+        TimeKeeper::with_clock(CpuClock::new())
+    }
+}
+
+impl<C: Clock> TimeKeeper<C> {
+    /// Create a TimeKeeper getting the current date from `clock`
+    pub fn with_clock(clock: C) -> TimeKeeper<C> {
         TimeKeeper {
-            now: 0,
+            clock,
             // Force a sync at the start to initialize evrything
             next_sync: 0,
-            timesheets: [TimeSheet::new(); 6],
+            timesheets: [TimeSheet::new(); 7],
         }
     }
 
-    pub fn tick(&mut self, cycles: Cycles) {
-        self.now += cycles;
+    /// Return the current date: the number of CPU cycles (at
+    /// 33.8685MHz) elapsed since the emulator started. All the dates
+    /// and durations handled by the TimeKeeper use this unit.
+    pub fn now(&self) -> Cycles {
+        self.clock.now()
+    }
+
+    /// Move the current date forward by `cycles`. This is the only
+    /// way time progresses in the emulator.
+    pub fn advance(&mut self, cycles: Cycles) {
+        self.clock.advance(cycles);
     }
 
     /// Synchronize the timesheet for the given peripheral and return
-    /// the elapsed time synce the last sync.
+    /// the elapsed time, in CPU cycles, since the last sync.
     pub fn sync(&mut self, who: Peripheral) -> Cycles {
-        self.timesheets[who as usize].sync(self.now)
+        self.timesheets[who as usize].sync(self.now())
     }
 
+    /// Schedule the next sync of `who` at the absolute date `at` (in
+    /// CPU cycles, see `now`). This replaces any sync previously
+    /// scheduled for this peripheral.
+    pub fn set_next_event(&mut self, who: Peripheral, at: Cycles) {
+        self.timesheets[who as usize].set_next_sync(at);
+
+        if at < self.next_sync {
+            self.next_sync = at;
+        }
+    }
+
+    /// Schedule the next sync of `who` `delta` CPU cycles from now
     pub fn set_next_sync_delta(&mut self, who: Peripheral, delta: Cycles) {
-        let date = self.now + delta;
+        let date = self.now() + delta;
 
-        self.timesheets[who as usize].set_next_sync(date);
+        self.set_next_event(who, date);
+    }
 
-        if date < self.next_sync {
-            self.next_sync = date;
-        }
+    /// Return the date of the soonest pending event. The CPU can run
+    /// until then without having to synchronize any peripheral. Only
+    /// accurate after `update_sync_pending` has been called.
+    pub fn next_event(&self) -> Cycles {
+        self.next_sync
     }
 
     /// Set next sync *only* if it's closer than what's already
@@ -68,7 +137,7 @@ impl TimeKeeper {
     pub fn set_next_sync_delta_if_sooner(&mut self,
                                          who: Peripheral,
                                          delta: Cycles) {
-        let date = self.now + delta;
+        let date = self.now() + delta;
 
         let timesheet = &mut self.timesheets[who as usize];
 
@@ -89,11 +158,11 @@ impl TimeKeeper {
     }
 
     pub fn sync_pending(&self) -> bool{
-        self.next_sync <= self.now
+        self.next_sync <= self.now()
     }
 
     pub fn needs_sync(&self, who: Peripheral) -> bool {
-        self.timesheets[who as usize].needs_sync(self.now)
+        self.timesheets[who as usize].needs_sync(self.now())
     }
 
     pub fn update_sync_pending(&mut self) {
@@ -102,9 +171,9 @@ impl TimeKeeper {
     }
 }
 
-impl fmt::Display for TimeKeeper {
+impl<C: Clock> fmt::Display for TimeKeeper<C> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let now = self.now;
+        let now = self.now();
         let cpu_freq = ::cpu::CPU_FREQ_HZ as Cycles;
 
         let seconds = now / cpu_freq;
@@ -224,3 +293,152 @@ impl FracCycles {
         (self.0 + align) >> shift
     }
 }
+
+#[test]
+fn timekeeper_advance() {
+    // Simulate peripherals with periodic events and return the list
+    // of (date, peripheral) syncs triggered
+    fn run() -> Vec<(Cycles, Peripheral)> {
+        let mut tk = TimeKeeper::new();
+
+        let periods = [(Peripheral::Gpu, 100),
+                       (Peripheral::Timer0, 250),
+                       (Peripheral::CdRom, 1000)];
+
+        for &(who, period) in &periods {
+            tk.sync(who);
+            tk.set_next_sync_delta(who, period);
+        }
+
+        for &who in &[Peripheral::Timer1,
+                      Peripheral::Timer2,
+                      Peripheral::PadMemCard] {
+            tk.no_sync_needed(who);
+        }
+
+        tk.update_sync_pending();
+
+        let mut events = Vec::new();
+
+        for _ in 0..40 {
+            tk.advance(50);
+
+            if !tk.sync_pending() {
+                continue;
+            }
+
+            for &(who, period) in &periods {
+                if tk.needs_sync(who) {
+                    let elapsed = tk.sync(who);
+
+                    assert!(elapsed == period);
+
+                    events.push((tk.now(), who));
+
+                    tk.set_next_sync_delta(who, period);
+                }
+            }
+
+            tk.update_sync_pending();
+        }
+
+        events
+    }
+
+    let events = run();
+
+    assert!(events.len() == 20 + 8 + 2);
+    assert!(events[0] == (100, Peripheral::Gpu));
+    assert!(events[2] == (250, Peripheral::Timer0));
+    assert!(events.contains(&(1000, Peripheral::CdRom)));
+    assert!(events.contains(&(2000, Peripheral::CdRom)));
+
+    // Same thing again: the result must be identical
+    assert!(run() == events);
+}
+
+#[test]
+fn timekeeper_next_event() {
+    let mut tk = TimeKeeper::new();
+
+    // Initial sync
+    assert!(tk.sync_pending());
+
+    tk.set_next_event(Peripheral::Gpu, 500);
+    tk.set_next_sync_delta(Peripheral::CdRom, 300);
+
+    for &who in &[Peripheral::Timer0,
+                  Peripheral::Timer1,
+                  Peripheral::Timer2,
+                  Peripheral::PadMemCard,
+                  Peripheral::Spu] {
+        tk.no_sync_needed(who);
+    }
+
+    tk.update_sync_pending();
+
+    assert!(tk.next_event() == 300);
+
+    tk.advance(299);
+
+    assert!(!tk.sync_pending());
+
+    tk.advance(1);
+
+    assert!(tk.sync_pending());
+    assert!(tk.needs_sync(Peripheral::CdRom));
+    assert!(!tk.needs_sync(Peripheral::Gpu));
+    assert!(tk.sync(Peripheral::CdRom) == 300);
+
+    tk.no_sync_needed(Peripheral::CdRom);
+    tk.update_sync_pending();
+
+    assert!(tk.next_event() == 500);
+}
+
+#[test]
+fn timekeeper_fake_clock() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Clock driven by the test, `advance` has no effect
+    struct FakeClock(Rc<Cell<Cycles>>);
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Cycles {
+            self.0.get()
+        }
+
+        fn advance(&mut self, _: Cycles) {
+        }
+    }
+
+    let date = Rc::new(Cell::new(0));
+    let mut tk = TimeKeeper::with_clock(FakeClock(date.clone()));
+
+    tk.sync(Peripheral::Gpu);
+    tk.set_next_sync_delta(Peripheral::Gpu, 1000);
+
+    for &who in &[Peripheral::Timer0,
+                  Peripheral::Timer1,
+                  Peripheral::Timer2,
+                  Peripheral::PadMemCard,
+                  Peripheral::CdRom,
+                  Peripheral::Spu] {
+        tk.no_sync_needed(who);
+    }
+
+    tk.update_sync_pending();
+
+    tk.advance(5000);
+
+    assert!(tk.now() == 0);
+    assert!(!tk.sync_pending());
+
+    date.set(1000);
+
+    assert!(tk.sync_pending());
+    assert!(tk.needs_sync(Peripheral::Gpu));
+    assert!(tk.sync(Peripheral::Gpu) == 1000);
+    assert!(tk.to_string() == "0s+00001000");
+}